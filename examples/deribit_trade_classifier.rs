@@ -12,7 +12,7 @@
 
 use anyhow::Result;
 use rust_streamz::sources::websocket_client::{WebSocketClient, WebSocketClientConfigBuilder};
-use rust_streamz::EngineBuilder;
+use rust_streamz::{EngineBuilder, Level, Logger};
 use serde_json::{json, Value};
 use std::env;
 use std::time::Duration;
@@ -46,53 +46,57 @@ async fn main() -> Result<()> {
     let instrument = env::args()
         .nth(1)
         .unwrap_or_else(|| "BTC-PERPETUAL".to_string());
-    println!("Monitoring instrument: {}", instrument);
+    // one JSON line per event; repeated book updates are collapsed
+    let logger = Logger::stdout().with_throttle(Duration::from_secs(5));
+    logger.install();
+    logger.log(
+        Level::Info,
+        "example",
+        &format!("monitoring {}", instrument),
+    );
 
     let orderbook_config = WebSocketClientConfigBuilder::new("wss://www.deribit.com/ws/api/v2")
         .with_message(&serde_json::to_string(&subscribe_orderbook(&instrument))?)
         .with_buffer_size(1024)
-        .build();
+        .build()?;
 
     let trades_config = WebSocketClientConfigBuilder::new("wss://www.deribit.com/ws/api/v2")
         .with_message(&serde_json::to_string(&subscribe_trades(&instrument))?)
         .with_buffer_size(1024)
-        .build();
+        .build()?;
 
     let orderbook_client = WebSocketClient::new(orderbook_config).await?;
     let trades_client = WebSocketClient::new(trades_config).await?;
 
-    let orderbook_stream = orderbook_client.source().to_stream().accumulate(
-        OrderBookSnapshot::default(),
-        |snapshot, message| {
-            println!("{:?}", message);
+    let orderbook_stream = orderbook_client
+        .source()
+        .to_stream()
+        .accumulate(OrderBookSnapshot::default(), |snapshot, message| {
             apply_orderbook(snapshot, message)
-        },
-    );
+        });
 
-    orderbook_stream.tap(|book| {
-        println!("{:?}", book);
-    });
+    orderbook_stream
+        .map(|book| format!("best bid {:?}, best ask {:?}", book.best_bid, book.best_ask))
+        .sink_to(logger.sink("book", Level::Info));
 
     let trades_stream = trades_client.source().to_stream();
 
     let classification_stream = trades_stream.zip(&orderbook_stream);
 
     let instrument_for_trades = instrument.clone();
-    classification_stream.clone().sink(move |pair| {
-        let (trade, snapshot) = pair;
-        process_trade(*snapshot, instrument_for_trades.as_str(), trade.as_str());
-    });
+    classification_stream
+        .map(move |(trade, snapshot)| {
+            classify_trades(*snapshot, instrument_for_trades.as_str(), trade.as_str())
+        })
+        .sink_batches_to(logger.sink("trades", Level::Info));
 
     let trade_batch_buffer = classification_stream
         .clone()
         .timed_buffer(Duration::from_secs(5));
-    trade_batch_buffer.tap(|batch| {
-        if !batch.is_empty() {
-            println!("Emitting batch of {} trades", batch.len());
-        }
-    });
-
-    println!("Subscribed to order book and trades channels. Press Ctrl+C to exit.\n");
+    trade_batch_buffer
+        .filter(|batch| !batch.is_empty())
+        .map(|batch| format!("batch of {} trades", batch.len()))
+        .sink_to(logger.sink("batches", Level::Debug));
 
     EngineBuilder::new()
         .add_stream(orderbook_stream)
@@ -100,7 +104,8 @@ async fn main() -> Result<()> {
         .add_stream(classification_stream)
         .add_source_owned("Order book", orderbook_client)
         .add_source_owned("Trades", trades_client)
-        .build()
+        .add_timed_buffer(trade_batch_buffer)
+        .build()?
         .run()
         .await?;
 
@@ -129,9 +134,13 @@ fn subscribe_trades(instrument: &str) -> Value {
     })
 }
 
-fn process_trade(snapshot: OrderBookSnapshot, instrument: &str, trade_message: &str) {
+fn classify_trades(
+    snapshot: OrderBookSnapshot,
+    instrument: &str,
+    trade_message: &str,
+) -> Vec<String> {
     let Ok(value) = serde_json::from_str::<Value>(trade_message) else {
-        return;
+        return Vec::new();
     };
 
     let Some(data) = value
@@ -139,9 +148,10 @@ fn process_trade(snapshot: OrderBookSnapshot, instrument: &str, trade_message: &
         .and_then(|params| params.get("data"))
         .and_then(|entries| entries.as_array())
     else {
-        return;
+        return Vec::new();
     };
 
+    let mut lines = Vec::new();
     for trade in data {
         let Some(price) = trade.get("price").and_then(|p| p.as_f64()) else {
             continue;
@@ -154,10 +164,11 @@ fn process_trade(snapshot: OrderBookSnapshot, instrument: &str, trade_message: &
 
         let side = classify_trade(&snapshot, price);
 
-        println!(
+        lines.push(format!(
             "[{instrument}] Trade price: {price:.2}, amount: {amount:.4}, direction: {direction}, classified side: {side:?}",
-        );
+        ));
     }
+    lines
 }
 
 fn apply_orderbook(mut snapshot: OrderBookSnapshot, message: &str) -> OrderBookSnapshot {
@@ -169,13 +180,8 @@ fn apply_orderbook(mut snapshot: OrderBookSnapshot, message: &str) -> OrderBookS
                 return snapshot;
             }
         }
-        Err(err) => {
-            println!("ERR={:?}", err);
-            return snapshot;
-        }
+        Err(_) => return snapshot,
     };
-    let old_bid = snapshot.best_bid;
-    let old_ask = snapshot.best_ask;
 
     if let Some(best_bid) = extract_best_bid(&data) {
         snapshot.best_bid = Some(best_bid);
@@ -185,18 +191,6 @@ fn apply_orderbook(mut snapshot: OrderBookSnapshot, message: &str) -> OrderBookS
         snapshot.best_ask = Some(best_ask);
     }
 
-    if snapshot.best_bid != old_bid {
-        if let Some(bid) = snapshot.best_bid {
-            println!("Best bid updated: {:.2}", bid);
-        }
-    }
-
-    if snapshot.best_ask != old_ask {
-        if let Some(ask) = snapshot.best_ask {
-            println!("Best ask updated: {:.2}", ask);
-        }
-    }
-
     snapshot
 }
 
@@ -1,7 +1,7 @@
 use rust_streamz::Source;
 
 fn main() {
-    let source = Source::new();
+    let source = Source::<i32>::new();
 
     source
         .to_stream()
@@ -0,0 +1,45 @@
+/* Generated by cbindgen from src/capi.rs; do not edit. */
+
+#ifndef STREAMZ_H
+#define STREAMZ_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct StreamzEngine StreamzEngine;
+
+typedef void (*StreamzCallback)(const char *source, const uint8_t *data, size_t len, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *streamz_last_error(void);
+
+struct StreamzEngine *streamz_engine_new(void);
+
+void streamz_engine_free(struct StreamzEngine *engine);
+
+int32_t streamz_add_ws_source(struct StreamzEngine *engine,
+                              const char *name,
+                              const char *url,
+                              const char *const *messages,
+                              size_t count);
+
+int32_t streamz_subscribe(struct StreamzEngine *engine,
+                          const char *source,
+                          StreamzCallback callback,
+                          void *user_data);
+
+int32_t streamz_run(struct StreamzEngine *engine);
+
+void streamz_stop(const struct StreamzEngine *engine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* STREAMZ_H */
@@ -0,0 +1,20 @@
+/* Types for the streamz Node binding (`node` feature, see src/node.rs). */
+
+export declare class Stream {
+  map(callback: (item: any) => any): Stream
+  filter(callback: (item: any) => boolean): Stream
+  subscribe(callback: (item: any) => void): void
+  /** Items this stream's callback threw on so far. */
+  get errors(): number
+}
+
+export declare class Engine {
+  constructor()
+  /** Sends `messages` (subscriptions) after each connect; items are the text frames. */
+  websocket(name: string, url: string, messages?: Array<string> | undefined | null): Stream
+  /** GETs `url` every `periodMs`; items are the response bodies. */
+  poll(name: string, url: string, periodMs: number): Stream
+  /** Resolves when `stop` is called or every source finishes. An engine runs once. */
+  run(): Promise<void>
+  stop(): void
+}
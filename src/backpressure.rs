@@ -0,0 +1,46 @@
+use crate::Source;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Published by bounded stages when items queue up or are dropped; subscribe
+// with `EngineBuilder::backpressure()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backpressure {
+    pub stream: String,
+    pub depth: usize,
+    pub dropped: u64,
+}
+
+thread_local! {
+    // Installed by `Engine::run` for the lifetime of the engine.
+    static ACTIVE: RefCell<Option<Rc<Source<Backpressure>>>> = const { RefCell::new(None) };
+}
+
+// Restores the previously installed event source on drop.
+pub(crate) struct BackpressureGuard {
+    previous: Option<Rc<Source<Backpressure>>>,
+}
+
+pub(crate) fn install(events: Rc<Source<Backpressure>>) -> BackpressureGuard {
+    let previous = ACTIVE.with(|active| active.replace(Some(events)));
+    BackpressureGuard { previous }
+}
+
+impl Drop for BackpressureGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ACTIVE.with(|active| *active.borrow_mut() = previous);
+    }
+}
+
+// Outside a running engine events are discarded.
+pub(crate) fn report(stream: &str, depth: usize, dropped: u64) {
+    let events = ACTIVE.with(|active| active.borrow().clone());
+    if let Some(events) = events {
+        events.emit(Backpressure {
+            stream: stream.to_string(),
+            depth,
+            dropped,
+        });
+    }
+}
@@ -0,0 +1,125 @@
+//! Runs a declarative pipeline config.
+//!
+//! ```bash
+//! cargo install rust_streamz --features cli
+//! streamz examples/configs/deribit_trades.toml --dry-run
+//! streamz examples/configs/deribit_trades.toml --record capture.jsonl --metrics-addr 127.0.0.1:9100
+//! streamz examples/configs/deribit_trades.toml --replay capture.jsonl --replay-speed 10
+//! streamz examples/configs/deribit_trades.toml --watch
+//! ```
+
+use anyhow::Result;
+use clap::Parser;
+use rust_streamz::config::{parse_duration, BuildOptions, PipelineConfig, Registry};
+use rust_streamz::metrics::MetricsRegistry;
+use rust_streamz::CancellationToken;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "streamz",
+    version,
+    about = "Run a declarative rust_streamz pipeline"
+)]
+struct Args {
+    /// Pipeline config (.toml, .yaml or .yml)
+    config: PathBuf,
+
+    /// Validate the config and print the pipeline topology without connecting
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Append every raw source message to FILE
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Feed sources from a recording made with --record instead of connecting;
+    /// a directory replays every recording in it, in file name order
+    #[arg(long, value_name = "FILE|DIR")]
+    replay: Option<PathBuf>,
+
+    /// Replay speed multiplier; 0 replays as fast as possible
+    #[arg(long, default_value_t = 0.0, requires = "replay")]
+    replay_speed: f64,
+
+    /// Serve Prometheus metrics on ADDR (e.g. 127.0.0.1:9100)
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Warn when handling one source message takes longer than this (e.g. 5ms)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    callback_budget: Option<Duration>,
+
+    /// Apply safe config changes (sources, new pipelines and sinks) while running;
+    /// SIGHUP forces a reload
+    #[arg(long)]
+    watch: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = PipelineConfig::from_path(&args.config)?;
+    let registry = Registry::new();
+
+    if args.dry_run {
+        config.validate(&registry)?;
+        print!("{}", config.topology());
+        return Ok(());
+    }
+
+    let mut options = BuildOptions::new();
+    if let Some(path) = &args.record {
+        options = options.with_record(path);
+    }
+    if let Some(path) = &args.replay {
+        options = options.with_replay(path, args.replay_speed);
+    }
+
+    if let Some(budget) = args.callback_budget {
+        options = options.with_callback_budget(budget);
+    }
+
+    if args.watch {
+        options = options.with_hot_reload(&args.config, Duration::from_secs(1));
+    }
+
+    let metrics = MetricsRegistry::new();
+    if args.metrics_addr.is_some() {
+        options = options.with_metrics(metrics.clone());
+    }
+
+    let cancellation = CancellationToken::new();
+    let mut builder = config
+        .engine_builder(&registry, options)
+        .await?
+        .with_cancellation(cancellation.clone());
+    if args.metrics_addr.is_some() {
+        builder = builder.with_stats_interval(Duration::from_secs(1));
+    }
+    builder.events().sink(|event| println!("{}", event));
+    let engine = builder.build()?;
+
+    let report = match args.metrics_addr {
+        Some(addr) => {
+            println!("Serving metrics on http://{}/metrics", addr);
+            let run = engine.run();
+            tokio::pin!(run);
+            tokio::select! {
+                res = &mut run => res?,
+                res = metrics.serve(addr) => {
+                    // the server only returns on failure: shut the engine
+                    // down cleanly before reporting it
+                    cancellation.cancel();
+                    println!("{}", run.await?);
+                    return Ok(res?);
+                }
+            }
+        }
+        None => engine.run().await?,
+    };
+    println!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,361 @@
+use crate::backpressure;
+use crate::rt::Instant;
+use crate::{Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::hash::Hash;
+use std::mem;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+const DEFAULT_CALM_RATIO: f64 = 0.8;
+const DEFAULT_COOL_DOWN: Duration = Duration::from_secs(1);
+
+// How an adaptive stage treats items: passed on as they arrive, conflated
+// to the latest per key each controller tick, or dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BurstMode {
+    PassThrough,
+    Conflate,
+    Shed,
+}
+
+impl Display for BurstMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BurstMode::PassThrough => "pass-through",
+            BurstMode::Conflate => "conflate",
+            BurstMode::Shed => "shed",
+        })
+    }
+}
+
+// A stage switching modes, at the arrival rate (items per second) that
+// made it switch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeChange {
+    pub stage: String,
+    pub from: BurstMode,
+    pub to: BurstMode,
+    pub rate: f64,
+}
+
+// e.g. "book: pass-through -> conflate at 5200/s"
+impl Display for ModeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} at {:.0}/s",
+            self.stage, self.from, self.to, self.rate
+        )
+    }
+}
+
+trait AdaptiveStage {
+    fn name(&self) -> &str;
+    fn mode(&self) -> BurstMode;
+    fn set_mode(&self, mode: BurstMode);
+    fn take_arrivals(&self) -> u64;
+    fn calm_since(&self) -> &Cell<Option<Instant>>;
+    fn rate(&self) -> &Cell<f64>;
+    fn flush(&self);
+}
+
+struct ControllerInner {
+    period: Duration,
+    conflate_above: Cell<f64>,
+    shed_above: Cell<Option<f64>>,
+    calm_ratio: Cell<f64>,
+    cool_down: Cell<Duration>,
+    last_tick: Cell<Instant>,
+    stages: RefCell<Vec<Rc<dyn AdaptiveStage>>>,
+    changes: Source<ModeChange>,
+}
+
+// Watches the arrival rate of each adaptive stage and switches it to
+// conflation above `conflate_above` items per second, and to shedding
+// above `with_shedding`'s rate. A stage steps back down once its rate has
+// stayed below `with_calm_ratio` of the threshold it crossed for
+// `with_cool_down`. Rates are measured, and conflated items flushed, every
+// `period`: register `as_timed_emitter()` with the engine.
+#[derive(Clone)]
+pub struct BurstController {
+    inner: Rc<ControllerInner>,
+}
+
+impl BurstController {
+    pub fn new(period: Duration, conflate_above: f64) -> Self {
+        Self {
+            inner: Rc::new(ControllerInner {
+                period,
+                conflate_above: Cell::new(conflate_above),
+                shed_above: Cell::new(None),
+                calm_ratio: Cell::new(DEFAULT_CALM_RATIO),
+                cool_down: Cell::new(DEFAULT_COOL_DOWN),
+                last_tick: Cell::new(Instant::now()),
+                stages: RefCell::new(Vec::new()),
+                changes: Source::new(),
+            }),
+        }
+    }
+
+    // Drops items outright above this rate.
+    pub fn with_shedding(self, shed_above: f64) -> Self {
+        self.inner.shed_above.set(Some(shed_above));
+        self
+    }
+
+    pub fn with_calm_ratio(self, ratio: f64) -> Self {
+        self.inner.calm_ratio.set(ratio.clamp(0.0, 1.0));
+        self
+    }
+
+    pub fn with_cool_down(self, cool_down: Duration) -> Self {
+        self.inner.cool_down.set(cool_down);
+        self
+    }
+
+    pub fn changes(&self) -> Stream<ModeChange> {
+        self.inner.changes.to_stream()
+    }
+
+    pub fn mode(&self, stage: &str) -> Option<BurstMode> {
+        self.stage(stage).map(|stage| stage.mode())
+    }
+
+    // Items per second over the last period.
+    pub fn rate(&self, stage: &str) -> Option<f64> {
+        self.stage(stage).map(|stage| stage.rate().get())
+    }
+
+    pub fn period(&self) -> Duration {
+        self.inner.period
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+
+    fn stage(&self, name: &str) -> Option<Rc<dyn AdaptiveStage>> {
+        let stages = self.inner.stages.borrow();
+        stages.iter().find(|stage| stage.name() == name).cloned()
+    }
+}
+
+impl ControllerInner {
+    fn threshold(&self, mode: BurstMode) -> f64 {
+        match mode {
+            BurstMode::PassThrough => 0.0,
+            BurstMode::Conflate => self.conflate_above.get(),
+            BurstMode::Shed => self.shed_above.get().unwrap_or(f64::INFINITY),
+        }
+    }
+
+    fn target(&self, rate: f64) -> BurstMode {
+        if self
+            .shed_above
+            .get()
+            .is_some_and(|shed_above| rate > shed_above)
+        {
+            BurstMode::Shed
+        } else if rate > self.conflate_above.get() {
+            BurstMode::Conflate
+        } else {
+            BurstMode::PassThrough
+        }
+    }
+
+    fn switch(&self, stage: &dyn AdaptiveStage, to: BurstMode, rate: f64) {
+        let from = stage.mode();
+        stage.set_mode(to);
+        stage.calm_since().set(None);
+        self.changes.emit(ModeChange {
+            stage: stage.name().to_string(),
+            from,
+            to,
+            rate,
+        });
+    }
+
+    // Called per item: a burst is caught as soon as the items so far this
+    // period exceed a threshold, rather than at the next tick.
+    fn escalate(&self, stage: &dyn AdaptiveStage, arrivals: u64) {
+        let elapsed = self.period.max(self.last_tick.get().elapsed());
+        let rate = arrivals as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let target = self.target(rate);
+        if target > stage.mode() {
+            self.switch(stage, target, rate);
+        }
+    }
+
+    fn tick(&self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick.replace(now));
+        let stages = self.stages.borrow().clone();
+        for stage in stages {
+            let rate = stage.take_arrivals() as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+            stage.rate().set(rate);
+            let mode = stage.mode();
+            let target = self.target(rate);
+            if target > mode {
+                self.switch(&*stage, target, rate);
+            } else if target < mode && rate < self.threshold(mode) * self.calm_ratio.get() {
+                match stage.calm_since().get() {
+                    None => stage.calm_since().set(Some(now)),
+                    Some(since) if now.duration_since(since) >= self.cool_down.get() => {
+                        stage.flush();
+                        self.switch(&*stage, target, rate);
+                    }
+                    Some(_) => {}
+                }
+            } else {
+                stage.calm_since().set(None);
+            }
+            stage.flush();
+        }
+    }
+}
+
+impl TimedEmitter for ControllerInner {
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn flush(&self) {
+        self.tick();
+    }
+
+    fn name(&self) -> String {
+        "burst_controller".to_string()
+    }
+}
+
+struct StageState<T, K> {
+    name: String,
+    controller: Weak<ControllerInner>,
+    mode: Cell<BurstMode>,
+    arrivals: Cell<u64>,
+    rate: Cell<f64>,
+    calm_since: Cell<Option<Instant>>,
+    shed: Cell<u64>,
+    // keys in order of first arrival since the last flush, and their latest
+    order: RefCell<Vec<K>>,
+    latest: RefCell<HashMap<K, T>>,
+    output: Source<T>,
+}
+
+impl<T, K> StageState<T, K>
+where
+    T: Clone + 'static,
+    K: Eq + Hash + Clone + 'static,
+{
+    fn on_item(&self, key: K, item: &T) {
+        let arrivals = self.arrivals.get() + 1;
+        self.arrivals.set(arrivals);
+        if let Some(controller) = self.controller.upgrade() {
+            controller.escalate(self, arrivals);
+        }
+        match self.mode.get() {
+            BurstMode::PassThrough => self.output.emit(item.clone()),
+            BurstMode::Conflate => {
+                let mut latest = self.latest.borrow_mut();
+                if latest.insert(key.clone(), item.clone()).is_none() {
+                    self.order.borrow_mut().push(key);
+                }
+            }
+            BurstMode::Shed => self.shed.set(self.shed.get() + 1),
+        }
+    }
+}
+
+impl<T, K> AdaptiveStage for StageState<T, K>
+where
+    T: Clone + 'static,
+    K: Eq + Hash + Clone + 'static,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mode(&self) -> BurstMode {
+        self.mode.get()
+    }
+
+    fn set_mode(&self, mode: BurstMode) {
+        self.mode.set(mode);
+    }
+
+    fn take_arrivals(&self) -> u64 {
+        self.arrivals.replace(0)
+    }
+
+    fn calm_since(&self) -> &Cell<Option<Instant>> {
+        &self.calm_since
+    }
+
+    fn rate(&self) -> &Cell<f64> {
+        &self.rate
+    }
+
+    fn flush(&self) {
+        let shed = self.shed.replace(0);
+        if shed > 0 {
+            backpressure::report(&self.name, 0, shed);
+        }
+        let order = mem::take(&mut *self.order.borrow_mut());
+        let mut latest = mem::take(&mut *self.latest.borrow_mut());
+        for key in order {
+            if let Some(item) = latest.remove(&key) {
+                self.output.emit(item);
+            }
+        }
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // A stage `controller` switches between passing items on, keeping only
+    // the latest per `key_fn` until its next tick, and dropping them (which
+    // is reported as backpressure on `name`). Mode changes are published on
+    // `controller.changes()` under `name`.
+    pub fn adaptive<K, F>(
+        &self,
+        controller: &BurstController,
+        name: impl Into<String>,
+        key_fn: F,
+    ) -> Stream<T>
+    where
+        K: Eq + Hash + Clone + 'static,
+        F: Fn(&T) -> K + 'static,
+    {
+        let stage = Rc::new(StageState {
+            name: name.into(),
+            controller: Rc::downgrade(&controller.inner),
+            mode: Cell::new(BurstMode::PassThrough),
+            arrivals: Cell::new(0),
+            rate: Cell::new(0.0),
+            calm_since: Cell::new(None),
+            shed: Cell::new(0),
+            order: RefCell::new(Vec::new()),
+            latest: RefCell::new(HashMap::new()),
+            output: Source::new(),
+        });
+        let output = stage.output.to_stream();
+        controller
+            .inner
+            .stages
+            .borrow_mut()
+            .push(stage.clone() as Rc<dyn AdaptiveStage>);
+        let stage_item = stage.clone();
+
+        self.sink(move |item: &T| stage_item.on_item(key_fn(item), item));
+        self.on_complete(move || {
+            stage.flush();
+            stage.output.complete();
+        });
+        output
+    }
+}
@@ -0,0 +1,160 @@
+use crate::rt::Instant;
+use crate::source::track_timed_emitter;
+use crate::{Resettable, Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::time::Duration;
+
+// Expired entries are swept four times per ttl, so evictions go out at most
+// a quarter of the ttl late.
+const SWEEPS_PER_TTL: u32 = 4;
+const MIN_SWEEP: Duration = Duration::from_millis(1);
+
+pub struct LatestCache<K, T> {
+    inner: Rc<LatestCacheInner<K, T>>,
+}
+
+struct LatestCacheInner<K, T> {
+    ttl: Duration,
+    entries: RefCell<HashMap<K, (T, Instant)>>,
+    evictions: Source<(K, T)>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    pub fn cache_latest_by_key<K, F>(&self, key_fn: F, ttl: Duration) -> LatestCache<K, T>
+    where
+        K: Eq + Hash + Clone + 'static,
+        F: Fn(&T) -> K + 'static,
+    {
+        let inner = Rc::new(LatestCacheInner {
+            ttl,
+            entries: RefCell::new(HashMap::new()),
+            evictions: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("cache_latest_by_key", ttl, &inner.registered);
+        let inner_clone = inner.clone();
+
+        self.sink(move |item: &T| {
+            inner_clone
+                .entries
+                .borrow_mut()
+                .insert(key_fn(item), (item.clone(), Instant::now()));
+        });
+
+        LatestCache { inner }
+    }
+}
+
+impl<K, T> LatestCache<K, T>
+where
+    K: Eq + Hash + Clone + 'static,
+    T: Clone + 'static,
+{
+    pub fn get(&self, key: &K) -> Option<T> {
+        self.inner
+            .entries
+            .borrow()
+            .get(key)
+            .filter(|(_, updated)| updated.elapsed() < self.inner.ttl)
+            .map(|(value, _)| value.clone())
+    }
+
+    pub fn snapshot(&self) -> HashMap<K, T> {
+        self.inner
+            .entries
+            .borrow()
+            .iter()
+            .filter(|(_, (_, updated))| updated.elapsed() < self.inner.ttl)
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .entries
+            .borrow()
+            .values()
+            .filter(|(_, updated)| updated.elapsed() < self.inner.ttl)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn evictions(&self) -> Stream<(K, T)> {
+        self.inner.evictions.to_stream()
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.inner.ttl
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+
+    // Clears the cache, without publishing evictions.
+    pub fn as_resettable(&self) -> Rc<dyn Resettable> {
+        self.inner.clone() as Rc<dyn Resettable>
+    }
+}
+
+impl<K, T> Clone for LatestCache<K, T> {
+    fn clone(&self) -> Self {
+        LatestCache {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, T> Resettable for LatestCacheInner<K, T>
+where
+    K: Eq + Hash + Clone + 'static,
+    T: Clone + 'static,
+{
+    fn reset(&self) {
+        self.entries.borrow_mut().clear();
+    }
+}
+
+impl<K, T> TimedEmitter for LatestCacheInner<K, T>
+where
+    K: Eq + Hash + Clone + 'static,
+    T: Clone + 'static,
+{
+    fn period(&self) -> Duration {
+        (self.ttl / SWEEPS_PER_TTL).max(MIN_SWEEP)
+    }
+
+    fn name(&self) -> String {
+        "latest_cache".to_string()
+    }
+
+    fn flush(&self) {
+        let expired: Vec<(K, T)> = {
+            let mut entries = self.entries.borrow_mut();
+            let keys: Vec<K> = entries
+                .iter()
+                .filter(|(_, (_, updated))| updated.elapsed() >= self.ttl)
+                .map(|(key, _)| key.clone())
+                .collect();
+            keys.into_iter()
+                .filter_map(|key| entries.remove(&key).map(|(value, _)| (key, value)))
+                .collect()
+        };
+
+        for eviction in expired {
+            self.evictions.emit(eviction);
+        }
+    }
+}
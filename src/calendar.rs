@@ -0,0 +1,424 @@
+use crate::rt;
+use crate::source::track_timed_emitter;
+use crate::{Error, Result, Source, Stream, TimedEmitter};
+use chrono::{
+    DateTime, Datelike, Days, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday,
+};
+use chrono_tz::Tz;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+struct SessionSpec {
+    name: String,
+    open: NaiveTime,
+    close: NaiveTime,
+}
+
+impl SessionSpec {
+    // Opens the evening before its trading day, e.g. CME Globex's 17:00 to
+    // 16:00 Central.
+    fn overnight(&self) -> bool {
+        self.close <= self.open
+    }
+}
+
+// One occurrence of a session. `trading_date` is the date it closes on, as
+// "YYYY-MM-DD"; times are milliseconds since the unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SessionInstance {
+    pub name: String,
+    pub trading_date: String,
+    pub open_millis: u64,
+    pub close_millis: u64,
+}
+
+impl SessionInstance {
+    pub fn contains(&self, timestamp_millis: u64) -> bool {
+        (self.open_millis..self.close_millis).contains(&timestamp_millis)
+    }
+}
+
+// e.g. "regular 2026-10-15"
+impl Display for SessionInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.trading_date)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SessionEvent {
+    Open(SessionInstance),
+    Close(SessionInstance),
+}
+
+impl SessionEvent {
+    pub fn session(&self) -> &SessionInstance {
+        match self {
+            SessionEvent::Open(session) | SessionEvent::Close(session) => session,
+        }
+    }
+
+    // When the session opened or closed.
+    pub fn timestamp_millis(&self) -> u64 {
+        match self {
+            SessionEvent::Open(session) => session.open_millis,
+            SessionEvent::Close(session) => session.close_millis,
+        }
+    }
+}
+
+// e.g. "open regular 2026-10-15"
+impl Display for SessionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionEvent::Open(session) => write!(f, "open {}", session),
+            SessionEvent::Close(session) => write!(f, "close {}", session),
+        }
+    }
+}
+
+// A venue's trading hours: sessions in the venue's timezone on its trading
+// days (Monday to Friday unless `with_trading_days` says otherwise), minus
+// holidays, with early closes. Dates are "YYYY-MM-DD" and times "HH:MM" or
+// "HH:MM:SS", local to the venue, so daylight saving is accounted for.
+#[derive(Clone, Debug)]
+pub struct TradingCalendar {
+    name: String,
+    timezone: Tz,
+    sessions: Vec<SessionSpec>,
+    trading_days: BTreeSet<u32>,
+    holidays: BTreeSet<NaiveDate>,
+    early_closes: BTreeMap<NaiveDate, NaiveTime>,
+}
+
+impl TradingCalendar {
+    // `timezone` is an IANA name such as "America/Chicago".
+    pub fn new(name: impl Into<String>, timezone: &str) -> Result<Self> {
+        let name = name.into();
+        let timezone = timezone.parse::<Tz>().map_err(|err| {
+            Error::config(format!("calendar {}: unknown timezone: {}", name, err))
+        })?;
+        Ok(Self {
+            name,
+            timezone,
+            sessions: Vec::new(),
+            trading_days: [
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ]
+            .iter()
+            .map(Weekday::num_days_from_monday)
+            .collect(),
+            holidays: BTreeSet::new(),
+            early_closes: BTreeMap::new(),
+        })
+    }
+
+    // A session closing at or before it opens runs overnight, belonging to
+    // the trading day it closes on.
+    pub fn with_session(
+        mut self,
+        name: impl Into<String>,
+        open: &str,
+        close: &str,
+    ) -> Result<Self> {
+        let name = name.into();
+        if open == close {
+            return Err(Error::config(format!(
+                "calendar {}: session {} opens and closes at {}",
+                self.name, name, open
+            )));
+        }
+        self.sessions.push(SessionSpec {
+            open: self.time(open)?,
+            close: self.time(close)?,
+            name,
+        });
+        Ok(self)
+    }
+
+    // Weekday names such as "Mon" or "Sunday".
+    pub fn with_trading_days(mut self, days: &[&str]) -> Result<Self> {
+        let mut trading_days = BTreeSet::new();
+        for day in days {
+            let day = day.parse::<Weekday>().map_err(|_| {
+                Error::config(format!("calendar {}: unknown weekday {:?}", self.name, day))
+            })?;
+            trading_days.insert(day.num_days_from_monday());
+        }
+        self.trading_days = trading_days;
+        Ok(self)
+    }
+
+    pub fn with_holiday(mut self, date: &str) -> Result<Self> {
+        let date = self.date(date)?;
+        self.holidays.insert(date);
+        Ok(self)
+    }
+
+    pub fn with_holidays<'a>(self, dates: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        dates
+            .into_iter()
+            .try_fold(self, |calendar, date| calendar.with_holiday(date))
+    }
+
+    // Sessions of `date` close at `close` instead, if they would close later.
+    pub fn with_early_close(mut self, date: &str, close: &str) -> Result<Self> {
+        let date = self.date(date)?;
+        let close = self.time(close)?;
+        self.early_closes.insert(date, close);
+        Ok(self)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_open(&self, timestamp_millis: u64) -> bool {
+        self.session_at(timestamp_millis).is_some()
+    }
+
+    // The session in progress at `timestamp_millis`, if any.
+    pub fn session_at(&self, timestamp_millis: u64) -> Option<SessionInstance> {
+        let date = self.local_date(timestamp_millis)?;
+        // an overnight session in progress belongs to the next day
+        [Some(date), date.checked_add_days(Days::new(1))]
+            .into_iter()
+            .flatten()
+            .flat_map(|date| self.sessions_on(date))
+            .find(|session| session.contains(timestamp_millis))
+    }
+
+    // Sessions overlapping `[start_millis, end_millis)`, in order of opening.
+    pub fn sessions_between(&self, start_millis: u64, end_millis: u64) -> Vec<SessionInstance> {
+        let (Some(first), Some(last)) =
+            (self.local_date(start_millis), self.local_date(end_millis))
+        else {
+            return Vec::new();
+        };
+        let mut sessions = Vec::new();
+        let mut date = first;
+        while date <= last.checked_add_days(Days::new(1)).unwrap_or(last) {
+            sessions.extend(self.sessions_on(date).into_iter().filter(|session| {
+                session.open_millis < end_millis && session.close_millis > start_millis
+            }));
+            let Some(next) = date.checked_add_days(Days::new(1)) else {
+                break;
+            };
+            date = next;
+        }
+        sessions.sort_by_key(|session| session.open_millis);
+        sessions
+    }
+
+    // Publishes `SessionEvent`s by the local clock, checking every `period`:
+    // register `as_timed_emitter()` with the engine. A session already open
+    // at the first check is announced then.
+    pub fn clock(&self, period: Duration) -> SessionClock {
+        let inner = Rc::new(ClockInner {
+            calendar: self.clone(),
+            period,
+            last_check: Cell::new(None),
+            output: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("session clock", period, &inner.registered);
+        SessionClock { inner }
+    }
+
+    fn sessions_on(&self, date: NaiveDate) -> Vec<SessionInstance> {
+        if !self
+            .trading_days
+            .contains(&date.weekday().num_days_from_monday())
+            || self.holidays.contains(&date)
+        {
+            return Vec::new();
+        }
+        let early_close = self.early_closes.get(&date);
+        self.sessions
+            .iter()
+            .filter_map(|spec| {
+                let open_date = if spec.overnight() {
+                    date.checked_sub_days(Days::new(1))?
+                } else {
+                    date
+                };
+                let close = match early_close {
+                    Some(early_close) if *early_close < spec.close => *early_close,
+                    _ => spec.close,
+                };
+                let open_millis = self.utc_millis(open_date.and_time(spec.open))?;
+                let close_millis = self.utc_millis(date.and_time(close))?;
+                (open_millis < close_millis).then(|| SessionInstance {
+                    name: spec.name.clone(),
+                    trading_date: date.to_string(),
+                    open_millis,
+                    close_millis,
+                })
+            })
+            .collect()
+    }
+
+    fn local_date(&self, timestamp_millis: u64) -> Option<NaiveDate> {
+        let utc = DateTime::from_timestamp_millis(i64::try_from(timestamp_millis).ok()?)?;
+        Some(utc.with_timezone(&self.timezone).date_naive())
+    }
+
+    // Local times skipped by a daylight saving change count from the change.
+    fn utc_millis(&self, local: NaiveDateTime) -> Option<u64> {
+        let utc = match self.timezone.from_local_datetime(&local) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time,
+            LocalResult::None => self
+                .timezone
+                .from_local_datetime(&(local + chrono::Duration::hours(1)))
+                .earliest()?,
+        };
+        u64::try_from(utc.timestamp_millis()).ok()
+    }
+
+    fn date(&self, date: &str) -> Result<NaiveDate> {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").map_err(|err| {
+            Error::config(format!("calendar {}: date {:?}: {}", self.name, date, err))
+        })
+    }
+
+    fn time(&self, time: &str) -> Result<NaiveTime> {
+        let time = time.trim();
+        NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .map_err(|err| {
+                Error::config(format!("calendar {}: time {:?}: {}", self.name, time, err))
+            })
+    }
+}
+
+struct ClockInner {
+    calendar: TradingCalendar,
+    period: Duration,
+    last_check: Cell<Option<u64>>,
+    output: Source<SessionEvent>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+#[derive(Clone)]
+pub struct SessionClock {
+    inner: Rc<ClockInner>,
+}
+
+impl SessionClock {
+    pub fn stream(&self) -> Stream<SessionEvent> {
+        self.inner.output.to_stream()
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl TimedEmitter for ClockInner {
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn flush(&self) {
+        let now = rt::now_millis();
+        let Some(last) = self.last_check.replace(Some(now)) else {
+            if let Some(session) = self.calendar.session_at(now) {
+                self.output.emit(SessionEvent::Open(session));
+            }
+            return;
+        };
+        let mut events = Vec::new();
+        for session in self.calendar.sessions_between(last, now + 1) {
+            if last < session.open_millis && session.open_millis <= now {
+                events.push(SessionEvent::Open(session.clone()));
+            }
+            if last < session.close_millis && session.close_millis <= now {
+                events.push(SessionEvent::Close(session));
+            }
+        }
+        events.sort_by_key(SessionEvent::timestamp_millis);
+        for event in events {
+            self.output.emit(event);
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("calendar {}", self.calendar.name)
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: 'static,
+{
+    // Items whose `timestamp_fn` (milliseconds since the unix epoch, e.g.
+    // the exchange time) falls within a session.
+    pub fn filter_in_session<F>(&self, calendar: &TradingCalendar, timestamp_fn: F) -> Stream<T>
+    where
+        F: Fn(&T) -> u64 + 'static,
+    {
+        let calendar = calendar.clone();
+        let current = RefCell::new(None::<SessionInstance>);
+        self.filter(move |item: &T| {
+            let timestamp = timestamp_fn(item);
+            let mut current = current.borrow_mut();
+            if current
+                .as_ref()
+                .is_some_and(|session| session.contains(timestamp))
+            {
+                return true;
+            }
+            *current = calendar.session_at(timestamp);
+            current.is_some()
+        })
+    }
+
+    // Session opens and closes in the items' own time, e.g. to close daily
+    // aggregates on replays: a session is closed by the first item at or
+    // after its close, and opened by its first item. Closes come before
+    // the item that reveals them.
+    pub fn session_boundaries<F>(
+        &self,
+        calendar: &TradingCalendar,
+        timestamp_fn: F,
+    ) -> Stream<SessionEvent>
+    where
+        F: Fn(&T) -> u64 + 'static,
+    {
+        let calendar = calendar.clone();
+        let output = Rc::new(Source::new());
+        let stream = output.to_stream();
+        let output_complete = output.clone();
+        let current = RefCell::new(None::<SessionInstance>);
+
+        self.sink(move |item: &T| {
+            let timestamp = timestamp_fn(item);
+            let mut current = current.borrow_mut();
+            if current
+                .as_ref()
+                .is_some_and(|session| session.contains(timestamp))
+            {
+                return;
+            }
+            if let Some(session) = current.take() {
+                output.emit(SessionEvent::Close(session));
+            }
+            if let Some(session) = calendar.session_at(timestamp) {
+                *current = Some(session.clone());
+                output.emit(SessionEvent::Open(session));
+            }
+        });
+        self.on_complete(move || output_complete.complete());
+        stream
+    }
+}
@@ -0,0 +1,188 @@
+// A C ABI for embedding the engine: create an engine, add websocket
+// sources, subscribe callbacks to them and run it on the calling thread.
+// `include/streamz.h` is generated from this file with
+// `cbindgen --config cbindgen.toml --output include/streamz.h`, and the
+// library built with `cargo rustc --release --features capi --crate-type cdylib`
+// (or `staticlib`).
+//
+// Functions returning `int32_t` give 0 on success and -1 on failure, with
+// the reason from `streamz_last_error` on the same thread. An engine is used
+// from one thread, except `streamz_stop`, which any thread may call.
+use crate::sources::websocket_client::{WebSocketClient, WebSocketClientConfigBuilder};
+use crate::{CancellationToken, EngineBuilder, Error, Result};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+use tokio::runtime::{Builder, Runtime};
+
+// Called with the source's name and each message (not NUL-terminated); both
+// pointers are only valid during the call.
+pub type StreamzCallback = Option<
+    unsafe extern "C" fn(
+        source: *const c_char,
+        data: *const u8,
+        len: usize,
+        user_data: *mut c_void,
+    ),
+>;
+
+pub struct StreamzEngine {
+    runtime: Runtime,
+    token: CancellationToken,
+    sources: RefCell<Vec<(String, WebSocketClient)>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn fail(err: Error) -> i32 {
+    let message = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    -1
+}
+
+fn status(result: Result<()>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(err) => fail(err),
+    }
+}
+
+unsafe fn text<'a>(value: *const c_char, name: &str) -> Result<&'a str> {
+    if value.is_null() {
+        return Err(Error::config(format!("{} is null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| Error::config(format!("{} is not valid utf-8", name)))
+}
+
+unsafe fn engine<'a>(engine: *const StreamzEngine) -> Result<&'a StreamzEngine> {
+    engine
+        .as_ref()
+        .ok_or_else(|| Error::config("engine is null"))
+}
+
+// The last error on this thread, or null; valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn streamz_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+// Null on failure. Free with `streamz_engine_free`.
+#[no_mangle]
+pub extern "C" fn streamz_engine_new() -> *mut StreamzEngine {
+    let runtime = match Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            fail(Error::other("streamz", err));
+            return ptr::null_mut();
+        }
+    };
+    Box::into_raw(Box::new(StreamzEngine {
+        runtime,
+        token: CancellationToken::new(),
+        sources: RefCell::new(Vec::new()),
+    }))
+}
+
+// `engine` must come from `streamz_engine_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn streamz_engine_free(engine: *mut StreamzEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+// Adds a websocket source sending `messages` (`count` NUL-terminated
+// strings; null when 0) after each connect.
+#[no_mangle]
+pub unsafe extern "C" fn streamz_add_ws_source(
+    engine: *mut StreamzEngine,
+    name: *const c_char,
+    url: *const c_char,
+    messages: *const *const c_char,
+    count: usize,
+) -> i32 {
+    status((|| {
+        let engine = self::engine(engine)?;
+        let name = text(name, "name")?;
+        let mut config = WebSocketClientConfigBuilder::new(text(url, "url")?);
+        for index in 0..count {
+            config = config.with_message(text(*messages.add(index), "message")?);
+        }
+        if engine
+            .sources
+            .borrow()
+            .iter()
+            .any(|(existing, _)| existing == name)
+        {
+            return Err(Error::config(format!("duplicate source name {:?}", name)));
+        }
+        let client = engine
+            .runtime
+            .block_on(WebSocketClient::new(config.build()?))?;
+        engine.sources.borrow_mut().push((name.to_string(), client));
+        Ok(())
+    })())
+}
+
+// Calls `callback` with each message of the source called `source`, on the
+// thread running `streamz_run`. `user_data` is passed through untouched and
+// must stay valid while the engine runs.
+#[no_mangle]
+pub unsafe extern "C" fn streamz_subscribe(
+    engine: *mut StreamzEngine,
+    source: *const c_char,
+    callback: StreamzCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    status((|| {
+        let engine = self::engine(engine)?;
+        let source = text(source, "source")?;
+        let callback = callback.ok_or_else(|| Error::config("callback is null"))?;
+        let sources = engine.sources.borrow();
+        let (name, client) = sources
+            .iter()
+            .find(|(name, _)| name == source)
+            .ok_or_else(|| Error::config(format!("no source called {:?}", source)))?;
+        let name = CString::new(name.as_str()).unwrap_or_default();
+        client.source().to_stream().sink(move |message: &String| {
+            callback(name.as_ptr(), message.as_ptr(), message.len(), user_data)
+        });
+        Ok(())
+    })())
+}
+
+// Runs the engine on the calling thread until `streamz_stop` or every
+// source finishes. An engine runs once.
+#[no_mangle]
+pub unsafe extern "C" fn streamz_run(engine: *mut StreamzEngine) -> i32 {
+    status((|| {
+        let engine = self::engine(engine)?;
+        let sources = engine.sources.take();
+        if sources.is_empty() {
+            return Err(Error::config("the engine has no sources"));
+        }
+        let mut builder = EngineBuilder::new().with_cancellation(engine.token.clone());
+        for (name, client) in sources {
+            builder = builder.add_source_owned(&name, client);
+        }
+        let built = builder.build()?;
+        engine.runtime.block_on(built.run()).map(|_| ())
+    })())
+}
+
+// Asks a running engine to stop; `streamz_run` then returns. Safe to call
+// from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn streamz_stop(engine: *const StreamzEngine) {
+    if let Some(engine) = engine.as_ref() {
+        engine.token.cancel();
+    }
+}
@@ -0,0 +1,77 @@
+use crate::EngineSource;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// The engine each registered source belongs to, across threads, keyed by
+// the source's address. An engine holds on to its sources, so an address is
+// not reused while claimed.
+static OWNERS: Mutex<BTreeMap<usize, Owner>> = Mutex::new(BTreeMap::new());
+static NEXT_ENGINE: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Owner {
+    pub(crate) engine: u64,
+    pub(crate) label: String,
+}
+
+fn key(source: &Arc<dyn EngineSource>) -> usize {
+    Arc::as_ptr(source) as *const () as usize
+}
+
+fn owners() -> std::sync::MutexGuard<'static, BTreeMap<usize, Owner>> {
+    OWNERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn owner(source: &Arc<dyn EngineSource>) -> Option<Owner> {
+    owners().get(&key(source)).cloned()
+}
+
+// The sources one engine claimed, released when it is dropped.
+pub(crate) struct Claims {
+    engine: u64,
+    keys: RefCell<Vec<usize>>,
+}
+
+impl Claims {
+    pub(crate) fn new() -> Self {
+        Self {
+            engine: NEXT_ENGINE.fetch_add(1, Ordering::Relaxed),
+            keys: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn engine(&self) -> u64 {
+        self.engine
+    }
+
+    // Callers check `owner` first; a source this engine claimed already is
+    // relabelled.
+    pub(crate) fn claim(&self, label: &str, source: &Arc<dyn EngineSource>) {
+        let key = key(source);
+        let owner = Owner {
+            engine: self.engine,
+            label: label.to_string(),
+        };
+        if owners().insert(key, owner).is_none() {
+            self.keys.borrow_mut().push(key);
+        }
+    }
+}
+
+impl Drop for Claims {
+    fn drop(&mut self) {
+        let mut owners = owners();
+        for key in self.keys.borrow().iter() {
+            if owners
+                .get(key)
+                .is_some_and(|owner| owner.engine == self.engine)
+            {
+                owners.remove(key);
+            }
+        }
+    }
+}
@@ -0,0 +1,1175 @@
+use crate::metrics::MetricsRegistry;
+use crate::report::SourceStats;
+use crate::rt::{self, Instant};
+use crate::sources::http_client::{HttpMethod, PollingHttpClient, PollingHttpClientConfig};
+use crate::sources::replay::{Recorder, ReplaySource};
+use crate::sources::websocket_client::{WebSocketClient, WebSocketClientConfigBuilder};
+#[cfg(feature = "plugins")]
+use crate::WasmPlugin;
+use crate::{
+    Engine, EngineBuilder, EngineEvent, EngineHandle, EngineSource, Error, FileSink, Result,
+    Signal, SignalAction, Source, StdoutSink, Stream, TimedEmitter,
+};
+#[cfg(any(feature = "rhai", feature = "lua"))]
+use crate::{Script, ScriptLanguage};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineConfig {
+    #[serde(default)]
+    pub sources: Vec<SourceSpec>,
+    #[serde(default)]
+    pub pipelines: Vec<PipelineSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SourceSpec {
+    Websocket {
+        name: String,
+        url: String,
+        #[serde(default)]
+        messages: Vec<Value>,
+    },
+    Http {
+        name: String,
+        url: String,
+        interval: ConfigDuration,
+        #[serde(default)]
+        method: Option<String>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        body: Option<String>,
+    },
+}
+
+impl SourceSpec {
+    pub fn name(&self) -> &str {
+        match self {
+            SourceSpec::Websocket { name, .. } | SourceSpec::Http { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineSpec {
+    pub name: String,
+    pub input: String,
+    #[serde(default)]
+    pub operators: Vec<OperatorSpec>,
+    #[serde(default)]
+    pub sinks: Vec<SinkSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct OperatorSpec {
+    pub op: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct SinkSpec {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+// Accepts either a number of milliseconds or a string such as "250ms", "5s",
+// "2m" or "1h".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigDuration(pub Duration);
+
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Millis(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Millis(ms) => Ok(ConfigDuration(Duration::from_millis(ms))),
+            Raw::Text(text) => parse_duration(&text)
+                .map(ConfigDuration)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let value: f64 = number
+        .parse()
+        .map_err(|_| Error::config(format!("invalid duration {:?}", text)))?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        other => {
+            return Err(Error::config(format!(
+                "invalid duration unit {:?} in {:?} (expected ms, s, m or h)",
+                other, text
+            )))
+        }
+    };
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| Error::config(format!("invalid duration {:?}", text)))
+}
+
+impl PipelineConfig {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&text),
+            Some("yaml" | "yml") => Self::from_yaml_str(&text),
+            _ => {
+                return Err(Error::config(format!(
+                    "unsupported config format for {} (expected .toml, .yaml or .yml)",
+                    path.display()
+                )))
+            }
+        }
+        .with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|err| Error::config(err.to_string()))
+    }
+
+    pub fn from_yaml_str(text: &str) -> Result<Self> {
+        serde_yaml::from_str(text).map_err(|err| Error::config(err.to_string()))
+    }
+
+    pub fn validate(&self, registry: &Registry) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut names = HashSet::new();
+
+        for source in &self.sources {
+            let name = source.name();
+            if name.is_empty() {
+                problems.push("source with an empty name".to_string());
+            } else if !names.insert(name) {
+                problems.push(format!("duplicate name {:?}", name));
+            }
+            match source {
+                SourceSpec::Websocket { url, .. } | SourceSpec::Http { url, .. }
+                    if url.is_empty() =>
+                {
+                    problems.push(format!("source {:?}: url is empty", name));
+                }
+                _ => {}
+            }
+            if let SourceSpec::Http {
+                interval, method, ..
+            } = source
+            {
+                if interval.0.is_zero() {
+                    problems.push(format!("source {:?}: interval must be non-zero", name));
+                }
+                if let Some(method) = method {
+                    if let Err(err) = parse_method(method) {
+                        problems.push(format!("source {:?}: {}", name, err));
+                    }
+                }
+            }
+        }
+
+        for pipeline in &self.pipelines {
+            let name = pipeline.name.as_str();
+            if !names.contains(pipeline.input.as_str()) {
+                problems.push(format!(
+                    "pipeline {:?}: input {:?} is not a source or an earlier pipeline",
+                    name, pipeline.input
+                ));
+            }
+            if name.is_empty() {
+                problems.push("pipeline with an empty name".to_string());
+            } else if !names.insert(name) {
+                problems.push(format!("duplicate name {:?}", name));
+            }
+            let consumed = self
+                .pipelines
+                .iter()
+                .any(|other| other.input == pipeline.name);
+            if pipeline.sinks.is_empty() && !consumed {
+                problems.push(format!(
+                    "pipeline {:?} has no sinks and no pipeline reads it",
+                    name
+                ));
+            }
+            // operators are applied to a detached stream so parameter errors
+            // (bad expressions, missing periods) are reported up front
+            let scratch = Source::<Value>::new().to_stream();
+            for (index, operator) in pipeline.operators.iter().enumerate() {
+                match registry.operators.get(&operator.op) {
+                    Some(build) => {
+                        let mut context = OperatorContext::default();
+                        if let Err(err) = build(&scratch, &operator.params, &mut context) {
+                            problems.push(format!(
+                                "pipeline {:?} operator #{} ({}): {}",
+                                name,
+                                index + 1,
+                                operator.op,
+                                err
+                            ));
+                        }
+                    }
+                    None => problems.push(format!(
+                        "pipeline {:?} operator #{}: unknown operator {:?} (available: {})",
+                        name,
+                        index + 1,
+                        operator.op,
+                        registry.operator_names().join(", ")
+                    )),
+                }
+            }
+            for (index, sink) in pipeline.sinks.iter().enumerate() {
+                if !registry.sinks.contains_key(&sink.kind) {
+                    problems.push(format!(
+                        "pipeline {:?} sink #{}: unknown sink {:?} (available: {})",
+                        name,
+                        index + 1,
+                        sink.kind,
+                        registry.sink_names().join(", ")
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::config(format!(
+                "invalid pipeline configuration:\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
+    }
+
+    pub async fn build(&self, registry: &Registry) -> Result<Engine> {
+        self.engine_builder(registry, BuildOptions::new())
+            .await?
+            .build()
+    }
+
+    pub async fn engine_builder(
+        &self,
+        registry: &Registry,
+        options: BuildOptions,
+    ) -> Result<EngineBuilder> {
+        self.validate(registry)?;
+
+        let mut builder = EngineBuilder::new();
+        if let Some(metrics) = &options.metrics {
+            builder = builder.with_metrics(metrics.clone());
+        }
+        if let Some(budget) = options.callback_budget {
+            builder = builder.with_callback_budget(budget);
+        }
+        let mut streams: HashMap<String, Stream<Value>> = HashMap::new();
+        let mut relays: HashMap<String, Rc<Source<String>>> = HashMap::new();
+        let mut context = OperatorContext::default();
+        let recorder = options.record.as_ref().map(Recorder::create).transpose()?;
+        let replay = options
+            .replay
+            .as_ref()
+            .map(|(path, speed)| ReplaySource::new(path).with_speed(*speed));
+
+        for source in &self.sources {
+            let name = source.name();
+            let raw = match &replay {
+                Some(replay) => replay.source(name),
+                None => {
+                    let client = SourceClient::connect(source).await?;
+                    let raw = if options.hot_reload.is_some() {
+                        // reloads swap the client behind a stable relay
+                        let relay = Rc::new(Source::new());
+                        client.relay_to(&relay);
+                        relays.insert(name.to_string(), relay.clone());
+                        relay.to_stream()
+                    } else {
+                        client.source().to_stream()
+                    };
+                    builder = builder.add_source_owned(name, client);
+                    raw
+                }
+            };
+            let stream = source_stream(name, &raw, recorder.as_ref(), options.metrics.as_ref());
+            streams.insert(name.to_string(), stream);
+        }
+
+        let replaying = replay.is_some();
+        if let Some(replay) = replay {
+            builder = builder.add_source_owned("replay", replay);
+        }
+
+        for pipeline in &self.pipelines {
+            let stream = build_pipeline(
+                pipeline,
+                &streams,
+                registry,
+                &mut context,
+                options.metrics.as_ref(),
+            )?;
+            builder = builder.add_stream(stream.clone());
+            streams.insert(pipeline.name.clone(), stream);
+        }
+
+        for emitter in context.timed_emitters {
+            builder = builder.add_timed_emitter(emitter);
+        }
+
+        if let Some((path, interval)) = options.hot_reload {
+            let live = Rc::new(LiveConfig {
+                config: RefCell::new(self.clone()),
+                registry: registry.clone(),
+                handle: builder.handle(),
+                metrics: options.metrics,
+                recorder,
+                replaying,
+                relays: RefCell::new(relays),
+                streams: RefCell::new(streams),
+            });
+            let reload = Rc::new(Notify::new());
+            let notify = reload.clone();
+            builder
+                .signals()
+                .filter(|signal| *signal == Signal::Hangup)
+                .sink(move |_| notify.notify_one());
+            builder = builder
+                .on_signal(Signal::Hangup, SignalAction::Notify)
+                .add_source_owned(
+                    "config_watcher",
+                    ConfigWatcher {
+                        path,
+                        interval,
+                        live,
+                        reload,
+                    },
+                );
+        }
+
+        Ok(builder)
+    }
+
+    pub fn topology(&self) -> String {
+        let mut out = String::from("sources:\n");
+        for source in &self.sources {
+            let _ = match source {
+                SourceSpec::Websocket {
+                    name,
+                    url,
+                    messages,
+                } => writeln!(
+                    out,
+                    "  {}: websocket {} ({} init message(s))",
+                    name,
+                    url,
+                    messages.len()
+                ),
+                SourceSpec::Http {
+                    name,
+                    url,
+                    interval,
+                    method,
+                    ..
+                } => writeln!(
+                    out,
+                    "  {}: http {} {} every {:?}",
+                    name,
+                    method.as_deref().unwrap_or("GET").to_ascii_uppercase(),
+                    url,
+                    interval.0
+                ),
+            };
+        }
+
+        out.push_str("pipelines:\n");
+        for pipeline in &self.pipelines {
+            let mut stages = vec![pipeline.input.clone()];
+            stages.extend(
+                pipeline
+                    .operators
+                    .iter()
+                    .map(|operator| describe(&operator.op, &operator.params)),
+            );
+            let _ = write!(out, "  {}: {}", pipeline.name, stages.join(" -> "));
+            if !pipeline.sinks.is_empty() {
+                let sinks: Vec<String> = pipeline
+                    .sinks
+                    .iter()
+                    .map(|sink| describe(&sink.kind, &sink.params))
+                    .collect();
+                let _ = write!(out, " => {}", sinks.join(", "));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BuildOptions {
+    record: Option<PathBuf>,
+    replay: Option<(PathBuf, f64)>,
+    metrics: Option<MetricsRegistry>,
+    callback_budget: Option<Duration>,
+    hot_reload: Option<(PathBuf, Duration)>,
+}
+
+impl BuildOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Appends every raw source message to `path` for later replay.
+    pub fn with_record(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record = Some(path.into());
+        self
+    }
+
+    // Feeds sources from a recording instead of connecting; a speed of 0
+    // replays as fast as possible.
+    pub fn with_replay(mut self, path: impl Into<PathBuf>, speed: f64) -> Self {
+        self.replay = Some((path.into(), speed));
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: MetricsRegistry) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Warns when handling a single source message takes longer than `budget`.
+    pub fn with_callback_budget(mut self, budget: Duration) -> Self {
+        self.callback_budget = Some(budget);
+        self
+    }
+
+    // Re-reads the config at `path` whenever it changes (checked every
+    // `interval`) or on SIGHUP and applies safe changes to the running engine:
+    // new, removed or updated sources, new pipelines and extra sinks. The
+    // outcome is published on `EngineBuilder::events()`.
+    pub fn with_hot_reload(mut self, path: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.hot_reload = Some((path.into(), interval));
+        self
+    }
+}
+
+// What a built config keeps around to apply reloads.
+struct LiveConfig {
+    config: RefCell<PipelineConfig>,
+    registry: Registry,
+    handle: EngineHandle,
+    metrics: Option<MetricsRegistry>,
+    recorder: Option<Recorder>,
+    replaying: bool,
+    relays: RefCell<HashMap<String, Rc<Source<String>>>>,
+    streams: RefCell<HashMap<String, Stream<Value>>>,
+}
+
+impl LiveConfig {
+    async fn reload(&self, path: &Path) {
+        let event = match self.apply(path).await {
+            Ok(changes) if changes.is_empty() => return,
+            Ok(changes) => EngineEvent::ConfigReloaded { changes },
+            Err(err) => EngineEvent::ConfigRejected {
+                reason: format!("{:#}", err),
+            },
+        };
+        self.handle.publish(event);
+    }
+
+    async fn apply(&self, path: &Path) -> Result<Vec<String>> {
+        let next = PipelineConfig::from_path(path)?;
+        // dry run: nothing is touched unless the whole file is valid and safe
+        next.validate(&self.registry)?;
+        let current = self.config.borrow().clone();
+        let plan = ReloadPlan::new(&current, &next)?;
+        if self.replaying && plan.changes_sources() {
+            return Err(Error::config(
+                "sources cannot change while replaying a recording".to_string(),
+            ));
+        }
+        let mut clients = Vec::new();
+        for spec in plan.added_sources.iter().chain(&plan.updated_sources) {
+            let client = SourceClient::connect(spec)
+                .await
+                .with_context(|| format!("source {:?}", spec.name()))?;
+            clients.push((spec.name(), client));
+        }
+
+        let mut relays = self.relays.borrow_mut();
+        let mut streams = self.streams.borrow_mut();
+        for name in &plan.removed_sources {
+            self.handle.remove_source(*name);
+            relays.remove(*name);
+            streams.remove(*name);
+        }
+        for (name, client) in clients {
+            let relay = match relays.get(name) {
+                Some(relay) => {
+                    self.handle.remove_source(name);
+                    relay.clone()
+                }
+                None => {
+                    let relay = Rc::new(Source::new());
+                    let stream = source_stream(
+                        name,
+                        &relay.to_stream(),
+                        self.recorder.as_ref(),
+                        self.metrics.as_ref(),
+                    );
+                    relays.insert(name.to_string(), relay.clone());
+                    streams.insert(name.to_string(), stream);
+                    relay
+                }
+            };
+            client.relay_to(&relay);
+            self.handle.add_source_owned(name, client);
+        }
+
+        let mut context = OperatorContext::default();
+        for pipeline in &plan.added_pipelines {
+            let stream = build_pipeline(
+                pipeline,
+                &streams,
+                &self.registry,
+                &mut context,
+                self.metrics.as_ref(),
+            )?;
+            self.handle.add_stream(stream.clone());
+            streams.insert(pipeline.name.clone(), stream);
+        }
+        for (pipeline, from) in &plan.added_sinks {
+            attach_sinks(pipeline, *from, &streams[&pipeline.name], &self.registry)?;
+        }
+        for emitter in context.timed_emitters {
+            self.handle.add_timed_emitter(emitter);
+        }
+
+        let changes = plan.describe();
+        *self.config.borrow_mut() = next;
+        Ok(changes)
+    }
+}
+
+// The difference between two valid configs, if it can be applied live.
+// Changing or removing an existing pipeline needs a restart.
+struct ReloadPlan<'a> {
+    added_sources: Vec<&'a SourceSpec>,
+    updated_sources: Vec<&'a SourceSpec>,
+    removed_sources: Vec<&'a str>,
+    added_pipelines: Vec<&'a PipelineSpec>,
+    added_sinks: Vec<(&'a PipelineSpec, usize)>,
+}
+
+impl<'a> ReloadPlan<'a> {
+    fn new(current: &'a PipelineConfig, next: &'a PipelineConfig) -> Result<Self> {
+        let mut plan = ReloadPlan {
+            added_sources: Vec::new(),
+            updated_sources: Vec::new(),
+            removed_sources: Vec::new(),
+            added_pipelines: Vec::new(),
+            added_sinks: Vec::new(),
+        };
+        for spec in &next.sources {
+            match current.sources.iter().find(|old| old.name() == spec.name()) {
+                None => plan.added_sources.push(spec),
+                Some(old) if old != spec => plan.updated_sources.push(spec),
+                Some(_) => {}
+            }
+        }
+        for old in &current.sources {
+            if !next.sources.iter().any(|spec| spec.name() == old.name()) {
+                plan.removed_sources.push(old.name());
+            }
+        }
+
+        let mut problems = Vec::new();
+        for old in &current.pipelines {
+            match next.pipelines.iter().find(|spec| spec.name == old.name) {
+                None => problems.push(format!("pipeline {:?} was removed", old.name)),
+                Some(spec) if spec.input != old.input || spec.operators != old.operators => {
+                    problems.push(format!(
+                        "pipeline {:?} changed its input or operators",
+                        old.name
+                    ));
+                }
+                Some(spec) if !spec.sinks.starts_with(&old.sinks) => {
+                    problems.push(format!(
+                        "pipeline {:?} changed existing sinks (only appending is supported)",
+                        old.name
+                    ));
+                }
+                Some(spec) if spec.sinks.len() > old.sinks.len() => {
+                    plan.added_sinks.push((spec, old.sinks.len()));
+                }
+                Some(_) => {}
+            }
+        }
+        for spec in &next.pipelines {
+            if !current.pipelines.iter().any(|old| old.name == spec.name) {
+                plan.added_pipelines.push(spec);
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(plan)
+        } else {
+            Err(Error::config(format!(
+                "changes need a restart:\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
+    }
+
+    fn changes_sources(&self) -> bool {
+        !(self.added_sources.is_empty()
+            && self.updated_sources.is_empty()
+            && self.removed_sources.is_empty())
+    }
+
+    fn describe(&self) -> Vec<String> {
+        let mut changes = Vec::new();
+        changes.extend(
+            self.added_sources
+                .iter()
+                .map(|spec| format!("added source {}", spec.name())),
+        );
+        changes.extend(
+            self.updated_sources
+                .iter()
+                .map(|spec| format!("updated source {}", spec.name())),
+        );
+        changes.extend(
+            self.removed_sources
+                .iter()
+                .map(|name| format!("removed source {}", name)),
+        );
+        changes.extend(
+            self.added_pipelines
+                .iter()
+                .map(|spec| format!("added pipeline {}", spec.name)),
+        );
+        changes.extend(self.added_sinks.iter().map(|(spec, from)| {
+            format!(
+                "added {} sink(s) to pipeline {}",
+                spec.sinks.len() - from,
+                spec.name
+            )
+        }));
+        changes
+    }
+}
+
+struct ConfigWatcher {
+    path: PathBuf,
+    interval: Duration,
+    live: Rc<LiveConfig>,
+    reload: Rc<Notify>,
+}
+
+impl EngineSource for ConfigWatcher {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move {
+            let mut modified = modified_at(&self.path);
+            loop {
+                tokio::select! {
+                    _ = rt::sleep_until(Instant::now() + self.interval) => {
+                        let current = modified_at(&self.path);
+                        if current == modified {
+                            continue;
+                        }
+                        modified = current;
+                    }
+                    _ = self.reload.notified() => {}
+                }
+                self.live.reload(&self.path).await;
+            }
+        })
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// The clients a `SourceSpec` can describe.
+enum SourceClient {
+    Websocket(WebSocketClient),
+    Http(PollingHttpClient),
+}
+
+impl SourceClient {
+    async fn connect(spec: &SourceSpec) -> Result<Self> {
+        match spec {
+            SourceSpec::Websocket { url, messages, .. } => {
+                let mut config = WebSocketClientConfigBuilder::new(url);
+                for message in messages {
+                    config = config.with_message(&message_text(message));
+                }
+                Ok(SourceClient::Websocket(
+                    WebSocketClient::new(config.build()?).await?,
+                ))
+            }
+            SourceSpec::Http {
+                name,
+                url,
+                interval,
+                method,
+                headers,
+                body,
+            } => {
+                let mut config = PollingHttpClientConfig::new(url, interval.0)
+                    .with_headers(headers.clone())
+                    .with_context(|| format!("source {:?}: invalid header", name))?;
+                if let Some(method) = method {
+                    config = config.with_method(parse_method(method)?);
+                }
+                if let Some(body) = body {
+                    config = config.with_body(body.clone());
+                }
+                Ok(SourceClient::Http(PollingHttpClient::new(config).await?))
+            }
+        }
+    }
+
+    fn source(&self) -> &Source<String> {
+        match self {
+            SourceClient::Websocket(client) => client.source(),
+            SourceClient::Http(client) => client.source(),
+        }
+    }
+
+    fn relay_to(&self, relay: &Rc<Source<String>>) {
+        let relay = relay.clone();
+        self.source()
+            .to_stream()
+            .sink(move |message: &String| relay.emit(message.clone()));
+    }
+}
+
+impl EngineSource for SourceClient {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        match self {
+            SourceClient::Websocket(client) => client.run(),
+            SourceClient::Http(client) => client.run(),
+        }
+    }
+
+    fn stats(&self) -> SourceStats {
+        match self {
+            SourceClient::Websocket(client) => client.stats(),
+            SourceClient::Http(client) => client.stats(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        match self {
+            SourceClient::Websocket(client) => client.subscribers(),
+            SourceClient::Http(client) => client.subscribers(),
+        }
+    }
+
+    fn connected(&self) -> Option<bool> {
+        match self {
+            SourceClient::Websocket(client) => EngineSource::connected(client),
+            SourceClient::Http(client) => client.connected(),
+        }
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+fn describe(name: &str, params: &Map<String, Value>) -> String {
+    if params.is_empty() {
+        return name.to_string();
+    }
+    let params: Vec<String> = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{}({})", name, params.join(", "))
+}
+
+fn parse_json(stream: &Stream<String>) -> Stream<Value> {
+    stream.map(|text| {
+        serde_json::from_str::<Value>(text).unwrap_or_else(|_| Value::String(text.clone()))
+    })
+}
+
+fn source_stream(
+    name: &str,
+    raw: &Stream<String>,
+    recorder: Option<&Recorder>,
+    metrics: Option<&MetricsRegistry>,
+) -> Stream<Value> {
+    if let Some(recorder) = recorder {
+        recorder.record(name, raw);
+    }
+    if let Some(metrics) = metrics {
+        let counter = metrics.counter(
+            "streamz_source_messages_total",
+            "Messages received per source.",
+            &[("source", name)],
+        );
+        raw.sink(move |_| counter.inc());
+    }
+    parse_json(raw).named(name)
+}
+
+// Expects a validated pipeline whose input is already in `streams`.
+fn build_pipeline(
+    pipeline: &PipelineSpec,
+    streams: &HashMap<String, Stream<Value>>,
+    registry: &Registry,
+    context: &mut OperatorContext,
+    metrics: Option<&MetricsRegistry>,
+) -> Result<Stream<Value>> {
+    let mut stream = streams[&pipeline.input].clone();
+    for (index, operator) in pipeline.operators.iter().enumerate() {
+        let build = &registry.operators[&operator.op];
+        stream = build(&stream, &operator.params, context).with_context(|| {
+            format!(
+                "pipeline {:?} operator #{} ({})",
+                pipeline.name,
+                index + 1,
+                operator.op
+            )
+        })?;
+    }
+    attach_sinks(pipeline, 0, &stream, registry)?;
+    if let Some(metrics) = metrics {
+        let counter = metrics.counter(
+            "streamz_pipeline_items_total",
+            "Items emitted per pipeline.",
+            &[("pipeline", &pipeline.name)],
+        );
+        stream.sink(move |_| counter.inc());
+    }
+    Ok(stream)
+}
+
+// Attaches the pipeline's sinks from index `from` onwards.
+fn attach_sinks(
+    pipeline: &PipelineSpec,
+    from: usize,
+    stream: &Stream<Value>,
+    registry: &Registry,
+) -> Result<()> {
+    for (index, sink) in pipeline.sinks.iter().enumerate().skip(from) {
+        let attach = &registry.sinks[&sink.kind];
+        attach(stream, &sink.params).with_context(|| {
+            format!(
+                "pipeline {:?} sink #{} ({})",
+                pipeline.name,
+                index + 1,
+                sink.kind
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn message_text(message: &Value) -> String {
+    match message {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_method(method: &str) -> Result<HttpMethod> {
+    match method.to_ascii_uppercase().as_str() {
+        "GET" => Ok(HttpMethod::Get),
+        "POST" => Ok(HttpMethod::Post),
+        _ => Err(Error::config(format!(
+            "unsupported http method {:?} (expected GET or POST)",
+            method
+        ))),
+    }
+}
+
+#[derive(Default)]
+pub struct OperatorContext {
+    timed_emitters: Vec<Rc<dyn TimedEmitter>>,
+}
+
+impl OperatorContext {
+    pub fn add_timed_emitter(&mut self, emitter: Rc<dyn TimedEmitter>) {
+        self.timed_emitters.push(emitter);
+    }
+}
+
+pub type OperatorFn =
+    Rc<dyn Fn(&Stream<Value>, &Map<String, Value>, &mut OperatorContext) -> Result<Stream<Value>>>;
+pub type SinkFn = Rc<dyn Fn(&Stream<Value>, &Map<String, Value>) -> Result<()>>;
+
+#[derive(Clone)]
+pub struct Registry {
+    operators: HashMap<String, OperatorFn>,
+    sinks: HashMap<String, SinkFn>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let registry = Self::empty()
+            .register_operator("filter", |stream, params, _| {
+                match script_stage(stream, params, true)? {
+                    Some(stream) => Ok(stream),
+                    None => stream.filter_expr(param_str(params, "expr")?),
+                }
+            })
+            .register_operator("map", |stream, params, _| {
+                match script_stage(stream, params, false)? {
+                    Some(stream) => Ok(stream),
+                    None => stream.map_expr(param_str(params, "expr")?),
+                }
+            })
+            .register_operator("timed_buffer", |stream, params, context| {
+                let mut buffer = stream.timed_buffer(param_duration(params, "period")?);
+                if let Some(max_items) = params.get("max_items") {
+                    let max_items = max_items
+                        .as_u64()
+                        .filter(|max_items| *max_items > 0)
+                        .ok_or_else(|| {
+                            Error::config(
+                                "parameter \"max_items\" must be a positive integer".to_string(),
+                            )
+                        })?;
+                    buffer = buffer.with_max_items(max_items as usize);
+                }
+                context.add_timed_emitter(buffer.as_timed_emitter());
+                Ok(buffer.map(|batch| Value::Array(batch.clone())))
+            })
+            .register_sink("stdout", |stream, params| {
+                let prefix = params
+                    .get("prefix")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                stream.sink_to(StdoutSink::with_prefix(prefix));
+                Ok(())
+            })
+            .register_sink("file", |stream, params| {
+                let path = param_str(params, "path")?;
+                let file =
+                    FileSink::append(path).with_context(|| format!("failed to open {}", path))?;
+                stream.sink_to(file);
+                Ok(())
+            });
+        #[cfg(feature = "plugins")]
+        let registry = registry.register_operator("wasm", |stream, params, _| {
+            let mut plugin = WasmPlugin::from_file(param_str(params, "path")?)?;
+            if let Some(fuel) = params.get("fuel") {
+                let fuel = fuel.as_u64().ok_or_else(|| {
+                    Error::config("parameter \"fuel\" must be a positive integer")
+                })?;
+                plugin = plugin.with_fuel(fuel);
+            }
+            if let Some(bytes) = params.get("max_memory") {
+                let bytes = bytes.as_u64().ok_or_else(|| {
+                    Error::config("parameter \"max_memory\" must be a number of bytes")
+                })?;
+                plugin = plugin.with_max_memory(bytes as usize);
+            }
+            Ok(stream.process_wasm_json(plugin))
+        });
+        registry
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            operators: HashMap::new(),
+            sinks: HashMap::new(),
+        }
+    }
+
+    pub fn register_operator<F>(mut self, name: impl Into<String>, operator: F) -> Self
+    where
+        F: Fn(&Stream<Value>, &Map<String, Value>, &mut OperatorContext) -> Result<Stream<Value>>
+            + 'static,
+    {
+        self.operators.insert(name.into(), Rc::new(operator));
+        self
+    }
+
+    pub fn register_sink<F>(mut self, name: impl Into<String>, sink: F) -> Self
+    where
+        F: Fn(&Stream<Value>, &Map<String, Value>) -> Result<()> + 'static,
+    {
+        self.sinks.insert(name.into(), Rc::new(sink));
+        self
+    }
+
+    pub fn operator_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.operators.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn sink_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.sinks.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+pub fn param_str<'a>(params: &'a Map<String, Value>, key: &str) -> Result<&'a str> {
+    match params.get(key) {
+        Some(Value::String(value)) => Ok(value),
+        Some(other) => Err(Error::config(format!(
+            "parameter {:?} must be a string, found {}",
+            key, other
+        ))),
+        None => Err(Error::config(format!("missing parameter {:?}", key))),
+    }
+}
+
+// A `filter` / `map` script: inline `script` or a hot-reloaded
+// `script_file`, in `lang` ("rhai" or "lua"; by default from the file's
+// extension, or Rhai when built with it), with an optional
+// `reload_interval`.
+#[cfg(any(feature = "rhai", feature = "lua"))]
+fn param_script(params: &Map<String, Value>) -> Result<Option<Script>> {
+    let language = match params.get("lang") {
+        Some(_) => {
+            let name = param_str(params, "lang")?;
+            Some(ScriptLanguage::from_name(name).ok_or_else(|| {
+                Error::config(format!("script language {:?} is not built in", name))
+            })?)
+        }
+        None => None,
+    };
+    let script = if params.contains_key("script_file") {
+        let path = param_str(params, "script_file")?;
+        match language {
+            Some(language) => Script::from_file_as(language, path)?,
+            None => Script::from_file(path)?,
+        }
+    } else if params.contains_key("script") {
+        #[cfg(feature = "rhai")]
+        let default = ScriptLanguage::Rhai;
+        #[cfg(not(feature = "rhai"))]
+        let default = ScriptLanguage::Lua;
+        Script::new(language.unwrap_or(default), param_str(params, "script")?)?
+    } else {
+        return Ok(None);
+    };
+    match params.get("reload_interval") {
+        Some(_) => Ok(Some(
+            script.with_reload_interval(param_duration(params, "reload_interval")?),
+        )),
+        None => Ok(Some(script)),
+    }
+}
+
+#[cfg(any(feature = "rhai", feature = "lua"))]
+fn script_stage(
+    stream: &Stream<Value>,
+    params: &Map<String, Value>,
+    filter: bool,
+) -> Result<Option<Stream<Value>>> {
+    Ok(param_script(params)?.map(|script| {
+        if filter {
+            stream.filter_script(script)
+        } else {
+            stream.map_script(script)
+        }
+    }))
+}
+
+#[cfg(not(any(feature = "rhai", feature = "lua")))]
+fn script_stage(
+    _stream: &Stream<Value>,
+    params: &Map<String, Value>,
+    _filter: bool,
+) -> Result<Option<Stream<Value>>> {
+    if params.contains_key("script") || params.contains_key("script_file") {
+        return Err(Error::config(
+            "scripted stages need the `rhai` or `lua` feature",
+        ));
+    }
+    Ok(None)
+}
+
+pub fn param_duration(params: &Map<String, Value>, key: &str) -> Result<Duration> {
+    let duration = match params.get(key) {
+        Some(Value::Number(ms)) => ms.as_u64().map(Duration::from_millis).ok_or_else(|| {
+            Error::config(format!(
+                "parameter {:?} must be a positive number of ms",
+                key
+            ))
+        })?,
+        Some(Value::String(text)) => parse_duration(text)?,
+        Some(other) => {
+            return Err(Error::config(format!(
+                "parameter {:?} must be a duration, found {}",
+                key, other
+            )))
+        }
+        None => return Err(Error::config(format!("missing parameter {:?}", key))),
+    };
+    if duration.is_zero() {
+        return Err(Error::config(format!(
+            "parameter {:?} must be non-zero",
+            key
+        )));
+    }
+    Ok(duration)
+}
+
+// Prefixes an error with what was being done when it happened.
+trait Context<T> {
+    fn with_context<F>(self, what: F) -> Result<T>
+    where
+        F: FnOnce() -> String;
+}
+
+impl<T, E> Context<T> for std::result::Result<T, E>
+where
+    E: fmt::Display,
+{
+    fn with_context<F>(self, what: F) -> Result<T>
+    where
+        F: FnOnce() -> String,
+    {
+        self.map_err(|err| Error::config(format!("{}: {}", what(), err)))
+    }
+}
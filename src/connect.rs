@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    // in the order the resolver returns them, alternating families
+    #[default]
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+// How websocket and TCP sources reach their host. Names are resolved again
+// on every connect and reconnect, so a failover to new addresses is followed
+// at once. With several addresses, attempts are raced ("happy eyeballs",
+// RFC 8305): the next one starts `attempt_delay` after the previous one
+// unless that failed sooner, alternating families, and the first to connect
+// wins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectOptions {
+    family: AddressFamily,
+    attempt_delay: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            family: AddressFamily::Any,
+            attempt_delay: Some(DEFAULT_ATTEMPT_DELAY),
+            timeout: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    // Head start of each attempt over the next; 250ms by default.
+    pub fn with_attempt_delay(mut self, delay: Duration) -> Self {
+        self.attempt_delay = Some(delay);
+        self
+    }
+
+    // One address at a time, each until it fails.
+    pub fn sequential(mut self) -> Self {
+        self.attempt_delay = None;
+        self
+    }
+
+    // Gives up on the whole connect, all addresses, after `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn address_family(&self) -> AddressFamily {
+        self.family
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AddressFamily {
+    fn label(&self) -> &'static str {
+        match self {
+            AddressFamily::Ipv4Only => "IPv4 ",
+            AddressFamily::Ipv6Only => "IPv6 ",
+            _ => "",
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod dial {
+    use super::{AddressFamily, ConnectOptions};
+    use futures_util::stream::{FuturesUnordered, StreamExt};
+    use std::io;
+    use std::net::SocketAddr;
+    use tokio::net::{lookup_host, TcpStream};
+    use tokio::time::{sleep, timeout};
+
+    impl ConnectOptions {
+        // `addresses` filtered and ordered for the attempts.
+        fn order(&self, addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+            let v4_first = addresses.first().is_some_and(SocketAddr::is_ipv4);
+            let (v4, v6): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv4);
+            let (first, second) = match self.family {
+                AddressFamily::Ipv4Only => return v4,
+                AddressFamily::Ipv6Only => return v6,
+                AddressFamily::PreferIpv4 => (v4, v6),
+                AddressFamily::PreferIpv6 => (v6, v4),
+                // the resolver's first answer goes first
+                AddressFamily::Any if v4_first => (v4, v6),
+                AddressFamily::Any => (v6, v4),
+            };
+            let mut ordered = Vec::with_capacity(first.len() + second.len());
+            let (mut first, mut second) = (first.into_iter(), second.into_iter());
+            loop {
+                match (first.next(), second.next()) {
+                    (None, None) => return ordered,
+                    (a, b) => ordered.extend(a.into_iter().chain(b)),
+                }
+            }
+        }
+    }
+
+    // A TCP connection to `host:port`, resolved now, per `options`.
+    pub(crate) async fn connect(
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> io::Result<TcpStream> {
+        match options.timeout {
+            Some(limit) => timeout(limit, race(host, port, options))
+                .await
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("connecting to {}:{} timed out", host, port),
+                    )
+                })?,
+            None => race(host, port, options).await,
+        }
+    }
+
+    async fn race(host: &str, port: u16, options: &ConnectOptions) -> io::Result<TcpStream> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let resolved: Vec<SocketAddr> = lookup_host((host, port)).await?.collect();
+        let mut addresses = options.order(resolved).into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_error = None;
+        loop {
+            if attempts.is_empty() {
+                match addresses.next() {
+                    Some(address) => attempts.push(attempt(address)),
+                    None => {
+                        return Err(last_error.unwrap_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!("{} has no {}address", host, options.family.label()),
+                            )
+                        }))
+                    }
+                }
+            }
+            let more = addresses.len() > 0;
+            let next_attempt = async {
+                match options.attempt_delay {
+                    Some(delay) if more => sleep(delay).await,
+                    _ => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                Some(result) = attempts.next() => match result {
+                    Ok(stream) => return Ok(stream),
+                    Err(err) => {
+                        last_error = Some(err);
+                        // the next address need not wait out the delay
+                        if let Some(address) = addresses.next() {
+                            attempts.push(attempt(address));
+                        }
+                    }
+                },
+                _ = next_attempt => {
+                    if let Some(address) = addresses.next() {
+                        attempts.push(attempt(address));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn attempt(address: SocketAddr) -> io::Result<TcpStream> {
+        TcpStream::connect(address)
+            .await
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", address, err)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use dial::connect;
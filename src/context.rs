@@ -0,0 +1,168 @@
+use crate::{RouteTable, Sink, Stream};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+// Who an item belongs to and how to correlate it: set at the source with
+// `Stream::with_context` and carried alongside the payload in an `Envelope`,
+// so operators, sinks and log lines see it without it being in the payload.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Context {
+    pub tenant: Option<String>,
+    pub trace_id: Option<String>,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn for_tenant(tenant: impl Into<String>) -> Self {
+        Self {
+            tenant: Some(tenant.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+}
+
+// e.g. "tenant=desk-a trace_id=7f3a region=eu"
+impl Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(tenant) = &self.tenant {
+            fields.push(format!("tenant={}", tenant));
+        }
+        if let Some(trace_id) = &self.trace_id {
+            fields.push(format!("trace_id={}", trace_id));
+        }
+        for (key, value) in &self.tags {
+            fields.push(format!("{}={}", key, value));
+        }
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+// An item and its context. The context is shared, so carrying it through a
+// pipeline costs a reference count per item. Displays as the item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope<T> {
+    pub context: Arc<Context>,
+    pub item: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(context: Arc<Context>, item: T) -> Self {
+        Self { context, item }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.context.tenant.as_deref()
+    }
+
+    // The same context around another item.
+    pub fn with_item<U>(&self, item: U) -> Envelope<U> {
+        Envelope {
+            context: self.context.clone(),
+            item,
+        }
+    }
+}
+
+impl<T> Display for Envelope<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.item.fmt(f)
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Puts every item in an envelope with the same context.
+    pub fn with_context(&self, context: Context) -> Stream<Envelope<T>> {
+        let context = Arc::new(context);
+        self.map(move |item: &T| Envelope::new(context.clone(), item.clone()))
+    }
+
+    // Puts every item in an envelope with a context of its own, e.g. the
+    // tenant taken from a subscription id.
+    pub fn with_context_by<F>(&self, context: F) -> Stream<Envelope<T>>
+    where
+        F: Fn(&T) -> Context + 'static,
+    {
+        self.map(move |item: &T| Envelope::new(Arc::new(context(item)), item.clone()))
+    }
+}
+
+// The operators below work on the payload and keep each item's context.
+impl<T> Stream<Envelope<T>>
+where
+    T: Clone + 'static,
+{
+    pub fn map_items<U, F>(&self, f: F) -> Stream<Envelope<U>>
+    where
+        U: 'static,
+        F: Fn(&T) -> U + 'static,
+    {
+        self.map(move |envelope: &Envelope<T>| envelope.with_item(f(&envelope.item)))
+    }
+
+    pub fn filter_items<F>(&self, predicate: F) -> Stream<Envelope<T>>
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        self.filter(move |envelope: &Envelope<T>| predicate(&envelope.item))
+    }
+
+    pub fn filter_map_items<U, F>(&self, f: F) -> Stream<Envelope<U>>
+    where
+        U: 'static,
+        F: Fn(&T) -> Option<U> + 'static,
+    {
+        self.filter_map(move |envelope: &Envelope<T>| {
+            f(&envelope.item).map(|item| envelope.with_item(item))
+        })
+    }
+
+    // Drops the contexts.
+    pub fn items(&self) -> Stream<T> {
+        self.map(|envelope: &Envelope<T>| envelope.item.clone())
+    }
+
+    // Items of one tenant, still in their envelopes.
+    pub fn for_tenant(&self, tenant: impl Into<String>) -> Stream<Envelope<T>> {
+        let tenant = tenant.into();
+        self.filter(move |envelope: &Envelope<T>| envelope.tenant() == Some(tenant.as_str()))
+    }
+
+    // A route per tenant; items without one go to `default_route()`.
+    pub fn route_by_tenant(&self) -> RouteTable<Option<String>, Envelope<T>> {
+        self.route(|envelope: &Envelope<T>| envelope.context.tenant.clone())
+    }
+
+    // Hands the payloads to a sink that knows nothing of contexts.
+    pub fn sink_items_to<S>(&self, sink: S)
+    where
+        S: Sink<T>,
+    {
+        self.items().sink_to(sink);
+    }
+}
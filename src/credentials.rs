@@ -0,0 +1,463 @@
+use crate::log;
+use crate::rt::{self, Instant};
+use crate::{EngineSource, Error, Result};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Duration;
+
+// Secret values by key, e.g. `api_key` and `api_secret`. Debug output lists
+// the keys only.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    values: BTreeMap<String, String>,
+}
+
+impl Credentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    // `get`, or a config error naming the missing key.
+    pub fn require(&self, key: &str) -> Result<&str> {
+        self.get(key)
+            .ok_or_else(|| Error::config(format!("credential {:?} is missing", key)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.values.keys().map(|key| (key, "***")))
+            .finish()
+    }
+}
+
+// Where authenticated sources and sinks get their secrets from, so keys are
+// never baked into configs or code. Wrap one in `SharedCredentials` to cache
+// and rotate it.
+pub trait CredentialsProvider: 'static {
+    // For logs and errors, e.g. the variable prefix or the secret's path.
+    fn name(&self) -> &str;
+
+    fn fetch<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<Credentials>> + 'a>>;
+}
+
+// Credentials from environment variables.
+pub struct EnvCredentials {
+    name: String,
+    prefix: Option<String>,
+    vars: Vec<(String, String)>,
+}
+
+impl EnvCredentials {
+    // Every variable starting with `prefix`, keyed by the rest of its name
+    // in lower case: `BINANCE_API_KEY` is `api_key` under `BINANCE_`.
+    pub fn prefixed(prefix: &str) -> Self {
+        Self {
+            name: format!("env {}*", prefix),
+            prefix: Some(prefix.to_string()),
+            vars: Vec::new(),
+        }
+    }
+
+    // Only the variables added with `with_var`.
+    pub fn new() -> Self {
+        Self {
+            name: "env".to_string(),
+            prefix: None,
+            vars: Vec::new(),
+        }
+    }
+
+    // Reads `key` from `var`, which must be set.
+    pub fn with_var(mut self, key: &str, var: &str) -> Self {
+        self.vars.push((key.to_string(), var.to_string()));
+        self
+    }
+
+    fn read(&self) -> Result<Credentials> {
+        let mut credentials = Credentials::new();
+        if let Some(prefix) = &self.prefix {
+            for (var, value) in env::vars() {
+                if let Some(key) = var.strip_prefix(prefix.as_str()) {
+                    if !key.is_empty() {
+                        credentials.insert(key.to_lowercase(), value);
+                    }
+                }
+            }
+        }
+        for (key, var) in &self.vars {
+            let value = env::var(var).map_err(|_| Error::config(format!("{} is not set", var)))?;
+            credentials.insert(key.clone(), value);
+        }
+        if credentials.is_empty() {
+            return Err(Error::config(format!("{}: no credentials set", self.name)));
+        }
+        Ok(credentials)
+    }
+}
+
+impl Default for EnvCredentials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CredentialsProvider for EnvCredentials {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<Credentials>> + 'a>> {
+        Box::pin(async move { self.read() })
+    }
+}
+
+// Credentials from a file or a directory, re-read on every fetch so a
+// rotated secret is picked up. A directory (e.g. a mounted Kubernetes or
+// Docker secret) holds one file per key, named after it. A file holds
+// `key=value` lines, with `#` comments; a file without any is a single
+// value under `secret`.
+pub struct FileCredentials {
+    name: String,
+    path: PathBuf,
+}
+
+impl FileCredentials {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            name: path.display().to_string(),
+            path,
+        }
+    }
+
+    fn read(&self) -> Result<Credentials> {
+        let label = &self.name;
+        let mut credentials = Credentials::new();
+        if self.path.is_dir() {
+            let entries = fs::read_dir(&self.path).map_err(|err| Error::io(label, err))?;
+            for entry in entries {
+                let entry = entry.map_err(|err| Error::io(label, err))?;
+                let key = entry.file_name().to_string_lossy().into_owned();
+                // Kubernetes keeps the real files in hidden `..data` entries
+                if key.starts_with('.') || !entry.path().is_file() {
+                    continue;
+                }
+                let value =
+                    fs::read_to_string(entry.path()).map_err(|err| Error::io(label, err))?;
+                credentials.insert(key, value.trim_end_matches(['\r', '\n']));
+            }
+        } else {
+            let contents = fs::read_to_string(&self.path).map_err(|err| Error::io(label, err))?;
+            let lines: Vec<&str> = contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect();
+            if lines.iter().any(|line| line.contains('=')) {
+                for (index, line) in lines.iter().enumerate() {
+                    let (key, value) = line.split_once('=').ok_or_else(|| {
+                        Error::decode(label, format!("line {}: expected key=value", index + 1))
+                    })?;
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|value| value.strip_suffix('"'))
+                        .unwrap_or(value);
+                    credentials.insert(key.trim(), value);
+                }
+            } else if !lines.is_empty() {
+                credentials.insert("secret", contents.trim());
+            }
+        }
+        if credentials.is_empty() {
+            return Err(Error::config(format!("{}: no credentials", label)));
+        }
+        Ok(credentials)
+    }
+}
+
+impl CredentialsProvider for FileCredentials {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<Credentials>> + 'a>> {
+        Box::pin(async move { self.read() })
+    }
+}
+
+// A key/value secret in HashiCorp Vault's KV version 2 engine.
+#[cfg(feature = "vault")]
+pub struct VaultCredentials {
+    name: String,
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    namespace: Option<String>,
+}
+
+#[cfg(feature = "vault")]
+impl VaultCredentials {
+    // The secret at `path` in the KV engine mounted at `mount` (usually
+    // `secret`), read with `token`.
+    pub fn new(address: &str, mount: &str, path: &str, token: &str) -> Result<Self> {
+        let url = format!(
+            "{}/v1/{}/data/{}",
+            address.trim_end_matches('/'),
+            mount.trim_matches('/'),
+            path.trim_matches('/')
+        );
+        Ok(Self {
+            name: format!(
+                "vault {}/{}",
+                mount.trim_matches('/'),
+                path.trim_matches('/')
+            ),
+            client: crate::sources::http_client::client(&url)?,
+            url,
+            token: token.to_string(),
+            namespace: None,
+        })
+    }
+
+    // `VAULT_ADDR`, `VAULT_TOKEN` and, if set, `VAULT_NAMESPACE`.
+    pub fn from_env(mount: &str, path: &str) -> Result<Self> {
+        let var =
+            |name: &str| env::var(name).map_err(|_| Error::config(format!("{} is not set", name)));
+        let vault = Self::new(&var("VAULT_ADDR")?, mount, path, &var("VAULT_TOKEN")?)?;
+        Ok(match env::var("VAULT_NAMESPACE") {
+            Ok(namespace) => vault.with_namespace(&namespace),
+            Err(_) => vault,
+        })
+    }
+
+    // Vault Enterprise namespace.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    async fn read(&self) -> Result<Credentials> {
+        use crate::sources::http_client::request_error;
+
+        let label = &self.name;
+        let mut request = self
+            .client
+            .get(&self.url)
+            .header("X-Vault-Token", &self.token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        if !status.is_success() {
+            let detail = text.trim();
+            let message = if detail.is_empty() {
+                status.to_string()
+            } else {
+                format!("{}: {}", status, detail)
+            };
+            return Err(Error::protocol(label, message));
+        }
+        let body: serde_json::Value =
+            serde_json::from_str(&text).map_err(|err| Error::decode(label, err))?;
+        let data = body["data"]["data"]
+            .as_object()
+            .ok_or_else(|| Error::protocol(label, "no data in the response"))?;
+        Ok(from_json(data))
+    }
+}
+
+#[cfg(feature = "vault")]
+impl CredentialsProvider for VaultCredentials {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<Credentials>> + 'a>> {
+        Box::pin(self.read())
+    }
+}
+
+// String values as they are, anything else as JSON.
+#[cfg(any(feature = "vault", feature = "secrets-manager"))]
+pub(crate) fn from_json(object: &serde_json::Map<String, serde_json::Value>) -> Credentials {
+    let mut credentials = Credentials::new();
+    for (key, value) in object {
+        match value {
+            serde_json::Value::String(value) => credentials.insert(key.clone(), value.clone()),
+            value => credentials.insert(key.clone(), value.to_string()),
+        }
+    }
+    credentials
+}
+
+type RotateCallback = Box<dyn Fn(&Credentials)>;
+
+struct SharedInner {
+    provider: Box<dyn CredentialsProvider>,
+    current: RefCell<Option<Credentials>>,
+    refresh_every: Cell<Option<Duration>>,
+    on_rotate: RefCell<Vec<RotateCallback>>,
+    rotations: Cell<u64>,
+    failures: Cell<u64>,
+}
+
+// A provider's credentials, fetched on first use and cached. Sources given
+// the same handle share one copy. With `with_refresh_interval`, adding the
+// handle to the engine (`add_source_owned`) re-fetches it periodically;
+// when the values change, sources use the new ones from their next request
+// or login and `on_rotate` callbacks run. A failed refresh keeps the
+// previous credentials.
+#[derive(Clone)]
+pub struct SharedCredentials {
+    inner: Rc<SharedInner>,
+}
+
+impl SharedCredentials {
+    pub fn new<P>(provider: P) -> Self
+    where
+        P: CredentialsProvider,
+    {
+        Self {
+            inner: Rc::new(SharedInner {
+                provider: Box::new(provider),
+                current: RefCell::new(None),
+                refresh_every: Cell::new(None),
+                on_rotate: RefCell::new(Vec::new()),
+                rotations: Cell::new(0),
+                failures: Cell::new(0),
+            }),
+        }
+    }
+
+    pub fn with_refresh_interval(self, period: Duration) -> Self {
+        self.inner.refresh_every.set(Some(period));
+        self
+    }
+
+    // Runs `callback` with the new credentials whenever a refresh finds
+    // they changed, e.g. to re-authenticate a long-lived session.
+    pub fn on_rotate<F>(&self, callback: F)
+    where
+        F: Fn(&Credentials) + 'static,
+    {
+        self.inner.on_rotate.borrow_mut().push(Box::new(callback));
+    }
+
+    pub fn name(&self) -> &str {
+        self.inner.provider.name()
+    }
+
+    // The cached credentials, fetching them the first time.
+    pub async fn get(&self) -> Result<Credentials> {
+        if let Some(current) = &*self.inner.current.borrow() {
+            return Ok(current.clone());
+        }
+        self.refresh().await?;
+        Ok(self.inner.current.borrow().clone().unwrap_or_default())
+    }
+
+    // Fetches the credentials again; whether they changed.
+    pub async fn refresh(&self) -> Result<bool> {
+        let inner = &self.inner;
+        let fetched = match inner.provider.fetch().await {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                inner.failures.set(inner.failures.get() + 1);
+                return Err(err);
+            }
+        };
+        let previous = inner.current.borrow_mut().replace(fetched.clone());
+        let rotated = previous.is_some_and(|previous| previous != fetched);
+        if rotated {
+            inner.rotations.set(inner.rotations.get() + 1);
+            log::info(
+                "credentials",
+                format_args!("{}: credentials rotated", self.name()),
+            );
+            for callback in inner.on_rotate.borrow().iter() {
+                callback(&fetched);
+            }
+        }
+        Ok(rotated)
+    }
+
+    pub fn rotations(&self) -> u64 {
+        self.inner.rotations.get()
+    }
+
+    // Fetches or refreshes that failed.
+    pub fn failures(&self) -> u64 {
+        self.inner.failures.get()
+    }
+}
+
+impl fmt::Debug for SharedCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCredentials")
+            .field("provider", &self.name())
+            .field("current", &*self.inner.current.borrow())
+            .finish()
+    }
+}
+
+impl EngineSource for SharedCredentials {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move {
+            let Some(period) = self.inner.refresh_every.get() else {
+                return Ok(());
+            };
+            loop {
+                rt::sleep_until(Instant::now() + period).await;
+                if let Err(err) = self.refresh().await {
+                    log::warn(
+                        "credentials",
+                        format_args!(
+                            "{}: refresh failed: {}; keeping the previous credentials",
+                            self.name(),
+                            err
+                        ),
+                    );
+                }
+            }
+        })
+    }
+}
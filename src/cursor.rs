@@ -0,0 +1,90 @@
+use crate::{Error, Result};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// Where incremental sources keep their last-seen position (an ETag, a block
+// number, an object key) across restarts, so a restart neither re-emits nor
+// skips data. Keys are per source, e.g. its url.
+pub trait CursorStore: 'static {
+    fn load(&self, key: &str) -> Option<String>;
+
+    fn save(&self, key: &str, cursor: &str) -> Result<()>;
+}
+
+// Keeps cursors in a small text file, one `key<TAB>cursor` line each,
+// rewritten (via a temporary file and a rename) on every save.
+pub struct FileCursorStore {
+    path: PathBuf,
+    cursors: RefCell<BTreeMap<String, String>>,
+}
+
+impl FileCursorStore {
+    // Reads the cursors saved in `path`, if it exists.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let label = path.display().to_string();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(Error::io(label, err)),
+        };
+        let mut cursors = BTreeMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let (key, cursor) = line.split_once('\t').ok_or_else(|| {
+                Error::decode(
+                    &label,
+                    format!("line {}: expected key<TAB>cursor", index + 1),
+                )
+            })?;
+            cursors.insert(key.to_string(), cursor.to_string());
+        }
+        Ok(Self {
+            path,
+            cursors: RefCell::new(cursors),
+        })
+    }
+
+    fn write(&self) -> Result<()> {
+        let label = self.path.display().to_string();
+        let mut contents = String::new();
+        for (key, cursor) in self.cursors.borrow().iter() {
+            contents.push_str(key);
+            contents.push('\t');
+            contents.push_str(cursor);
+            contents.push('\n');
+        }
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents).map_err(|err| Error::io(&label, err))?;
+        fs::rename(&temporary, &self.path).map_err(|err| Error::io(&label, err))
+    }
+}
+
+impl CursorStore for FileCursorStore {
+    fn load(&self, key: &str) -> Option<String> {
+        self.cursors.borrow().get(key).cloned()
+    }
+
+    fn save(&self, key: &str, cursor: &str) -> Result<()> {
+        if [key, cursor].iter().any(|part| part.contains(['\t', '\n'])) {
+            return Err(Error::config(format!(
+                "cursor {:?}: keys and cursors may not contain tabs or newlines",
+                key
+            )));
+        }
+        let previous = self
+            .cursors
+            .borrow_mut()
+            .insert(key.to_string(), cursor.to_string());
+        if previous.as_deref() == Some(cursor) {
+            return Ok(());
+        }
+        self.write()
+    }
+}
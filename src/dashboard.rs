@@ -0,0 +1,303 @@
+use crate::rt::Instant;
+use crate::{Stream, TimedEmitter};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::{Hide, Show};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::widgets::{Block, Borders, List, Row, Sparkline, Table};
+use ratatui::{Frame, Terminal};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{stdout, Stdout};
+use std::rc::Rc;
+use std::time::Duration;
+
+const HISTORY: usize = 240;
+
+trait Panel {
+    fn title(&self) -> String;
+    fn tick(&self, _elapsed: Duration) {}
+    fn render(&self, frame: &mut Frame, area: Rect, block: Block);
+}
+
+// A terminal dashboard redrawn on every engine timer tick. Register it with
+// `EngineBuilder::add_timed_emitter(dashboard.as_timed_emitter())`; other
+// sinks should stop printing to stdout while it is running.
+pub struct Dashboard {
+    inner: Rc<DashboardInner>,
+}
+
+struct DashboardInner {
+    title: String,
+    refresh: Cell<Duration>,
+    panels: RefCell<Vec<Rc<dyn Panel>>>,
+    terminal: RefCell<Option<Terminal<CrosstermBackend<Stdout>>>>,
+    last_draw: Cell<Option<Instant>>,
+}
+
+impl Dashboard {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            inner: Rc::new(DashboardInner {
+                title: title.into(),
+                refresh: Cell::new(Duration::from_millis(250)),
+                panels: RefCell::new(Vec::new()),
+                terminal: RefCell::new(None),
+                last_draw: Cell::new(None),
+            }),
+        }
+    }
+
+    pub fn with_refresh(self, refresh: Duration) -> Self {
+        self.inner.refresh.set(refresh);
+        self
+    }
+
+    // Latest key/value rows derived from each item, e.g. best bid/ask.
+    pub fn with_table<T, F>(self, title: &str, stream: &Stream<T>, rows: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&T) -> Vec<(String, String)> + 'static,
+    {
+        let panel = Rc::new(TablePanel {
+            title: title.to_string(),
+            rows: RefCell::new(Vec::new()),
+        });
+        let panel_clone = panel.clone();
+        stream.sink(move |item| *panel_clone.rows.borrow_mut() = rows(item));
+        self.add_panel(panel)
+    }
+
+    // The most recent `capacity` items, newest first, e.g. a trade tape.
+    pub fn with_tape<T, F>(self, title: &str, stream: &Stream<T>, capacity: usize, line: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&T) -> String + 'static,
+    {
+        let panel = Rc::new(TapePanel {
+            title: title.to_string(),
+            capacity,
+            lines: RefCell::new(VecDeque::with_capacity(capacity)),
+        });
+        let panel_clone = panel.clone();
+        stream.sink(move |item| {
+            let mut lines = panel_clone.lines.borrow_mut();
+            lines.push_front(line(item));
+            lines.truncate(panel_clone.capacity);
+        });
+        self.add_panel(panel)
+    }
+
+    pub fn with_sparkline<T, F>(self, title: &str, stream: &Stream<T>, value: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&T) -> f64 + 'static,
+    {
+        let panel = Rc::new(SparklinePanel {
+            title: title.to_string(),
+            unit: "",
+            values: RefCell::new(VecDeque::with_capacity(HISTORY)),
+        });
+        let panel_clone = panel.clone();
+        stream.sink(move |item| panel_clone.push(value(item)));
+        self.add_panel(panel)
+    }
+
+    // Items per second, sampled on every refresh.
+    pub fn with_rate<T>(self, title: &str, stream: &Stream<T>) -> Self
+    where
+        T: 'static,
+    {
+        let panel = Rc::new(RatePanel {
+            count: Cell::new(0),
+            sparkline: SparklinePanel {
+                title: title.to_string(),
+                unit: "/s",
+                values: RefCell::new(VecDeque::with_capacity(HISTORY)),
+            },
+        });
+        let panel_clone = panel.clone();
+        stream.sink(move |_| panel_clone.count.set(panel_clone.count.get() + 1));
+        self.add_panel(panel)
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+
+    fn add_panel(self, panel: Rc<dyn Panel>) -> Self {
+        self.inner.panels.borrow_mut().push(panel);
+        self
+    }
+}
+
+impl TimedEmitter for DashboardInner {
+    fn period(&self) -> Duration {
+        self.refresh.get()
+    }
+
+    fn name(&self) -> String {
+        "dashboard".to_string()
+    }
+
+    fn flush(&self) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_draw
+            .replace(Some(now))
+            .map(|last| now - last)
+            .unwrap_or(self.refresh.get());
+        for panel in self.panels.borrow().iter() {
+            panel.tick(elapsed);
+        }
+
+        let mut terminal = self.terminal.borrow_mut();
+        if terminal.is_none() {
+            let mut out = stdout();
+            if execute!(out, EnterAlternateScreen, Hide).is_err() {
+                return;
+            }
+            match Terminal::new(CrosstermBackend::new(out)) {
+                Ok(created) => *terminal = Some(created),
+                Err(_) => return,
+            }
+        }
+
+        if let Some(terminal) = terminal.as_mut() {
+            let _ = terminal.draw(|frame| self.render(frame));
+        }
+    }
+}
+
+impl DashboardInner {
+    fn render(&self, frame: &mut Frame) {
+        let outer = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" {} (Ctrl+C to exit) ", self.title));
+        let area = outer.inner(frame.area());
+        frame.render_widget(outer, frame.area());
+
+        let panels = self.panels.borrow();
+        let constraints = vec![Constraint::Fill(1); panels.len().max(1)];
+        let areas = Layout::vertical(constraints).split(area);
+        for (panel, area) in panels.iter().zip(areas.iter()) {
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" {} ", panel.title()));
+            panel.render(frame, *area, block);
+        }
+    }
+}
+
+impl Drop for DashboardInner {
+    fn drop(&mut self) {
+        if let Some(mut terminal) = self.terminal.get_mut().take() {
+            let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen, Show);
+        }
+    }
+}
+
+struct TablePanel {
+    title: String,
+    rows: RefCell<Vec<(String, String)>>,
+}
+
+impl Panel for TablePanel {
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn render(&self, frame: &mut Frame, area: Rect, block: Block) {
+        let rows: Vec<Row> = self
+            .rows
+            .borrow()
+            .iter()
+            .map(|(key, value)| Row::new(vec![key.clone(), value.clone()]))
+            .collect();
+        let table =
+            Table::new(rows, [Constraint::Percentage(40), Constraint::Fill(1)]).block(block);
+        frame.render_widget(table, area);
+    }
+}
+
+struct TapePanel {
+    title: String,
+    capacity: usize,
+    lines: RefCell<VecDeque<String>>,
+}
+
+impl Panel for TapePanel {
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    fn render(&self, frame: &mut Frame, area: Rect, block: Block) {
+        let list = List::new(self.lines.borrow().iter().cloned()).block(block);
+        frame.render_widget(list, area);
+    }
+}
+
+struct SparklinePanel {
+    title: String,
+    unit: &'static str,
+    values: RefCell<VecDeque<f64>>,
+}
+
+impl SparklinePanel {
+    fn push(&self, value: f64) {
+        let mut values = self.values.borrow_mut();
+        if values.len() == HISTORY {
+            values.pop_front();
+        }
+        values.push_back(value);
+    }
+}
+
+impl Panel for SparklinePanel {
+    fn title(&self) -> String {
+        match self.values.borrow().back() {
+            Some(last) => format!("{}: {:.2}{}", self.title, last, self.unit),
+            None => self.title.clone(),
+        }
+    }
+
+    fn render(&self, frame: &mut Frame, area: Rect, block: Block) {
+        let values = self.values.borrow();
+        // plot relative to the visible range so small moves on large values
+        // (prices) remain visible
+        let width = area.width.saturating_sub(2) as usize;
+        let visible: Vec<f64> = values.iter().rev().take(width).rev().copied().collect();
+        let min = visible.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = visible.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let range = if max > min { max - min } else { 1.0 };
+        let data: Vec<u64> = visible
+            .iter()
+            .map(|value| (((value - min) / range) * 100.0) as u64 + 1)
+            .collect();
+        frame.render_widget(Sparkline::default().block(block).data(&data), area);
+    }
+}
+
+struct RatePanel {
+    count: Cell<u64>,
+    sparkline: SparklinePanel,
+}
+
+impl Panel for RatePanel {
+    fn title(&self) -> String {
+        self.sparkline.title()
+    }
+
+    fn tick(&self, elapsed: Duration) {
+        let count = self.count.replace(0);
+        let seconds = elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.sparkline.push(count as f64 / seconds);
+        }
+    }
+
+    fn render(&self, frame: &mut Frame, area: Rect, block: Block) {
+        self.sparkline.render(frame, area, block)
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{backpressure, log, ConnectionEvent, Error, Result, Source, Stream};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const SEGMENT_ITEMS: u64 = 10_000;
+const SEGMENT_EXTENSION: &str = "seg";
+const CURSOR_FILE: &str = "cursor";
+
+// Items spilled to disk as JSON lines in numbered segment files, oldest
+// first. `cursor` records how far into the oldest segment replay got.
+struct SegmentQueue {
+    dir: PathBuf,
+    label: String,
+    segments: VecDeque<u64>,
+    reader: Option<(u64, BufReader<File>)>,
+    // items of the oldest segment already replayed
+    head_offset: u64,
+    writer: Option<LineWriter<File>>,
+    tail_items: u64,
+    len: usize,
+}
+
+impl SegmentQueue {
+    fn open(dir: &Path) -> Result<Self> {
+        let label = dir.display().to_string();
+        fs::create_dir_all(dir).map_err(|err| Error::io(&label, err))?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir).map_err(|err| Error::io(&label, err))? {
+            let path = entry.map_err(|err| Error::io(&label, err))?.path();
+            if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+                if let Some(number) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    segments.push(number);
+                }
+            }
+        }
+        segments.sort_unstable();
+
+        let mut queue = Self {
+            dir: dir.to_path_buf(),
+            label,
+            segments: segments.into(),
+            reader: None,
+            head_offset: 0,
+            writer: None,
+            tail_items: 0,
+            len: 0,
+        };
+        let (cursor_segment, cursor_offset) = queue.read_cursor()?;
+        while queue
+            .segments
+            .front()
+            .is_some_and(|segment| *segment < cursor_segment)
+        {
+            let segment = queue.segments.pop_front().unwrap();
+            queue.remove(segment)?;
+        }
+        if queue.segments.front() == Some(&cursor_segment) {
+            queue.head_offset = cursor_offset;
+        }
+        let mut total = 0;
+        for segment in &queue.segments {
+            total += queue.count(*segment)?;
+        }
+        queue.len = total.saturating_sub(queue.head_offset) as usize;
+        if let Some(tail) = queue.segments.back().copied() {
+            queue.tail_items = queue.count(tail)?;
+        }
+        Ok(queue)
+    }
+
+    fn path(&self, segment: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:020}.{}", segment, SEGMENT_EXTENSION))
+    }
+
+    fn count(&self, segment: u64) -> Result<u64> {
+        let file = File::open(self.path(segment)).map_err(|err| Error::io(&self.label, err))?;
+        Ok(BufReader::new(file).lines().count() as u64)
+    }
+
+    fn remove(&self, segment: u64) -> Result<()> {
+        match fs::remove_file(self.path(segment)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(Error::io(&self.label, err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_cursor(&self) -> Result<(u64, u64)> {
+        let contents = match fs::read_to_string(self.dir.join(CURSOR_FILE)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+            Err(err) => return Err(Error::io(&self.label, err)),
+        };
+        let mut parts = contents.split_whitespace().map(str::parse::<u64>);
+        match (parts.next(), parts.next()) {
+            (Some(Ok(segment)), Some(Ok(offset))) => Ok((segment, offset)),
+            _ => Err(Error::decode(
+                &self.label,
+                "cursor: expected `segment offset`",
+            )),
+        }
+    }
+
+    // Records replay progress, so a restart resumes from here.
+    fn commit(&self) -> Result<()> {
+        let cursor = self.dir.join(CURSOR_FILE);
+        let Some(head) = self.segments.front() else {
+            return self.remove_cursor();
+        };
+        let temporary = self.dir.join(format!("{}.tmp", CURSOR_FILE));
+        fs::write(&temporary, format!("{} {}\n", head, self.head_offset))
+            .map_err(|err| Error::io(&self.label, err))?;
+        fs::rename(&temporary, &cursor).map_err(|err| Error::io(&self.label, err))
+    }
+
+    fn remove_cursor(&self) -> Result<()> {
+        match fs::remove_file(self.dir.join(CURSOR_FILE)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(Error::io(&self.label, err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn push(&mut self, line: &str) -> Result<()> {
+        if self.writer.is_none() || self.tail_items >= SEGMENT_ITEMS {
+            let segment = match self.segments.back() {
+                Some(tail) if self.writer.is_none() && self.tail_items < SEGMENT_ITEMS => *tail,
+                Some(tail) => tail + 1,
+                None => 0,
+            };
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(segment))
+                .map_err(|err| Error::io(&self.label, err))?;
+            if self.segments.back() != Some(&segment) {
+                self.segments.push_back(segment);
+                self.tail_items = 0;
+            }
+            self.writer = Some(LineWriter::new(file));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writeln!(writer, "{}", line).map_err(|err| Error::io(&self.label, err))?;
+        self.tail_items += 1;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Option<String>> {
+        while let Some(head) = self.segments.front().copied() {
+            if self
+                .reader
+                .as_ref()
+                .is_none_or(|(segment, _)| *segment != head)
+            {
+                let file =
+                    File::open(self.path(head)).map_err(|err| Error::io(&self.label, err))?;
+                let mut reader = BufReader::new(file);
+                let mut skipped = String::new();
+                for _ in 0..self.head_offset {
+                    skipped.clear();
+                    reader
+                        .read_line(&mut skipped)
+                        .map_err(|err| Error::io(&self.label, err))?;
+                }
+                self.reader = Some((head, reader));
+            }
+
+            let (_, reader) = self.reader.as_mut().unwrap();
+            let mut line = String::new();
+            let read = reader
+                .read_line(&mut line)
+                .map_err(|err| Error::io(&self.label, err))?;
+            if read > 0 && line.ends_with('\n') {
+                line.pop();
+                self.head_offset += 1;
+                self.len -= 1;
+                if self.len == 0 {
+                    self.clear()?;
+                }
+                return Ok(Some(line));
+            }
+            if self.segments.len() == 1 {
+                return Ok(None);
+            }
+            // the segment is used up; later ones follow
+            self.segments.pop_front();
+            self.reader = None;
+            self.head_offset = 0;
+            self.remove(head)?;
+        }
+        Ok(None)
+    }
+
+    // Everything was replayed: starts over with no files.
+    fn clear(&mut self) -> Result<()> {
+        self.reader = None;
+        self.writer = None;
+        self.head_offset = 0;
+        self.tail_items = 0;
+        while let Some(segment) = self.segments.pop_front() {
+            self.remove(segment)?;
+        }
+        self.remove_cursor()
+    }
+}
+
+// Returned by `Stream::disk_buffer`. Items pass straight through while the
+// buffer runs; while it is paused (the downstream sink is down) they are
+// appended to segment files on disk instead, and replayed in order on
+// `resume`. Items spilled by an earlier run are replayed ahead of the first
+// new item, or on `resume`. Replay is at least once: progress is recorded
+// when replay stops, so a crash mid-replay repeats some items.
+pub struct DiskBuffer<T> {
+    inner: Rc<DiskBufferInner<T>>,
+}
+
+struct DiskBufferInner<T> {
+    label: String,
+    capacity: usize,
+    queue: RefCell<SegmentQueue>,
+    paused: Cell<bool>,
+    draining: Cell<bool>,
+    spilled: Cell<u64>,
+    dropped: Cell<u64>,
+    output: Source<T>,
+}
+
+impl<T> Stream<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    // Spills to the directory `path`, holding at most `capacity` items there;
+    // items beyond that are dropped and counted.
+    pub fn disk_buffer(&self, path: impl AsRef<Path>, capacity: usize) -> Result<DiskBuffer<T>> {
+        let queue = SegmentQueue::open(path.as_ref())?;
+        let inner = Rc::new(DiskBufferInner {
+            label: queue.label.clone(),
+            capacity,
+            queue: RefCell::new(queue),
+            paused: Cell::new(false),
+            draining: Cell::new(false),
+            spilled: Cell::new(0),
+            dropped: Cell::new(0),
+            output: Source::new(),
+        });
+
+        let inner_item = inner.clone();
+        self.sink(move |item: &T| inner_item.on_item(item));
+
+        let inner_done = inner.clone();
+        self.on_complete(move || {
+            inner_done.commit();
+            inner_done.output.complete();
+        });
+
+        Ok(DiskBuffer { inner })
+    }
+}
+
+impl<T> DiskBuffer<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    pub fn stream(&self) -> Stream<T> {
+        self.inner.output.to_stream()
+    }
+
+    // Spills every item from now on.
+    pub fn pause(&self) {
+        if !self.inner.paused.replace(true) {
+            self.inner.commit();
+        }
+    }
+
+    // Replays what was spilled, then passes items through again.
+    pub fn resume(&self) {
+        self.inner.paused.set(false);
+        self.inner.drain();
+    }
+
+    // Pauses on `Disconnected` and resumes on (re)connection, e.g. with a
+    // sink's connection events.
+    pub fn pause_while_disconnected(&self, events: &Stream<ConnectionEvent>) {
+        let buffer = self.clone();
+        events.sink(move |event: &ConnectionEvent| match event {
+            ConnectionEvent::Disconnected => buffer.pause(),
+            ConnectionEvent::Connected | ConnectionEvent::Reconnected => buffer.resume(),
+        });
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.get()
+    }
+
+    // Items on disk waiting to be replayed.
+    pub fn len(&self) -> usize {
+        self.inner.queue.borrow().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn spilled(&self) -> u64 {
+        self.inner.spilled.get()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.get()
+    }
+}
+
+impl<T> Clone for DiskBuffer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> DiskBufferInner<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    fn on_item(&self, item: &T) {
+        let backlog = self.queue.borrow().len > 0;
+        if !self.paused.get() && !self.draining.get() && !backlog {
+            self.output.emit(item.clone());
+            return;
+        }
+        self.spill(item);
+        if !self.paused.get() && !self.draining.get() {
+            self.drain();
+        }
+    }
+
+    fn spill(&self, item: &T) {
+        let mut queue = self.queue.borrow_mut();
+        if queue.len >= self.capacity {
+            let dropped = self.dropped.get() + 1;
+            self.dropped.set(dropped);
+            if dropped == 1 {
+                log::warn(
+                    "disk_buffer",
+                    format_args!("{} is full ({} items); dropping", self.label, self.capacity),
+                );
+            }
+            backpressure::report(&self.label, queue.len, dropped);
+            return;
+        }
+        let written = serde_json::to_string(item)
+            .map_err(|err| Error::decode(&self.label, err))
+            .and_then(|line| queue.push(&line));
+        match written {
+            Ok(()) => {
+                self.spilled.set(self.spilled.get() + 1);
+                backpressure::report(&self.label, queue.len, self.dropped.get());
+            }
+            Err(err) => {
+                self.dropped.set(self.dropped.get() + 1);
+                log::error("disk_buffer", format_args!("spill failed: {}", err));
+            }
+        }
+    }
+
+    fn drain(&self) {
+        if self.draining.replace(true) {
+            return;
+        }
+        while !self.paused.get() {
+            let line = self.queue.borrow_mut().pop();
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    log::error("disk_buffer", format_args!("replay failed: {}", err));
+                    break;
+                }
+            };
+            match serde_json::from_str::<T>(&line) {
+                Ok(item) => self.output.emit(item),
+                Err(err) => {
+                    self.dropped.set(self.dropped.get() + 1);
+                    log::error(
+                        "disk_buffer",
+                        format_args!("{}: undecodable item skipped: {}", self.label, err),
+                    );
+                }
+            }
+        }
+        self.draining.set(false);
+        self.commit();
+    }
+
+    fn commit(&self) {
+        if let Err(err) = self.queue.borrow().commit() {
+            log::error("disk_buffer", format_args!("cursor save failed: {}", err));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::rt::{self, Instant};
+use std::cell::Cell;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+thread_local! {
+    // Async work started from callbacks that has not finished yet, e.g.
+    // pending `enrich` lookups.
+    static IN_FLIGHT: Cell<usize> = const { Cell::new(0) };
+}
+
+// Counts as in flight until dropped.
+pub(crate) struct InFlight(());
+
+impl InFlight {
+    pub(crate) fn start() -> Self {
+        IN_FLIGHT.with(|count| count.set(count.get() + 1));
+        InFlight(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|count| count.set(count.get() - 1));
+    }
+}
+
+// Waits for in-flight work to finish, giving up after `timeout`. Returns
+// whether everything finished.
+pub(crate) async fn settled(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if IN_FLIGHT.with(Cell::get) == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        rt::sleep_until(deadline.min(now + POLL_INTERVAL)).await;
+    }
+}
@@ -1,30 +1,151 @@
+use crate::backpressure::{self, Backpressure};
+use crate::claim::{self, Claims};
+use crate::drain;
+use crate::handle::{EngineCommand, EngineEvent, EngineHandle};
+#[cfg(feature = "kinesis")]
+use crate::integrations::aws::KinesisSource;
+#[cfg(feature = "axum")]
+use crate::integrations::axum::AdminServer;
+#[cfg(feature = "axum")]
+use crate::integrations::axum::HttpServer;
+#[cfg(all(feature = "axum", feature = "query"))]
+use crate::integrations::axum::QueryServer;
+#[cfg(all(feature = "eventhubs", not(target_arch = "wasm32")))]
+use crate::integrations::eventhubs::EventHubsSource;
+#[cfg(feature = "gcp")]
+use crate::integrations::gcp::PubSubSource;
+use crate::log;
+use crate::metrics::MetricsRegistry;
+use crate::pipeline::{Pipeline, PipelineContext, PipelineError};
+use crate::plan::{
+    BufferStatus, EnginePlan, EngineStatus, PlannedSource, PlannedTimer, SourceState, SourceStatus,
+};
+use crate::profile::{self, CallbackProfiler};
+#[cfg(feature = "query")]
+use crate::query::{Queries, Queryable};
+use crate::reconnect::ReconnectBudget;
+use crate::report::{RunReport, SourceRate, SourceStats, StatsSampler};
+use crate::rt::{self, Instant, Signals};
+use crate::schedule::{PollDelay, SourceTask, SourceTasks};
+#[cfg(feature = "schema")]
+use crate::schema::{self, Schema};
+use crate::signal::{Signal, SignalAction};
+use crate::sink::{self, SinkFlusher};
+use crate::source::{
+    take_duplicate_subscriptions, take_unregistered_buffers, unregistered_buffers, BuilderScope,
+    RetainedStream,
+};
+#[cfg(feature = "books")]
+use crate::sources::book::SyncedBookSource;
+use crate::sources::channel::{BroadcastSource, WatchSource};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::sources::dedicated::DedicatedThreadSource;
+#[cfg(feature = "graphql")]
+use crate::sources::graphql::{GraphQlPollingClient, GraphQlSubscriptionClient};
 #[cfg(feature = "requests")]
-use crate::sources::http_client::{JsonPollingHttpClient, PollingHttpClient};
-#[cfg(feature = "websockets")]
-use crate::sources::websocket_client::WebSocketClient;
-use crate::{Stream, TimedBuffer, TimedEmitter};
-use anyhow::{anyhow, Result};
-use futures_util::future::pending;
+use crate::sources::http_client::{JsonPollingHttpClient, OneShotRequest, PollingHttpClient};
+#[cfg(all(feature = "shm", unix))]
+use crate::sources::ipc::ShmSubscriber;
+#[cfg(all(feature = "ipc", unix))]
+use crate::sources::ipc::{IpcPublisher, IpcSubscriber};
+use crate::sources::iter::IterSource;
+#[cfg(feature = "requests")]
+use crate::sources::jsonrpc::JsonRpcBatchClient;
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+use crate::sources::order_entry::OrderEntryClient;
+#[cfg(feature = "replay")]
+use crate::sources::replay::ReplaySource;
+#[cfg(all(
+    feature = "websockets",
+    feature = "requests",
+    not(target_arch = "wasm32")
+))]
+use crate::sources::websocket_client::InstrumentUniverse;
+#[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+use crate::sources::websocket_client::WebSocketPool;
+#[cfg(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32")))]
+use crate::sources::websocket_client::{RedundantWebSocketClient, WebSocketClient};
+use crate::{Error, Result, Source, Stream, TimedBuffer, TimedEmitter};
+use futures_util::future::{abortable, pending, AbortHandle};
 use futures_util::stream::FuturesUnordered;
 use futures_util::StreamExt;
-#[cfg(feature = "requests")]
+#[cfg(any(feature = "requests", all(feature = "ipc", unix)))]
 use serde::de::DeserializeOwned;
-use std::any::Any;
+#[cfg(all(feature = "ipc", unix))]
+use serde::Serialize;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::future::Future;
+#[cfg(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32")))]
+use std::hash::Hash;
+use std::mem;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::Instant;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
 
 pub trait EngineSource: 'static {
     fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>>;
+
+    fn stats(&self) -> SourceStats {
+        SourceStats::default()
+    }
+
+    // Callbacks attached to the source's outputs, if it can tell; sources
+    // without any are reported by `Engine::validate`.
+    fn subscribers(&self) -> Option<usize> {
+        None
+    }
+
+    // Whether the source is connected upstream, if it can tell.
+    fn connected(&self) -> Option<bool> {
+        None
+    }
+
+    // Called after the source stops, when it is removed or the engine shuts
+    // down. Completing its outputs lets buffers downstream flush.
+    fn close(&self) {}
 }
 
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type SharedSources = Rc<RefCell<Vec<(String, Arc<dyn EngineSource>)>>>;
+// Live source tasks per label.
+type LiveTasks = Rc<RefCell<HashMap<String, usize>>>;
+// The latest backpressure depth, total dropped and when, per stream.
+type Buffers = Rc<RefCell<BTreeMap<String, (usize, u64, Instant)>>>;
+
 pub struct EngineBuilder {
-    streams: Vec<Box<dyn Any>>, // hold onto streams to keep pipelines alive
+    streams: Vec<Box<dyn RetainedStream>>, // hold onto streams to keep pipelines alive
     sources: Vec<(String, Arc<dyn EngineSource>)>,
     timed_emitters: Vec<Rc<dyn TimedEmitter>>,
+    callback_budget: Option<Duration>,
+    metrics: Option<MetricsRegistry>,
+    backpressure: Rc<Source<Backpressure>>,
+    signal_actions: HashMap<Signal, SignalAction>,
+    signals: Rc<Source<Signal>>,
+    signal_handling: bool,
+    cancellation: Option<CancellationToken>,
+    drain_timeout: Duration,
+    source_priorities: HashMap<String, i32>,
+    stats_interval: Option<Duration>,
+    sink_flush_interval: Option<Duration>,
+    source_rates: Rc<Source<SourceRate>>,
+    events: Rc<Source<EngineEvent>>,
+    pipeline_errors: Rc<Source<PipelineError>>,
+    commands: (
+        UnboundedSender<EngineCommand>,
+        UnboundedReceiver<EngineCommand>,
+    ),
+    engines: Vec<(String, EngineBuilder)>,
+    scope: BuilderScope,
+    #[cfg(feature = "query")]
+    queries: Queries,
+    #[cfg(feature = "schema")]
+    schemas: HashMap<String, Schema>,
 }
 
 impl Default for EngineBuilder {
@@ -39,7 +160,152 @@ impl EngineBuilder {
             streams: Vec::new(),
             sources: Vec::new(),
             timed_emitters: Vec::new(),
+            callback_budget: None,
+            metrics: None,
+            backpressure: Rc::new(Source::new()),
+            signal_actions: HashMap::new(),
+            signals: Rc::new(Source::new()),
+            signal_handling: true,
+            cancellation: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            source_priorities: HashMap::new(),
+            stats_interval: None,
+            sink_flush_interval: None,
+            source_rates: Rc::new(Source::new()),
+            events: Rc::new(Source::new()),
+            pipeline_errors: Rc::new(Source::new()),
+            commands: unbounded_channel(),
+            engines: Vec::new(),
+            scope: BuilderScope::new(),
+            #[cfg(feature = "query")]
+            queries: Queries::default(),
+            #[cfg(feature = "schema")]
+            schemas: HashMap::new(),
+        }
+    }
+
+    // For adding and removing sources, timers and streams while running.
+    #[allow(unused_mut)]
+    pub fn handle(&self) -> EngineHandle {
+        let mut handle = EngineHandle::new(
+            self.commands.0.clone(),
+            self.events.clone(),
+            self.pipeline_errors.clone(),
+        );
+        #[cfg(feature = "query")]
+        {
+            handle.queries = self.queries.clone();
         }
+        handle
+    }
+
+    // Makes `state` inspectable as `name` through `EngineHandle::query`, e.g.
+    // a `cache_latest_by_key` of book tops.
+    #[cfg(feature = "query")]
+    pub fn with_query<Q>(self, name: impl Into<String>, state: Q) -> Self
+    where
+        Q: Queryable,
+    {
+        self.queries.register(name.into(), Rc::new(state));
+        self
+    }
+
+    // Registers the schema of the named stream `name`: `build` fails unless
+    // every `Stream::produces` of it guarantees the schema and it guarantees
+    // what every `Stream::consumes` relies on. Without one, the first
+    // producer's schema stands in.
+    #[cfg(feature = "schema")]
+    pub fn with_schema(mut self, name: impl Into<String>, schema: Schema) -> Self {
+        self.schemas.insert(name.into(), schema);
+        self
+    }
+
+    // Topology changes made through `EngineHandle`s, plus their audit events.
+    pub fn events(&self) -> Stream<EngineEvent> {
+        self.events.to_stream()
+    }
+
+    // Errors reported by every pipeline added here or through a handle,
+    // tagged with the pipeline's name and stage.
+    pub fn pipeline_errors(&self) -> Stream<PipelineError> {
+        self.pipeline_errors.to_stream()
+    }
+
+    // Every signal shuts the engine down unless configured otherwise.
+    pub fn on_signal(mut self, signal: Signal, action: SignalAction) -> Self {
+        self.signal_actions.insert(signal, action);
+        self
+    }
+
+    // Signals configured with `SignalAction::Notify`.
+    pub fn signals(&self) -> Stream<Signal> {
+        self.signals.to_stream()
+    }
+
+    // Leaves signals to the host process, e.g. when embedded in Node; the
+    // engine then stops on cancellation or when its sources finish.
+    pub fn with_signal_handling(mut self, enabled: bool) -> Self {
+        self.signal_handling = enabled;
+        self
+    }
+
+    // Stops the engine when `token` is cancelled, e.g. from another task.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    // How long removed sources and a stopping engine wait for in-flight async
+    // work (e.g. `enrich` lookups) after intake stops.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    // Sources with a higher priority are polled first when several are ready,
+    // e.g. a control channel next to a replay firehose. The default is 0; it
+    // also applies to sources added later under the same label.
+    pub fn with_source_priority(mut self, label: impl Into<String>, priority: i32) -> Self {
+        self.source_priorities.insert(label.into(), priority);
+        self
+    }
+
+    // Publishes per-source totals and message/byte rates on `source_rates()`
+    // (and as metrics, if set) every `period`.
+    pub fn with_stats_interval(mut self, period: Duration) -> Self {
+        self.stats_interval = Some(period);
+        self
+    }
+
+    // Flushes sinks attached with `Stream::sink_to` every `period`; they are
+    // always flushed and closed on shutdown.
+    pub fn with_sink_flush_interval(mut self, period: Duration) -> Self {
+        self.sink_flush_interval = Some(period);
+        self
+    }
+
+    pub fn source_rates(&self) -> Stream<SourceRate> {
+        self.source_rates.to_stream()
+    }
+
+    // Warns whenever dispatching an item from a `Stream::named` stream takes
+    // longer than `budget`.
+    pub fn with_callback_budget(mut self, budget: Duration) -> Self {
+        self.callback_budget = Some(budget);
+        self
+    }
+
+    // Records per-stream callback timings for `Stream::named` streams and
+    // items dropped by bounded stages.
+    pub fn with_metrics(mut self, metrics: MetricsRegistry) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Events from bounded stages (broadcast bridges, axum subscribers, ...)
+    // running inside this engine.
+    pub fn backpressure(&self) -> Stream<Backpressure> {
+        self.backpressure.to_stream()
     }
 
     pub fn add_stream<T>(mut self, stream: Stream<T>) -> Self
@@ -75,20 +341,415 @@ impl EngineBuilder {
         self
     }
 
-    pub fn build(self) -> Engine {
+    pub fn add_timed_emitter(mut self, emitter: Rc<dyn TimedEmitter>) -> Self {
+        self.timed_emitters.push(emitter);
+        self
+    }
+
+    // Publishes the budget's breaker changes on `events()`.
+    pub fn with_reconnect_budget(self, budget: &ReconnectBudget) -> Self {
+        budget.publish_to(self.events.clone());
+        self
+    }
+
+    // Runs the source `factory` builds, and the pipeline segment on top of it
+    // that `factory` returns the end of, on a thread of its own; see
+    // `DedicatedThreadSource`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_source_on_dedicated_thread<T, S, F, Fut>(
+        &mut self,
+        label: impl Into<String>,
+        factory: F,
+    ) -> Stream<T>
+    where
+        T: Clone + Send + 'static,
+        S: EngineSource,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(S, Stream<T>)>> + 'static,
+    {
+        let label = label.into();
+        let source = Arc::new(DedicatedThreadSource::new(label.clone(), factory));
+        let stream = source.source().to_stream();
+        self.sources.push((label, source));
+        stream
+    }
+
+    // Runs `child` as part of this engine, e.g. one engine per venue: it is
+    // built with this one, stops when this one does and shows up as one
+    // source under `label`, with its sources' totals. Its events arrive here
+    // as `EngineEvent::Child`. Signals, callback budgets and backpressure
+    // events are handled by the outermost engine.
+    pub fn add_engine(mut self, label: impl Into<String>, child: EngineBuilder) -> Self {
+        let label = label.into();
+        let events = self.events.clone();
+        let engine = label.clone();
+        child.events().sink(move |event: &EngineEvent| {
+            events.emit(EngineEvent::Child {
+                engine: engine.clone(),
+                event: Box::new(event.clone()),
+            })
+        });
+        self.engines.push((label, child));
+        self
+    }
+
+    // Builds `pipeline` on `input` and registers everything it created,
+    // returning its outputs for further wiring.
+    pub fn add_pipeline<P>(&mut self, pipeline: &P, input: P::Input) -> P::Output
+    where
+        P: Pipeline,
+    {
+        let mut context =
+            PipelineContext::reporting_to(&pipeline.name(), self.pipeline_errors.clone());
+        let output = self.scope.building(|| pipeline.build(input, &mut context));
+        self.streams.extend(context.streams);
+        self.sources.extend(context.sources);
+        self.timed_emitters.extend(context.timed_emitters);
+        output
+    }
+
+    // Fails, listing every problem found, on setups that would otherwise do
+    // nothing at runtime: duplicate source labels, zero periods, timed
+    // buffers nothing flushes, registered streams nothing consumes,
+    // `subscribe_once` subscribers attached twice and named streams whose
+    // producers and consumers disagree on the schema. It also fails on a
+    // source registered twice, or already registered with another engine
+    // that has not been dropped, which would connect it twice.
+    pub fn build(self) -> Result<Engine> {
+        let mut problems = self.problems();
+        let mut seen: HashMap<*const (), String> = HashMap::new();
+        for (label, source) in self.registered_sources() {
+            if let Some(first) = seen.insert(Arc::as_ptr(source) as *const (), label.clone()) {
+                problems.push(format!(
+                    "source {:?} is the same source as {:?}; register it once",
+                    label, first
+                ));
+            } else if let Some(owner) = claim::owner(source) {
+                problems.push(format!(
+                    "source {:?} is already registered with another engine (as {:?})",
+                    label, owner.label
+                ));
+            }
+        }
+        // children's buffers are covered here
+        for (kind, period) in take_unregistered_buffers(&self.builder_ids()) {
+            problems.push(never_flushed(kind, period));
+        }
+        for (label, attached) in take_duplicate_subscriptions(&self.builder_ids()) {
+            problems.push(format!(
+                "subscriber {:?} is attached to the same stream {} times",
+                label, attached
+            ));
+        }
+        #[cfg(feature = "schema")]
+        problems.extend(schema::take_problems(&self.schemas, &self.builder_ids()));
+        if !problems.is_empty() {
+            return Err(Error::config(format!(
+                "invalid engine setup:\n  - {}",
+                problems.join("\n  - ")
+            )));
+        }
+        Ok(self.into_engine(None))
+    }
+
+    fn into_engine(mut self, nested: Option<String>) -> Engine {
+        self.scope.built();
+        let mut engines = Vec::new();
+        for (label, child) in mem::take(&mut self.engines) {
+            let engine = child.into_engine(Some(label.clone()));
+            let sources = engine.sources.clone();
+            let engine = Rc::new(RefCell::new(Some(engine)));
+            engines.push((label.clone(), engine.clone()));
+            self = self.add_source_owned(
+                label.clone(),
+                ChildEngine {
+                    label,
+                    engine,
+                    sources,
+                },
+            );
+        }
+        if let Some(metrics) = &self.metrics {
+            let metrics = metrics.clone();
+            self.backpressure
+                .to_stream()
+                .sink(move |event: &Backpressure| {
+                    let labels = [("stream", event.stream.as_str())];
+                    metrics
+                        .counter(
+                            "streamz_backpressure_dropped_total",
+                            "Items dropped by bounded stages.",
+                            &labels,
+                        )
+                        .add(event.dropped);
+                    metrics
+                        .gauge(
+                            "streamz_backpressure_depth",
+                            "Queue depth last reported by bounded stages.",
+                            &labels,
+                        )
+                        .set(event.depth as f64);
+                });
+        }
+        let buffers = Buffers::default();
+        let tracked = buffers.clone();
+        self.backpressure
+            .to_stream()
+            .sink(move |event: &Backpressure| {
+                let mut buffers = tracked.borrow_mut();
+                let entry = buffers
+                    .entry(event.stream.clone())
+                    .or_insert((0, 0, Instant::now()));
+                *entry = (event.depth, entry.1 + event.dropped, Instant::now());
+            });
+        let metrics = self.metrics.clone();
+        let claims = Claims::new();
+        for (label, source) in &self.sources {
+            claims.claim(label, source);
+        }
+        let builders = self.builder_ids();
+        if let Some(period) = self.sink_flush_interval {
+            self.timed_emitters.push(Rc::new(SinkFlusher {
+                period,
+                builders: builders.clone(),
+            }));
+        }
+        let profiler = (self.callback_budget.is_some() || self.metrics.is_some())
+            .then(|| Rc::new(CallbackProfiler::new(self.callback_budget, self.metrics)));
         Engine {
+            nested,
+            builders,
+            scope: self.scope,
+            claims,
+            engines,
             streams: self.streams,
-            sources: self.sources,
+            sources: Rc::new(RefCell::new(self.sources)),
+            live: LiveTasks::default(),
+            buffers,
             timed_emitters: self.timed_emitters,
+            profiler,
+            backpressure: self.backpressure,
+            signal_actions: self.signal_actions,
+            signals: self.signals,
+            signal_handling: self.signal_handling,
+            cancellation: self.cancellation,
+            drain_timeout: self.drain_timeout,
+            source_priorities: self.source_priorities,
+            stats_interval: self.stats_interval,
+            source_rates: self.source_rates,
+            metrics,
+            events: self.events,
+            commands: self.commands.1,
+        }
+    }
+
+    // This builder's and its children's, for the buffers they own.
+    fn builder_ids(&self) -> Vec<u64> {
+        let mut ids = vec![self.scope.id()];
+        for (_, child) in &self.engines {
+            ids.extend(child.builder_ids());
+        }
+        ids
+    }
+
+    // Every source of this engine and its children, children's labelled
+    // "child/source".
+    fn registered_sources(&self) -> Vec<(String, &Arc<dyn EngineSource>)> {
+        let mut sources: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(label, source)| (label.clone(), source))
+            .collect();
+        for (label, child) in &self.engines {
+            for (nested, source) in child.registered_sources() {
+                sources.push((format!("{}/{}", label, nested), source));
+            }
+        }
+        sources
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut labels = HashSet::new();
+        for (label, _) in &self.sources {
+            if label.is_empty() {
+                problems.push("source with an empty label".to_string());
+            } else if !labels.insert(label.as_str()) {
+                problems.push(format!("duplicate source label {:?}", label));
+            }
+        }
+        for (index, emitter) in self.timed_emitters.iter().enumerate() {
+            if emitter.period().is_zero() {
+                problems.push(format!("timed emitter #{} has a zero period", index + 1));
+            }
+        }
+        if self.stats_interval.is_some_and(|period| period.is_zero()) {
+            problems.push("stats interval must be non-zero".to_string());
+        }
+        for (index, stream) in self.streams.iter().enumerate() {
+            if !stream.has_sinks() {
+                problems.push(format!("registered stream #{} has no sinks", index + 1));
+            }
+        }
+        for (label, child) in &self.engines {
+            if labels.contains(label.as_str()) {
+                problems.push(format!("duplicate source label {:?}", label));
+            }
+            if !child.signal_actions.is_empty() {
+                problems.push(format!(
+                    "engine {:?}: signals are handled by the outermost engine",
+                    label
+                ));
+            }
+            if child.callback_budget.is_some() {
+                problems.push(format!(
+                    "engine {:?}: callback budgets apply to the outermost engine only",
+                    label
+                ));
+            }
+            for problem in child.problems() {
+                problems.push(format!("engine {:?}: {}", label, problem));
+            }
         }
+        problems
     }
 }
 
-#[cfg(feature = "websockets")]
+#[cfg(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32")))]
 impl EngineSource for WebSocketClient {
     fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
         Box::pin(async move { self.start().await })
     }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.decode_errors(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn connected(&self) -> Option<bool> {
+        Some(WebSocketClient::connected(self))
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32")))]
+impl<K> EngineSource for RedundantWebSocketClient<K>
+where
+    K: Hash + Eq + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.decode_errors(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+impl EngineSource for WebSocketPool {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.decode_errors(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    // Up while any connection is, or when there is nothing to subscribe to.
+    fn connected(&self) -> Option<bool> {
+        Some(self.connected() > 0 || self.channels().is_empty())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(all(
+    feature = "websockets",
+    feature = "requests",
+    not(target_arch = "wasm32")
+))]
+impl EngineSource for InstrumentUniverse {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.failures(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    // Down while polls fail and are backed off.
+    fn connected(&self) -> Option<bool> {
+        Some(self.healthy())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(feature = "books")]
+impl EngineSource for SyncedBookSource {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.resyncs(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
 }
 
 #[cfg(feature = "requests")]
@@ -96,6 +757,45 @@ impl EngineSource for PollingHttpClient {
     fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
         Box::pin(async move { self.start().await })
     }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(feature = "requests")]
+impl EngineSource for OneShotRequest {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
 }
 
 #[cfg(feature = "requests")]
@@ -106,61 +806,587 @@ where
     fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
         Box::pin(async move { self.start().await })
     }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
 }
 
-pub struct Engine {
-    #[allow(dead_code)]
-    streams: Vec<Box<dyn Any>>,
-    sources: Vec<(String, Arc<dyn EngineSource>)>,
-    timed_emitters: Vec<Rc<dyn TimedEmitter>>,
+#[cfg(all(feature = "ipc", unix))]
+impl<T> EngineSource for IpcPublisher<T>
+where
+    T: Serialize + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    // Counts what was published and, as errors, what lagging subscribers missed.
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.published(),
+            bytes: self.bytes_sent(),
+            errors: self.dropped(),
+        }
+    }
+
+    fn close(&self) {
+        IpcPublisher::close(self);
+    }
 }
 
-impl Engine {
-    pub async fn run(self) -> Result<()> {
-        if self.sources.is_empty() {
-            println!("No sources registered; waiting for Ctrl+C to exit.");
-            tokio::signal::ctrl_c().await?;
-            return Ok(());
+#[cfg(all(feature = "ipc", unix))]
+impl<T> EngineSource for IpcSubscriber<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.decode_errors(),
         }
+    }
 
-        let tasks = FuturesUnordered::new();
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
 
-        let mut timers: Vec<TimerEntry> = self
-            .timed_emitters
-            .iter()
-            .map(|emitter| TimerEntry {
-                period: emitter.period(),
-                next_tick: Instant::now() + emitter.period(),
-                emitter: emitter.clone(),
-            })
-            .collect();
+    fn close(&self) {
+        self.source().complete();
+        self.gaps().complete();
+    }
+}
 
-        for (label, source) in &self.sources {
-            let label_clone = label.clone();
-            let source_clone = Arc::clone(source);
-            tasks.push(async move { source_clone.run().await.map_err(|err| (label_clone, err)) });
+#[cfg(all(feature = "shm", unix))]
+impl<T> EngineSource for ShmSubscriber<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.decode_errors(),
         }
+    }
 
-        tokio::pin!(tasks);
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
 
-        loop {
-            let next_timer = timers.iter().map(|timer| timer.next_tick).min();
+    fn close(&self) {
+        self.source().complete();
+        self.gaps().complete();
+    }
+}
 
-            tokio::select! {
-                res = tasks.next() => {
-                    match res {
-                        Some(Ok(_)) => continue,
-                        Some(Err((label, err))) => return Err(anyhow!("{} source error: {}", label, err)),
-                        None => {
-                            println!("All sources completed.");
-                            return Ok(());
-                        }
-                    }
-                }
-                triggered = async {
-                    if let Some(instant) = next_timer {
-                        tokio::time::sleep_until(instant).await;
-                        true
+#[cfg(feature = "requests")]
+impl<T> EngineSource for JsonRpcBatchClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.errors().emitted(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+        self.errors().complete();
+    }
+}
+
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+impl EngineSource for OrderEntryClient {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.rejected(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn connected(&self) -> Option<bool> {
+        Some(OrderEntryClient::connected(self))
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<T> EngineSource for GraphQlPollingClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.errors().emitted(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+        self.errors().complete();
+    }
+}
+
+#[cfg(feature = "graphql")]
+impl<T> EngineSource for GraphQlSubscriptionClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.errors().emitted(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+        self.errors().complete();
+    }
+}
+
+#[cfg(feature = "replay")]
+impl EngineSource for ReplaySource {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.replayed(),
+            bytes: self.bytes_replayed(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.subscribers())
+    }
+
+    fn close(&self) {
+        self.complete();
+    }
+}
+
+#[cfg(feature = "kinesis")]
+impl EngineSource for KinesisSource {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            errors: self.throttled(),
+            bytes: self.bytes_received(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(all(feature = "eventhubs", not(target_arch = "wasm32")))]
+impl EngineSource for EventHubsSource {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        if let Err(err) = self.release() {
+            log::warn(
+                "eventhubs",
+                format_args!("event hubs source could not save its checkpoints: {}", err),
+            );
+        }
+        self.source().complete();
+    }
+}
+
+#[cfg(feature = "gcp")]
+impl EngineSource for PubSubSource {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(feature = "axum")]
+impl EngineSource for HttpServer {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+}
+
+#[cfg(feature = "axum")]
+impl EngineSource for AdminServer {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+}
+
+#[cfg(all(feature = "axum", feature = "query"))]
+impl EngineSource for QueryServer {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+}
+
+impl<T> EngineSource for BroadcastSource<T>
+where
+    T: Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+impl<T> EngineSource for IterSource<T>
+where
+    T: 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+impl<T> EngineSource for WatchSource<T>
+where
+    T: Clone + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            ..SourceStats::default()
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        self.source().complete();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> EngineSource for DedicatedThreadSource<T>
+where
+    T: Clone + Send + 'static,
+{
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        Box::pin(async move { self.start().await })
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            messages: self.source().emitted(),
+            bytes: self.bytes_received(),
+            errors: self.dropped(),
+        }
+    }
+
+    fn subscribers(&self) -> Option<usize> {
+        Some(self.source().subscribers())
+    }
+
+    fn close(&self) {
+        DedicatedThreadSource::close(self);
+    }
+}
+
+pub struct Engine {
+    // the label of a child engine
+    nested: Option<String>,
+    // of the builder it came from, see `BuilderScope`
+    builders: Vec<u64>,
+    #[allow(dead_code)]
+    scope: BuilderScope,
+    claims: Claims,
+    // taken by their `ChildEngine` source when it starts
+    engines: Vec<(String, Rc<RefCell<Option<Engine>>>)>,
+    #[allow(dead_code)]
+    streams: Vec<Box<dyn RetainedStream>>,
+    sources: SharedSources,
+    live: LiveTasks,
+    buffers: Buffers,
+    timed_emitters: Vec<Rc<dyn TimedEmitter>>,
+    profiler: Option<Rc<CallbackProfiler>>,
+    backpressure: Rc<Source<Backpressure>>,
+    signal_actions: HashMap<Signal, SignalAction>,
+    signals: Rc<Source<Signal>>,
+    signal_handling: bool,
+    cancellation: Option<CancellationToken>,
+    drain_timeout: Duration,
+    source_priorities: HashMap<String, i32>,
+    stats_interval: Option<Duration>,
+    source_rates: Rc<Source<SourceRate>>,
+    metrics: Option<MetricsRegistry>,
+    events: Rc<Source<EngineEvent>>,
+    commands: UnboundedReceiver<EngineCommand>,
+}
+
+impl Engine {
+    // Connects nothing: lays out what `run` would start and fails, listing
+    // every problem, if a source has nothing subscribed to it or a timed
+    // buffer was never registered. Call it once the pipelines are wired.
+    pub fn validate(&self) -> Result<EnginePlan> {
+        let plan = self.plan();
+        let mut problems = plan_problems(&plan);
+        for (kind, period) in unregistered_buffers(&self.builders) {
+            problems.push(never_flushed(kind, period));
+        }
+        if !problems.is_empty() {
+            return Err(Error::config(format!(
+                "invalid engine setup:\n  - {}",
+                problems.join("\n  - ")
+            )));
+        }
+        Ok(plan)
+    }
+
+    fn plan(&self) -> EnginePlan {
+        let engines: Vec<(String, EnginePlan)> = self
+            .engines
+            .iter()
+            .filter_map(|(label, engine)| {
+                let plan = engine.borrow().as_ref().map(Engine::plan)?;
+                Some((label.clone(), plan))
+            })
+            .collect();
+        let sources = self
+            .sources
+            .borrow()
+            .iter()
+            .filter(|(label, _)| !self.engines.iter().any(|(engine, _)| engine == label))
+            .map(|(label, source)| PlannedSource {
+                label: label.clone(),
+                priority: self.priority(label),
+                subscribers: source.subscribers(),
+            })
+            .collect();
+        let mut timers: Vec<PlannedTimer> = self
+            .timed_emitters
+            .iter()
+            .map(|emitter| PlannedTimer {
+                name: emitter.name(),
+                period: emitter.period(),
+                priority: emitter.priority(),
+            })
+            .collect();
+        // the engine flushes higher priorities first
+        timers.sort_by_key(|timer| Reverse(timer.priority));
+        EnginePlan {
+            sources,
+            timers,
+            streams: self.streams.len(),
+            engines,
+        }
+    }
+
+    pub async fn run(self) -> Result<RunReport> {
+        // operators such as `enrich` spawn local tasks from within callbacks
+        rt::run_local(self.run_local()).await
+    }
+
+    async fn run_local(mut self) -> Result<RunReport> {
+        // a child engine shares its parent's hubs and signal handling
+        let nested = self.nested.is_some();
+        let _profiler = (!nested).then(|| profile::install(self.profiler.clone()));
+        let _backpressure = (!nested).then(|| backpressure::install(self.backpressure.clone()));
+        let started = Instant::now();
+        let mut report = RunReport::default();
+        let mut signals = if nested || !self.signal_handling {
+            None
+        } else {
+            Some(Signals::new()?)
+        };
+
+        // sources can still be added through an `EngineHandle`
+        if self.sources.borrow().is_empty() && !nested {
+            log::info(
+                "engine",
+                format_args!("No sources registered; waiting for Ctrl+C to exit."),
+            );
+        }
+
+        let mut tasks = SourceTasks::default();
+        let mut aborts: HashMap<String, Vec<AbortHandle>> = HashMap::new();
+        let mut draining = FuturesUnordered::new();
+        let mut states = SourceStates::new(started);
+        let mut failure = None;
+
+        let mut timers: Vec<TimerEntry> = Vec::new();
+        for emitter in &self.timed_emitters {
+            TimerEntry::insert(&mut timers, emitter.clone());
+        }
+        let mut sampler = self.stats_interval.map(|period| {
+            StatsSampler::new(period, self.source_rates.clone(), self.metrics.clone())
+        });
+
+        let initial = self.sources.borrow().clone();
+        for (label, source) in &initial {
+            let (task, abort) = self.source_task(label, source);
+            tasks.push(self.priority(label), task);
+            aborts.entry(label.clone()).or_default().push(abort);
+        }
+
+        loop {
+            let next_timer = timers
+                .iter()
+                .map(|timer| timer.next_tick)
+                .chain(sampler.as_ref().map(|sampler| sampler.next_tick))
+                .min();
+
+            tokio::select! {
+                res = tasks.next(), if !tasks.is_empty() => {
+                    match res {
+                        Some(Err((label, err))) => {
+                            failure = Some(err.with_source(&label));
+                            break;
+                        }
+                        _ if tasks.is_empty() && draining.is_empty() && states.paused.is_empty() => {
+                            self.log("All sources completed.");
+                            break;
+                        }
+                        _ => continue,
+                    }
+                }
+                triggered = async {
+                    if let Some(instant) = next_timer {
+                        rt::sleep_until(instant).await;
+                        true
                     } else {
                         pending::<()>().await;
                         false
@@ -168,27 +1394,498 @@ impl Engine {
                 } => {
                     if triggered {
                         let now = Instant::now();
+                        // highest priority first
                         for timer in timers.iter_mut() {
                             if now >= timer.next_tick {
+                                let started = Instant::now();
                                 timer.emitter.flush();
+                                let took = started.elapsed();
+                                report.timer_flushes += 1;
+                                report.timers.entry(timer.name.clone()).or_default().record(took);
+                                if let Some(metrics) = &self.metrics {
+                                    metrics
+                                        .gauge(
+                                            "streamz_timer_flush_seconds",
+                                            "Duration of the last flush per timed emitter.",
+                                            &[("timer", &timer.name)],
+                                        )
+                                        .set(took.as_secs_f64());
+                                }
+                                timer.next_tick += timer.period;
                                 while timer.next_tick <= now {
                                     timer.next_tick += timer.period;
+                                    report.timer_overruns += 1;
                                 }
                             }
                         }
+                        if let Some(sampler) = sampler.as_mut().filter(|sampler| now >= sampler.next_tick) {
+                            sampler.sample(&self.sources.borrow());
+                        }
                     }
                 }
-                _ = tokio::signal::ctrl_c() => {
-                    println!("\nReceived interrupt. Shutting down engine...");
-                    return Ok(());
+                Some(command) = self.commands.recv() => match command {
+                    EngineCommand::AddSource(label, source) => match self.already_registered(&source, &states) {
+                        Some(owner) => self.log(&format!("Source {} not added: it is already registered as {}.", label, owner)),
+                        None => {
+                            self.claims.claim(&label, &source);
+                            let (task, abort) = self.source_task(&label, &source);
+                            tasks.push(self.priority(&label), task);
+                            aborts.entry(label.clone()).or_default().push(abort);
+                            states.removed.remove(&label);
+                            states.retired.retain(|retired| !Arc::ptr_eq(retired, &source));
+                            // a removed source added again is listed once
+                            let mut sources = self.sources.borrow_mut();
+                            sources.retain(|(_, listed)| !Arc::ptr_eq(listed, &source));
+                            sources.push((label.clone(), source));
+                            drop(sources);
+                            self.events.emit(EngineEvent::SourceAdded(label));
+                        }
+                    },
+                    EngineCommand::RemoveSource(label) => {
+                        if let Some(handles) = aborts.remove(&label) {
+                            handles.iter().for_each(AbortHandle::abort);
+                            states.paused.remove(&label);
+                            states.removed.insert(label.clone());
+                            states.retired.extend(
+                                self.sources
+                                    .borrow()
+                                    .iter()
+                                    .filter(|(source_label, _)| *source_label == label)
+                                    .map(|(_, source)| source.clone()),
+                            );
+                            self.close_sources(|source_label| source_label == label);
+                            let timeout = self.drain_timeout;
+                            draining.push(async move { (label, drain::settled(timeout).await) });
+                        }
+                    }
+                    EngineCommand::PauseSource(label) => {
+                        if self.is_engine(&label) {
+                            self.log(&format!("Engine {} cannot be paused.", label));
+                        } else if let Some(handles) = aborts.get_mut(&label) {
+                            if states.paused.insert(label.clone()) {
+                                handles.drain(..).for_each(|handle| handle.abort());
+                                self.events.emit(EngineEvent::SourcePaused(label));
+                            }
+                        }
+                    }
+                    EngineCommand::ResumeSource(label) => {
+                        if states.paused.remove(&label) {
+                            self.start_sources(&label, &states, &mut tasks, &mut aborts);
+                            self.events.emit(EngineEvent::SourceResumed(label));
+                        }
+                    }
+                    EngineCommand::RestartSource(label) => {
+                        if self.is_engine(&label) {
+                            self.log(&format!("Engine {} cannot be restarted.", label));
+                        } else if let Some(handles) = aborts.get_mut(&label) {
+                            handles.drain(..).for_each(|handle| handle.abort());
+                            states.paused.remove(&label);
+                            self.start_sources(&label, &states, &mut tasks, &mut aborts);
+                            self.events.emit(EngineEvent::SourceRestarted(label));
+                        }
+                    }
+                    EngineCommand::Status(reply) => {
+                        let _ = reply.send(self.status(&mut states));
+                    }
+                    EngineCommand::AddTimedEmitter(emitter) => TimerEntry::insert(&mut timers, emitter),
+                    EngineCommand::AddStream(stream) => self.streams.push(stream),
+                },
+                Some((label, drained)) = draining.next(), if !draining.is_empty() => {
+                    if !drained {
+                        self.log(&format!("Source {} removed before in-flight work finished.", label));
+                    }
+                    self.events.emit(EngineEvent::SourceRemoved(label));
+                    if tasks.is_empty() && draining.is_empty() && states.paused.is_empty() {
+                        self.log("All sources completed.");
+                        break;
+                    }
+                }
+                reason = shutdown_requested(
+                    &mut signals,
+                    &self.signal_actions,
+                    &self.signals,
+                    self.cancellation.as_ref(),
+                ) => {
+                    self.log(&reason);
+                    break;
+                }
+            }
+        }
+
+        drop(tasks);
+        self.close_sources(|_| true);
+        // in-flight work and sinks are shared, so the outermost engine waits
+        // for and closes them
+        let settled = nested || drain::settled(self.drain_timeout).await;
+        if !nested {
+            sink::close_all(&self.builders);
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+
+        report.runtime = started.elapsed();
+        for (label, source) in self.sources.borrow().iter() {
+            *report.sources.entry(label.clone()).or_default() += source.stats();
+        }
+        if !settled {
+            return Err(Error::ShutdownTimeout {
+                waited: self.drain_timeout,
+                report: Box::new(report),
+            });
+        }
+        Ok(report)
+    }
+
+    // A removed source's task finishes cleanly.
+    fn source_task(
+        &self,
+        label: &str,
+        source: &Arc<dyn EngineSource>,
+    ) -> (SourceTask, AbortHandle) {
+        let error_label = label.to_string();
+        let source = Arc::clone(source);
+        let live = Live::start(label, &self.live);
+        let (task, abort) = abortable(async move {
+            let _live = live;
+            source.run().await.map_err(|err| (error_label, err))
+        });
+        let task: SourceTask = Box::pin(async move { task.await.unwrap_or(Ok(())) });
+        match &self.metrics {
+            Some(metrics) => (Box::pin(PollDelay::new(task, label, metrics)), abort),
+            None => (task, abort),
+        }
+    }
+
+    // (Re)starts every source registered under `label`, except removed ones
+    // a new source has replaced.
+    fn start_sources(
+        &self,
+        label: &str,
+        states: &SourceStates,
+        tasks: &mut SourceTasks,
+        aborts: &mut HashMap<String, Vec<AbortHandle>>,
+    ) {
+        let sources = self.sources.borrow().clone();
+        for (_, source) in sources.iter().filter(|(source_label, source)| {
+            source_label == label
+                && !states
+                    .retired
+                    .iter()
+                    .any(|retired| Arc::ptr_eq(retired, source))
+        }) {
+            let (task, abort) = self.source_task(label, source);
+            tasks.push(self.priority(label), task);
+            aborts.entry(label.to_string()).or_default().push(abort);
+        }
+    }
+
+    fn is_engine(&self, label: &str) -> bool {
+        self.engines.iter().any(|(engine, _)| engine == label)
+    }
+
+    fn status(&self, states: &mut SourceStates) -> EngineStatus {
+        let now = Instant::now();
+        let live = self.live.borrow();
+        let mut sources: Vec<SourceStatus> = Vec::new();
+        for (label, source) in self.sources.borrow().iter() {
+            let stats = source.stats();
+            let connected = source.connected();
+            if let Some(status) = sources.iter_mut().find(|status| status.label == *label) {
+                status.stats += stats;
+                status.connected = match (status.connected, connected) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    _ => None,
+                };
+                continue;
+            }
+            let state = if states.removed.contains(label) {
+                SourceState::Removed
+            } else if states.paused.contains(label) {
+                SourceState::Paused
+            } else if live.get(label).is_some_and(|count| *count > 0) {
+                SourceState::Running
+            } else {
+                SourceState::Finished
+            };
+            sources.push(SourceStatus {
+                label: label.clone(),
+                state,
+                stats,
+                connected,
+                idle: Duration::ZERO,
+            });
+        }
+        for status in &mut sources {
+            let (messages, since) = states
+                .activity
+                .entry(status.label.clone())
+                .or_insert((0, states.started));
+            if status.stats.messages > *messages {
+                *messages = status.stats.messages;
+                *since = now;
+            }
+            status.idle = now - *since;
+        }
+        let buffers = self
+            .buffers
+            .borrow()
+            .iter()
+            .map(|(stream, (depth, dropped, at))| BufferStatus {
+                stream: stream.clone(),
+                depth: *depth,
+                dropped: *dropped,
+                age: now - *at,
+            })
+            .collect();
+        EngineStatus {
+            plan: self.plan(),
+            sources,
+            buffers,
+        }
+    }
+
+    fn priority(&self, label: &str) -> i32 {
+        self.source_priorities
+            .get(label)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn close_sources(&self, matches: impl Fn(&str) -> bool) {
+        close_sources(&self.sources, matches);
+    }
+
+    // Where `source` is registered, unless it is free or a source this
+    // engine removed.
+    fn already_registered(
+        &self,
+        source: &Arc<dyn EngineSource>,
+        states: &SourceStates,
+    ) -> Option<String> {
+        let owner = claim::owner(source)?;
+        if owner.engine != self.claims.engine() {
+            Some(format!("{} with another engine", owner.label))
+        } else if !states.removed.contains(&owner.label) {
+            Some(owner.label)
+        } else {
+            None
+        }
+    }
+
+    fn log(&self, message: &str) {
+        match &self.nested {
+            Some(label) => log::info(
+                "engine",
+                format_args!("[{}] {}", label, message.trim_start()),
+            ),
+            None => log::info("engine", format_args!("{}", message)),
+        }
+    }
+}
+
+fn never_flushed(kind: &str, period: Duration) -> String {
+    match kind {
+        "timed buffer" => format!(
+            "timed buffer with period {:?} is never flushed (register it with add_timed_buffer)",
+            period
+        ),
+        _ => format!(
+            "{} with period {:?} is never flushed (register its as_timed_emitter() with add_timed_emitter)",
+            kind, period
+        ),
+    }
+}
+
+fn plan_problems(plan: &EnginePlan) -> Vec<String> {
+    let mut problems: Vec<String> = plan
+        .sources
+        .iter()
+        .filter(|source| source.subscribers == Some(0))
+        .map(|source| format!("source {:?} has no subscribers", source.label))
+        .collect();
+    for (label, child) in &plan.engines {
+        for problem in plan_problems(child) {
+            problems.push(format!("engine {:?}: {}", label, problem));
+        }
+    }
+    problems
+}
+
+// Closing a source may complete streams whose callbacks touch the engine, so
+// the list is not borrowed meanwhile.
+fn close_sources(sources: &SharedSources, matches: impl Fn(&str) -> bool) {
+    let sources = sources.borrow().clone();
+    for (label, source) in &sources {
+        if matches(label) {
+            source.close();
+        }
+    }
+}
+
+// What the engine loop knows about its sources besides their tasks.
+struct SourceStates {
+    // paused labels keep an empty entry in `aborts`
+    paused: HashSet<String>,
+    removed: HashSet<String>,
+    // removed sources stay listed for the report but are never run again
+    retired: Vec<Arc<dyn EngineSource>>,
+    // per label, the message count last seen by `status` and since when
+    activity: HashMap<String, (u64, Instant)>,
+    started: Instant,
+}
+
+impl SourceStates {
+    fn new(started: Instant) -> Self {
+        Self {
+            paused: HashSet::new(),
+            removed: HashSet::new(),
+            retired: Vec::new(),
+            activity: HashMap::new(),
+            started,
+        }
+    }
+}
+
+// Counts a source task as live until it returns or is aborted.
+struct Live {
+    label: String,
+    live: LiveTasks,
+}
+
+impl Live {
+    fn start(label: &str, live: &LiveTasks) -> Self {
+        *live.borrow_mut().entry(label.to_string()).or_default() += 1;
+        Self {
+            label: label.to_string(),
+            live: live.clone(),
+        }
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        if let Some(count) = self.live.borrow_mut().get_mut(&self.label) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+// A child engine registered with `EngineBuilder::add_engine`. Stopping the
+// parent drops its loop, so closing it closes the child's sources directly.
+struct ChildEngine {
+    label: String,
+    engine: Rc<RefCell<Option<Engine>>>,
+    sources: SharedSources,
+}
+
+impl EngineSource for ChildEngine {
+    fn run<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+        let engine = self.engine.borrow_mut().take();
+        Box::pin(async move {
+            let Some(engine) = engine else {
+                return Err(Error::AlreadyStarted {
+                    source: self.label.clone(),
+                });
+            };
+            engine.run_local().await.map(|_| ()).map_err(|err| {
+                match err
+                    .source_label()
+                    .map(|inner| format!("{}/{}", self.label, inner))
+                {
+                    Some(label) => err.with_source(&label),
+                    None => err,
+                }
+            })
+        })
+    }
+
+    fn stats(&self) -> SourceStats {
+        let mut stats = SourceStats::default();
+        for (_, source) in self.sources.borrow().iter() {
+            stats += source.stats();
+        }
+        stats
+    }
+
+    fn close(&self) {
+        close_sources(&self.sources, |_| true);
+    }
+}
+
+// Resolves once a signal configured to shut down arrives or the engine is
+// cancelled, describing why.
+async fn shutdown_requested(
+    signals: &mut Option<Signals>,
+    actions: &HashMap<Signal, SignalAction>,
+    notify: &Source<Signal>,
+    cancellation: Option<&CancellationToken>,
+) -> String {
+    let cancelled = async {
+        match cancellation {
+            Some(token) => token.cancelled().await,
+            None => pending::<()>().await,
+        }
+    };
+    tokio::pin!(cancelled);
+
+    loop {
+        tokio::select! {
+            signal = async {
+                match signals.as_mut() {
+                    Some(signals) => signals.recv().await,
+                    None => pending().await,
+                }
+            } => {
+                let action = actions
+                    .get(&signal)
+                    .copied()
+                    .unwrap_or(SignalAction::Shutdown);
+                match action {
+                    SignalAction::Shutdown => {
+                        return format!("\nReceived {}. Shutting down engine...", signal);
+                    }
+                    SignalAction::Notify => notify.emit(signal),
+                    SignalAction::Ignore => {}
                 }
             }
+            _ = &mut cancelled => return "Engine cancelled. Shutting down...".to_string(),
         }
     }
 }
 
 struct TimerEntry {
+    name: String,
+    priority: i32,
     period: Duration,
     next_tick: Instant,
     emitter: Rc<dyn TimedEmitter>,
 }
+
+impl TimerEntry {
+    // Keeps `timers` in flush order: by descending priority, then in the
+    // order they were added. Emitters with a zero period, which would tick
+    // forever, are dropped.
+    fn insert(timers: &mut Vec<TimerEntry>, emitter: Rc<dyn TimedEmitter>) {
+        if emitter.period().is_zero() {
+            log::warn(
+                "engine",
+                format_args!(
+                    "timed emitter {:?} has a zero period and is never flushed",
+                    emitter.name()
+                ),
+            );
+            return;
+        }
+        let entry = Self {
+            name: emitter.name(),
+            priority: emitter.priority(),
+            period: emitter.period(),
+            next_tick: Instant::now() + emitter.period(),
+            emitter,
+        };
+        let index = timers.partition_point(|timer| timer.priority >= entry.priority);
+        timers.insert(index, entry);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::drain::InFlight;
+use crate::rt::{self, Instant};
+use crate::{Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+pub enum LookupResult<V> {
+    Hit(V),
+    Miss,
+    Pending(Pin<Box<dyn Future<Output = Option<V>>>>),
+}
+
+pub trait Lookup<T>: 'static {
+    type Value: 'static;
+
+    fn lookup(&self, item: &T) -> LookupResult<Self::Value>;
+}
+
+pub struct HashMapLookup<K, V, F> {
+    table: Rc<RefCell<HashMap<K, V>>>,
+    key_fn: F,
+}
+
+impl<K, V, F> HashMapLookup<K, V, F>
+where
+    K: Eq + Hash,
+{
+    pub fn new(table: HashMap<K, V>, key_fn: F) -> Self {
+        Self {
+            table: Rc::new(RefCell::new(table)),
+            key_fn,
+        }
+    }
+
+    // shared handle so reference data can be updated while the pipeline runs
+    pub fn table(&self) -> Rc<RefCell<HashMap<K, V>>> {
+        self.table.clone()
+    }
+}
+
+impl<T, K, V, F> Lookup<T> for HashMapLookup<K, V, F>
+where
+    K: Eq + Hash + 'static,
+    V: Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+{
+    type Value = V;
+
+    fn lookup(&self, item: &T) -> LookupResult<V> {
+        match self.table.borrow().get(&(self.key_fn)(item)) {
+            Some(value) => LookupResult::Hit(value.clone()),
+            None => LookupResult::Miss,
+        }
+    }
+}
+
+pub struct CachedLookup<K, V, KF, F> {
+    key_fn: KF,
+    fetch: Rc<F>,
+    ttl: Duration,
+    cache: Rc<RefCell<HashMap<K, (V, Instant)>>>,
+    // keys being fetched, with the lookups waiting on each fetch
+    pending: Fetches<K, V>,
+}
+
+type Fetches<K, V> = Rc<RefCell<HashMap<K, Vec<oneshot::Sender<Option<V>>>>>>;
+
+// Ends a fetch: its waiters get the value, or a miss if it never finished.
+struct FetchGuard<K, V>
+where
+    K: Eq + Hash,
+{
+    key: Option<K>,
+    pending: Fetches<K, V>,
+}
+
+impl<K, V> FetchGuard<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    fn finish(mut self, value: &Option<V>) {
+        let waiters = self
+            .key
+            .take()
+            .and_then(|key| self.pending.borrow_mut().remove(&key));
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(value.clone());
+        }
+    }
+}
+
+impl<K, V> Drop for FetchGuard<K, V>
+where
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.pending.borrow_mut().remove(&key);
+        }
+    }
+}
+
+impl<K, V, KF, F> CachedLookup<K, V, KF, F>
+where
+    K: Eq + Hash,
+{
+    pub fn new(key_fn: KF, fetch: F, ttl: Duration) -> Self {
+        Self {
+            key_fn,
+            fetch: Rc::new(fetch),
+            ttl,
+            cache: Rc::new(RefCell::new(HashMap::new())),
+            pending: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub fn invalidate(&self, key: &K) {
+        self.cache.borrow_mut().remove(key);
+    }
+}
+
+impl<T, K, V, KF, F, Fut> Lookup<T> for CachedLookup<K, V, KF, F>
+where
+    K: Eq + Hash + Clone + 'static,
+    V: Clone + 'static,
+    KF: Fn(&T) -> K + 'static,
+    F: Fn(K) -> Fut + 'static,
+    Fut: Future<Output = Option<V>> + 'static,
+{
+    type Value = V;
+
+    fn lookup(&self, item: &T) -> LookupResult<V> {
+        let key = (self.key_fn)(item);
+        if let Some((value, fetched_at)) = self.cache.borrow().get(&key) {
+            if fetched_at.elapsed() < self.ttl {
+                return LookupResult::Hit(value.clone());
+            }
+        }
+
+        // concurrent misses on a key share one fetch
+        if let Some(waiters) = self.pending.borrow_mut().get_mut(&key) {
+            let (waiter, value) = oneshot::channel();
+            waiters.push(waiter);
+            return LookupResult::Pending(Box::pin(async move { value.await.ok().flatten() }));
+        }
+        self.pending.borrow_mut().insert(key.clone(), Vec::new());
+        let guard = FetchGuard {
+            key: Some(key.clone()),
+            pending: self.pending.clone(),
+        };
+        let fetch = self.fetch.clone();
+        let cache = self.cache.clone();
+        LookupResult::Pending(Box::pin(async move {
+            let value = fetch(key.clone()).await;
+            if let Some(value) = &value {
+                cache
+                    .borrow_mut()
+                    .insert(key, (value.clone(), Instant::now()));
+            }
+            guard.finish(&value);
+            value
+        }))
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Pending lookups are resolved on the engine's local task set, so their
+    // results may be emitted after items that arrived later. Both outputs
+    // complete once the input has completed and every lookup has resolved.
+    pub fn enrich<L>(&self, lookup: L) -> (Stream<(T, L::Value)>, Stream<T>)
+    where
+        L: Lookup<T>,
+    {
+        let hits = Rc::new(Source::new());
+        let misses = Rc::new(Source::new());
+        let streams = (hits.to_stream(), misses.to_stream());
+        let in_flight = Rc::new(Cell::new(0usize));
+        let input_done = Rc::new(Cell::new(false));
+
+        let finish = {
+            let input_done = input_done.clone();
+            let in_flight = in_flight.clone();
+            let hits = hits.clone();
+            let misses = misses.clone();
+            Rc::new(move || {
+                if input_done.get() && in_flight.get() == 0 {
+                    hits.complete();
+                    misses.complete();
+                }
+            })
+        };
+        let finish_clone = finish.clone();
+        self.on_complete(move || {
+            input_done.set(true);
+            finish_clone();
+        });
+
+        self.sink(move |item: &T| match lookup.lookup(item) {
+            LookupResult::Hit(value) => hits.emit((item.clone(), value)),
+            LookupResult::Miss => misses.emit(item.clone()),
+            LookupResult::Pending(pending) => {
+                let item = item.clone();
+                let hits = hits.clone();
+                let misses = misses.clone();
+                let in_flight = in_flight.clone();
+                let finish = finish.clone();
+                in_flight.set(in_flight.get() + 1);
+                let tracked = InFlight::start();
+                rt::spawn_local(async move {
+                    let _tracked = tracked;
+                    match pending.await {
+                        Some(value) => hits.emit((item, value)),
+                        None => misses.emit(item),
+                    }
+                    in_flight.set(in_flight.get() - 1);
+                    finish();
+                });
+            }
+        });
+
+        streams
+    }
+}
@@ -0,0 +1,185 @@
+use crate::RunReport;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Errors raised by sources and the engine. `source` is the label the source
+// was registered under (or, outside an engine, what it connects to), so
+// callers can decide per source and per kind, e.g. to reconnect only on
+// `Connect`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    // The remote end could not be reached or refused the handshake.
+    Connect {
+        source: String,
+        error: BoxError,
+    },
+    // The remote end answered, but not as the protocol allows: error
+    // statuses, rejected requests, unexpected frames.
+    Protocol {
+        source: String,
+        message: String,
+    },
+    // A payload could not be decoded.
+    Decode {
+        source: String,
+        error: BoxError,
+    },
+    // A local file, socket or mapping failed.
+    Io {
+        source: String,
+        error: io::Error,
+    },
+    // The upstream started over, so its sequence numbers did too.
+    SourceRestarted {
+        source: String,
+    },
+    // A one-shot source was started a second time, or a client while it
+    // is still running.
+    AlreadyStarted {
+        source: String,
+    },
+    // The engine stopped before in-flight work finished; the report covers
+    // everything up to that point.
+    ShutdownTimeout {
+        waited: Duration,
+        report: Box<RunReport>,
+    },
+    // A `filter_expr` / `map_expr` expression, or a script, did not parse.
+    InvalidExpression {
+        expression: String,
+        message: String,
+    },
+    // A pipeline config or an `EngineBuilder` setup is invalid, or a config
+    // could not be loaded.
+    Config {
+        message: String,
+    },
+    // Anything else, e.g. from a custom `EngineSource`.
+    Other {
+        source: String,
+        error: BoxError,
+    },
+}
+
+impl Error {
+    pub fn connect(source: impl Into<String>, error: impl Into<BoxError>) -> Self {
+        Error::Connect {
+            source: source.into(),
+            error: error.into(),
+        }
+    }
+
+    pub fn protocol(source: impl Into<String>, message: impl Into<String>) -> Self {
+        Error::Protocol {
+            source: source.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn decode(source: impl Into<String>, error: impl Into<BoxError>) -> Self {
+        Error::Decode {
+            source: source.into(),
+            error: error.into(),
+        }
+    }
+
+    pub fn io(source: impl Into<String>, error: io::Error) -> Self {
+        Error::Io {
+            source: source.into(),
+            error,
+        }
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Error::Config {
+            message: message.into(),
+        }
+    }
+
+    pub fn other(source: impl Into<String>, error: impl Into<BoxError>) -> Self {
+        Error::Other {
+            source: source.into(),
+            error: error.into(),
+        }
+    }
+
+    // The source the error came from; `None` for engine-wide errors.
+    pub fn source_label(&self) -> Option<&str> {
+        match self {
+            Error::Connect { source, .. }
+            | Error::Protocol { source, .. }
+            | Error::Decode { source, .. }
+            | Error::Io { source, .. }
+            | Error::SourceRestarted { source }
+            | Error::AlreadyStarted { source }
+            | Error::Other { source, .. } => Some(source),
+            Error::ShutdownTimeout { .. }
+            | Error::InvalidExpression { .. }
+            | Error::Config { .. } => None,
+        }
+    }
+
+    // The engine relabels source errors with the label they were registered
+    // under; errors from child engines keep their "child/source" label.
+    pub(crate) fn with_source(mut self, label: &str) -> Self {
+        let nested = format!("{}/", label);
+        match &mut self {
+            Error::Connect { source, .. }
+            | Error::Protocol { source, .. }
+            | Error::Decode { source, .. }
+            | Error::Io { source, .. }
+            | Error::SourceRestarted { source }
+            | Error::AlreadyStarted { source }
+            | Error::Other { source, .. } => {
+                if !source.starts_with(&nested) {
+                    *source = label.to_string();
+                }
+            }
+            Error::ShutdownTimeout { .. }
+            | Error::InvalidExpression { .. }
+            | Error::Config { .. } => {}
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect { source, error } => write!(f, "{}: connect failed: {}", source, error),
+            Error::Protocol { source, message } => {
+                write!(f, "{}: protocol error: {}", source, message)
+            }
+            Error::Decode { source, error } => write!(f, "{}: decode failed: {}", source, error),
+            Error::Io { source, error } => write!(f, "{}: {}", source, error),
+            Error::SourceRestarted { source } => write!(f, "{}: upstream restarted", source),
+            Error::AlreadyStarted { source } => write!(f, "{}: already started", source),
+            Error::ShutdownTimeout { waited, .. } => {
+                write!(f, "in-flight work abandoned after waiting {:?}", waited)
+            }
+            Error::InvalidExpression {
+                expression,
+                message,
+            } => write!(f, "invalid expression {:?}: {}", expression, message),
+            Error::Config { message } => f.write_str(message),
+            Error::Other { source, error } => write!(f, "{}: {}", source, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect { error, .. }
+            | Error::Decode { error, .. }
+            | Error::Other { error, .. } => Some(error.as_ref()),
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use crate::{Error, Result, Stream};
+use regex::Regex;
+use serde_json::{Number, Value};
+use std::cmp::Ordering;
+use std::fmt;
+
+// A small expression language for filtering and projecting JSON values at
+// runtime, e.g. `params.data.price > 50000 && channel =~ "trades"`.
+//
+// Supported syntax: dotted paths (array elements by index, `data.0.price`),
+// number/string/`true`/`false`/`null` literals, `+ - * /`, comparisons
+// (`== != < <= > >=`), regex matching with `=~`, `&& || !` and parentheses.
+// Paths that don't resolve evaluate to `null`.
+#[derive(Clone)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self> {
+        let root = parse(source).map_err(|message| Error::InvalidExpression {
+            expression: source.to_string(),
+            message,
+        })?;
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, value: &Value) -> Value {
+        self.root.eval(value)
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        truthy(&self.eval(value))
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Expr").field(&self.source).finish()
+    }
+}
+
+impl Stream<Value> {
+    pub fn filter_expr(&self, expression: &str) -> Result<Stream<Value>> {
+        let expr = Expr::parse(expression)?;
+        Ok(self.filter(move |value| expr.matches(value)))
+    }
+
+    pub fn map_expr(&self, expression: &str) -> Result<Stream<Value>> {
+        let expr = Expr::parse(expression)?;
+        Ok(self.map(move |value| expr.eval(value)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone)]
+enum Node {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Matches(Box<Node>, Regex),
+}
+
+impl Node {
+    fn eval(&self, root: &Value) -> Value {
+        match self {
+            Node::Literal(value) => value.clone(),
+            Node::Path(segments) => resolve(root, segments).cloned().unwrap_or(Value::Null),
+            Node::Not(inner) => Value::Bool(!truthy(&inner.eval(root))),
+            Node::Neg(inner) => inner
+                .eval(root)
+                .as_f64()
+                .map(|n| number(-n))
+                .unwrap_or(Value::Null),
+            Node::Matches(inner, regex) => match inner.eval(root) {
+                Value::String(text) => Value::Bool(regex.is_match(&text)),
+                _ => Value::Bool(false),
+            },
+            Node::Binary(BinaryOp::And, left, right) => {
+                Value::Bool(truthy(&left.eval(root)) && truthy(&right.eval(root)))
+            }
+            Node::Binary(BinaryOp::Or, left, right) => {
+                Value::Bool(truthy(&left.eval(root)) || truthy(&right.eval(root)))
+            }
+            Node::Binary(op, left, right) => binary(*op, left.eval(root), right.eval(root)),
+        }
+    }
+}
+
+fn resolve<'a>(root: &'a Value, segments: &[String]) -> Option<&'a Value> {
+    segments
+        .iter()
+        .try_fold(root, |current, segment| match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+}
+
+fn binary(op: BinaryOp, left: Value, right: Value) -> Value {
+    match op {
+        BinaryOp::Eq => Value::Bool(compare(&left, &right) == Some(Ordering::Equal)),
+        BinaryOp::Ne => Value::Bool(compare(&left, &right) != Some(Ordering::Equal)),
+        BinaryOp::Lt => Value::Bool(compare(&left, &right) == Some(Ordering::Less)),
+        BinaryOp::Le => Value::Bool(matches!(
+            compare(&left, &right),
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        BinaryOp::Gt => Value::Bool(compare(&left, &right) == Some(Ordering::Greater)),
+        BinaryOp::Ge => Value::Bool(matches!(
+            compare(&left, &right),
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        BinaryOp::Add => match (&left, &right) {
+            (Value::String(a), Value::String(b)) => Value::String(format!("{a}{b}")),
+            _ => arithmetic(&left, &right, |a, b| a + b),
+        },
+        BinaryOp::Sub => arithmetic(&left, &right, |a, b| a - b),
+        BinaryOp::Mul => arithmetic(&left, &right, |a, b| a * b),
+        BinaryOp::Div => arithmetic(&left, &right, |a, b| a / b),
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short-circuit"),
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn arithmetic(left: &Value, right: &Value, f: impl Fn(f64, f64) -> f64) -> Value {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => number(f(a, b)),
+        _ => Value::Null,
+    }
+}
+
+fn number(value: f64) -> Value {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::Str(s) => write!(f, "string {s:?}"),
+            Token::Ident(name) => write!(f, "identifier `{name}`"),
+            Token::Op(op) => write!(f, "operator `{op}`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+        }
+    }
+}
+
+const OPERATORS: [&str; 15] = [
+    "&&", "||", "==", "!=", "<=", ">=", "=~", "<", ">", "!", "+", "-", "*", "/", "=",
+];
+
+// Parse errors are plain messages; `Expr::parse` adds the expression.
+type ParseResult<T> = std::result::Result<T, String>;
+
+fn parse(source: &str) -> ParseResult<Node> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let root = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {}", token));
+    }
+    Ok(root)
+}
+
+fn tokenize(source: &str) -> ParseResult<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number {:?} at offset {}", text, start))?;
+            tokens.push(Token::Number(value));
+        } else if c == '"' || c == '\'' {
+            let start = i;
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err(format!("unterminated string starting at offset {}", start))
+                    }
+                    Some('\\') => {
+                        let escaped = chars
+                            .get(i + 1)
+                            .ok_or_else(|| format!("unterminated string at offset {}", start))?;
+                        text.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => *other,
+                        });
+                        i += 2;
+                    }
+                    Some(q) if *q == c => {
+                        i += 1;
+                        break;
+                    }
+                    Some(other) => {
+                        text.push(*other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected character {:?} at offset {}", c, i))?;
+            if *op == "=" {
+                return Err(format!(
+                    "unexpected `=` at offset {} (did you mean `==`?)",
+                    i
+                ));
+            }
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_or(&mut self) -> ParseResult<Node> {
+        let mut node = self.parse_and()?;
+        while self.eat_op(&["||"]).is_some() {
+            node = Node::Binary(BinaryOp::Or, Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> ParseResult<Node> {
+        let mut node = self.parse_comparison()?;
+        while self.eat_op(&["&&"]).is_some() {
+            node = Node::Binary(
+                BinaryOp::And,
+                Box::new(node),
+                Box::new(self.parse_comparison()?),
+            );
+        }
+        Ok(node)
+    }
+
+    fn parse_comparison(&mut self) -> ParseResult<Node> {
+        let left = self.parse_additive()?;
+        let Some(op) = self.eat_op(&["==", "!=", "<=", ">=", "<", ">", "=~"]) else {
+            return Ok(left);
+        };
+
+        if op == "=~" {
+            return match self.next() {
+                Some(Token::Str(pattern)) => {
+                    let regex = Regex::new(&pattern)
+                        .map_err(|err| format!("invalid regex {:?}: {}", pattern, err))?;
+                    Ok(Node::Matches(Box::new(left), regex))
+                }
+                Some(other) => {
+                    return Err(format!("`=~` expects a string pattern, found {}", other))
+                }
+                None => {
+                    return Err("`=~` expects a string pattern, found end of expression".to_string())
+                }
+            };
+        }
+
+        let op = match op {
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<=" => BinaryOp::Le,
+            ">=" => BinaryOp::Ge,
+            "<" => BinaryOp::Lt,
+            _ => BinaryOp::Gt,
+        };
+        Ok(Node::Binary(
+            op,
+            Box::new(left),
+            Box::new(self.parse_additive()?),
+        ))
+    }
+
+    fn parse_additive(&mut self) -> ParseResult<Node> {
+        let mut node = self.parse_multiplicative()?;
+        while let Some(op) = self.eat_op(&["+", "-"]) {
+            let op = if op == "+" {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.parse_multiplicative()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_multiplicative(&mut self) -> ParseResult<Node> {
+        let mut node = self.parse_unary()?;
+        while let Some(op) = self.eat_op(&["*", "/"]) {
+            let op = if op == "*" {
+                BinaryOp::Mul
+            } else {
+                BinaryOp::Div
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.parse_unary()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Node> {
+        if self.eat_op(&["!"]).is_some() {
+            return Ok(Node::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat_op(&["-"]).is_some() {
+            return Ok(Node::Neg(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<Node> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Node::Literal(number(n))),
+            Some(Token::Str(s)) => Ok(Node::Literal(Value::String(s))),
+            Some(Token::Ident(name)) => Ok(match name.as_str() {
+                "true" => Node::Literal(Value::Bool(true)),
+                "false" => Node::Literal(Value::Bool(false)),
+                "null" => Node::Literal(Value::Null),
+                _ => {
+                    let segments: Vec<String> = name.split('.').map(str::to_string).collect();
+                    if segments.iter().any(String::is_empty) {
+                        return Err(format!("invalid path `{}`", name));
+                    }
+                    Node::Path(segments)
+                }
+            }),
+            Some(Token::LParen) => {
+                let node = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(node),
+                    Some(other) => Err(format!("expected `)`, found {}", other)),
+                    None => Err("expected `)`, found end of expression".to_string()),
+                }
+            }
+            Some(other) => Err(format!("unexpected {}", other)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
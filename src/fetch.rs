@@ -0,0 +1,190 @@
+use crate::backpressure;
+use crate::drain::InFlight;
+use crate::rt;
+use crate::{Error, Result, Source, Stream};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, RequestBuilder, Response};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct FetchConfig {
+    // requests in flight at once; later items wait in a queue
+    pub max_concurrency: usize,
+    // items waiting beyond this are dropped and reported as backpressure
+    pub max_queued: usize,
+    // sent with every request
+    pub headers: HeaderMap,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 4,
+            max_queued: 1024,
+            headers: HeaderMap::new(),
+            timeout: None,
+        }
+    }
+}
+
+impl FetchConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Result<Self> {
+        let name =
+            HeaderName::from_bytes(key.as_bytes()).map_err(|err| Error::other("fetch", err))?;
+        let value = HeaderValue::from_str(value).map_err(|err| Error::other("fetch", err))?;
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+// A request that failed, with the status if the server answered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchError {
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl FetchError {
+    fn new(err: reqwest::Error) -> Self {
+        Self {
+            status: err.status().map(|status| status.as_u16()),
+            message: err.to_string(),
+        }
+    }
+}
+
+// Response bodies and failures, each paired with the item that asked.
+type Fetched<T> = (Stream<(T, String)>, Stream<(T, FetchError)>);
+
+struct Fetcher<T, F> {
+    client: std::result::Result<Client, FetchError>,
+    request: F,
+    max_concurrency: usize,
+    max_queued: usize,
+    queued: RefCell<VecDeque<T>>,
+    in_flight: Cell<usize>,
+    input_done: Cell<bool>,
+    responses: Source<(T, String)>,
+    errors: Source<(T, FetchError)>,
+}
+
+impl<T, F> Fetcher<T, F>
+where
+    T: Clone + 'static,
+    F: Fn(&Client, &T) -> RequestBuilder + 'static,
+{
+    fn push(self: &Rc<Self>, item: T) {
+        let depth = self.queued.borrow().len();
+        if depth >= self.max_queued {
+            backpressure::report("fetch", depth, 1);
+            return;
+        }
+        self.queued.borrow_mut().push_back(item);
+        self.pump();
+    }
+
+    fn pump(self: &Rc<Self>) {
+        while self.in_flight.get() < self.max_concurrency {
+            let Some(item) = self.queued.borrow_mut().pop_front() else {
+                break;
+            };
+            let client = match &self.client {
+                Ok(client) => client,
+                Err(err) => {
+                    self.errors.emit((item, err.clone()));
+                    continue;
+                }
+            };
+            let pending = (self.request)(client, &item).send();
+            let fetcher = self.clone();
+            let tracked = InFlight::start();
+            self.in_flight.set(self.in_flight.get() + 1);
+            rt::spawn_local(async move {
+                let _tracked = tracked;
+                match body(pending.await).await {
+                    Ok(body) => fetcher.responses.emit((item, body)),
+                    Err(err) => fetcher.errors.emit((item, FetchError::new(err))),
+                }
+                fetcher.in_flight.set(fetcher.in_flight.get() - 1);
+                fetcher.pump();
+                fetcher.finish();
+            });
+        }
+        self.finish();
+    }
+
+    fn finish(&self) {
+        if self.input_done.get() && self.in_flight.get() == 0 && self.queued.borrow().is_empty() {
+            self.responses.complete();
+            self.errors.complete();
+        }
+    }
+}
+
+async fn body(response: reqwest::Result<Response>) -> reqwest::Result<String> {
+    response?.error_for_status()?.text().await
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Makes the request `request` builds for each item, e.g. instrument
+    // details for each new symbol, and emits the response body paired with
+    // the item. Requests run on the engine's local task set, so responses
+    // may come out of order; failed requests and error statuses go to the
+    // second output. Both complete once the input has and every request has
+    // finished.
+    pub fn fetch<F>(&self, config: FetchConfig, request: F) -> Fetched<T>
+    where
+        F: Fn(&Client, &T) -> RequestBuilder + 'static,
+    {
+        let mut client = Client::builder().no_proxy().default_headers(config.headers);
+        if let Some(timeout) = config.timeout {
+            client = client.timeout(timeout);
+        }
+        let fetcher = Rc::new(Fetcher {
+            client: client.build().map_err(FetchError::new),
+            request,
+            max_concurrency: config.max_concurrency.max(1),
+            max_queued: config.max_queued,
+            queued: RefCell::new(VecDeque::new()),
+            in_flight: Cell::new(0),
+            input_done: Cell::new(false),
+            responses: Source::new(),
+            errors: Source::new(),
+        });
+        let streams = (fetcher.responses.to_stream(), fetcher.errors.to_stream());
+
+        let fetcher_clone = fetcher.clone();
+        self.on_complete(move || {
+            fetcher_clone.input_done.set(true);
+            fetcher_clone.finish();
+        });
+        self.sink(move |item: &T| fetcher.push(item.clone()));
+        streams
+    }
+}
@@ -0,0 +1,282 @@
+use crate::log;
+use crate::manifest::{write_atomically, BatchManifest};
+use crate::rt::now_millis;
+use crate::{Error, Result, Sink, Stream};
+use polars::prelude::{
+    AnyValue, Column, DataFrame, IpcWriter, ParquetWriter, PolarsError, PolarsResult, SerWriter,
+    Series,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+
+// Builds DataFrame rows without serde, or with exact dtypes: one value per
+// column, in `columns()` order.
+pub trait FrameRow {
+    fn columns() -> &'static [&'static str];
+    fn values(&self) -> Vec<AnyValue<'_>>;
+}
+
+impl<T> Stream<Vec<T>>
+where
+    T: Serialize + 'static,
+{
+    // One DataFrame per batch (e.g. from `timed_buffer`), with a column per
+    // serialized field, sorted by name. Integers become i64 (u64 past
+    // its range), other numbers f64, nested values JSON strings, missing
+    // fields nulls, and a column mixing types its common supertype.
+    // Non-struct items land in a `value` column. Empty batches are skipped,
+    // batches that fail to convert dropped, and the first failure printed.
+    pub fn to_polars(&self) -> Stream<DataFrame> {
+        let reported = Cell::new(false);
+        self.filter_map(move |batch| convert(batch, &reported, serde_frame))
+    }
+}
+
+impl<T> Stream<Vec<T>>
+where
+    T: FrameRow + 'static,
+{
+    // `to_polars`, with columns from `FrameRow`.
+    pub fn to_polars_rows(&self) -> Stream<DataFrame> {
+        let reported = Cell::new(false);
+        self.filter_map(move |batch| convert(batch, &reported, row_frame))
+    }
+}
+
+fn convert<T>(
+    batch: &[T],
+    reported: &Cell<bool>,
+    build: fn(&[T]) -> PolarsResult<DataFrame>,
+) -> Option<DataFrame> {
+    if batch.is_empty() {
+        return None;
+    }
+    match build(batch) {
+        Ok(frame) => Some(frame),
+        Err(err) => {
+            if !reported.replace(true) {
+                log::error(
+                    "frame",
+                    format_args!("failed to build a DataFrame: {}", err),
+                );
+            }
+            None
+        }
+    }
+}
+
+fn serde_frame<T: Serialize>(batch: &[T]) -> PolarsResult<DataFrame> {
+    let mut names: Vec<String> = Vec::new();
+    let mut columns: Vec<Vec<AnyValue<'static>>> = Vec::new();
+    for (row, item) in batch.iter().enumerate() {
+        let fields = match serde_json::to_value(item) {
+            Ok(Value::Object(fields)) => fields.into_iter().collect(),
+            Ok(value) => vec![("value".to_string(), value)],
+            Err(err) => return Err(PolarsError::ComputeError(err.to_string().into())),
+        };
+        for (name, value) in fields {
+            let index = match names.iter().position(|existing| *existing == name) {
+                Some(index) => index,
+                None => {
+                    names.push(name);
+                    columns.push(vec![AnyValue::Null; row]);
+                    columns.len() - 1
+                }
+            };
+            columns[index].push(any_value(value));
+        }
+        for column in &mut columns {
+            column.resize(row + 1, AnyValue::Null);
+        }
+    }
+    let columns = names
+        .iter()
+        .zip(&columns)
+        .map(|(name, values)| {
+            Series::from_any_values(name.as_str().into(), values, false).map(Column::from)
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    DataFrame::new(batch.len(), columns)
+}
+
+fn any_value(value: Value) -> AnyValue<'static> {
+    match value {
+        Value::Null => AnyValue::Null,
+        Value::Bool(value) => AnyValue::Boolean(value),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(value), _) => AnyValue::Int64(value),
+            (None, Some(value)) => AnyValue::UInt64(value),
+            _ => number.as_f64().map_or(AnyValue::Null, AnyValue::Float64),
+        },
+        Value::String(value) => AnyValue::StringOwned(value.into()),
+        nested => AnyValue::StringOwned(nested.to_string().into()),
+    }
+}
+
+fn row_frame<T: FrameRow>(batch: &[T]) -> PolarsResult<DataFrame> {
+    let names = T::columns();
+    let mut columns: Vec<Vec<AnyValue<'_>>> = vec![Vec::with_capacity(batch.len()); names.len()];
+    for item in batch {
+        let values = item.values();
+        if values.len() != names.len() {
+            return Err(PolarsError::ShapeMismatch(
+                format!(
+                    "a row has {} values for {} columns",
+                    values.len(),
+                    names.len()
+                )
+                .into(),
+            ));
+        }
+        for (column, value) in columns.iter_mut().zip(values) {
+            column.push(value);
+        }
+    }
+    let columns = names
+        .iter()
+        .zip(&columns)
+        .map(|(name, values)| Series::from_any_values((*name).into(), values, false))
+        .map(|series| series.map(Column::from))
+        .collect::<PolarsResult<Vec<_>>>()?;
+    DataFrame::new(batch.len(), columns)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameFormat {
+    Parquet,
+    // Arrow IPC (Feather v2)
+    Ipc,
+}
+
+// Writes each DataFrame to its own file. `pattern` may contain `{seq}` (the
+// frame's number, from 0) and `{ts}` (milliseconds since the unix epoch),
+// e.g. "frames/trades-{ts}.parquet"; the format comes from the extension
+// (`.parquet`, or `.arrow` / `.ipc` / `.feather`). Files appear whole, via a
+// temporary file and a rename. Existing files are never overwritten; a
+// numbered part is written instead.
+pub struct FrameWriter {
+    pattern: String,
+    format: FrameFormat,
+    seq: Cell<u64>,
+    files: RefCell<Vec<PathBuf>>,
+    manifest: Option<BatchManifest>,
+}
+
+impl FrameWriter {
+    pub fn new(pattern: impl Into<String>) -> Result<Self> {
+        let pattern = pattern.into();
+        let extension = pattern.rsplit_once('.').map(|(_, extension)| extension);
+        let format = match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("parquet") => FrameFormat::Parquet,
+            Some("arrow" | "ipc" | "feather") => FrameFormat::Ipc,
+            _ => {
+                return Err(Error::config(format!(
+                    "{:?} is not a .parquet or .arrow path",
+                    pattern
+                )))
+            }
+        };
+        Ok(Self {
+            pattern,
+            format,
+            seq: Cell::new(0),
+            files: RefCell::new(Vec::new()),
+            manifest: None,
+        })
+    }
+
+    // Lists every file in a write-ahead `BatchManifest`, with the cursor
+    // given to `write_with_cursor`; files of batches a crash interrupted are
+    // deleted on open. `{seq}` continues from the manifest's batches.
+    pub fn with_manifest(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let manifest = BatchManifest::open(path)?;
+        self.seq.set(manifest.next_batch());
+        self.manifest = Some(manifest);
+        Ok(self)
+    }
+
+    pub fn manifest(&self) -> Option<&BatchManifest> {
+        self.manifest.as_ref()
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.format
+    }
+
+    // Files written so far.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.borrow().clone()
+    }
+
+    pub fn write(&self, frame: &DataFrame) -> Result<PathBuf> {
+        self.write_frame(frame, None)
+    }
+
+    // `cursor` is the source position the frame covers, recorded in the
+    // manifest.
+    pub fn write_with_cursor(&self, frame: &DataFrame, cursor: &str) -> Result<PathBuf> {
+        self.write_frame(frame, Some(cursor))
+    }
+
+    fn write_frame(&self, frame: &DataFrame, cursor: Option<&str>) -> Result<PathBuf> {
+        let seq = self.seq.replace(self.seq.get() + 1);
+        let rendered = self
+            .pattern
+            .replace("{seq}", &seq.to_string())
+            .replace("{ts}", &now_millis().to_string());
+        let path = unused_path(PathBuf::from(rendered));
+        let label = path.display().to_string();
+        let write = |file: &mut _| {
+            let mut frame = frame.clone();
+            let written = match self.format {
+                FrameFormat::Parquet => ParquetWriter::new(file).finish(&mut frame).map(|_| ()),
+                FrameFormat::Ipc => IpcWriter::new(file).finish(&mut frame),
+            };
+            written.map_err(|err| Error::other(&label, err))
+        };
+        match &self.manifest {
+            Some(manifest) => manifest.commit(&path, cursor, write).map(|_| ())?,
+            None => write_atomically(&path, write)?,
+        }
+        self.files.borrow_mut().push(path.clone());
+        Ok(path)
+    }
+}
+
+// `path`, or the first free "stem.N.ext" next to it.
+fn unused_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|part| path.with_file_name(format!("{}.{}.{}", stem, part, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(path)
+}
+
+impl Sink<DataFrame> for FrameWriter {
+    fn on_item(&self, frame: &DataFrame) {
+        if let Err(err) = self.write(frame) {
+            log::error(
+                "frame",
+                format_args!("failed to write a DataFrame: {}", err),
+            );
+        }
+    }
+}
+
+// Frames paired with the source cursor they cover.
+impl Sink<(DataFrame, String)> for FrameWriter {
+    fn on_item(&self, (frame, cursor): &(DataFrame, String)) {
+        if let Err(err) = self.write_with_cursor(frame, cursor) {
+            log::error(
+                "frame",
+                format_args!("failed to write a DataFrame: {}", err),
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::log;
+use crate::{Error, Result, Source, Stream};
+use std::cell::Cell;
+use std::fmt::{self, Display};
+
+// Bytes of a rejected payload kept in its violation.
+const PREVIEW_BYTES: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GuardPolicy {
+    // drop the payload, count it and report it on the violations stream
+    #[default]
+    Reject,
+    // report it, then fail the source, e.g. for a supervisor to reconnect
+    Fail,
+}
+
+// Limits checked on every payload a network source receives, before it is
+// emitted: websocket frames and HTTP response bodies. HTTP bodies are read
+// no further than `max_frame_bytes`; JSON depth counts nested objects and
+// arrays, outside strings, without parsing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameGuard {
+    max_frame_bytes: Option<usize>,
+    max_json_depth: Option<usize>,
+    policy: GuardPolicy,
+}
+
+impl FrameGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_frame_bytes(mut self, max: usize) -> Self {
+        self.max_frame_bytes = Some(max);
+        self
+    }
+
+    pub fn with_max_json_depth(mut self, max: usize) -> Self {
+        self.max_json_depth = Some(max);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: GuardPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn max_frame_bytes(&self) -> Option<usize> {
+        self.max_frame_bytes
+    }
+
+    pub fn policy(&self) -> GuardPolicy {
+        self.policy
+    }
+
+    fn inspect(&self, payload: &[u8]) -> Option<ViolationKind> {
+        if let Some(limit) = self.max_frame_bytes.filter(|&limit| payload.len() > limit) {
+            return Some(ViolationKind::FrameTooLarge {
+                size: payload.len(),
+                limit,
+            });
+        }
+        let limit = self.max_json_depth?;
+        let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+        for &byte in payload {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => {
+                    depth += 1;
+                    if depth > limit {
+                        return Some(ViolationKind::TooDeep { limit });
+                    }
+                }
+                b'}' | b']' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    // `size` is the declared length of an HTTP body, or as much of it as was
+    // read before giving up
+    FrameTooLarge { size: usize, limit: usize },
+    TooDeep { limit: usize },
+}
+
+impl Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViolationKind::FrameTooLarge { size, limit } => write!(
+                f,
+                "payload of {} bytes is over the {} byte limit",
+                size, limit
+            ),
+            ViolationKind::TooDeep { limit } => {
+                write!(f, "JSON nested deeper than {} levels", limit)
+            }
+        }
+    }
+}
+
+// A payload a guard rejected, with the start of it for inspection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuardViolation {
+    pub source: String,
+    pub kind: ViolationKind,
+    pub preview: String,
+}
+
+// A source's guard, its violations and its count of rejected payloads.
+pub(crate) struct Guarded {
+    guard: Option<FrameGuard>,
+    violations: Source<GuardViolation>,
+    rejected: Cell<u64>,
+}
+
+impl Guarded {
+    pub(crate) fn new(guard: Option<FrameGuard>) -> Self {
+        Self {
+            guard,
+            violations: Source::new(),
+            rejected: Cell::new(0),
+        }
+    }
+
+    // HTTP bodies are read no further
+    #[cfg_attr(not(feature = "requests"), allow(dead_code))]
+    pub(crate) fn max_frame_bytes(&self) -> Option<usize> {
+        self.guard.and_then(|guard| guard.max_frame_bytes)
+    }
+
+    pub(crate) fn violations(&self) -> Stream<GuardViolation> {
+        self.violations.to_stream()
+    }
+
+    pub(crate) fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+
+    // Whether `payload` may be emitted; an error under `GuardPolicy::Fail`.
+    pub(crate) fn check(&self, label: &str, payload: &[u8]) -> Result<bool> {
+        match self.guard.and_then(|guard| guard.inspect(payload)) {
+            None => Ok(true),
+            Some(kind) => self.reject(label, payload, kind).map(|()| false),
+        }
+    }
+
+    // Reports a payload already known to violate the guard, e.g. a body cut
+    // off at the size limit; `payload` is what was read of it.
+    pub(crate) fn reject(&self, label: &str, payload: &[u8], kind: ViolationKind) -> Result<()> {
+        let rejected = self.rejected.get() + 1;
+        self.rejected.set(rejected);
+        let fail = self
+            .guard
+            .is_some_and(|guard| guard.policy == GuardPolicy::Fail);
+        if rejected == 1 && !fail {
+            log::warn(
+                "guard",
+                format_args!(
+                    "{}: rejected a payload ({}); counting further ones",
+                    label, kind
+                ),
+            );
+        }
+        let preview = &payload[..payload.len().min(PREVIEW_BYTES)];
+        self.violations.emit(GuardViolation {
+            source: label.to_string(),
+            kind,
+            preview: String::from_utf8_lossy(preview).into_owned(),
+        });
+        if fail {
+            return Err(Error::protocol(
+                label,
+                format!("rejected payload: {}", kind),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+#[cfg(feature = "query")]
+use crate::query::{Queries, Queryable};
+use crate::source::RetainedStream;
+use crate::{
+    BreakerState, EngineSource, EngineStatus, Pipeline, PipelineContext, PipelineError, Source,
+    Stream, TimedEmitter,
+};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EngineEvent {
+    SourceAdded(String),
+    SourceRemoved(String),
+    SourcePaused(String),
+    SourceResumed(String),
+    SourceRestarted(String),
+    // the shared reconnect budget's breaker changed state
+    ReconnectBreaker {
+        state: BreakerState,
+        failures: u32,
+    },
+    // audit trail for config hot-reload
+    ConfigReloaded {
+        changes: Vec<String>,
+    },
+    ConfigRejected {
+        reason: String,
+    },
+    // from an engine added with `EngineBuilder::add_engine`
+    Child {
+        engine: String,
+        event: Box<EngineEvent>,
+    },
+}
+
+impl fmt::Display for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineEvent::SourceAdded(label) => write!(f, "source {} added", label),
+            EngineEvent::SourceRemoved(label) => write!(f, "source {} removed", label),
+            EngineEvent::SourcePaused(label) => write!(f, "source {} paused", label),
+            EngineEvent::SourceResumed(label) => write!(f, "source {} resumed", label),
+            EngineEvent::SourceRestarted(label) => write!(f, "source {} restarted", label),
+            EngineEvent::ReconnectBreaker { state, failures } => write!(
+                f,
+                "reconnect breaker {} after {} failed attempts",
+                state, failures
+            ),
+            EngineEvent::ConfigReloaded { changes } => {
+                write!(f, "config reloaded: {}", changes.join(", "))
+            }
+            EngineEvent::ConfigRejected { reason } => write!(f, "config rejected: {}", reason),
+            EngineEvent::Child { engine, event } => write!(f, "{}: {}", engine, event),
+        }
+    }
+}
+
+pub(crate) enum EngineCommand {
+    AddSource(String, Arc<dyn EngineSource>),
+    RemoveSource(String),
+    PauseSource(String),
+    ResumeSource(String),
+    RestartSource(String),
+    Status(oneshot::Sender<EngineStatus>),
+    AddTimedEmitter(Rc<dyn TimedEmitter>),
+    AddStream(Box<dyn RetainedStream>),
+}
+
+// Changes the topology of a running engine. Commands sent before `run` are
+// applied as soon as the engine starts; after it stops they are ignored.
+#[derive(Clone)]
+pub struct EngineHandle {
+    commands: UnboundedSender<EngineCommand>,
+    events: Rc<Source<EngineEvent>>,
+    pipeline_errors: Rc<Source<PipelineError>>,
+    #[cfg(feature = "query")]
+    pub(crate) queries: Queries,
+}
+
+impl EngineHandle {
+    pub(crate) fn new(
+        commands: UnboundedSender<EngineCommand>,
+        events: Rc<Source<EngineEvent>>,
+        pipeline_errors: Rc<Source<PipelineError>>,
+    ) -> Self {
+        Self {
+            commands,
+            events,
+            pipeline_errors,
+            #[cfg(feature = "query")]
+            queries: Queries::default(),
+        }
+    }
+
+    pub fn add_source<S>(&self, label: impl Into<String>, source: Arc<S>)
+    where
+        S: EngineSource,
+    {
+        self.send(EngineCommand::AddSource(
+            label.into(),
+            source as Arc<dyn EngineSource>,
+        ));
+    }
+
+    pub fn add_source_owned<S>(&self, label: impl Into<String>, source: S)
+    where
+        S: EngineSource,
+    {
+        self.add_source(label, Arc::new(source));
+    }
+
+    // Stops every source registered under `label`; what they already emitted
+    // stays counted in the run report.
+    pub fn remove_source(&self, label: impl Into<String>) {
+        self.send(EngineCommand::RemoveSource(label.into()));
+    }
+
+    // Stops the sources registered under `label` without completing their
+    // outputs, until `resume_source` runs them again. Child engines from
+    // `EngineBuilder::add_engine` cannot be paused or restarted.
+    pub fn pause_source(&self, label: impl Into<String>) {
+        self.send(EngineCommand::PauseSource(label.into()));
+    }
+
+    pub fn resume_source(&self, label: impl Into<String>) {
+        self.send(EngineCommand::ResumeSource(label.into()));
+    }
+
+    // Stops and reruns them, e.g. to reconnect a wedged websocket; paused or
+    // finished sources are started again too.
+    pub fn restart_source(&self, label: impl Into<String>) {
+        self.send(EngineCommand::RestartSource(label.into()));
+    }
+
+    // The engine's topology and the state of its sources. Waits for the
+    // engine to start; `None` once it has stopped.
+    pub async fn status(&self) -> Option<EngineStatus> {
+        let (reply, status) = oneshot::channel();
+        self.send(EngineCommand::Status(reply));
+        status.await.ok()
+    }
+
+    pub fn add_timed_emitter(&self, emitter: Rc<dyn TimedEmitter>) {
+        self.send(EngineCommand::AddTimedEmitter(emitter));
+    }
+
+    pub fn add_stream<T>(&self, stream: Stream<T>)
+    where
+        T: 'static,
+    {
+        self.send(EngineCommand::AddStream(Box::new(stream)));
+    }
+
+    pub fn add_pipeline<P>(&self, pipeline: &P, input: P::Input) -> P::Output
+    where
+        P: Pipeline,
+    {
+        let mut context =
+            PipelineContext::reporting_to(&pipeline.name(), self.pipeline_errors.clone());
+        let output = pipeline.build(input, &mut context);
+        for stream in context.streams {
+            self.send(EngineCommand::AddStream(stream));
+        }
+        for (label, source) in context.sources {
+            self.send(EngineCommand::AddSource(label, source));
+        }
+        for emitter in context.timed_emitters {
+            self.add_timed_emitter(emitter);
+        }
+        output
+    }
+
+    pub fn events(&self) -> Stream<EngineEvent> {
+        self.events.to_stream()
+    }
+
+    pub fn pipeline_errors(&self) -> Stream<PipelineError> {
+        self.pipeline_errors.to_stream()
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        self.events.emit(event);
+    }
+
+    // Queryable state is registered with the engine's builder and its
+    // handles alike, and can be added at any time.
+    #[cfg(feature = "query")]
+    pub fn register_query<Q>(&self, name: impl Into<String>, state: Q)
+    where
+        Q: Queryable,
+    {
+        self.queries.register(name.into(), Rc::new(state));
+    }
+
+    #[cfg(feature = "query")]
+    pub fn query_names(&self) -> Vec<String> {
+        self.queries.names()
+    }
+
+    // The current state registered as `name`, as JSON.
+    #[cfg(feature = "query")]
+    pub fn query(&self, name: &str) -> Option<serde_json::Value> {
+        self.queries.snapshot(name)
+    }
+
+    // One entry of it, e.g. `query_key("book_tops", "BTC-PERPETUAL")`.
+    #[cfg(feature = "query")]
+    pub fn query_key(&self, name: &str, key: &str) -> Option<serde_json::Value> {
+        self.queries.get(name, key)
+    }
+
+    fn send(&self, command: EngineCommand) {
+        let _ = self.commands.send(command);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{EngineStatus, SourceState};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const DEFAULT_BUFFER_WINDOW: Duration = Duration::from_secs(30);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Judges liveness and readiness from an `EngineStatus`, e.g. for Kubernetes
+// probes via `health_routes`. An engine is live unless a running source has
+// been idle past its limit (wedged). It is ready when it is live and every
+// source is running and connected, and no bounded stage reported a depth
+// over the limit within the buffer window. Nothing is checked unless
+// configured, other than sources being up.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    max_idle: Option<Duration>,
+    source_max_idle: HashMap<String, Duration>,
+    max_buffer_depth: Option<usize>,
+    buffer_window: Duration,
+    optional: HashSet<String>,
+    timeout: Duration,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthCheck {
+    pub fn new() -> Self {
+        Self {
+            max_idle: None,
+            source_max_idle: HashMap::new(),
+            max_buffer_depth: None,
+            buffer_window: DEFAULT_BUFFER_WINDOW,
+            optional: HashSet::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // How long any running source may go without a message. Idle time is
+    // measured between status calls, so to the resolution of the probes.
+    pub fn with_max_idle(mut self, max_idle: Duration) -> Self {
+        self.max_idle = Some(max_idle);
+        self
+    }
+
+    // Overrides `with_max_idle` for `label`, e.g. for a quiet reference feed.
+    pub fn with_source_max_idle(mut self, label: impl Into<String>, max_idle: Duration) -> Self {
+        self.source_max_idle.insert(label.into(), max_idle);
+        self
+    }
+
+    pub fn with_max_buffer_depth(mut self, depth: usize) -> Self {
+        self.max_buffer_depth = Some(depth);
+        self
+    }
+
+    // How long a backpressure report counts against readiness; 30s by default.
+    pub fn with_buffer_window(mut self, window: Duration) -> Self {
+        self.buffer_window = window;
+        self
+    }
+
+    // Sources left out of both checks, e.g. the `HttpServer` serving the
+    // probes or a one-shot snapshot request that is expected to finish.
+    pub fn with_optional_source(mut self, label: impl Into<String>) -> Self {
+        self.optional.insert(label.into());
+        self
+    }
+
+    // How long a probe waits for the engine to answer before failing; 5s by
+    // default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn evaluate(&self, status: &EngineStatus) -> HealthReport {
+        let mut report = HealthReport::default();
+        for source in &status.sources {
+            if self.optional.contains(&source.label) {
+                continue;
+            }
+            let max_idle = self
+                .source_max_idle
+                .get(&source.label)
+                .copied()
+                .or(self.max_idle);
+            if let Some(max_idle) = max_idle {
+                if source.state == SourceState::Running && source.idle > max_idle {
+                    report.liveness.push(format!(
+                        "source {} idle for {:?} (limit {:?})",
+                        source.label, source.idle, max_idle
+                    ));
+                }
+            }
+            if source.state == SourceState::Removed {
+                continue;
+            }
+            if source.state != SourceState::Running {
+                report
+                    .readiness
+                    .push(format!("source {} is {}", source.label, source.state));
+            } else if source.connected == Some(false) {
+                report
+                    .readiness
+                    .push(format!("source {} is disconnected", source.label));
+            }
+        }
+        if let Some(max_depth) = self.max_buffer_depth {
+            for buffer in &status.buffers {
+                if buffer.age <= self.buffer_window && buffer.depth > max_depth {
+                    report.readiness.push(format!(
+                        "stream {} backed up to {} items (limit {})",
+                        buffer.stream, buffer.depth, max_depth
+                    ));
+                }
+            }
+        }
+        report
+    }
+}
+
+// Problems found by `HealthCheck::evaluate`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub liveness: Vec<String>,
+    // besides the liveness problems, which also make an engine unready
+    pub readiness: Vec<String>,
+}
+
+impl HealthReport {
+    pub fn is_live(&self) -> bool {
+        self.liveness.is_empty()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_live() && self.readiness.is_empty()
+    }
+}
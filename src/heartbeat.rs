@@ -0,0 +1,108 @@
+use crate::source::track_timed_emitter;
+use crate::{Source, Stream, TimedEmitter};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+// An item, or a period without one: `Missed(n)` is the `n`th period in a row
+// with no items, so a quiet market and a dead feed can be told apart.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Heartbeat<T> {
+    Item(T),
+    Missed(u64),
+}
+
+pub struct HeartbeatMonitor<T> {
+    inner: Rc<HeartbeatInner<T>>,
+}
+
+struct HeartbeatInner<T> {
+    period: Duration,
+    seen: Cell<bool>,
+    missed: Cell<u64>,
+    output: Source<Heartbeat<T>>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Checks for items every `period`; register the monitor's timed emitter
+    // with the engine.
+    pub fn heartbeat(&self, period: Duration) -> HeartbeatMonitor<T> {
+        let inner = Rc::new(HeartbeatInner {
+            period,
+            seen: Cell::new(false),
+            missed: Cell::new(0),
+            output: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("heartbeat", period, &inner.registered);
+        let inner_item = inner.clone();
+        let inner_complete = inner.clone();
+
+        self.sink(move |item: &T| {
+            inner_item.seen.set(true);
+            inner_item.missed.set(0);
+            inner_item.output.emit(Heartbeat::Item(item.clone()));
+        });
+        self.on_complete(move || inner_complete.output.complete());
+
+        HeartbeatMonitor { inner }
+    }
+}
+
+impl<T> HeartbeatMonitor<T>
+where
+    T: Clone + 'static,
+{
+    pub fn stream(&self) -> Stream<Heartbeat<T>> {
+        self.inner.output.to_stream()
+    }
+
+    pub fn period(&self) -> Duration {
+        self.inner.period
+    }
+
+    // Periods in a row without an item, so far.
+    pub fn missed(&self) -> u64 {
+        self.inner.missed.get()
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl<T> Clone for HeartbeatMonitor<T> {
+    fn clone(&self) -> Self {
+        HeartbeatMonitor {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> TimedEmitter for HeartbeatInner<T>
+where
+    T: Clone + 'static,
+{
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn name(&self) -> String {
+        "heartbeat".to_string()
+    }
+
+    fn flush(&self) {
+        if self.seen.replace(false) || self.output.is_complete() {
+            return;
+        }
+        let missed = self.missed.get() + 1;
+        self.missed.set(missed);
+        self.output.emit(Heartbeat::Missed(missed));
+    }
+}
@@ -0,0 +1,103 @@
+use super::{AwsClient, AwsConfig, PendingRecord, Producer, Target};
+use crate::log;
+use crate::{Result, Sink};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+// The largest record Firehose accepts, 1000 KiB.
+const MAX_RECORD_BYTES: usize = 1000 << 10;
+
+// Sends items as JSON lines through `PutRecordBatch`, batched up to the
+// call's limits and sent at each flush. With aggregation, lines are joined
+// into records of up to `max_bytes`, which Firehose bills and delivers the
+// same as the lines on their own.
+pub struct FirehoseSink<T> {
+    producer: Rc<Producer>,
+    aggregate_bytes: usize,
+    aggregate: RefCell<Vec<u8>>,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T> FirehoseSink<T>
+where
+    T: Serialize + 'static,
+{
+    pub fn new(config: AwsConfig, delivery_stream: &str) -> Result<Self> {
+        let client = AwsClient::new(config, "firehose", "Firehose_20150804")?;
+        Ok(Self {
+            producer: Rc::new(Producer::new(
+                client,
+                Target::FirehoseDeliveryStream,
+                delivery_stream,
+            )),
+            aggregate_bytes: 0,
+            aggregate: RefCell::new(Vec::new()),
+            _item: PhantomData,
+        })
+    }
+
+    // Joins lines into records of up to `max_bytes` (at most 1000 KiB); 0,
+    // the default, sends one record per item.
+    pub fn with_aggregation(mut self, max_bytes: usize) -> Self {
+        self.aggregate_bytes = max_bytes.min(MAX_RECORD_BYTES);
+        self
+    }
+
+    // Records accepted by Firehose so far.
+    pub fn sent(&self) -> u64 {
+        self.producer.sent()
+    }
+
+    // Records dropped because their call failed as a whole.
+    pub fn failed(&self) -> u64 {
+        self.producer.failed()
+    }
+
+    fn seal(&self) {
+        let data = std::mem::take(&mut *self.aggregate.borrow_mut());
+        if !data.is_empty() {
+            self.producer.push(PendingRecord {
+                partition_key: None,
+                data,
+            });
+        }
+    }
+}
+
+impl<T> Sink<T> for FirehoseSink<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        let mut line = match serde_json::to_vec(item) {
+            Ok(line) => line,
+            Err(err) => {
+                log::warn(
+                    "firehose",
+                    format_args!("firehose sink could not encode an item: {}", err),
+                );
+                return;
+            }
+        };
+        line.push(b'\n');
+        if self.aggregate_bytes == 0 {
+            self.producer.push(PendingRecord {
+                partition_key: None,
+                data: line,
+            });
+            return;
+        }
+        if self.aggregate.borrow().len() + line.len() > self.aggregate_bytes {
+            self.seal();
+        }
+        self.aggregate.borrow_mut().extend_from_slice(&line);
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.seal();
+        self.producer.flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,525 @@
+use super::{is_service_error, AwsClient, AwsConfig, PendingRecord, Producer, Target};
+use crate::log;
+use crate::{CursorStore, Error, Result, Sink, Source};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use md5::{Digest, Md5};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// Marks a shard read to its end in the cursor store.
+const SHARD_END: &str = "SHARD_END";
+// GetRecords allows five calls a second per shard.
+const CATCH_UP_DELAY: Duration = Duration::from_millis(200);
+const AGGREGATED_MAGIC: [u8; 4] = [0xf3, 0x89, 0x9a, 0xc2];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StartPosition {
+    TrimHorizon,
+    Latest,
+}
+
+// A user record; records packed by a KPL-style aggregating producer come out
+// one by one, sharing the sequence number with increasing `sub_sequence`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KinesisRecord {
+    pub shard_id: String,
+    pub sequence_number: String,
+    pub sub_sequence: u64,
+    pub partition_key: String,
+    pub data: Vec<u8>,
+    pub arrival_ms: u64,
+}
+
+// Reads every shard of a stream, parents before their children across
+// resharding. With a cursor store, each shard's position is saved after
+// each batch is emitted (`kinesis/{stream}/{shard}`) and reading resumes
+// after it; shards read to their end are not read again.
+pub struct KinesisSource {
+    client: AwsClient,
+    stream: String,
+    start: StartPosition,
+    period: Duration,
+    cursors: Option<Rc<dyn CursorStore>>,
+    source: Source<KinesisRecord>,
+    throttled: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+type ShardTask<'a> = Pin<Box<dyn Future<Output = Result<(String, Vec<Value>)>> + 'a>>;
+
+impl KinesisSource {
+    pub async fn new(config: AwsConfig, stream: &str) -> Result<Self> {
+        if stream.trim().is_empty() {
+            return Err(Error::config("kinesis stream name is empty"));
+        }
+        Ok(Self {
+            client: AwsClient::new(config, "kinesis", "Kinesis_20131202")?,
+            stream: stream.to_string(),
+            start: StartPosition::Latest,
+            period: Duration::from_secs(1),
+            cursors: None,
+            source: Source::new(),
+            throttled: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    // Where shards without a saved position start; `Latest` by default.
+    pub fn with_start(mut self, start: StartPosition) -> Self {
+        self.start = start;
+        self
+    }
+
+    // How long a caught-up shard waits before asking again; 1s by default.
+    pub fn with_poll_interval(mut self, period: Duration) -> Self {
+        self.period = period.max(CATCH_UP_DELAY);
+        self
+    }
+
+    pub fn with_cursor_store(mut self, cursors: Rc<dyn CursorStore>) -> Self {
+        self.cursors = Some(cursors);
+        self
+    }
+
+    pub fn source(&self) -> &Source<KinesisRecord> {
+        &self.source
+    }
+
+    // GetRecords calls refused for exceeding the shard's throughput.
+    pub fn throttled(&self) -> u64 {
+        self.throttled.get()
+    }
+
+    // Record payload bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        // shard -> its parents, as far as they are known
+        let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+        let mut token: Option<String> = None;
+        loop {
+            let body = match &token {
+                Some(token) => json!({ "NextToken": token }),
+                None => json!({ "StreamName": self.stream }),
+            };
+            let response = self.client.call("ListShards", &body).await?;
+            for shard in response["Shards"].as_array().into_iter().flatten() {
+                let id = text(&shard["ShardId"]);
+                let shard_parents = ["ParentShardId", "AdjacentParentShardId"]
+                    .iter()
+                    .filter_map(|field| shard[*field].as_str().map(str::to_string))
+                    .collect();
+                parents.insert(id, shard_parents);
+            }
+            match response["NextToken"].as_str() {
+                Some(next) => token = Some(next.to_string()),
+                None => break,
+            }
+        }
+
+        let mut ended: HashSet<String> = parents
+            .keys()
+            .filter(|shard| self.load(shard).as_deref() == Some(SHARD_END))
+            .cloned()
+            .collect();
+        let mut started = ended.clone();
+        let mut tasks: FuturesUnordered<ShardTask<'_>> = FuturesUnordered::new();
+        let mut ready: Vec<String> = parents
+            .keys()
+            .filter(|shard| readable(shard, &parents, &ended))
+            .cloned()
+            .collect();
+        ready.sort();
+        for shard in ready {
+            started.insert(shard.clone());
+            tasks.push(Box::pin(self.consume(shard)));
+        }
+
+        while let Some(finished) = tasks.next().await {
+            let (shard, children) = finished?;
+            ended.insert(shard);
+            for child in children {
+                let id = text(&child["ShardId"]);
+                let child_parents = child["ParentShards"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|parent| parent.as_str().map(str::to_string))
+                    .collect();
+                parents.entry(id).or_insert(child_parents);
+            }
+            let mut ready: Vec<String> = parents
+                .keys()
+                .filter(|shard| !started.contains(*shard) && readable(shard, &parents, &ended))
+                .cloned()
+                .collect();
+            ready.sort();
+            for shard in ready {
+                started.insert(shard.clone());
+                tasks.push(Box::pin(self.consume(shard)));
+            }
+        }
+        Ok(())
+    }
+
+    // Reads one shard to its end; returns its children.
+    async fn consume(&self, shard: String) -> Result<(String, Vec<Value>)> {
+        let mut last = self.load(&shard);
+        let mut iterator = self.iterator(&shard, last.as_deref()).await?;
+        loop {
+            let body = json!({ "ShardIterator": iterator, "Limit": 10_000 });
+            let response = match self.client.call("GetRecords", &body).await {
+                Ok(response) => response,
+                Err(err) if is_service_error(&err, "ProvisionedThroughputExceededException") => {
+                    self.throttled.set(self.throttled.get() + 1);
+                    sleep(self.period).await;
+                    continue;
+                }
+                Err(err) if is_service_error(&err, "ExpiredIteratorException") => {
+                    iterator = self.iterator(&shard, last.as_deref()).await?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let records = response["Records"].as_array().cloned().unwrap_or_default();
+            for record in &records {
+                self.emit(&shard, record)?;
+            }
+            if let Some(sequence) = records.last().map(|record| text(&record["SequenceNumber"])) {
+                self.save(&shard, &sequence)?;
+                last = Some(sequence);
+            }
+
+            match response["NextShardIterator"].as_str() {
+                Some(next) => iterator = next.to_string(),
+                None => {
+                    self.save(&shard, SHARD_END)?;
+                    let children = response["ChildShards"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default();
+                    return Ok((shard, children));
+                }
+            }
+            let behind = response["MillisBehindLatest"].as_u64().unwrap_or(0);
+            sleep(if behind > 0 && !records.is_empty() {
+                CATCH_UP_DELAY
+            } else {
+                self.period
+            })
+            .await;
+        }
+    }
+
+    async fn iterator(&self, shard: &str, after: Option<&str>) -> Result<String> {
+        let body = match after {
+            Some(sequence) => json!({
+                "StreamName": self.stream,
+                "ShardId": shard,
+                "ShardIteratorType": "AFTER_SEQUENCE_NUMBER",
+                "StartingSequenceNumber": sequence,
+            }),
+            None => json!({
+                "StreamName": self.stream,
+                "ShardId": shard,
+                "ShardIteratorType": match self.start {
+                    StartPosition::TrimHorizon => "TRIM_HORIZON",
+                    StartPosition::Latest => "LATEST",
+                },
+            }),
+        };
+        let response = self.client.call("GetShardIterator", &body).await?;
+        response["ShardIterator"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                Error::protocol(self.client.label(), "GetShardIterator returned no iterator")
+            })
+    }
+
+    fn emit(&self, shard: &str, record: &Value) -> Result<()> {
+        let label = self.client.label();
+        let data = BASE64
+            .decode(record["Data"].as_str().unwrap_or_default())
+            .map_err(|err| Error::decode(label, err))?;
+        self.bytes.set(self.bytes.get() + data.len() as u64);
+        let sequence_number = text(&record["SequenceNumber"]);
+        let arrival_ms = record["ApproximateArrivalTimestamp"]
+            .as_f64()
+            .map_or(0, |seconds| (seconds * 1000.0) as u64);
+        let partition_key = text(&record["PartitionKey"]);
+        let records = deaggregate(&data).unwrap_or_else(|| vec![(partition_key, data)]);
+        for (sub_sequence, (partition_key, data)) in records.into_iter().enumerate() {
+            self.source.emit(KinesisRecord {
+                shard_id: shard.to_string(),
+                sequence_number: sequence_number.clone(),
+                sub_sequence: sub_sequence as u64,
+                partition_key,
+                data,
+                arrival_ms,
+            });
+        }
+        Ok(())
+    }
+
+    fn key(&self, shard: &str) -> String {
+        format!("kinesis/{}/{}", self.stream, shard)
+    }
+
+    fn load(&self, shard: &str) -> Option<String> {
+        self.cursors.as_ref()?.load(&self.key(shard))
+    }
+
+    fn save(&self, shard: &str, cursor: &str) -> Result<()> {
+        match &self.cursors {
+            Some(cursors) => cursors.save(&self.key(shard), cursor),
+            None => Ok(()),
+        }
+    }
+}
+
+// Not read yet, and every parent we know of has been read to its end.
+fn readable(shard: &str, parents: &HashMap<String, Vec<String>>, ended: &HashSet<String>) -> bool {
+    !ended.contains(shard)
+        && parents[shard]
+            .iter()
+            .all(|parent| ended.contains(parent) || !parents.contains_key(parent))
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+// Sends items as JSON through `PutRecords`, batched up to the call's limits
+// and sent at each flush. With aggregation, items are first packed into
+// KPL-format aggregated records (keyed by their first item's partition key),
+// which `KinesisSource` and the KCL unpack again.
+pub struct KinesisSink<T> {
+    producer: Rc<Producer>,
+    partition_key: Box<dyn Fn(&T) -> String>,
+    aggregate_bytes: usize,
+    aggregate: RefCell<Vec<(String, Vec<u8>)>>,
+    aggregated: Cell<usize>,
+}
+
+impl<T> KinesisSink<T>
+where
+    T: Serialize + 'static,
+{
+    pub fn new<F>(config: AwsConfig, stream: &str, partition_key: F) -> Result<Self>
+    where
+        F: Fn(&T) -> String + 'static,
+    {
+        let client = AwsClient::new(config, "kinesis", "Kinesis_20131202")?;
+        Ok(Self {
+            producer: Rc::new(Producer::new(client, Target::KinesisStream, stream)),
+            partition_key: Box::new(partition_key),
+            aggregate_bytes: 0,
+            aggregate: RefCell::new(Vec::new()),
+            aggregated: Cell::new(0),
+        })
+    }
+
+    // Packs items into aggregated records of up to `max_bytes` (at most
+    // 1 MiB, the record limit); 0, the default, sends one record per item.
+    pub fn with_aggregation(mut self, max_bytes: usize) -> Self {
+        self.aggregate_bytes = max_bytes.min(1 << 20);
+        self
+    }
+
+    // Records accepted by Kinesis so far; aggregated records count once.
+    pub fn sent(&self) -> u64 {
+        self.producer.sent()
+    }
+
+    // Records dropped because their call failed as a whole.
+    pub fn failed(&self) -> u64 {
+        self.producer.failed()
+    }
+
+    fn seal(&self) {
+        let items = std::mem::take(&mut *self.aggregate.borrow_mut());
+        self.aggregated.set(0);
+        let Some((partition_key, _)) = items.first() else {
+            return;
+        };
+        let partition_key = partition_key.clone();
+        self.producer.push(PendingRecord {
+            partition_key: Some(partition_key),
+            data: aggregate(&items),
+        });
+    }
+}
+
+impl<T> Sink<T> for KinesisSink<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        let data = match serde_json::to_vec(item) {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn(
+                    "kinesis",
+                    format_args!("kinesis sink could not encode an item: {}", err),
+                );
+                return;
+            }
+        };
+        let partition_key = (self.partition_key)(item);
+        if self.aggregate_bytes == 0 {
+            self.producer.push(PendingRecord {
+                partition_key: Some(partition_key),
+                data,
+            });
+            return;
+        }
+        // protobuf framing, the key table entry and the checksum
+        let size = data.len() + partition_key.len() + 16;
+        if self.aggregated.get() + size + 20 > self.aggregate_bytes {
+            self.seal();
+        }
+        self.aggregated.set(self.aggregated.get() + size);
+        self.aggregate.borrow_mut().push((partition_key, data));
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.seal();
+        self.producer.flush();
+        Ok(())
+    }
+}
+
+// KPL aggregated record: magic, an `AggregatedRecord` protobuf and its MD5.
+fn aggregate(items: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut keys: Vec<&str> = Vec::new();
+    let mut message = Vec::new();
+    let mut records = Vec::new();
+    for (key, data) in items {
+        let index = match keys.iter().position(|known| known == key) {
+            Some(index) => index,
+            None => {
+                keys.push(key);
+                keys.len() - 1
+            }
+        };
+        let mut record = Vec::new();
+        put_varint(&mut record, 1 << 3);
+        put_varint(&mut record, index as u64);
+        put_bytes(&mut record, 3, data);
+        records.push(record);
+    }
+    for key in keys {
+        put_bytes(&mut message, 1, key.as_bytes());
+    }
+    for record in records {
+        put_bytes(&mut message, 3, &record);
+    }
+    let mut out = AGGREGATED_MAGIC.to_vec();
+    out.extend_from_slice(&message);
+    out.extend_from_slice(&Md5::digest(&message));
+    out
+}
+
+// The (partition key, data) pairs of an aggregated record; `None` for plain
+// records and anything that does not check out.
+fn deaggregate(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    if data.len() < AGGREGATED_MAGIC.len() + 16 || data[..4] != AGGREGATED_MAGIC {
+        return None;
+    }
+    let (message, checksum) = data[4..].split_at(data.len() - 4 - 16);
+    if Md5::digest(message).as_slice() != checksum {
+        return None;
+    }
+    let mut keys = Vec::new();
+    let mut records = Vec::new();
+    for (field, value) in fields(message)? {
+        match (field, value) {
+            (1, Field::Bytes(key)) => keys.push(String::from_utf8(key.to_vec()).ok()?),
+            (3, Field::Bytes(record)) => {
+                let mut index = 0;
+                let mut payload = Vec::new();
+                for (field, value) in fields(record)? {
+                    match (field, value) {
+                        (1, Field::Varint(value)) => index = value as usize,
+                        (3, Field::Bytes(bytes)) => payload = bytes.to_vec(),
+                        _ => {}
+                    }
+                }
+                records.push((index, payload));
+            }
+            _ => {}
+        }
+    }
+    records
+        .into_iter()
+        .map(|(index, payload)| Some((keys.get(index)?.clone(), payload)))
+        .collect()
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn fields(mut message: &[u8]) -> Option<Vec<(u64, Field<'_>)>> {
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        let tag = take_varint(&mut message)?;
+        let field = match tag & 7 {
+            0 => Field::Varint(take_varint(&mut message)?),
+            2 => {
+                let len = take_varint(&mut message)? as usize;
+                if len > message.len() {
+                    return None;
+                }
+                let (bytes, rest) = message.split_at(len);
+                message = rest;
+                Field::Bytes(bytes)
+            }
+            _ => return None,
+        };
+        fields.push((tag >> 3, field));
+    }
+    Some(fields)
+}
+
+fn take_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
@@ -0,0 +1,455 @@
+use crate::drain::InFlight;
+use crate::log;
+use crate::rt;
+use crate::sources::http_client::{client, request_error};
+use crate::{Credentials, Error, Result, SharedCredentials};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod firehose;
+mod kinesis;
+#[cfg(feature = "secrets-manager")]
+mod secrets_manager;
+
+pub use firehose::FirehoseSink;
+pub use kinesis::{KinesisRecord, KinesisSink, KinesisSource, StartPosition};
+#[cfg(feature = "secrets-manager")]
+pub use secrets_manager::SecretsManagerCredentials;
+
+#[derive(Clone, Debug)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn new(access_key_id: &str, secret_access_key: &str) -> Self {
+        Self {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+        }
+    }
+
+    pub fn with_session_token(mut self, token: &str) -> Self {
+        self.session_token = Some(token.to_string());
+        self
+    }
+
+    // `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+    pub fn from_env() -> Result<Self> {
+        let access_key_id = required("AWS_ACCESS_KEY_ID")?;
+        let secret_access_key = required("AWS_SECRET_ACCESS_KEY")?;
+        Ok(Self {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    // The `access_key_id`, `secret_access_key` and optional `session_token`
+    // credentials, as `EnvCredentials::prefixed("AWS_")` reads them.
+    pub fn from_credentials(credentials: &Credentials) -> Result<Self> {
+        Ok(Self {
+            access_key_id: credentials.require("access_key_id")?.to_string(),
+            secret_access_key: credentials.require("secret_access_key")?.to_string(),
+            session_token: credentials.get("session_token").map(str::to_string),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AwsConfig {
+    pub region: String,
+    pub credentials: AwsCredentials,
+    // read before every call instead of `credentials` when set, so rotated
+    // keys are picked up
+    pub credentials_provider: Option<SharedCredentials>,
+    // e.g. a LocalStack url; the regional endpoint otherwise
+    pub endpoint: Option<String>,
+}
+
+impl AwsConfig {
+    pub fn new(region: &str, credentials: AwsCredentials) -> Self {
+        Self {
+            region: region.to_string(),
+            credentials,
+            credentials_provider: None,
+            endpoint: None,
+        }
+    }
+
+    // Signs with the credentials `provider` holds at the time of each call
+    // (see `AwsCredentials::from_credentials`).
+    pub fn from_provider(region: &str, provider: &SharedCredentials) -> Self {
+        Self {
+            credentials_provider: Some(provider.clone()),
+            ..Self::new(region, AwsCredentials::new("", ""))
+        }
+    }
+
+    // `AWS_REGION` (or `AWS_DEFAULT_REGION`) and the credentials variables.
+    pub fn from_env() -> Result<Self> {
+        let region = required("AWS_REGION").or_else(|_| required("AWS_DEFAULT_REGION"))?;
+        Ok(Self::new(&region, AwsCredentials::from_env()?))
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.trim_end_matches('/').to_string());
+        self
+    }
+}
+
+fn required(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| Error::config(format!("{} is not set", name)))
+}
+
+// Signed calls to an AWS JSON 1.1 API such as Kinesis or Firehose.
+pub(crate) struct AwsClient {
+    http: reqwest::Client,
+    config: AwsConfig,
+    service: &'static str,
+    target: &'static str,
+    url: String,
+    host: String,
+}
+
+impl AwsClient {
+    pub(crate) fn new(
+        config: AwsConfig,
+        service: &'static str,
+        target: &'static str,
+    ) -> Result<Self> {
+        let url = match &config.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => format!("https://{}.{}.amazonaws.com", service, config.region),
+        };
+        let parsed = reqwest::Url::parse(&url)
+            .map_err(|err| Error::config(format!("{} endpoint {:?}: {}", service, url, err)))?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(Error::config(format!(
+                    "{} endpoint {:?} has no host",
+                    service, url
+                )))
+            }
+        };
+        Ok(Self {
+            http: client(&url)?,
+            config,
+            service,
+            target,
+            url,
+            host,
+        })
+    }
+
+    pub(crate) fn label(&self) -> &str {
+        &self.url
+    }
+
+    // Service errors come back as `Error::Protocol` with a message starting
+    // with the error type, e.g. `ExpiredIteratorException: ...`.
+    pub(crate) async fn call(&self, action: &str, body: &Value) -> Result<Value> {
+        let label = &self.url;
+        let body = body.to_string();
+        let target = format!("{}.{}", self.target, action);
+        let date = amz_date(SystemTime::now());
+        let provided = match &self.config.credentials_provider {
+            Some(provider) => Some(AwsCredentials::from_credentials(&provider.get().await?)?),
+            None => None,
+        };
+        let credentials = provided.as_ref().unwrap_or(&self.config.credentials);
+        let authorization = self.authorization(credentials, &date, &target, &body);
+
+        let mut request = self
+            .http
+            .post(format!("{}/", self.url))
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-date", &date)
+            .header("x-amz-target", &target)
+            .header("authorization", authorization);
+        if let Some(token) = &credentials.session_token {
+            request = request.header("x-amz-security-token", token);
+        }
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let value: Value = if text.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(&text).map_err(|err| Error::decode(label, err))?
+        };
+        if status.is_success() {
+            return Ok(value);
+        }
+        let kind = value["__type"].as_str().unwrap_or("UnknownError");
+        let kind = kind.rsplit('#').next().unwrap_or(kind);
+        let message = value["message"]
+            .as_str()
+            .or(value["Message"].as_str())
+            .unwrap_or("");
+        Err(Error::protocol(
+            label,
+            format!("{}: {} ({})", kind, message, status),
+        ))
+    }
+
+    fn authorization(
+        &self,
+        credentials: &AwsCredentials,
+        date: &str,
+        target: &str,
+        body: &str,
+    ) -> String {
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1"),
+            ("host", self.host.as_str()),
+            ("x-amz-date", date),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.as_str()));
+        }
+        headers.push(("x-amz-target", target));
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "POST\n/\n\n{}\n{}\n{}",
+            canonical_headers,
+            signed_headers,
+            hex(&Sha256::digest(body.as_bytes()))
+        );
+        let day = &date[..8];
+        let scope = format!(
+            "{}/{}/{}/aws4_request",
+            day, self.config.region, self.service
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = hmac(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            day,
+        );
+        for part in [self.config.region.as_str(), self.service, "aws4_request"] {
+            key = hmac(&key, part);
+        }
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id,
+            scope,
+            signed_headers,
+            hex(&hmac(&key, &string_to_sign))
+        )
+    }
+}
+
+pub(crate) fn is_service_error(err: &Error, kind: &str) -> bool {
+    matches!(err, Error::Protocol { message, .. } if message.starts_with(kind))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// `YYYYMMDDTHHMMSSZ`, in UTC.
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rest) = (seconds / 86_400, seconds % 86_400);
+    // days since the epoch to a civil date, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
+
+// Where `PutRecords` / `PutRecordBatch` sends its records.
+pub(crate) enum Target {
+    KinesisStream,
+    FirehoseDeliveryStream,
+}
+
+// One record waiting to be sent, with a partition key for Kinesis.
+pub(crate) struct PendingRecord {
+    pub(crate) partition_key: Option<String>,
+    pub(crate) data: Vec<u8>,
+}
+
+impl PendingRecord {
+    fn size(&self) -> usize {
+        self.data.len() + self.partition_key.as_ref().map_or(0, String::len)
+    }
+}
+
+// Batches records into `PutRecords` / `PutRecordBatch` calls. Calls run on
+// the engine's local task set and count as in-flight work, so a draining
+// engine waits for them; records the service rejects (throttling, mostly)
+// go out again with the next batch.
+pub(crate) struct Producer {
+    client: AwsClient,
+    target: Target,
+    stream: String,
+    max_records: usize,
+    max_bytes: usize,
+    pending: RefCell<Vec<PendingRecord>>,
+    pending_bytes: Cell<usize>,
+    sent: Cell<u64>,
+    failed: Cell<u64>,
+}
+
+impl Producer {
+    pub(crate) fn new(client: AwsClient, target: Target, stream: &str) -> Self {
+        let (max_records, max_bytes) = match target {
+            Target::KinesisStream => (500, 5 << 20),
+            Target::FirehoseDeliveryStream => (500, 4 << 20),
+        };
+        Self {
+            client,
+            target,
+            stream: stream.to_string(),
+            max_records,
+            max_bytes,
+            pending: RefCell::new(Vec::new()),
+            pending_bytes: Cell::new(0),
+            sent: Cell::new(0),
+            failed: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent.get()
+    }
+
+    pub(crate) fn failed(&self) -> u64 {
+        self.failed.get()
+    }
+
+    pub(crate) fn push(self: &Rc<Self>, record: PendingRecord) {
+        let full = {
+            let pending = self.pending.borrow();
+            pending.len() >= self.max_records
+                || self.pending_bytes.get() + record.size() > self.max_bytes
+        };
+        if full {
+            self.flush();
+        }
+        self.pending_bytes
+            .set(self.pending_bytes.get() + record.size());
+        self.pending.borrow_mut().push(record);
+    }
+
+    pub(crate) fn flush(self: &Rc<Self>) {
+        let records = std::mem::take(&mut *self.pending.borrow_mut());
+        self.pending_bytes.set(0);
+        if records.is_empty() {
+            return;
+        }
+        let producer = self.clone();
+        let tracked = InFlight::start();
+        rt::spawn_local(async move {
+            let _tracked = tracked;
+            producer.send(records).await;
+        });
+    }
+
+    async fn send(self: Rc<Self>, records: Vec<PendingRecord>) {
+        let entries: Vec<Value> = records
+            .iter()
+            .map(|record| match &record.partition_key {
+                Some(key) => json!({ "Data": BASE64.encode(&record.data), "PartitionKey": key }),
+                None => json!({ "Data": BASE64.encode(&record.data) }),
+            })
+            .collect();
+        let (action, body, results) = match self.target {
+            Target::KinesisStream => (
+                "PutRecords",
+                json!({ "StreamName": self.stream, "Records": entries }),
+                "Records",
+            ),
+            Target::FirehoseDeliveryStream => (
+                "PutRecordBatch",
+                json!({ "DeliveryStreamName": self.stream, "Records": entries }),
+                "RequestResponses",
+            ),
+        };
+        let response = match self.client.call(action, &body).await {
+            Ok(response) => response,
+            Err(err) => {
+                self.failed.set(self.failed.get() + records.len() as u64);
+                log::warn(
+                    "aws",
+                    format_args!("{} {} failed: {}", action, self.stream, err),
+                );
+                return;
+            }
+        };
+        let outcomes = response[results].as_array().cloned().unwrap_or_default();
+        let mut retry = Vec::new();
+        for (index, record) in records.into_iter().enumerate() {
+            if outcomes
+                .get(index)
+                .is_some_and(|outcome| outcome.get("ErrorCode").is_some_and(|code| !code.is_null()))
+            {
+                retry.push(record);
+            } else {
+                self.sent.set(self.sent.get() + 1);
+            }
+        }
+        for record in retry {
+            self.pending_bytes
+                .set(self.pending_bytes.get() + record.size());
+            self.pending.borrow_mut().push(record);
+        }
+    }
+}
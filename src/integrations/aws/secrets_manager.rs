@@ -0,0 +1,63 @@
+use super::{AwsClient, AwsConfig};
+use crate::credentials::from_json;
+use crate::{Credentials, CredentialsProvider, Error, Result};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+
+// A secret in AWS Secrets Manager. A JSON object secret (the console's
+// key/value form) gives one credential per key; any other string is a
+// single value under `secret`.
+pub struct SecretsManagerCredentials {
+    name: String,
+    client: AwsClient,
+    secret_id: String,
+    version_stage: Option<String>,
+}
+
+impl SecretsManagerCredentials {
+    // `secret_id` is the secret's name or ARN; `config` the credentials
+    // allowed to read it.
+    pub fn new(config: AwsConfig, secret_id: &str) -> Result<Self> {
+        Ok(Self {
+            name: format!("secretsmanager {}", secret_id),
+            client: AwsClient::new(config, "secretsmanager", "secretsmanager")?,
+            secret_id: secret_id.to_string(),
+            version_stage: None,
+        })
+    }
+
+    // e.g. `AWSPENDING` during a rotation; `AWSCURRENT` by default.
+    pub fn with_version_stage(mut self, stage: &str) -> Self {
+        self.version_stage = Some(stage.to_string());
+        self
+    }
+
+    async fn read(&self) -> Result<Credentials> {
+        let mut body = json!({ "SecretId": self.secret_id });
+        if let Some(stage) = &self.version_stage {
+            body["VersionStage"] = json!(stage);
+        }
+        let response = self.client.call("GetSecretValue", &body).await?;
+        let secret = response["SecretString"].as_str().ok_or_else(|| {
+            Error::protocol(
+                &self.name,
+                "no SecretString in the response (binary secret?)",
+            )
+        })?;
+        Ok(match serde_json::from_str::<Value>(secret) {
+            Ok(Value::Object(object)) => from_json(&object),
+            _ => Credentials::new().with("secret", secret),
+        })
+    }
+}
+
+impl CredentialsProvider for SecretsManagerCredentials {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch<'a>(&'a self) -> Pin<Box<dyn Future<Output = Result<Credentials>> + 'a>> {
+        Box::pin(self.read())
+    }
+}
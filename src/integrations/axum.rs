@@ -0,0 +1,735 @@
+use crate::backpressure;
+use crate::metrics::MetricsRegistry;
+#[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+use crate::sources::websocket_client::WebSocketPool;
+use crate::{EngineHandle, EnginePlan, EngineStatus, Error, HealthCheck, Result, Stream};
+use ::axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use ::axum::extract::Path;
+use ::axum::http::{header, StatusCode};
+use ::axum::response::sse::{Event, KeepAlive, Sse};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::{get, post};
+use ::axum::{Json, Router};
+use futures_util::stream;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::sync::{broadcast, watch};
+
+const BROADCAST_CAPACITY: usize = 1024;
+
+// Handle to a stream that axum handlers can share across threads.
+pub struct StreamChannel<T> {
+    updates: broadcast::Sender<T>,
+    latest: watch::Receiver<Option<T>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Clone for StreamChannel<T> {
+    fn clone(&self) -> Self {
+        Self {
+            updates: self.updates.clone(),
+            latest: self.latest.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<T> StreamChannel<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    pub fn new(stream: &Stream<T>) -> Self {
+        Self::named("stream_channel", stream)
+    }
+
+    // Items skipped by lagging subscribers are reported as backpressure under
+    // `name` the next time the stream emits.
+    pub fn named(name: &str, stream: &Stream<T>) -> Self {
+        let updates = stream.to_broadcast(BROADCAST_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let name = name.to_string();
+        let updates_clone = updates.clone();
+        let dropped_clone = dropped.clone();
+        stream.sink(move |_| {
+            let skipped = dropped_clone.swap(0, Ordering::Relaxed);
+            if skipped > 0 {
+                backpressure::report(&name, updates_clone.len(), skipped);
+            }
+        });
+
+        Self {
+            updates,
+            latest: stream.to_watch(),
+            dropped,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<T> {
+        self.updates.subscribe()
+    }
+
+    pub fn latest(&self) -> Option<T> {
+        self.latest.borrow().clone()
+    }
+}
+
+pub trait StreamRouterExt {
+    // Adds `{path}/sse`, `{path}/ws` and `{path}/latest`.
+    fn stream_routes<T>(self, path: &str, stream: &Stream<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static;
+
+    fn sse_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static;
+
+    fn ws_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static;
+
+    fn latest_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static;
+
+    // Adds `{path}` (the names), `{path}/{name}` and `{path}/{name}/{key}`.
+    #[cfg(feature = "query")]
+    fn query_routes(self, path: &str, server: &QueryServer) -> Self;
+
+    // Adds `{path}/topology`, `{path}/sources`, `POST
+    // {path}/sources/{label}/{pause,resume,restart}`, `{path}/metrics` (with
+    // `AdminServer::with_metrics`) and `{path}/subscriptions/{label}` (GET,
+    // and POST / DELETE with a JSON array of channels).
+    fn admin_routes(self, path: &str, server: &AdminServer) -> Self;
+
+    // Adds `{path}/healthz` (liveness) and `{path}/readyz` (readiness),
+    // answering 200 or 503 with the problems found by the server's
+    // `HealthCheck`.
+    fn health_routes(self, path: &str, server: &AdminServer) -> Self;
+}
+
+impl<S> StreamRouterExt for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn stream_routes<T>(self, path: &str, stream: &Stream<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static,
+    {
+        let path = path.trim_end_matches('/');
+        let channel = StreamChannel::named(path, stream);
+        self.sse_route(&format!("{}/sse", path), channel.clone())
+            .ws_route(&format!("{}/ws", path), channel.clone())
+            .latest_route(&format!("{}/latest", path), channel)
+    }
+
+    fn sse_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static,
+    {
+        self.route(
+            path,
+            get(move || {
+                let receiver = channel.subscribe();
+                let dropped = channel.dropped.clone();
+                async move {
+                    let events =
+                        stream::unfold((receiver, dropped), |(mut receiver, dropped)| async move {
+                            let item = next_item(&mut receiver, &dropped).await?;
+                            Some((Event::default().json_data(item), (receiver, dropped)))
+                        });
+                    Sse::new(events).keep_alive(KeepAlive::default())
+                }
+            }),
+        )
+    }
+
+    fn ws_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static,
+    {
+        self.route(
+            path,
+            get(move |upgrade: WebSocketUpgrade| {
+                let receiver = channel.subscribe();
+                let dropped = channel.dropped.clone();
+                async move { upgrade.on_upgrade(move |socket| forward(socket, receiver, dropped)) }
+            }),
+        )
+    }
+
+    fn latest_route<T>(self, path: &str, channel: StreamChannel<T>) -> Self
+    where
+        T: Serialize + Clone + Send + Sync + 'static,
+    {
+        self.route(
+            path,
+            get(move || {
+                let latest = channel.latest();
+                async move {
+                    match latest {
+                        Some(item) => Json(item).into_response(),
+                        None => StatusCode::NO_CONTENT.into_response(),
+                    }
+                }
+            }),
+        )
+    }
+
+    #[cfg(feature = "query")]
+    fn query_routes(self, path: &str, server: &QueryServer) -> Self {
+        let path = path.trim_end_matches('/');
+        let names = server.requests.clone();
+        let snapshot = server.requests.clone();
+        let entry = server.requests.clone();
+        self.route(path, get(move || ask(names.clone(), QueryRequest::Names)))
+            .route(
+                &format!("{}/{{name}}", path),
+                get(move |Path(name): Path<String>| {
+                    ask(snapshot.clone(), |reply| {
+                        QueryRequest::Snapshot(name, reply)
+                    })
+                }),
+            )
+            .route(
+                &format!("{}/{{name}}/{{key}}", path),
+                get(move |Path((name, key)): Path<(String, String)>| {
+                    ask(entry.clone(), |reply| QueryRequest::Get(name, key, reply))
+                }),
+            )
+    }
+
+    fn admin_routes(self, path: &str, server: &AdminServer) -> Self {
+        let path = path.trim_end_matches('/');
+        let topology = server.requests.clone();
+        let sources = server.requests.clone();
+        let control = server.requests.clone();
+        let listed = server.requests.clone();
+        let added = server.requests.clone();
+        let removed = server.requests.clone();
+        let router = self
+            .route(
+                &format!("{}/topology", path),
+                get(move || admin(topology.clone(), AdminRequest::Topology)),
+            )
+            .route(
+                &format!("{}/sources", path),
+                get(move || admin(sources.clone(), AdminRequest::Sources)),
+            )
+            .route(
+                &format!("{}/sources/{{label}}/{{action}}", path),
+                post(move |Path((label, action)): Path<(String, String)>| {
+                    admin(control.clone(), |reply| {
+                        AdminRequest::Control(label, action, reply)
+                    })
+                }),
+            )
+            .route(
+                &format!("{}/subscriptions/{{label}}", path),
+                get(move |Path(label): Path<String>| {
+                    admin(listed.clone(), |reply| {
+                        AdminRequest::Subscriptions(label, reply)
+                    })
+                })
+                .post(
+                    move |Path(label): Path<String>, Json(channels): Json<Vec<String>>| {
+                        admin(added.clone(), |reply| {
+                            AdminRequest::Subscribe(label, channels, true, reply)
+                        })
+                    },
+                )
+                .delete(
+                    move |Path(label): Path<String>, Json(channels): Json<Vec<String>>| {
+                        admin(removed.clone(), |reply| {
+                            AdminRequest::Subscribe(label, channels, false, reply)
+                        })
+                    },
+                ),
+            );
+        match server.metrics.clone() {
+            Some(metrics) => router.route(
+                &format!("{}/metrics", path),
+                get(move || {
+                    let body = metrics.render();
+                    async move { ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body) }
+                }),
+            ),
+            None => router,
+        }
+    }
+
+    fn health_routes(self, path: &str, server: &AdminServer) -> Self {
+        let path = path.trim_end_matches('/');
+        let timeout = server.health.timeout();
+        let live = server.requests.clone();
+        let ready = server.requests.clone();
+        self.route(
+            &format!("{}/healthz", path),
+            get(move || probe(live.clone(), false, timeout)),
+        )
+        .route(
+            &format!("{}/readyz", path),
+            get(move || probe(ready.clone(), true, timeout)),
+        )
+    }
+}
+
+// Slow clients skip items they lagged behind on rather than disconnecting.
+async fn next_item<T: Clone>(
+    receiver: &mut broadcast::Receiver<T>,
+    dropped: &AtomicU64,
+) -> Option<T> {
+    loop {
+        match receiver.recv().await {
+            Ok(item) => return Some(item),
+            Err(RecvError::Lagged(skipped)) => {
+                dropped.fetch_add(skipped, Ordering::Relaxed);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn forward<T>(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<T>,
+    dropped: Arc<AtomicU64>,
+) where
+    T: Serialize + Clone,
+{
+    loop {
+        tokio::select! {
+            item = next_item(&mut receiver, &dropped) => {
+                let Some(item) = item else { break };
+                let Ok(text) = serde_json::to_string(&item) else { continue };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Serves a router for as long as the engine runs.
+pub struct HttpServer {
+    addr: SocketAddr,
+    router: Router,
+}
+
+impl HttpServer {
+    pub fn new(addr: SocketAddr, router: Router) -> Self {
+        Self { addr, router }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let label = format!("http://{}", self.addr);
+        let listener = tokio::net::TcpListener::bind(self.addr)
+            .await
+            .map_err(|err| Error::io(&label, err))?;
+        ::axum::serve(listener, self.router.clone())
+            .await
+            .map_err(|err| Error::io(&label, err))
+    }
+}
+
+#[cfg(feature = "query")]
+type Reply = oneshot::Sender<Option<Value>>;
+
+#[cfg(feature = "query")]
+enum QueryRequest {
+    Names(Reply),
+    Snapshot(String, Reply),
+    Get(String, String, Reply),
+}
+
+// Answers `query_routes` requests from the engine's thread, where the
+// queryable state lives; register it as a source next to the `HttpServer`.
+#[cfg(feature = "query")]
+pub struct QueryServer {
+    handle: EngineHandle,
+    requests: UnboundedSender<QueryRequest>,
+    receiver: RefCell<Option<UnboundedReceiver<QueryRequest>>>,
+}
+
+#[cfg(feature = "query")]
+impl QueryServer {
+    pub fn new(handle: &EngineHandle) -> Self {
+        let (requests, receiver) = unbounded_channel();
+        Self {
+            handle: handle.clone(),
+            requests,
+            receiver: RefCell::new(Some(receiver)),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver =
+            self.receiver
+                .borrow_mut()
+                .take()
+                .ok_or_else(|| Error::AlreadyStarted {
+                    source: "query_server".to_string(),
+                })?;
+        while let Some(request) = receiver.recv().await {
+            let _ = match request {
+                QueryRequest::Names(reply) => {
+                    reply.send(Some(Value::from(self.handle.query_names())))
+                }
+                QueryRequest::Snapshot(name, reply) => reply.send(self.handle.query(&name)),
+                QueryRequest::Get(name, key, reply) => {
+                    reply.send(self.handle.query_key(&name, &key))
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "query")]
+async fn ask<F>(requests: UnboundedSender<QueryRequest>, request: F) -> impl IntoResponse
+where
+    F: FnOnce(Reply) -> QueryRequest,
+{
+    let (reply, answer) = oneshot::channel();
+    if requests.send(request(reply)).is_err() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match answer.await {
+        Ok(Some(value)) => Json(value).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+type AdminReply = oneshot::Sender<(StatusCode, Value)>;
+
+enum AdminRequest {
+    Topology(AdminReply),
+    Sources(AdminReply),
+    // label, action
+    Control(String, String, AdminReply),
+    Subscriptions(String, AdminReply),
+    // label, channels, subscribe (or unsubscribe)
+    Subscribe(String, Vec<String>, bool, AdminReply),
+    // readiness (or liveness)
+    Health(bool, AdminReply),
+}
+
+// Answers `admin_routes` requests on the engine's thread, by way of an
+// `EngineHandle`; register it as a source next to the `HttpServer`. Pausing
+// or restarting the admin server's own label cuts off the API.
+pub struct AdminServer {
+    handle: EngineHandle,
+    metrics: Option<MetricsRegistry>,
+    health: HealthCheck,
+    #[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+    pools: Vec<(String, Arc<WebSocketPool>)>,
+    requests: UnboundedSender<AdminRequest>,
+    receiver: RefCell<Option<UnboundedReceiver<AdminRequest>>>,
+}
+
+impl AdminServer {
+    pub fn new(handle: &EngineHandle) -> Self {
+        let (requests, receiver) = unbounded_channel();
+        Self {
+            handle: handle.clone(),
+            metrics: None,
+            health: HealthCheck::new(),
+            #[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+            pools: Vec::new(),
+            requests,
+            receiver: RefCell::new(Some(receiver)),
+        }
+    }
+
+    // Serves `registry` on `{path}/metrics`; call before `admin_routes`.
+    pub fn with_metrics(mut self, registry: MetricsRegistry) -> Self {
+        self.metrics = Some(registry);
+        self
+    }
+
+    // Thresholds for `health_routes`; call before it.
+    pub fn with_health(mut self, health: HealthCheck) -> Self {
+        self.health = health;
+        self
+    }
+
+    // Manages the channels of the pool registered as `label`.
+    #[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+    pub fn with_subscriptions(
+        mut self,
+        label: impl Into<String>,
+        pool: Arc<WebSocketPool>,
+    ) -> Self {
+        self.pools.push((label.into(), pool));
+        self
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver =
+            self.receiver
+                .borrow_mut()
+                .take()
+                .ok_or_else(|| Error::AlreadyStarted {
+                    source: "admin_server".to_string(),
+                })?;
+        while let Some(request) = receiver.recv().await {
+            match request {
+                AdminRequest::Topology(reply) => {
+                    let answer = match self.handle.status().await {
+                        Some(status) => (StatusCode::OK, plan_json(&status.plan)),
+                        None => unavailable(),
+                    };
+                    let _ = reply.send(answer);
+                }
+                AdminRequest::Sources(reply) => {
+                    let answer = match self.handle.status().await {
+                        Some(status) => (StatusCode::OK, sources_json(&status)),
+                        None => unavailable(),
+                    };
+                    let _ = reply.send(answer);
+                }
+                AdminRequest::Control(label, action, reply) => {
+                    let _ = reply.send(self.control(label, &action).await);
+                }
+                AdminRequest::Subscriptions(label, reply) => {
+                    let _ = reply.send(self.subscriptions(&label, None));
+                }
+                AdminRequest::Subscribe(label, channels, subscribe, reply) => {
+                    let _ = reply.send(self.subscriptions(&label, Some((channels, subscribe))));
+                }
+                AdminRequest::Health(readiness, reply) => {
+                    let answer = match self.handle.status().await {
+                        Some(status) => health_json(&self.health, &status, readiness),
+                        None => unavailable(),
+                    };
+                    let _ = reply.send(answer);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn control(&self, label: String, action: &str) -> (StatusCode, Value) {
+        let Some(status) = self.handle.status().await else {
+            return unavailable();
+        };
+        if !status.sources.iter().any(|source| source.label == label) {
+            return not_found(format!("no source {:?}", label));
+        }
+        if status
+            .plan
+            .engines
+            .iter()
+            .any(|(engine, _)| *engine == label)
+        {
+            return (
+                StatusCode::CONFLICT,
+                json!({ "error": format!("{:?} is an engine", label) }),
+            );
+        }
+        match action {
+            "pause" => self.handle.pause_source(label.as_str()),
+            "resume" => self.handle.resume_source(label.as_str()),
+            "restart" => self.handle.restart_source(label.as_str()),
+            _ => return not_found(format!("no action {:?}", action)),
+        }
+        (
+            StatusCode::ACCEPTED,
+            json!({ "source": label, "action": action }),
+        )
+    }
+
+    #[cfg(all(feature = "websockets", not(target_arch = "wasm32")))]
+    fn subscriptions(
+        &self,
+        label: &str,
+        change: Option<(Vec<String>, bool)>,
+    ) -> (StatusCode, Value) {
+        let Some((_, pool)) = self.pools.iter().find(|(pool, _)| pool == label) else {
+            return not_found(format!("no subscriptions for {:?}", label));
+        };
+        let changed = match change {
+            None => None,
+            Some((channels, true)) => Some(pool.add_channels(&channels)),
+            Some((channels, false)) => match pool.remove_channels(&channels) {
+                Ok(removed) => Some(removed),
+                Err(err) => {
+                    return (StatusCode::CONFLICT, json!({ "error": err.to_string() }));
+                }
+            },
+        };
+        let mut body = json!({
+            "channels": pool.channels(),
+            "assignments": pool.assignments(),
+            "unassigned": pool.unassigned(),
+        });
+        if let Some(changed) = changed {
+            body["changed"] = Value::from(changed);
+        }
+        (StatusCode::OK, body)
+    }
+
+    #[cfg(not(all(feature = "websockets", not(target_arch = "wasm32"))))]
+    fn subscriptions(
+        &self,
+        label: &str,
+        _change: Option<(Vec<String>, bool)>,
+    ) -> (StatusCode, Value) {
+        not_found(format!("no subscriptions for {:?}", label))
+    }
+}
+
+fn plan_json(plan: &EnginePlan) -> Value {
+    let sources: Vec<Value> = plan
+        .sources
+        .iter()
+        .map(|source| {
+            json!({
+                "label": source.label,
+                "priority": source.priority,
+                "subscribers": source.subscribers,
+            })
+        })
+        .collect();
+    let timers: Vec<Value> = plan
+        .timers
+        .iter()
+        .map(|timer| {
+            json!({
+                "name": timer.name,
+                "period_ms": timer.period.as_millis() as u64,
+                "priority": timer.priority,
+            })
+        })
+        .collect();
+    let engines: serde_json::Map<String, Value> = plan
+        .engines
+        .iter()
+        .map(|(label, plan)| (label.clone(), plan_json(plan)))
+        .collect();
+    json!({
+        "sources": sources,
+        "timers": timers,
+        "streams": plan.streams,
+        "engines": engines,
+    })
+}
+
+fn sources_json(status: &EngineStatus) -> Value {
+    status
+        .sources
+        .iter()
+        .map(|source| {
+            json!({
+                "label": source.label,
+                "state": source.state.to_string(),
+                "messages": source.stats.messages,
+                "bytes": source.stats.bytes,
+                "errors": source.stats.errors,
+            })
+        })
+        .collect()
+}
+
+fn health_json(
+    health: &HealthCheck,
+    status: &EngineStatus,
+    readiness: bool,
+) -> (StatusCode, Value) {
+    let report = health.evaluate(status);
+    let mut problems = report.liveness.clone();
+    if readiness {
+        problems.extend(report.readiness.iter().cloned());
+    }
+    let ok = if readiness {
+        report.is_ready()
+    } else {
+        report.is_live()
+    };
+    let sources: Vec<Value> = status
+        .sources
+        .iter()
+        .map(|source| {
+            json!({
+                "label": source.label,
+                "state": source.state.to_string(),
+                "connected": source.connected,
+                "idle_ms": source.idle.as_millis() as u64,
+            })
+        })
+        .collect();
+    let code = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if ok { "ok" } else { "fail" },
+        "problems": problems,
+        "sources": sources,
+    });
+    (code, body)
+}
+
+// An engine too busy to answer within `timeout` fails the probe.
+async fn probe(
+    requests: UnboundedSender<AdminRequest>,
+    readiness: bool,
+    timeout: Duration,
+) -> Response {
+    let (reply, answer) = oneshot::channel();
+    if requests
+        .send(AdminRequest::Health(readiness, reply))
+        .is_err()
+    {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match tokio::time::timeout(timeout, answer).await {
+        Ok(Ok((status, body))) => (status, Json(body)).into_response(),
+        Ok(Err(_)) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "fail", "problems": ["the engine did not answer in time"] })),
+        )
+            .into_response(),
+    }
+}
+
+fn not_found(message: String) -> (StatusCode, Value) {
+    (StatusCode::NOT_FOUND, json!({ "error": message }))
+}
+
+fn unavailable() -> (StatusCode, Value) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        json!({ "error": "the engine is not running" }),
+    )
+}
+
+async fn admin<F>(requests: UnboundedSender<AdminRequest>, request: F) -> Response
+where
+    F: FnOnce(AdminReply) -> AdminRequest,
+{
+    let (reply, answer) = oneshot::channel();
+    if requests.send(request(reply)).is_err() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match answer.await {
+        Ok((status, body)) => (status, Json(body)).into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
@@ -0,0 +1,173 @@
+use crate::drain::InFlight;
+use crate::log;
+use crate::{rt, BoxError, Error, Result, Source, Stream};
+use ::datafusion::arrow::datatypes::SchemaRef;
+use ::datafusion::arrow::json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
+use ::datafusion::arrow::record_batch::RecordBatch;
+use ::datafusion::datasource::MemTable;
+use ::datafusion::prelude::{SessionConfig, SessionContext};
+use ::datafusion::sql::parser::DFParser;
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+// A SQL query run over each window of a stream, registered as `table`, e.g.
+// `SqlQuery::new("trades", "SELECT instrument, sum(amount) AS volume FROM
+// trades GROUP BY instrument")` over `timed_buffer(Duration::from_secs(5))`.
+pub struct SqlQuery {
+    table: String,
+    sql: String,
+    schema: Option<SchemaRef>,
+    context: SessionContext,
+}
+
+impl SqlQuery {
+    // Fails on SQL that does not parse.
+    pub fn new(table: impl Into<String>, sql: impl Into<String>) -> Result<Self> {
+        let sql = sql.into();
+        DFParser::parse_sql(&sql).map_err(|err| Error::InvalidExpression {
+            expression: sql.clone(),
+            message: err.to_string(),
+        })?;
+        Ok(Self {
+            table: table.into(),
+            sql,
+            schema: None,
+            context: SessionContext::new_with_config(
+                SessionConfig::new().with_target_partitions(1),
+            ),
+        })
+    }
+
+    // Decodes every window with `schema` instead of inferring one per window
+    // from the items, so columns keep their types when a window lacks a field.
+    pub fn with_schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    // A context with UDFs or reference tables registered; the window table
+    // is (re)registered in it per window.
+    pub fn with_context(mut self, context: SessionContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    async fn run(&self, rows: Vec<Value>) -> Result<Vec<RecordBatch>> {
+        let schema = match &self.schema {
+            Some(schema) => schema.clone(),
+            None => Arc::new(
+                infer_json_schema_from_iterator(rows.iter().map(|row| Ok(row.clone())))
+                    .map_err(failed)?,
+            ),
+        };
+        let mut decoder = ReaderBuilder::new(schema.clone())
+            .build_decoder()
+            .map_err(failed)?;
+        decoder.serialize(&rows).map_err(failed)?;
+        let batches = decoder.flush().map_err(failed)?;
+        let table =
+            MemTable::try_new(schema, vec![batches.into_iter().collect()]).map_err(failed)?;
+        self.context
+            .deregister_table(self.table.as_str())
+            .map_err(failed)?;
+        self.context
+            .register_table(self.table.as_str(), Arc::new(table))
+            .map_err(failed)?;
+        self.context
+            .sql(&self.sql)
+            .await
+            .map_err(failed)?
+            .collect()
+            .await
+            .map_err(failed)
+    }
+}
+
+fn failed(err: impl Into<BoxError>) -> Error {
+    Error::other("datafusion", err)
+}
+
+type Windows = (Vec<Value>, InFlight);
+
+impl<T> Stream<Vec<T>>
+where
+    T: Serialize + 'static,
+{
+    // Runs `query` over each batch (e.g. from `timed_buffer`) and emits its
+    // result batches, one item per window, in window order. Queries run on
+    // the engine's local task set one window at a time. Windows that fail to
+    // decode or query are dropped, and the first failure is printed. The
+    // output completes once the input has and every window has been queried.
+    pub fn sql(&self, query: SqlQuery) -> Stream<Vec<RecordBatch>> {
+        let results = Rc::new(Source::new());
+        let stream = results.to_stream();
+        let (sender, receiver) = unbounded_channel::<Windows>();
+        let sender: Rc<RefCell<Option<UnboundedSender<Windows>>>> =
+            Rc::new(RefCell::new(Some(sender)));
+        // the worker starts with the first window, inside the engine
+        let idle = Rc::new(RefCell::new(Some((receiver, query, results.clone()))));
+
+        let idle_complete = idle.clone();
+        let sender_complete = sender.clone();
+        self.on_complete(move || {
+            sender_complete.borrow_mut().take();
+            if idle_complete.borrow_mut().take().is_some() {
+                results.complete();
+            }
+        });
+
+        let reported = Cell::new(false);
+        self.sink(move |batch: &Vec<T>| {
+            if batch.is_empty() {
+                return;
+            }
+            let rows = batch
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<std::result::Result<Vec<_>, _>>();
+            let rows = match rows {
+                Ok(rows) => rows,
+                Err(err) => {
+                    if !reported.replace(true) {
+                        log::warn(
+                            "sql",
+                            format_args!("sql window failed to serialize: {}", err),
+                        );
+                    }
+                    return;
+                }
+            };
+            if let Some((receiver, query, results)) = idle.borrow_mut().take() {
+                rt::spawn_local(run_windows(receiver, query, results));
+            }
+            if let Some(sender) = sender.borrow().as_ref() {
+                let _ = sender.send((rows, InFlight::start()));
+            }
+        });
+
+        stream
+    }
+}
+
+async fn run_windows(
+    mut windows: UnboundedReceiver<Windows>,
+    query: SqlQuery,
+    results: Rc<Source<Vec<RecordBatch>>>,
+) {
+    let reported = Cell::new(false);
+    while let Some((rows, _tracked)) = windows.recv().await {
+        match query.run(rows).await {
+            Ok(batches) => results.emit(batches),
+            Err(err) => {
+                if !reported.replace(true) {
+                    log::warn("sql", format_args!("sql window failed: {}", err));
+                }
+            }
+        }
+    }
+    results.complete();
+}
@@ -0,0 +1,326 @@
+use crate::log;
+use crate::{BoxError, Error, Result, Sink};
+use ::duckdb::types::Value as Column;
+use ::duckdb::{params_from_iter, Connection};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+// Inserts serialized items as rows of `table` in an embedded DuckDB file
+// (or ":memory:"), `batch_size` rows per transaction, and on flush and close.
+// The table is created from the first batch, with a column per field:
+// booleans, BIGINT / UBIGINT integers, DOUBLE other numbers, and VARCHAR
+// strings and nested values (as JSON). Fields that appear later are added as
+// columns; rows missing a field get NULL. A batch that fails to insert is
+// dropped, and the first failure printed.
+pub struct DuckDbSink<T> {
+    label: String,
+    table: String,
+    connection: RefCell<Connection>,
+    batch_size: usize,
+    pending: RefCell<Vec<Map<String, Value>>>,
+    columns: RefCell<Vec<String>>,
+    reported: Cell<bool>,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T> DuckDbSink<T>
+where
+    T: Serialize + 'static,
+{
+    pub fn open(path: &str, table: impl Into<String>) -> Result<Self> {
+        let label = format!("duckdb {}", path);
+        let connection = if path == ":memory:" {
+            Connection::open_in_memory()
+        } else {
+            Connection::open(path)
+        }
+        .map_err(|err| Error::connect(&label, err))?;
+        Ok(Self {
+            label,
+            table: table.into(),
+            connection: RefCell::new(connection),
+            batch_size: 1000,
+            pending: RefCell::new(Vec::new()),
+            columns: RefCell::new(Vec::new()),
+            reported: Cell::new(false),
+            _item: PhantomData,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // Runs ad-hoc SQL against the same database from any thread; it sees
+    // rows once their batch is inserted.
+    pub fn query_handle(&self) -> Result<DuckDbQuery> {
+        let connection = self
+            .connection
+            .borrow()
+            .try_clone()
+            .map_err(|err| Error::connect(&self.label, err))?;
+        Ok(DuckDbQuery {
+            label: self.label.clone(),
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn push(&self, item: &T) {
+        let row = match serde_json::to_value(item) {
+            Ok(Value::Object(fields)) => fields,
+            Ok(value) => Map::from_iter([("value".to_string(), value)]),
+            Err(err) => {
+                self.report(&Error::decode(&self.label, err));
+                return;
+            }
+        };
+        let full = {
+            let mut pending = self.pending.borrow_mut();
+            pending.push(row);
+            pending.len() >= self.batch_size
+        };
+        if full {
+            if let Err(err) = self.insert() {
+                self.report(&err);
+            }
+        }
+    }
+
+    fn report(&self, err: &Error) {
+        if !self.reported.replace(true) {
+            log::error(
+                "duckdb",
+                format_args!("{} insert failed: {}", self.label, err),
+            );
+        }
+    }
+
+    fn insert(&self) -> Result<()> {
+        let rows = self.pending.take();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let failed = |err: ::duckdb::Error| Error::other(&self.label, err);
+        let mut connection = self.connection.borrow_mut();
+        self.add_columns(&connection, &rows).map_err(failed)?;
+        let columns = self.columns.borrow();
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(&self.table),
+            columns
+                .iter()
+                .map(|name| quote(name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        let transaction = connection.transaction().map_err(failed)?;
+        {
+            let mut statement = transaction.prepare(&sql).map_err(failed)?;
+            for row in &rows {
+                let values = columns
+                    .iter()
+                    .map(|name| row.get(name).map_or(Column::Null, column_value));
+                statement
+                    .execute(params_from_iter(values))
+                    .map_err(failed)?;
+            }
+        }
+        transaction.commit().map_err(failed)
+    }
+
+    // Creates the table, or adds columns for fields it lacks.
+    fn add_columns(
+        &self,
+        connection: &Connection,
+        rows: &[Map<String, Value>],
+    ) -> ::duckdb::Result<()> {
+        let mut columns = self.columns.borrow_mut();
+        if columns.is_empty() {
+            let mut statement = connection.prepare(
+                "SELECT column_name FROM information_schema.columns \
+                 WHERE table_name = ? ORDER BY ordinal_position",
+            )?;
+            *columns = statement
+                .query_map([&self.table], |row| row.get(0))?
+                .collect::<::duckdb::Result<_>>()?;
+        }
+        let mut added = Vec::new();
+        for row in rows {
+            for name in row.keys() {
+                if !columns.contains(name) && !added.contains(name) {
+                    added.push(name.clone());
+                }
+            }
+        }
+        let definitions = added.iter().map(|name| {
+            let sql_type = rows
+                .iter()
+                .filter_map(|row| row.get(name))
+                .find(|value| !value.is_null())
+                .map_or("VARCHAR", sql_type);
+            format!("{} {}", quote(name), sql_type)
+        });
+        if columns.is_empty() {
+            let definitions = definitions.collect::<Vec<_>>();
+            connection.execute_batch(&format!(
+                "CREATE TABLE {} ({})",
+                quote(&self.table),
+                definitions.join(", ")
+            ))?;
+        } else {
+            for definition in definitions {
+                connection.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    quote(&self.table),
+                    definition
+                ))?;
+            }
+        }
+        columns.extend(added);
+        Ok(())
+    }
+}
+
+impl<T> Sink<T> for DuckDbSink<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        self.push(item);
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.insert()
+    }
+}
+
+// A cloneable handle for querying a `DuckDbSink`'s database.
+#[derive(Clone)]
+pub struct DuckDbQuery {
+    label: String,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl DuckDbQuery {
+    // One JSON object per result row, keyed by column name. Decimals and
+    // dates come back as numbers (dates as days, times and timestamps as
+    // microseconds since the epoch), blobs as byte arrays.
+    pub fn query(&self, sql: &str) -> Result<Vec<Map<String, Value>>> {
+        let failed = |err: BoxError| Error::other(&self.label, err);
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|err| failed(err.to_string().into()))?;
+        let mut statement = connection.prepare(sql).map_err(|err| failed(err.into()))?;
+        let mut rows = statement.query([]).map_err(|err| failed(err.into()))?;
+        let names = rows.as_ref().map(|statement| statement.column_names());
+        let names = names.unwrap_or_default();
+        let mut results = Vec::new();
+        while let Some(row) = rows.next().map_err(|err| failed(err.into()))? {
+            let mut result = Map::new();
+            for (index, name) in names.iter().enumerate() {
+                let value: Column = row.get(index).map_err(|err| failed(err.into()))?;
+                result.insert(name.clone(), json_value(value));
+            }
+            results.push(result);
+        }
+        Ok(results)
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn sql_type(value: &Value) -> &'static str {
+    match value {
+        Value::Bool(_) => "BOOLEAN",
+        Value::Number(number) if number.is_i64() => "BIGINT",
+        Value::Number(number) if number.is_u64() => "UBIGINT",
+        Value::Number(_) => "DOUBLE",
+        _ => "VARCHAR",
+    }
+}
+
+fn column_value(value: &Value) -> Column {
+    match value {
+        Value::Null => Column::Null,
+        Value::Bool(value) => Column::Boolean(*value),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(value), _) => Column::BigInt(value),
+            (None, Some(value)) => Column::UBigInt(value),
+            _ => number.as_f64().map_or(Column::Null, Column::Double),
+        },
+        Value::String(value) => Column::Text(value.clone()),
+        nested => Column::Text(nested.to_string()),
+    }
+}
+
+fn json_value(value: Column) -> Value {
+    match value {
+        Column::Null => Value::Null,
+        Column::Boolean(value) => Value::Bool(value),
+        Column::TinyInt(value) => value.into(),
+        Column::SmallInt(value) => value.into(),
+        Column::Int(value) => value.into(),
+        Column::BigInt(value) => value.into(),
+        Column::UTinyInt(value) => value.into(),
+        Column::USmallInt(value) => value.into(),
+        Column::UInt(value) => value.into(),
+        Column::UBigInt(value) => value.into(),
+        Column::HugeInt(value) => i64::try_from(value)
+            .map(Value::from)
+            .unwrap_or_else(|_| float(value as f64)),
+        Column::UHugeInt(value) => u64::try_from(value)
+            .map(Value::from)
+            .unwrap_or_else(|_| float(value as f64)),
+        Column::Float(value) => float(value.into()),
+        Column::Double(value) => float(value),
+        Column::Decimal(value) => float(value.to_string().parse().unwrap_or(f64::NAN)),
+        Column::Timestamp(unit, value) | Column::Time64(unit, value) => {
+            unit.to_micros(value).into()
+        }
+        Column::Date32(days) => days.into(),
+        Column::Interval {
+            months,
+            days,
+            nanos,
+        } => serde_json::json!({ "months": months, "days": days, "nanos": nanos }),
+        Column::Text(value) | Column::Enum(value) => Value::String(value),
+        Column::Blob(bytes) | Column::Geometry(bytes) => bytes.into(),
+        Column::List(values) | Column::Array(values) => {
+            values.into_iter().map(json_value).collect()
+        }
+        Column::Struct(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), json_value(value.clone())))
+                .collect(),
+        ),
+        Column::Map(entries) => Value::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key_text(key), json_value(value.clone())))
+                .collect(),
+        ),
+        Column::Union(value) => json_value(*value),
+        other => Value::String(format!("{:?}", other)),
+    }
+}
+
+fn key_text(key: &Column) -> String {
+    match json_value(key.clone()) {
+        Value::String(text) => text,
+        other => other.to_string(),
+    }
+}
+
+// NaN and infinities become null.
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
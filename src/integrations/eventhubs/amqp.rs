@@ -0,0 +1,676 @@
+// Just enough AMQP 1.0 for Event Hubs receivers: the type system, frames,
+// SASL PLAIN and the performatives a receiving (and request-sending) client
+// needs.
+use crate::{Error, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub(crate) const OPEN: u64 = 0x10;
+pub(crate) const BEGIN: u64 = 0x11;
+pub(crate) const ATTACH: u64 = 0x12;
+pub(crate) const FLOW: u64 = 0x13;
+pub(crate) const TRANSFER: u64 = 0x14;
+pub(crate) const DISPOSITION: u64 = 0x15;
+pub(crate) const DETACH: u64 = 0x16;
+pub(crate) const END: u64 = 0x17;
+pub(crate) const CLOSE: u64 = 0x18;
+const SASL_MECHANISMS: u64 = 0x40;
+const SASL_INIT: u64 = 0x41;
+const SASL_OUTCOME: u64 = 0x44;
+pub(crate) const SOURCE: u64 = 0x28;
+pub(crate) const TARGET: u64 = 0x29;
+const MESSAGE_ANNOTATIONS: u64 = 0x72;
+const PROPERTIES: u64 = 0x73;
+const APPLICATION_PROPERTIES: u64 = 0x74;
+const DATA: u64 = 0x75;
+const AMQP_VALUE: u64 = 0x77;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Ubyte(u8),
+    Ushort(u16),
+    Uint(u32),
+    Ulong(u64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Char(char),
+    Timestamp(i64),
+    Uuid([u8; 16]),
+    Binary(Vec<u8>),
+    String(String),
+    Symbol(String),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Array(Vec<Value>),
+    Described(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    pub(crate) fn symbol(text: &str) -> Self {
+        Value::Symbol(text.to_string())
+    }
+
+    pub(crate) fn string(text: &str) -> Self {
+        Value::String(text.to_string())
+    }
+
+    pub(crate) fn described(descriptor: u64, value: Value) -> Self {
+        Value::Described(Box::new(Value::Ulong(descriptor)), Box::new(value))
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Ubyte(value) => Some(value.into()),
+            Value::Ushort(value) => Some(value.into()),
+            Value::Uint(value) => Some(value.into()),
+            Value::Ulong(value) => Some(value),
+            Value::Byte(value) => u64::try_from(value).ok(),
+            Value::Short(value) => u64::try_from(value).ok(),
+            Value::Int(value) => u64::try_from(value).ok(),
+            Value::Long(value) => u64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Long(value) | Value::Timestamp(value) => Some(value),
+            Value::Int(value) => Some(value.into()),
+            _ => self.as_u64().and_then(|value| i64::try_from(value).ok()),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) | Value::Symbol(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // The descriptor code and fields of a described list.
+    pub(crate) fn performative(&self) -> Option<(u64, &[Value])> {
+        let Value::Described(descriptor, value) = self else {
+            return None;
+        };
+        match value.as_ref() {
+            Value::List(fields) => Some((descriptor.as_u64()?, fields)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        let Value::Map(entries) = self else {
+            return None;
+        };
+        entries
+            .iter()
+            .find(|(entry, _)| entry.as_str() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    // Text for simple values, as application properties are shown.
+    pub(crate) fn to_text(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::String(text) | Value::Symbol(text) => text.clone(),
+            Value::Bool(value) => value.to_string(),
+            Value::Float(value) => value.to_string(),
+            Value::Double(value) => value.to_string(),
+            Value::Char(value) => value.to_string(),
+            Value::Binary(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            value => match value.as_i64() {
+                Some(number) => number.to_string(),
+                None => format!("{:?}", value),
+            },
+        }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => out.push(0x40),
+            Value::Bool(true) => out.push(0x41),
+            Value::Bool(false) => out.push(0x42),
+            Value::Ubyte(value) => {
+                out.push(0x50);
+                out.push(*value);
+            }
+            Value::Ushort(value) => {
+                out.push(0x60);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Uint(value) => {
+                out.push(0x70);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Ulong(value) => {
+                out.push(0x80);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Byte(value) => {
+                out.push(0x51);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Short(value) => {
+                out.push(0x61);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Int(value) => {
+                out.push(0x71);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Long(value) => {
+                out.push(0x81);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Float(value) => {
+                out.push(0x72);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Double(value) => {
+                out.push(0x82);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Char(value) => {
+                out.push(0x73);
+                out.extend_from_slice(&u32::from(*value).to_be_bytes());
+            }
+            Value::Timestamp(value) => {
+                out.push(0x83);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Value::Uuid(value) => {
+                out.push(0x98);
+                out.extend_from_slice(value);
+            }
+            Value::Binary(bytes) => variable(out, 0xb0, bytes),
+            Value::String(text) => variable(out, 0xb1, text.as_bytes()),
+            Value::Symbol(text) => variable(out, 0xb3, text.as_bytes()),
+            Value::List(items) => {
+                let mut body = Vec::new();
+                for item in items {
+                    item.encode(&mut body);
+                }
+                compound(out, 0xd0, items.len(), &body);
+            }
+            Value::Map(entries) => {
+                let mut body = Vec::new();
+                for (key, value) in entries {
+                    key.encode(&mut body);
+                    value.encode(&mut body);
+                }
+                compound(out, 0xd1, entries.len() * 2, &body);
+            }
+            Value::Array(items) => {
+                // elements share the first one's constructor
+                let mut body = Vec::new();
+                let mut constructor = Vec::new();
+                for (index, item) in items.iter().enumerate() {
+                    let mut element = Vec::new();
+                    item.encode(&mut element);
+                    if index == 0 {
+                        constructor.push(element[0]);
+                    }
+                    body.extend_from_slice(&element[1..]);
+                }
+                if constructor.is_empty() {
+                    constructor.push(0x40);
+                }
+                constructor.extend_from_slice(&body);
+                compound(out, 0xf0, items.len(), &constructor);
+            }
+            Value::Described(descriptor, value) => {
+                out.push(0x00);
+                descriptor.encode(out);
+                value.encode(out);
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut &[u8]) -> Result<Value> {
+        let code = take(input, 1)?[0];
+        if code == 0x00 {
+            let descriptor = Value::decode(input)?;
+            let value = Value::decode(input)?;
+            return Ok(Value::Described(Box::new(descriptor), Box::new(value)));
+        }
+        decode_with(code, input)
+    }
+}
+
+fn variable(out: &mut Vec<u8>, code: u8, bytes: &[u8]) {
+    out.push(code);
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn compound(out: &mut Vec<u8>, code: u8, count: usize, body: &[u8]) {
+    out.push(code);
+    out.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    out.extend_from_slice(&(count as u32).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn malformed(message: impl Into<String>) -> Error {
+    Error::protocol("amqp", message)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(malformed("truncated value"));
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+fn array<const N: usize>(input: &mut &[u8]) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(take(input, N)?);
+    Ok(bytes)
+}
+
+fn text(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| malformed("invalid utf-8"))
+}
+
+fn decode_with(code: u8, input: &mut &[u8]) -> Result<Value> {
+    let value = match code {
+        0x40 => Value::Null,
+        0x41 => Value::Bool(true),
+        0x42 => Value::Bool(false),
+        0x56 => Value::Bool(take(input, 1)?[0] != 0),
+        0x50 => Value::Ubyte(take(input, 1)?[0]),
+        0x60 => Value::Ushort(u16::from_be_bytes(array(input)?)),
+        0x70 => Value::Uint(u32::from_be_bytes(array(input)?)),
+        0x52 => Value::Uint(take(input, 1)?[0].into()),
+        0x43 => Value::Uint(0),
+        0x80 => Value::Ulong(u64::from_be_bytes(array(input)?)),
+        0x53 => Value::Ulong(take(input, 1)?[0].into()),
+        0x44 => Value::Ulong(0),
+        0x51 => Value::Byte(i8::from_be_bytes(array(input)?)),
+        0x61 => Value::Short(i16::from_be_bytes(array(input)?)),
+        0x71 => Value::Int(i32::from_be_bytes(array(input)?)),
+        0x54 => Value::Int(i8::from_be_bytes(array(input)?).into()),
+        0x81 => Value::Long(i64::from_be_bytes(array(input)?)),
+        0x55 => Value::Long(i8::from_be_bytes(array(input)?).into()),
+        0x72 => Value::Float(f32::from_be_bytes(array(input)?)),
+        0x82 => Value::Double(f64::from_be_bytes(array(input)?)),
+        0x73 => Value::Char(
+            char::from_u32(u32::from_be_bytes(array(input)?))
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        ),
+        0x83 => Value::Timestamp(i64::from_be_bytes(array(input)?)),
+        0x98 => Value::Uuid(array(input)?),
+        // decimals are kept as their raw bytes
+        0x74 => Value::Binary(take(input, 4)?.to_vec()),
+        0x84 => Value::Binary(take(input, 8)?.to_vec()),
+        0x94 => Value::Binary(take(input, 16)?.to_vec()),
+        0xa0 | 0xa1 | 0xa3 | 0xb0 | 0xb1 | 0xb3 => {
+            let len = if code & 0x10 == 0 {
+                take(input, 1)?[0] as usize
+            } else {
+                u32::from_be_bytes(array(input)?) as usize
+            };
+            let bytes = take(input, len)?;
+            match code & 0x0f {
+                0x00 => Value::Binary(bytes.to_vec()),
+                0x01 => Value::String(text(bytes)?),
+                _ => Value::Symbol(text(bytes)?),
+            }
+        }
+        0x45 => Value::List(Vec::new()),
+        0xc0 | 0xc1 | 0xd0 | 0xd1 => {
+            let (size, count) = if code & 0x10 == 0 {
+                let header = take(input, 2)?;
+                (header[0] as usize, header[1] as usize)
+            } else {
+                let size = u32::from_be_bytes(array(input)?) as usize;
+                (size, u32::from_be_bytes(array(input)?) as usize)
+            };
+            let width = if code & 0x10 == 0 { 1 } else { 4 };
+            let mut body = take(input, size.saturating_sub(width))?;
+            let mut items = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                items.push(Value::decode(&mut body)?);
+            }
+            if code & 0x0f == 0 {
+                Value::List(items)
+            } else {
+                let mut entries = Vec::with_capacity(items.len() / 2);
+                let mut items = items.into_iter();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    entries.push((key, value));
+                }
+                Value::Map(entries)
+            }
+        }
+        0xe0 | 0xf0 => {
+            let (size, count) = if code == 0xe0 {
+                let header = take(input, 2)?;
+                (header[0] as usize, header[1] as usize)
+            } else {
+                let size = u32::from_be_bytes(array(input)?) as usize;
+                (size, u32::from_be_bytes(array(input)?) as usize)
+            };
+            let width = if code == 0xe0 { 1 } else { 4 };
+            let mut body = take(input, size.saturating_sub(width))?;
+            let mut element = take(&mut body, 1)?[0];
+            let mut descriptor = None;
+            if element == 0x00 {
+                descriptor = Some(Value::decode(&mut body)?);
+                element = take(&mut body, 1)?[0];
+            }
+            let mut items = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                let item = decode_with(element, &mut body)?;
+                items.push(match &descriptor {
+                    Some(descriptor) => {
+                        Value::Described(Box::new(descriptor.clone()), Box::new(item))
+                    }
+                    None => item,
+                });
+            }
+            Value::Array(items)
+        }
+        code => return Err(malformed(format!("unknown type code {:#04x}", code))),
+    };
+    Ok(value)
+}
+
+// A message as Event Hubs delivers it.
+#[derive(Debug)]
+pub(crate) struct Message {
+    pub(crate) annotations: Value,
+    pub(crate) application_properties: Value,
+    pub(crate) body: Vec<u8>,
+    pub(crate) value: Value,
+}
+
+impl Message {
+    pub(crate) fn decode(mut payload: &[u8]) -> Result<Message> {
+        let mut message = Message {
+            annotations: Value::Map(Vec::new()),
+            application_properties: Value::Map(Vec::new()),
+            body: Vec::new(),
+            value: Value::Null,
+        };
+        while !payload.is_empty() {
+            let Value::Described(descriptor, value) = Value::decode(&mut payload)? else {
+                return Err(malformed("message section is not described"));
+            };
+            match (descriptor.as_u64(), *value) {
+                (Some(MESSAGE_ANNOTATIONS), value) => message.annotations = value,
+                (Some(APPLICATION_PROPERTIES), value) => message.application_properties = value,
+                (Some(DATA), Value::Binary(bytes)) => message.body.extend_from_slice(&bytes),
+                (Some(AMQP_VALUE), value) => message.value = value,
+                _ => {}
+            }
+        }
+        Ok(message)
+    }
+
+    // A request to a management node: `message_id`, `reply_to` and
+    // application properties, with an empty body.
+    pub(crate) fn request(
+        message_id: &str,
+        reply_to: &str,
+        properties: Vec<(Value, Value)>,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        let fields = vec![
+            Value::string(message_id),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::string(reply_to),
+        ];
+        Value::described(PROPERTIES, Value::List(fields)).encode(&mut out);
+        Value::described(APPLICATION_PROPERTIES, Value::Map(properties)).encode(&mut out);
+        Value::described(AMQP_VALUE, Value::Null).encode(&mut out);
+        out
+    }
+}
+
+pub(crate) trait Io: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for T {}
+
+pub(crate) struct Frame {
+    // `None` for heartbeats
+    pub(crate) body: Option<Value>,
+    pub(crate) payload: Vec<u8>,
+}
+
+// Frames over a byte stream. `next_frame` keeps partial reads in its buffer,
+// so it can be raced against timers.
+pub(crate) struct Connection {
+    io: Box<dyn Io>,
+    buffer: Vec<u8>,
+    label: String,
+}
+
+impl Connection {
+    pub(crate) fn new(io: Box<dyn Io>, label: &str) -> Self {
+        Self {
+            io,
+            buffer: Vec::new(),
+            label: label.to_string(),
+        }
+    }
+
+    fn io_error(&self, err: std::io::Error) -> Error {
+        Error::io(&self.label, err)
+    }
+
+    async fn header(&mut self, protocol: u8) -> Result<()> {
+        let header = [b'A', b'M', b'Q', b'P', protocol, 1, 0, 0];
+        self.io
+            .write_all(&header)
+            .await
+            .map_err(|err| self.io_error(err))?;
+        while self.buffer.len() < 8 {
+            let read = self
+                .io
+                .read_buf(&mut self.buffer)
+                .await
+                .map_err(|err| self.io_error(err))?;
+            if read == 0 {
+                return Err(Error::connect(
+                    &self.label,
+                    "connection closed during the handshake",
+                ));
+            }
+        }
+        let reply: Vec<u8> = self.buffer.drain(..8).collect();
+        if reply != header {
+            return Err(Error::protocol(
+                &self.label,
+                format!("unexpected protocol header {:?}", reply),
+            ));
+        }
+        Ok(())
+    }
+
+    // SASL PLAIN, then the AMQP header.
+    pub(crate) async fn handshake(
+        &mut self,
+        username: &str,
+        password: &str,
+        hostname: &str,
+    ) -> Result<()> {
+        self.header(3).await?;
+        let mechanisms = self.expect(SASL_MECHANISMS).await?;
+        let offered = format!("{:?}", mechanisms.first());
+        if !offered.contains("PLAIN") {
+            return Err(Error::protocol(
+                &self.label,
+                format!("server does not offer SASL PLAIN: {}", offered),
+            ));
+        }
+        let response = format!("\0{}\0{}", username, password).into_bytes();
+        let init = vec![
+            Value::symbol("PLAIN"),
+            Value::Binary(response),
+            Value::string(hostname),
+        ];
+        self.send_on(1, 0, SASL_INIT, init, &[]).await?;
+        let outcome = self.expect(SASL_OUTCOME).await?;
+        match outcome.first().and_then(Value::as_u64) {
+            Some(0) => {}
+            code => {
+                return Err(Error::connect(
+                    &self.label,
+                    format!("authentication failed (sasl code {:?})", code),
+                ))
+            }
+        }
+        self.header(0).await
+    }
+
+    async fn expect(&mut self, code: u64) -> Result<Vec<Value>> {
+        loop {
+            let frame = self.next_frame().await?;
+            let Some(body) = frame.body else { continue };
+            match body.performative() {
+                Some((found, fields)) if found == code => return Ok(fields.to_vec()),
+                _ => {
+                    return Err(Error::protocol(
+                        &self.label,
+                        format!("expected performative {:#x}, got {:?}", code, body),
+                    ))
+                }
+            }
+        }
+    }
+
+    // Waits for `code`, skipping flows and heartbeats; a close or detach
+    // fails with the peer's error.
+    pub(crate) async fn expect_amqp(&mut self, code: u64) -> Result<(Vec<Value>, Vec<u8>)> {
+        loop {
+            let frame = self.next_frame().await?;
+            let Some(body) = frame.body else { continue };
+            let Some((found, fields)) = body.performative() else {
+                continue;
+            };
+            if found == code {
+                return Ok((fields.to_vec(), frame.payload));
+            }
+            if found == CLOSE || found == DETACH || found == END {
+                return Err(self.closed(found, fields));
+            }
+        }
+    }
+
+    // The error a peer's close, end or detach carries.
+    pub(crate) fn closed(&self, code: u64, fields: &[Value]) -> Error {
+        let index = if code == DETACH { 2 } else { 0 };
+        let description = match fields.get(index).and_then(Value::performative) {
+            Some((_, error)) => format!(
+                "{} {}",
+                error.first().map(Value::to_text).unwrap_or_default(),
+                error.get(1).map(Value::to_text).unwrap_or_default()
+            ),
+            None => "no error given".to_string(),
+        };
+        let what = match code {
+            CLOSE => "connection",
+            END => "session",
+            _ => "link",
+        };
+        Error::protocol(
+            &self.label,
+            format!("{} closed by the peer: {}", what, description.trim()),
+        )
+    }
+
+    pub(crate) async fn next_frame(&mut self) -> Result<Frame> {
+        loop {
+            if self.buffer.len() >= 8 {
+                let size = u32::from_be_bytes([
+                    self.buffer[0],
+                    self.buffer[1],
+                    self.buffer[2],
+                    self.buffer[3],
+                ]) as usize;
+                let offset = self.buffer[4] as usize * 4;
+                if size < 8 || offset < 8 || offset > size {
+                    return Err(Error::protocol(&self.label, "malformed frame header"));
+                }
+                if self.buffer.len() >= size {
+                    let frame: Vec<u8> = self.buffer.drain(..size).collect();
+                    let mut body = &frame[offset..];
+                    if body.is_empty() {
+                        return Ok(Frame {
+                            body: None,
+                            payload: Vec::new(),
+                        });
+                    }
+                    let value = Value::decode(&mut body)?;
+                    return Ok(Frame {
+                        body: Some(value),
+                        payload: body.to_vec(),
+                    });
+                }
+            }
+            let read = self
+                .io
+                .read_buf(&mut self.buffer)
+                .await
+                .map_err(|err| self.io_error(err))?;
+            if read == 0 {
+                return Err(Error::connect(&self.label, "connection closed"));
+            }
+        }
+    }
+
+    pub(crate) async fn send(
+        &mut self,
+        channel: u16,
+        code: u64,
+        fields: Vec<Value>,
+        payload: &[u8],
+    ) -> Result<()> {
+        self.send_on(0, channel, code, fields, payload).await
+    }
+
+    async fn send_on(
+        &mut self,
+        kind: u8,
+        channel: u16,
+        code: u64,
+        fields: Vec<Value>,
+        payload: &[u8],
+    ) -> Result<()> {
+        let mut body = Vec::new();
+        Value::described(code, Value::List(fields)).encode(&mut body);
+        body.extend_from_slice(payload);
+        let mut frame = Vec::with_capacity(body.len() + 8);
+        frame.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+        frame.extend_from_slice(&[2, kind]);
+        frame.extend_from_slice(&channel.to_be_bytes());
+        frame.extend_from_slice(&body);
+        self.io
+            .write_all(&frame)
+            .await
+            .map_err(|err| self.io_error(err))
+    }
+
+    pub(crate) async fn heartbeat(&mut self) -> Result<()> {
+        self.io
+            .write_all(&[0, 0, 0, 8, 2, 0, 0, 0])
+            .await
+            .map_err(|err| self.io_error(err))
+    }
+}
@@ -0,0 +1,901 @@
+mod amqp;
+
+use self::amqp::{
+    Connection, Io, Message, Value, ATTACH, BEGIN, CLOSE, DETACH, DISPOSITION, END, FLOW, OPEN,
+    SOURCE, TARGET, TRANSFER,
+};
+use crate::log;
+use crate::proxy;
+use crate::rt::now_millis;
+use crate::{ConnectOptions, CursorStore, Error, ProxyConfig, Result, Source};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+
+const SELECTOR_FILTER: &str = "apache.org:selector-filter:string";
+const ACCEPTED: u64 = 0x24;
+// session window, refreshed at half
+const WINDOW: u32 = 5000;
+const MAX_HEARTBEAT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct EventHubsConfig {
+    // `amqps://{namespace}.servicebus.windows.net`, or `amqp://host:port`
+    // for the emulator
+    pub endpoint: String,
+    pub event_hub: String,
+    pub key_name: String,
+    pub key: String,
+    // the default proxy, if any, when `None`
+    pub proxy: Option<ProxyConfig>,
+    pub connect_options: ConnectOptions,
+}
+
+impl EventHubsConfig {
+    pub fn new(namespace: &str, event_hub: &str, key_name: &str, key: &str) -> Self {
+        Self {
+            endpoint: format!("amqps://{}.servicebus.windows.net", namespace),
+            event_hub: event_hub.to_string(),
+            key_name: key_name.to_string(),
+            key: key.to_string(),
+            proxy: None,
+            connect_options: ConnectOptions::default(),
+        }
+    }
+
+    // `Endpoint=sb://...;SharedAccessKeyName=...;SharedAccessKey=...` with
+    // `EntityPath` naming the event hub (or `with_event_hub`).
+    pub fn from_connection_string(connection_string: &str) -> Result<Self> {
+        let mut fields = HashMap::new();
+        for field in connection_string
+            .split(';')
+            .filter(|field| !field.trim().is_empty())
+        {
+            let (name, value) = field.split_once('=').ok_or_else(|| {
+                Error::config(format!("malformed connection string field {:?}", field))
+            })?;
+            fields.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::config(format!("connection string has no {}", name)))
+        };
+        let endpoint = field("endpoint")?;
+        let host = endpoint
+            .trim_start_matches("sb://")
+            .trim_end_matches('/')
+            .to_string();
+        let scheme = match fields.get("usedevelopmentemulator").map(String::as_str) {
+            Some("true") => "amqp",
+            _ => "amqps",
+        };
+        Ok(Self {
+            endpoint: format!("{}://{}", scheme, host),
+            event_hub: fields.get("entitypath").cloned().unwrap_or_default(),
+            key_name: field("sharedaccesskeyname")?,
+            key: field("sharedaccesskey")?,
+            proxy: None,
+            connect_options: ConnectOptions::default(),
+        })
+    }
+
+    // `EVENTHUB_CONNECTION_STRING`, with `EVENTHUB_NAME` if it has no
+    // `EntityPath`.
+    pub fn from_env() -> Result<Self> {
+        let connection_string = env::var("EVENTHUB_CONNECTION_STRING")
+            .map_err(|_| Error::config("EVENTHUB_CONNECTION_STRING is not set"))?;
+        let config = Self::from_connection_string(&connection_string)?;
+        match env::var("EVENTHUB_NAME") {
+            Ok(event_hub) => Ok(config.with_event_hub(&event_hub)),
+            Err(_) => Ok(config),
+        }
+    }
+
+    pub fn with_event_hub(mut self, event_hub: &str) -> Self {
+        self.event_hub = event_hub.to_string();
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    // Connects through `proxy` rather than the default one (see
+    // `set_default_proxy`); an HTTP proxy must allow `CONNECT` to port 5671.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    // Address family preference and racing; the namespace is resolved on
+    // every connect either way.
+    pub fn with_connect_options(mut self, options: ConnectOptions) -> Self {
+        self.connect_options = options;
+        self
+    }
+
+    // (tls, host, port)
+    fn address(&self) -> Result<(bool, String, u16)> {
+        let (tls, rest) = if let Some(rest) = self.endpoint.strip_prefix("amqps://") {
+            (true, rest)
+        } else if let Some(rest) = self.endpoint.strip_prefix("amqp://") {
+            (false, rest)
+        } else {
+            return Err(Error::config(format!(
+                "event hubs endpoint {:?} is not amqp:// or amqps://",
+                self.endpoint
+            )));
+        };
+        let default_port = if tls { 5671 } else { 5672 };
+        match rest.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse().map_err(|_| {
+                    Error::config(format!(
+                        "event hubs endpoint {:?} has a bad port",
+                        self.endpoint
+                    ))
+                })?;
+                Ok((tls, host.to_string(), port))
+            }
+            None => Ok((tls, rest.to_string(), default_port)),
+        }
+    }
+}
+
+// Where partitions without a checkpoint start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventPosition {
+    Earliest,
+    Latest,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventData {
+    pub partition_id: String,
+    pub sequence_number: i64,
+    pub offset: String,
+    // milliseconds since the unix epoch
+    pub enqueued_time: i64,
+    pub partition_key: Option<String>,
+    pub properties: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+// A receiving link on one partition.
+struct Link {
+    partition: String,
+    name: String,
+    handle: u32,
+    remote_handle: Option<u32>,
+    delivery_count: u32,
+    credit: u32,
+    // a delivery split over several transfers, and whether it needs settling
+    payload: Vec<u8>,
+    unsettled: Option<u32>,
+}
+
+// One connection with one session, holding a link per owned partition.
+struct Session {
+    connection: Connection,
+    next_incoming_id: u32,
+    next_outgoing_id: u32,
+    // transfers since the window was last refreshed
+    received: u32,
+    next_handle: u32,
+    heartbeat: Duration,
+    links: Vec<Link>,
+}
+
+impl Session {
+    fn link(&mut self, remote_handle: Option<u64>) -> Option<&mut Link> {
+        let remote_handle = remote_handle? as u32;
+        self.links
+            .iter_mut()
+            .find(|link| link.remote_handle == Some(remote_handle))
+    }
+
+    async fn flow(&mut self, link: Option<(u32, u32, u32)>) -> Result<()> {
+        let mut fields = vec![
+            Value::Uint(self.next_incoming_id),
+            Value::Uint(WINDOW),
+            Value::Uint(self.next_outgoing_id),
+            Value::Uint(WINDOW),
+        ];
+        if let Some((handle, delivery_count, credit)) = link {
+            fields.extend([
+                Value::Uint(handle),
+                Value::Uint(delivery_count),
+                Value::Uint(credit),
+            ]);
+        }
+        self.received = 0;
+        self.connection.send(0, FLOW, fields, &[]).await
+    }
+
+    async fn attach(
+        &mut self,
+        name: &str,
+        role: bool,
+        source: Value,
+        target: Value,
+    ) -> Result<u32> {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        let mut fields = vec![
+            Value::string(name),
+            Value::Uint(handle),
+            Value::Bool(role),
+            // settled deliveries, as Event Hubs sends them
+            Value::Ubyte(1),
+            Value::Ubyte(0),
+            source,
+            target,
+        ];
+        if !role {
+            // senders give their initial delivery count
+            fields.extend([Value::Null, Value::Null, Value::Uint(0)]);
+        }
+        self.connection.send(0, ATTACH, fields, &[]).await?;
+        Ok(handle)
+    }
+
+    async fn detach(&mut self, handle: u32) -> Result<()> {
+        let fields = vec![Value::Uint(handle), Value::Bool(true)];
+        self.connection.send(0, DETACH, fields, &[]).await
+    }
+
+    // Asks the `$management` node for the event hub's partitions.
+    async fn partition_ids(&mut self, event_hub: &str, owner: &str) -> Result<Vec<String>> {
+        let reply_to = format!("{}-management", owner);
+        let management =
+            || Value::described(SOURCE, Value::List(vec![Value::string("$management")]));
+        let sender = self
+            .attach(
+                &format!("{}-sender", reply_to),
+                false,
+                Value::described(SOURCE, Value::List(vec![Value::string(&reply_to)])),
+                Value::described(TARGET, Value::List(vec![Value::string("$management")])),
+            )
+            .await?;
+        let receiver = self
+            .attach(
+                &reply_to,
+                true,
+                management(),
+                Value::described(TARGET, Value::List(vec![Value::string(&reply_to)])),
+            )
+            .await?;
+        self.flow(Some((receiver, 0, 1))).await?;
+
+        let sender_name = format!("{}-sender", reply_to);
+        let mut sent = false;
+        let mut sender_remote = None;
+        loop {
+            let frame = self.connection.next_frame().await?;
+            let Some(body) = frame.body else { continue };
+            let Some((code, fields)) = body.performative() else {
+                continue;
+            };
+            match code {
+                ATTACH if fields.first().and_then(Value::as_str) == Some(sender_name.as_str()) => {
+                    sender_remote = fields.get(1).and_then(Value::as_u64);
+                }
+                FLOW if !sent
+                    && sender_remote.is_some()
+                    && fields.get(4).and_then(Value::as_u64) == sender_remote =>
+                {
+                    let credit = fields.get(6).and_then(Value::as_u64).unwrap_or(0);
+                    if credit == 0 {
+                        continue;
+                    }
+                    let transfer = vec![
+                        Value::Uint(sender),
+                        Value::Uint(0),
+                        Value::Binary(vec![0]),
+                        Value::Uint(0),
+                        Value::Bool(true),
+                    ];
+                    let properties = vec![
+                        (Value::string("operation"), Value::string("READ")),
+                        (Value::string("name"), Value::string(event_hub)),
+                        (
+                            Value::string("type"),
+                            Value::string("com.microsoft:eventhub"),
+                        ),
+                    ];
+                    let request = Message::request("partitions", &reply_to, properties);
+                    self.connection
+                        .send(0, TRANSFER, transfer, &request)
+                        .await?;
+                    self.next_outgoing_id = self.next_outgoing_id.wrapping_add(1);
+                    sent = true;
+                }
+                TRANSFER => {
+                    self.next_incoming_id = self.next_incoming_id.wrapping_add(1);
+                    let response = Message::decode(&frame.payload)?;
+                    let status = response
+                        .application_properties
+                        .get("status-code")
+                        .and_then(Value::as_i64)
+                        .unwrap_or(200);
+                    self.detach(sender).await?;
+                    self.detach(receiver).await?;
+                    if status != 200 {
+                        let description = response
+                            .application_properties
+                            .get("status-description")
+                            .map(Value::to_text)
+                            .unwrap_or_default();
+                        return Err(Error::protocol(
+                            "eventhubs",
+                            format!("reading {} failed ({}): {}", event_hub, status, description),
+                        ));
+                    }
+                    let ids = match response.value.get("partition_ids") {
+                        Some(Value::Array(ids)) | Some(Value::List(ids)) => {
+                            ids.iter().map(Value::to_text).collect()
+                        }
+                        _ => Vec::new(),
+                    };
+                    if ids.is_empty() {
+                        return Err(Error::protocol(
+                            "eventhubs",
+                            format!("{} reported no partitions", event_hub),
+                        ));
+                    }
+                    return Ok(ids);
+                }
+                // a link closed without an error once it's done with is fine
+                DETACH if matches!(fields.get(2), None | Some(Value::Null)) => {}
+                CLOSE | END | DETACH => return Err(self.connection.closed(code, fields)),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn partition_address(event_hub: &str, consumer_group: &str, partition: &str) -> String {
+    format!(
+        "{}/ConsumerGroups/{}/Partitions/{}",
+        event_hub, consumer_group, partition
+    )
+}
+
+// Reads an event hub's partitions as a member of a consumer group.
+// Positions are checkpointed to the cursor store (the last emitted
+// sequence number under `eventhubs/{host}/{hub}/{group}/{partition}`) every
+// `checkpoint_interval` and on close, and reading resumes after them.
+//
+// Consumers sharing a cursor store split the partitions between them: each
+// holds a lease (`.../{partition}/owner`) it renews every third of
+// `lease_duration`, takes free or expired partitions up to its fair share,
+// steals one from the busiest consumer when short, and gives surplus ones
+// back. The store has no compare-and-set, so the last writer wins and a
+// consumer finding another owner on renewal stops reading. Without a store
+// every partition is read.
+pub struct EventHubsSource {
+    config: EventHubsConfig,
+    consumer_group: String,
+    owner: String,
+    partitions: Option<Vec<String>>,
+    start: EventPosition,
+    prefetch: u32,
+    lease_duration: Duration,
+    checkpoint_interval: Duration,
+    cursors: Option<Rc<dyn CursorStore>>,
+    // last emitted sequence number, and whether it is checkpointed
+    positions: RefCell<HashMap<String, (i64, bool)>>,
+    leases: RefCell<Vec<String>>,
+    source: Source<EventData>,
+    bytes: Cell<u64>,
+}
+
+impl EventHubsSource {
+    pub async fn new(config: EventHubsConfig, consumer_group: &str) -> Result<Self> {
+        if config.event_hub.trim().is_empty() {
+            return Err(Error::config("event hub name is empty"));
+        }
+        config.address()?;
+        Ok(Self {
+            config,
+            consumer_group: consumer_group.to_string(),
+            owner: format!("streamz-{}-{}", std::process::id(), now_millis()),
+            partitions: None,
+            start: EventPosition::Latest,
+            prefetch: 300,
+            lease_duration: Duration::from_secs(30),
+            checkpoint_interval: Duration::from_secs(5),
+            cursors: None,
+            positions: RefCell::new(HashMap::new()),
+            leases: RefCell::new(Vec::new()),
+            source: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    // Reads only these partitions instead of asking the event hub for all
+    // of them.
+    pub fn with_partitions(mut self, partitions: &[&str]) -> Self {
+        self.partitions = Some(partitions.iter().map(|id| id.to_string()).collect());
+        self
+    }
+
+    // Where partitions without a checkpoint start; `Latest` by default.
+    pub fn with_start(mut self, start: EventPosition) -> Self {
+        self.start = start;
+        self
+    }
+
+    // Events requested ahead per partition; 300 by default.
+    pub fn with_prefetch(mut self, prefetch: u32) -> Self {
+        self.prefetch = prefetch.max(2);
+        self
+    }
+
+    // This consumer's name in ownership leases; unique per process by
+    // default.
+    pub fn with_owner_id(mut self, owner: &str) -> Self {
+        self.owner = owner.replace(char::is_whitespace, "-");
+        self
+    }
+
+    // 30s by default.
+    pub fn with_lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration = lease_duration.max(Duration::from_secs(3));
+        self
+    }
+
+    // 5s by default.
+    pub fn with_checkpoint_interval(mut self, checkpoint_interval: Duration) -> Self {
+        self.checkpoint_interval = checkpoint_interval.max(Duration::from_millis(100));
+        self
+    }
+
+    pub fn with_cursor_store(mut self, cursors: Rc<dyn CursorStore>) -> Self {
+        self.cursors = Some(cursors);
+        self
+    }
+
+    pub fn source(&self) -> &Source<EventData> {
+        &self.source
+    }
+
+    // Event body bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Saves the positions emitted since the last checkpoint.
+    pub fn checkpoint(&self) -> Result<()> {
+        let Some(cursors) = &self.cursors else {
+            return Ok(());
+        };
+        for (partition, (sequence, saved)) in self.positions.borrow_mut().iter_mut() {
+            if !*saved {
+                cursors.save(&self.key(partition), &sequence.to_string())?;
+                *saved = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&self, partition: &str) -> String {
+        let (_, host, _) = self.config.address().unwrap_or_default();
+        format!(
+            "eventhubs/{}/{}/{}/{}",
+            host, self.config.event_hub, self.consumer_group, partition
+        )
+    }
+
+    fn owner_key(&self, partition: &str) -> String {
+        format!("{}/owner", self.key(partition))
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut session = self.connect().await?;
+        let partitions = match &self.partitions {
+            Some(partitions) => partitions.clone(),
+            None => {
+                session
+                    .partition_ids(&self.config.event_hub, &self.owner)
+                    .await?
+            }
+        };
+
+        let mut heartbeat = interval(session.heartbeat);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        heartbeat.reset();
+        let mut balance = interval(self.lease_duration / 3);
+        balance.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut checkpoint = interval(self.checkpoint_interval);
+        checkpoint.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        checkpoint.reset();
+        loop {
+            tokio::select! {
+                frame = session.connection.next_frame() => {
+                    let frame = frame?;
+                    if let Some(body) = frame.body {
+                        self.dispatch(&mut session, body, frame.payload).await?;
+                    }
+                }
+                _ = heartbeat.tick() => session.connection.heartbeat().await?,
+                _ = balance.tick() => self.balance(&mut session, &partitions).await?,
+                _ = checkpoint.tick() => self.checkpoint()?,
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Session> {
+        let (tls, host, port) = self.config.address()?;
+        let label = format!("eventhubs {}", host);
+        let tcp = proxy::connect_tcp(
+            &host,
+            port,
+            self.config.proxy.as_ref(),
+            Some(&self.config.connect_options),
+        )
+        .await
+        .map_err(|err| Error::connect(&label, err))?;
+        let io: Box<dyn Io> = if tls {
+            let connector = tokio_native_tls::native_tls::TlsConnector::new()
+                .map_err(|err| Error::connect(&label, err))?;
+            let stream = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&host, tcp)
+                .await
+                .map_err(|err| Error::connect(&label, err))?;
+            Box::new(stream)
+        } else {
+            Box::new(tcp)
+        };
+        let mut connection = Connection::new(io, &label);
+        connection
+            .handshake(&self.config.key_name, &self.config.key, &host)
+            .await?;
+
+        let open = vec![
+            Value::string(&self.owner),
+            Value::string(&host),
+            Value::Uint(1 << 16),
+        ];
+        connection.send(0, OPEN, open, &[]).await?;
+        let (open, _) = connection.expect_amqp(OPEN).await?;
+        let heartbeat = match open.get(4).and_then(Value::as_u64) {
+            Some(timeout) if timeout > 0 => Duration::from_millis(timeout / 2).min(MAX_HEARTBEAT),
+            _ => MAX_HEARTBEAT,
+        };
+        let begin = vec![
+            Value::Null,
+            Value::Uint(0),
+            Value::Uint(WINDOW),
+            Value::Uint(WINDOW),
+        ];
+        connection.send(0, BEGIN, begin, &[]).await?;
+        let (begin, _) = connection.expect_amqp(BEGIN).await?;
+        let next_incoming_id = begin.get(1).and_then(Value::as_u64).unwrap_or(0) as u32;
+        Ok(Session {
+            connection,
+            next_incoming_id,
+            next_outgoing_id: 0,
+            received: 0,
+            next_handle: 0,
+            heartbeat,
+            links: Vec::new(),
+        })
+    }
+
+    async fn dispatch(&self, session: &mut Session, body: Value, payload: Vec<u8>) -> Result<()> {
+        let Some((code, fields)) = body.performative() else {
+            return Ok(());
+        };
+        let handle = fields.first().and_then(Value::as_u64);
+        match code {
+            ATTACH => {
+                let name = fields.first().and_then(Value::as_str).unwrap_or_default();
+                let remote_handle = fields
+                    .get(1)
+                    .and_then(Value::as_u64)
+                    .map(|handle| handle as u32);
+                let refused = matches!(fields.get(5), None | Some(Value::Null));
+                let delivery_count = fields.get(9).and_then(Value::as_u64).unwrap_or(0) as u32;
+                let prefetch = self.prefetch;
+                if let Some(link) = session.links.iter_mut().find(|link| link.name == name) {
+                    link.remote_handle = remote_handle;
+                    link.delivery_count = delivery_count;
+                    link.credit = prefetch;
+                    // a refused link is followed by a detach
+                    if !refused {
+                        let flow = (link.handle, delivery_count, prefetch);
+                        session.flow(Some(flow)).await?;
+                    }
+                }
+            }
+            TRANSFER => {
+                session.next_incoming_id = session.next_incoming_id.wrapping_add(1);
+                session.received += 1;
+                if session.received >= WINDOW / 2 {
+                    session.flow(None).await?;
+                }
+                let delivery_id = fields.get(1).and_then(Value::as_u64);
+                let settled = fields.get(4).and_then(Value::as_bool).unwrap_or(false);
+                let more = fields.get(5).and_then(Value::as_bool).unwrap_or(false);
+                let Some(link) = session.link(handle) else {
+                    return Ok(());
+                };
+                if let (Some(delivery_id), false) = (delivery_id, settled) {
+                    link.unsettled = Some(delivery_id as u32);
+                }
+                link.payload.extend_from_slice(&payload);
+                if more {
+                    return Ok(());
+                }
+                let payload = std::mem::take(&mut link.payload);
+                let unsettled = link.unsettled.take();
+                let partition = link.partition.clone();
+                link.delivery_count = link.delivery_count.wrapping_add(1);
+                link.credit = link.credit.saturating_sub(1);
+                let top_up = (link.credit <= self.prefetch / 2).then_some((
+                    link.handle,
+                    link.delivery_count,
+                    self.prefetch,
+                ));
+                if top_up.is_some() {
+                    link.credit = self.prefetch;
+                }
+                if let Some(delivery_id) = unsettled {
+                    let disposition = vec![
+                        Value::Bool(true),
+                        Value::Uint(delivery_id),
+                        Value::Null,
+                        Value::Bool(true),
+                        Value::described(ACCEPTED, Value::List(Vec::new())),
+                    ];
+                    session
+                        .connection
+                        .send(0, DISPOSITION, disposition, &[])
+                        .await?;
+                }
+                if top_up.is_some() {
+                    session.flow(top_up).await?;
+                }
+                self.emit(partition, &payload)?;
+            }
+            DETACH => {
+                let Some(index) = session.links.iter().position(|link| {
+                    link.remote_handle.is_some() && link.remote_handle.map(u64::from) == handle
+                }) else {
+                    return Ok(());
+                };
+                // the partition is attached again at the next balance
+                let link = session.links.remove(index);
+                let error = session.connection.closed(DETACH, fields);
+                log::warn(
+                    "eventhubs",
+                    format_args!(
+                        "event hubs partition {} detached: {}",
+                        link.partition, error
+                    ),
+                );
+                session.detach(link.handle).await?;
+            }
+            END | CLOSE => return Err(session.connection.closed(code, fields)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn emit(&self, partition: String, payload: &[u8]) -> Result<()> {
+        let message = Message::decode(payload)?;
+        let annotation = |name: &str| message.annotations.get(name);
+        let sequence_number = annotation("x-opt-sequence-number")
+            .and_then(Value::as_i64)
+            .unwrap_or_default();
+        let properties = match &message.application_properties {
+            Value::Map(entries) => entries
+                .iter()
+                .map(|(key, value)| (key.to_text(), value.to_text()))
+                .collect(),
+            _ => HashMap::new(),
+        };
+        let event = EventData {
+            sequence_number,
+            offset: annotation("x-opt-offset")
+                .map(Value::to_text)
+                .unwrap_or_default(),
+            enqueued_time: annotation("x-opt-enqueued-time")
+                .and_then(Value::as_i64)
+                .unwrap_or_default(),
+            partition_key: annotation("x-opt-partition-key")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            properties,
+            body: message.body,
+            partition_id: partition.clone(),
+        };
+        self.bytes.set(self.bytes.get() + event.body.len() as u64);
+        self.source.emit(event);
+        self.positions
+            .borrow_mut()
+            .insert(partition, (sequence_number, false));
+        Ok(())
+    }
+
+    // Renews and claims leases, then attaches and detaches links to match.
+    async fn balance(&self, session: &mut Session, partitions: &[String]) -> Result<()> {
+        let held: HashSet<String> = session
+            .links
+            .iter()
+            .map(|link| link.partition.clone())
+            .collect();
+        let owned = match &self.cursors {
+            Some(cursors) => self.claim(cursors.as_ref(), partitions, &held)?,
+            None => partitions.to_vec(),
+        };
+        *self.leases.borrow_mut() = owned.clone();
+
+        let released: Vec<u32> = session
+            .links
+            .iter()
+            .filter(|link| !owned.contains(&link.partition))
+            .map(|link| link.handle)
+            .collect();
+        for handle in released {
+            session.links.retain(|link| link.handle != handle);
+            session.detach(handle).await?;
+        }
+        for partition in owned.iter().filter(|partition| !held.contains(*partition)) {
+            let expression = match self.position(partition) {
+                Some(sequence) => format!("amqp.annotation.x-opt-sequence-number > {}", sequence),
+                None => match self.start {
+                    EventPosition::Earliest => "amqp.annotation.x-opt-offset > '-1'".to_string(),
+                    EventPosition::Latest => "amqp.annotation.x-opt-offset > '@latest'".to_string(),
+                },
+            };
+            let filter = Value::Map(vec![(
+                Value::symbol(SELECTOR_FILTER),
+                Value::Described(
+                    Box::new(Value::symbol(SELECTOR_FILTER)),
+                    Box::new(Value::String(expression)),
+                ),
+            )]);
+            let address =
+                partition_address(&self.config.event_hub, &self.consumer_group, partition);
+            // address, then the defaults up to the filter field
+            let mut source = vec![Value::String(address)];
+            source.resize(7, Value::Null);
+            source.push(filter);
+            let name = format!("{}-{}-{}", self.owner, partition, session.next_handle);
+            let handle = session
+                .attach(
+                    &name,
+                    true,
+                    Value::described(SOURCE, Value::List(source)),
+                    Value::described(TARGET, Value::List(Vec::new())),
+                )
+                .await?;
+            session.links.push(Link {
+                partition: partition.clone(),
+                name,
+                handle,
+                remote_handle: None,
+                delivery_count: 0,
+                credit: 0,
+                payload: Vec::new(),
+                unsettled: None,
+            });
+        }
+        Ok(())
+    }
+
+    // The position to read after: emitted here, or checkpointed by whoever
+    // read the partition last.
+    fn position(&self, partition: &str) -> Option<i64> {
+        if let Some((sequence, _)) = self.positions.borrow().get(partition) {
+            return Some(*sequence);
+        }
+        self.cursors
+            .as_ref()?
+            .load(&self.key(partition))?
+            .parse()
+            .ok()
+    }
+
+    // The partitions this consumer should read, with their leases renewed.
+    fn claim(
+        &self,
+        cursors: &dyn CursorStore,
+        partitions: &[String],
+        held: &HashSet<String>,
+    ) -> Result<Vec<String>> {
+        let now = now_millis();
+        // live leases
+        let mut owners: HashMap<&str, String> = HashMap::new();
+        for partition in partitions {
+            let Some(lease) = cursors.load(&self.owner_key(partition)) else {
+                continue;
+            };
+            let Some((owner, expires)) = lease.split_once(' ') else {
+                continue;
+            };
+            if expires.parse::<u64>().is_ok_and(|expires| expires > now) {
+                owners.insert(partition.as_str(), owner.to_string());
+            }
+        }
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        counts.insert(&self.owner, 0);
+        for owner in owners.values() {
+            *counts.entry(owner).or_default() += 1;
+        }
+        let share = partitions.len().div_ceil(counts.len());
+        let floor = partitions.len() / counts.len();
+
+        let mut owned: Vec<String> = partitions
+            .iter()
+            .filter(|partition| match owners.get(partition.as_str()) {
+                Some(owner) => *owner == self.owner,
+                None => held.contains(*partition),
+            })
+            .cloned()
+            .collect();
+        for partition in partitions {
+            if owned.len() >= share {
+                break;
+            }
+            if !owners.contains_key(partition.as_str()) && !owned.contains(partition) {
+                owned.push(partition.clone());
+            }
+        }
+        if owned.len() < floor {
+            let busiest = counts
+                .iter()
+                .filter(|(owner, count)| **owner != self.owner && **count > share)
+                .max_by_key(|(_, count)| **count)
+                .map(|(owner, _)| owner.to_string());
+            if let Some(busiest) = busiest {
+                let stolen = partitions
+                    .iter()
+                    .find(|partition| owners.get(partition.as_str()) == Some(&busiest));
+                owned.extend(stolen.cloned());
+            }
+        }
+
+        // partitions lost to another consumer are left to it, surplus ones
+        // are checkpointed and handed back
+        let mut positions = self.positions.borrow_mut();
+        for partition in held.iter().filter(|partition| !owned.contains(partition)) {
+            positions.remove(partition);
+        }
+        for partition in owned.split_off(share.min(owned.len())) {
+            if let Some((sequence, _)) = positions.remove(&partition) {
+                cursors.save(&self.key(&partition), &sequence.to_string())?;
+            }
+            cursors.save(&self.owner_key(&partition), &format!("{} 0", self.owner))?;
+        }
+        drop(positions);
+
+        let expires = now + self.lease_duration.as_millis() as u64;
+        for partition in &owned {
+            cursors.save(
+                &self.owner_key(partition),
+                &format!("{} {}", self.owner, expires),
+            )?;
+        }
+        Ok(owned)
+    }
+
+    // Checkpoints and gives up the leases held.
+    pub fn release(&self) -> Result<()> {
+        self.checkpoint()?;
+        if let Some(cursors) = &self.cursors {
+            for partition in self.leases.borrow_mut().drain(..) {
+                cursors.save(&self.owner_key(&partition), &format!("{} 0", self.owner))?;
+            }
+        }
+        Ok(())
+    }
+}
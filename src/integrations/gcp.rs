@@ -0,0 +1,573 @@
+use crate::drain::InFlight;
+use crate::log;
+use crate::rt;
+use crate::sources::http_client::{client, request_error};
+use crate::{Error, Result, Sink, Source};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+// acknowledge and modifyAckDeadline take at most this many ids per call
+const MAX_ACK_IDS: usize = 2500;
+const MAX_PUBLISH_MESSAGES: usize = 1000;
+const MAX_PUBLISH_BYTES: usize = 9 << 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GcpAuth {
+    // An OAuth2 access token obtained elsewhere.
+    Token(String),
+    // The GCE / GKE metadata server's default service account.
+    MetadataServer,
+    // No auth, for the Pub/Sub emulator.
+    None,
+}
+
+#[derive(Clone, Debug)]
+pub struct GcpConfig {
+    pub project: String,
+    pub auth: GcpAuth,
+    pub endpoint: String,
+}
+
+impl GcpConfig {
+    pub fn new(project: &str, auth: GcpAuth) -> Self {
+        Self {
+            project: project.to_string(),
+            auth,
+            endpoint: "https://pubsub.googleapis.com".to_string(),
+        }
+    }
+
+    // `GOOGLE_CLOUD_PROJECT`, and `PUBSUB_EMULATOR_HOST` if set; otherwise
+    // `GOOGLE_OAUTH_ACCESS_TOKEN`, falling back to the metadata server.
+    pub fn from_env() -> Result<Self> {
+        let project = env::var("GOOGLE_CLOUD_PROJECT")
+            .map_err(|_| Error::config("GOOGLE_CLOUD_PROJECT is not set"))?;
+        if let Ok(host) = env::var("PUBSUB_EMULATOR_HOST") {
+            return Ok(
+                Self::new(&project, GcpAuth::None).with_endpoint(&format!("http://{}", host))
+            );
+        }
+        let auth = match env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            Ok(token) => GcpAuth::Token(token),
+            Err(_) => GcpAuth::MetadataServer,
+        };
+        Ok(Self::new(&project, auth))
+    }
+
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+}
+
+struct GcpClient {
+    http: reqwest::Client,
+    config: GcpConfig,
+    // metadata server token and when to fetch a new one
+    token: RefCell<Option<(String, Instant)>>,
+}
+
+impl GcpClient {
+    fn new(config: GcpConfig) -> Result<Self> {
+        if config.project.trim().is_empty() {
+            return Err(Error::config("gcp project is empty"));
+        }
+        Ok(Self {
+            http: client(&config.endpoint)?,
+            config,
+            token: RefCell::new(None),
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.config.endpoint
+    }
+
+    // `path` is relative to the project, e.g. `subscriptions/trades:pull`.
+    async fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let label = self.label();
+        let url = format!(
+            "{}/v1/projects/{}/{}",
+            self.config.endpoint, self.config.project, path
+        );
+        let mut request = self.http.post(&url).json(body);
+        if let Some(token) = self.token().await? {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let value: Value = if text.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(&text).map_err(|err| Error::decode(label, err))?
+        };
+        if !status.is_success() {
+            let message = value["error"]["message"].as_str().unwrap_or("");
+            return Err(Error::protocol(
+                label,
+                format!("{} failed: {} {}", path, status, message),
+            ));
+        }
+        Ok(value)
+    }
+
+    async fn token(&self) -> Result<Option<String>> {
+        match &self.config.auth {
+            GcpAuth::None => Ok(None),
+            GcpAuth::Token(token) => Ok(Some(token.clone())),
+            GcpAuth::MetadataServer => {
+                if let Some((token, refresh_at)) = &*self.token.borrow() {
+                    if Instant::now() < *refresh_at {
+                        return Ok(Some(token.clone()));
+                    }
+                }
+                let response = self
+                    .http
+                    .get(METADATA_TOKEN_URL)
+                    .header("Metadata-Flavor", "Google")
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .map_err(|err| request_error(METADATA_TOKEN_URL, err))?;
+                let value: Value = response
+                    .json()
+                    .await
+                    .map_err(|err| request_error(METADATA_TOKEN_URL, err))?;
+                let token = value["access_token"].as_str().ok_or_else(|| {
+                    Error::protocol(METADATA_TOKEN_URL, "no access_token in the response")
+                })?;
+                // refreshed a minute before it expires
+                let lifetime = value["expires_in"]
+                    .as_u64()
+                    .unwrap_or(300)
+                    .saturating_sub(60);
+                let refresh_at = Instant::now() + Duration::from_secs(lifetime);
+                *self.token.borrow_mut() = Some((token.to_string(), refresh_at));
+                Ok(Some(token.to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PubSubMessage {
+    pub message_id: String,
+    pub data: Vec<u8>,
+    pub attributes: HashMap<String, String>,
+    pub ordering_key: Option<String>,
+    // RFC 3339
+    pub publish_time: String,
+    pub ack_id: String,
+    // set when the subscription has a dead-letter policy
+    pub delivery_attempt: Option<u32>,
+}
+
+// Messages received but not acknowledged yet, whose ack deadlines the source
+// keeps extending, and acks waiting to be sent.
+struct Leases {
+    client: GcpClient,
+    subscription: String,
+    outstanding: RefCell<HashMap<String, Instant>>,
+    acks: RefCell<Vec<String>>,
+    acked: Cell<u64>,
+}
+
+impl Leases {
+    async fn acknowledge(&self, ack_ids: Vec<String>) -> Result<()> {
+        for chunk in ack_ids.chunks(MAX_ACK_IDS) {
+            let path = format!("subscriptions/{}:acknowledge", self.subscription);
+            self.client.post(&path, &json!({ "ackIds": chunk })).await?;
+            self.acked.set(self.acked.get() + chunk.len() as u64);
+        }
+        Ok(())
+    }
+
+    async fn extend(&self, ack_ids: Vec<String>, deadline: Duration) -> Result<()> {
+        for chunk in ack_ids.chunks(MAX_ACK_IDS) {
+            let path = format!("subscriptions/{}:modifyAckDeadline", self.subscription);
+            let body = json!({ "ackIds": chunk, "ackDeadlineSeconds": deadline.as_secs() });
+            self.client.post(&path, &body).await?;
+        }
+        Ok(())
+    }
+}
+
+// Pulls a subscription. Messages are acknowledged once emitted (every
+// callback downstream has run) unless an `acker` is attached, in which case
+// they are acknowledged as they reach it and its sink is flushed. Until then
+// their ack deadline is extended, up to `max_lease`, after which Pub/Sub
+// delivers them again.
+pub struct PubSubSource {
+    leases: Rc<Leases>,
+    max_messages: usize,
+    ack_deadline: Duration,
+    max_lease: Duration,
+    manual_ack: Cell<bool>,
+    source: Source<PubSubMessage>,
+    bytes: Cell<u64>,
+}
+
+impl PubSubSource {
+    pub async fn new(config: GcpConfig, subscription: &str) -> Result<Self> {
+        if subscription.trim().is_empty() {
+            return Err(Error::config("pubsub subscription is empty"));
+        }
+        Ok(Self {
+            leases: Rc::new(Leases {
+                client: GcpClient::new(config)?,
+                subscription: subscription.to_string(),
+                outstanding: RefCell::new(HashMap::new()),
+                acks: RefCell::new(Vec::new()),
+                acked: Cell::new(0),
+            }),
+            max_messages: 1000,
+            ack_deadline: Duration::from_secs(60),
+            max_lease: Duration::from_secs(600),
+            manual_ack: Cell::new(false),
+            source: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    // Messages per pull; 1000 by default.
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages.max(1);
+        self
+    }
+
+    // The deadline each extension sets, renewed at half of it; 60s by default.
+    pub fn with_ack_deadline(mut self, deadline: Duration) -> Self {
+        self.ack_deadline = deadline.clamp(Duration::from_secs(10), Duration::from_secs(600));
+        self
+    }
+
+    // How long an unacknowledged message is kept leased; 10 minutes by default.
+    pub fn with_max_lease(mut self, max_lease: Duration) -> Self {
+        self.max_lease = max_lease;
+        self
+    }
+
+    pub fn source(&self) -> &Source<PubSubMessage> {
+        &self.source
+    }
+
+    // A sink acknowledging the messages that reach it; attach it after the
+    // sinks that must have the messages first.
+    pub fn acker(&self) -> PubSubAcker {
+        self.manual_ack.set(true);
+        PubSubAcker {
+            leases: self.leases.clone(),
+        }
+    }
+
+    pub fn acked(&self) -> u64 {
+        self.leases.acked.get()
+    }
+
+    // Message payload bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut renew = interval(self.ack_deadline / 2);
+        renew.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        renew.reset();
+        let mut pull = Box::pin(self.pull());
+        loop {
+            tokio::select! {
+                messages = &mut pull => {
+                    self.dispatch(messages?).await?;
+                    pull = Box::pin(self.pull());
+                }
+                _ = renew.tick() => self.renew().await?,
+            }
+        }
+    }
+
+    async fn pull(&self) -> Result<Value> {
+        let path = format!("subscriptions/{}:pull", self.leases.subscription);
+        let body = json!({ "maxMessages": self.max_messages });
+        self.leases.client.post(&path, &body).await
+    }
+
+    async fn dispatch(&self, response: Value) -> Result<()> {
+        let label = self.leases.client.label();
+        let mut received = Vec::new();
+        for entry in response["receivedMessages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let message = &entry["message"];
+            let data = BASE64
+                .decode(message["data"].as_str().unwrap_or_default())
+                .map_err(|err| Error::decode(label, err))?;
+            self.bytes.set(self.bytes.get() + data.len() as u64);
+            let attributes = message["attributes"]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), value.as_str().unwrap_or_default().to_string()))
+                .collect();
+            received.push(PubSubMessage {
+                message_id: text(&message["messageId"]),
+                data,
+                attributes,
+                ordering_key: message["orderingKey"]
+                    .as_str()
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string),
+                publish_time: text(&message["publishTime"]),
+                ack_id: text(&entry["ackId"]),
+                delivery_attempt: entry["deliveryAttempt"]
+                    .as_u64()
+                    .map(|attempt| attempt as u32),
+            });
+        }
+
+        let now = Instant::now();
+        let manual = self.manual_ack.get();
+        let mut ack_ids = Vec::new();
+        for message in received {
+            if manual {
+                self.leases
+                    .outstanding
+                    .borrow_mut()
+                    .insert(message.ack_id.clone(), now);
+            } else {
+                ack_ids.push(message.ack_id.clone());
+            }
+            self.source.emit(message);
+        }
+        if !ack_ids.is_empty() {
+            self.leases.acknowledge(ack_ids).await?;
+        }
+        Ok(())
+    }
+
+    async fn renew(&self) -> Result<()> {
+        let now = Instant::now();
+        let ack_ids: Vec<String> = {
+            let mut outstanding = self.leases.outstanding.borrow_mut();
+            outstanding.retain(|_, received| now.duration_since(*received) < self.max_lease);
+            outstanding.keys().cloned().collect()
+        };
+        if ack_ids.is_empty() {
+            return Ok(());
+        }
+        self.leases.extend(ack_ids, self.ack_deadline).await
+    }
+}
+
+// Acknowledges the messages it receives when flushed, after the sinks
+// attached before it; from `PubSubSource::acker`.
+pub struct PubSubAcker {
+    leases: Rc<Leases>,
+}
+
+impl Sink<PubSubMessage> for PubSubAcker {
+    fn on_item(&self, item: &PubSubMessage) {
+        self.leases.acks.borrow_mut().push(item.ack_id.clone());
+    }
+
+    fn flush(&self) -> Result<()> {
+        let ack_ids = std::mem::take(&mut *self.leases.acks.borrow_mut());
+        if ack_ids.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut outstanding = self.leases.outstanding.borrow_mut();
+            for ack_id in &ack_ids {
+                outstanding.remove(ack_id);
+            }
+        }
+        let leases = self.leases.clone();
+        let tracked = InFlight::start();
+        rt::spawn_local(async move {
+            let _tracked = tracked;
+            if let Err(err) = leases.acknowledge(ack_ids).await {
+                log::warn("pubsub", format_args!("pubsub acknowledge failed: {}", err));
+            }
+        });
+        Ok(())
+    }
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+struct Publish {
+    client: GcpClient,
+    topic: String,
+    // batches in publish order; one request is in flight at a time so that
+    // messages sharing an ordering key arrive in order
+    queued: RefCell<VecDeque<Vec<Value>>>,
+    busy: Cell<bool>,
+    published: Cell<u64>,
+    failed: Cell<u64>,
+}
+
+impl Publish {
+    fn send_next(self: &Rc<Self>) {
+        if self.busy.get() {
+            return;
+        }
+        let Some(batch) = self.queued.borrow_mut().pop_front() else {
+            return;
+        };
+        self.busy.set(true);
+        let publish = self.clone();
+        let tracked = InFlight::start();
+        rt::spawn_local(async move {
+            let _tracked = tracked;
+            let path = format!("topics/{}:publish", publish.topic);
+            let count = batch.len() as u64;
+            match publish
+                .client
+                .post(&path, &json!({ "messages": batch }))
+                .await
+            {
+                Ok(_) => publish.published.set(publish.published.get() + count),
+                Err(err) => {
+                    publish.failed.set(publish.failed.get() + count);
+                    log::warn(
+                        "pubsub",
+                        format_args!("pubsub publish to {} failed: {}", publish.topic, err),
+                    );
+                }
+            }
+            publish.busy.set(false);
+            publish.send_next();
+        });
+    }
+}
+
+type KeyFn<T> = Box<dyn Fn(&T) -> String>;
+
+// Publishes items as JSON, batched and sent at each flush. Batches go out
+// one at a time, so messages sharing an ordering key keep their order (for
+// subscriptions with message ordering enabled).
+pub struct PubSubSink<T> {
+    publish: Rc<Publish>,
+    ordering_key: Option<KeyFn<T>>,
+    attributes: HashMap<String, String>,
+    batch: RefCell<Vec<Value>>,
+    batch_bytes: Cell<usize>,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T> PubSubSink<T>
+where
+    T: Serialize + 'static,
+{
+    pub fn new(config: GcpConfig, topic: &str) -> Result<Self> {
+        if topic.trim().is_empty() {
+            return Err(Error::config("pubsub topic is empty"));
+        }
+        Ok(Self {
+            publish: Rc::new(Publish {
+                client: GcpClient::new(config)?,
+                topic: topic.to_string(),
+                queued: RefCell::new(VecDeque::new()),
+                busy: Cell::new(false),
+                published: Cell::new(0),
+                failed: Cell::new(0),
+            }),
+            ordering_key: None,
+            attributes: HashMap::new(),
+            batch: RefCell::new(Vec::new()),
+            batch_bytes: Cell::new(0),
+            _item: PhantomData,
+        })
+    }
+
+    pub fn with_ordering_key<F>(mut self, key_fn: F) -> Self
+    where
+        F: Fn(&T) -> String + 'static,
+    {
+        self.ordering_key = Some(Box::new(key_fn));
+        self
+    }
+
+    // Sent with every message.
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn published(&self) -> u64 {
+        self.publish.published.get()
+    }
+
+    // Messages dropped because their publish call failed.
+    pub fn failed(&self) -> u64 {
+        self.publish.failed.get()
+    }
+
+    fn seal(&self) {
+        let batch = std::mem::take(&mut *self.batch.borrow_mut());
+        self.batch_bytes.set(0);
+        if !batch.is_empty() {
+            self.publish.queued.borrow_mut().push_back(batch);
+        }
+    }
+}
+
+impl<T> Sink<T> for PubSubSink<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        let data = match serde_json::to_vec(item) {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn(
+                    "pubsub",
+                    format_args!("pubsub sink could not encode an item: {}", err),
+                );
+                return;
+            }
+        };
+        let mut message = json!({ "data": BASE64.encode(&data) });
+        if !self.attributes.is_empty() {
+            message["attributes"] = json!(self.attributes);
+        }
+        if let Some(key_fn) = &self.ordering_key {
+            message["orderingKey"] = json!(key_fn(item));
+        }
+        let size = data.len() * 4 / 3 + 64;
+        if self.batch.borrow().len() >= MAX_PUBLISH_MESSAGES
+            || self.batch_bytes.get() + size > MAX_PUBLISH_BYTES
+        {
+            self.seal();
+        }
+        self.batch_bytes.set(self.batch_bytes.get() + size);
+        self.batch.borrow_mut().push(message);
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.seal();
+        self.publish.send_next();
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+#[cfg(feature = "kinesis")]
+pub mod aws;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(all(feature = "datafusion", not(target_arch = "wasm32")))]
+pub mod datafusion;
+#[cfg(all(feature = "duckdb", not(target_arch = "wasm32")))]
+pub mod duckdb;
+#[cfg(all(feature = "eventhubs", not(target_arch = "wasm32")))]
+pub mod eventhubs;
+#[cfg(feature = "gcp")]
+pub mod gcp;
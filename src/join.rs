@@ -0,0 +1,231 @@
+use crate::rt::Instant;
+use crate::source::track_timed_emitter;
+use crate::{Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::time::Duration;
+
+type Buffered<K, T> = RefCell<HashMap<K, Vec<Pending<T>>>>;
+
+pub struct WindowJoin<K, T, U> {
+    inner: Rc<WindowJoinInner<K, T, U>>,
+}
+
+struct WindowJoinInner<K, T, U> {
+    window: Duration,
+    left: Buffered<K, T>,
+    right: Buffered<K, U>,
+    joined: Source<(T, U)>,
+    left_unmatched: Source<T>,
+    right_unmatched: Source<U>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+struct Pending<T> {
+    item: T,
+    arrived: Instant,
+    matched: bool,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Pairs every item with each item of `other` sharing its key that arrived
+    // within `window`. Items that expire without ever matching are published
+    // on the unmatched streams.
+    pub fn window_join<U, K, FL, FR>(
+        &self,
+        other: &Stream<U>,
+        key_fn_l: FL,
+        key_fn_r: FR,
+        window: Duration,
+    ) -> WindowJoin<K, T, U>
+    where
+        U: Clone + 'static,
+        K: Eq + Hash + Clone + 'static,
+        FL: Fn(&T) -> K + 'static,
+        FR: Fn(&U) -> K + 'static,
+    {
+        let inner = Rc::new(WindowJoinInner {
+            window,
+            left: RefCell::new(HashMap::new()),
+            right: RefCell::new(HashMap::new()),
+            joined: Source::new(),
+            left_unmatched: Source::new(),
+            right_unmatched: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("window_join", window, &inner.registered);
+
+        let inner_left = inner.clone();
+        self.sink(move |item: &T| {
+            let now = Instant::now();
+            let key = key_fn_l(item);
+            let matches = probe(&inner_left.right, &key, now, inner_left.window);
+            buffer(&inner_left.left, key, item, now, !matches.is_empty());
+            for other in matches {
+                inner_left.joined.emit((item.clone(), other));
+            }
+        });
+
+        let inner_right = inner.clone();
+        other.sink(move |item: &U| {
+            let now = Instant::now();
+            let key = key_fn_r(item);
+            let matches = probe(&inner_right.left, &key, now, inner_right.window);
+            buffer(&inner_right.right, key, item, now, !matches.is_empty());
+            for other in matches {
+                inner_right.joined.emit((other, item.clone()));
+            }
+        });
+
+        // once both inputs are done nothing else can match
+        let remaining = Cell::new(2);
+        let inner_done = inner.clone();
+        let input_done = Rc::new(move || {
+            remaining.set(remaining.get() - 1);
+            if remaining.get() == 0 {
+                inner_done.expire(|_| true);
+                inner_done.joined.complete();
+                inner_done.left_unmatched.complete();
+                inner_done.right_unmatched.complete();
+            }
+        });
+        let input_done_clone = input_done.clone();
+        self.on_complete(move || input_done_clone());
+        other.on_complete(move || input_done());
+
+        WindowJoin { inner }
+    }
+}
+
+impl<K, T, U> WindowJoin<K, T, U>
+where
+    K: Eq + Hash + Clone + 'static,
+    T: Clone + 'static,
+    U: Clone + 'static,
+{
+    pub fn joined(&self) -> Stream<(T, U)> {
+        self.inner.joined.to_stream()
+    }
+
+    pub fn left_unmatched(&self) -> Stream<T> {
+        self.inner.left_unmatched.to_stream()
+    }
+
+    pub fn right_unmatched(&self) -> Stream<U> {
+        self.inner.right_unmatched.to_stream()
+    }
+
+    pub fn window(&self) -> Duration {
+        self.inner.window
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl<K, T, U> Clone for WindowJoin<K, T, U> {
+    fn clone(&self) -> Self {
+        WindowJoin {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, T, U> WindowJoinInner<K, T, U>
+where
+    K: Eq + Hash,
+{
+    fn expire<F>(&self, is_expired: F)
+    where
+        F: Fn(&Instant) -> bool,
+    {
+        let left = drain_expired(&self.left, &is_expired);
+        let right = drain_expired(&self.right, &is_expired);
+        for item in left {
+            self.left_unmatched.emit(item);
+        }
+        for item in right {
+            self.right_unmatched.emit(item);
+        }
+    }
+}
+
+impl<K, T, U> TimedEmitter for WindowJoinInner<K, T, U>
+where
+    K: Eq + Hash + 'static,
+    T: 'static,
+    U: 'static,
+{
+    fn period(&self) -> Duration {
+        self.window
+    }
+
+    fn name(&self) -> String {
+        "window_join".to_string()
+    }
+
+    fn flush(&self) {
+        self.expire(|arrived| arrived.elapsed() > self.window);
+    }
+}
+
+fn probe<K, T>(entries: &Buffered<K, T>, key: &K, now: Instant, window: Duration) -> Vec<T>
+where
+    K: Eq + Hash,
+    T: Clone,
+{
+    let mut entries = entries.borrow_mut();
+    let Some(pending) = entries.get_mut(key) else {
+        return Vec::new();
+    };
+    pending
+        .iter_mut()
+        .filter(|entry| now.duration_since(entry.arrived) <= window)
+        .map(|entry| {
+            entry.matched = true;
+            entry.item.clone()
+        })
+        .collect()
+}
+
+fn buffer<K, T>(entries: &Buffered<K, T>, key: K, item: &T, now: Instant, matched: bool)
+where
+    K: Eq + Hash,
+    T: Clone,
+{
+    entries.borrow_mut().entry(key).or_default().push(Pending {
+        item: item.clone(),
+        arrived: now,
+        matched,
+    });
+}
+
+// Removes expired entries, returning the ones that never matched.
+fn drain_expired<K, T, F>(entries: &Buffered<K, T>, is_expired: F) -> Vec<T>
+where
+    K: Eq + Hash,
+    F: Fn(&Instant) -> bool,
+{
+    let mut unmatched = Vec::new();
+    entries.borrow_mut().retain(|_, pending| {
+        let mut kept = Vec::with_capacity(pending.len());
+        for entry in pending.drain(..) {
+            if !is_expired(&entry.arrived) {
+                kept.push(entry);
+            } else if !entry.matched {
+                unmatched.push(entry.item);
+            }
+        }
+        *pending = kept;
+        !pending.is_empty()
+    });
+    unmatched
+}
@@ -0,0 +1,50 @@
+use crate::Stream;
+use serde::de::DeserializeOwned;
+use std::fmt::{self, Display};
+
+// A message `decode_json` could not decode, kept whole for a dead-letter
+// sink. `line` and `column` are 1-based, 0 when unknown.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonDecodeError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub input: String,
+}
+
+impl JsonDecodeError {
+    fn new(err: &serde_json::Error, input: &str) -> Self {
+        Self {
+            message: err.to_string(),
+            line: err.line(),
+            column: err.column(),
+            input: input.to_string(),
+        }
+    }
+}
+
+impl Display for JsonDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for JsonDecodeError {}
+
+impl<S> Stream<S>
+where
+    S: AsRef<str> + 'static,
+{
+    // Parses every message, e.g. a `WebSocketClient`'s, into `T`: decoded
+    // items on the first stream, messages that fail on the second.
+    pub fn decode_json<T>(&self) -> (Stream<T>, Stream<JsonDecodeError>)
+    where
+        T: DeserializeOwned + Clone + 'static,
+    {
+        self.map(|text: &S| {
+            let text = text.as_ref();
+            serde_json::from_str::<T>(text).map_err(|err| JsonDecodeError::new(&err, text))
+        })
+        .split_result()
+    }
+}
@@ -1,10 +1,168 @@
 //! Minimal streaming primitives and websocket client helpers used by the
 //! `deribit_trade_classifier` example.
 
+mod backpressure;
+mod burst;
+mod cache;
+#[cfg(feature = "calendar")]
+mod calendar;
+#[cfg(all(feature = "capi", not(target_arch = "wasm32")))]
+mod capi;
+mod claim;
+#[cfg(feature = "config")]
+pub mod config;
+// only the websocket sources and Event Hubs connect through it
+#[cfg_attr(
+    not(any(feature = "websockets", feature = "eventhubs")),
+    allow(dead_code)
+)]
+mod connect;
+mod context;
+mod credentials;
+#[cfg(not(target_arch = "wasm32"))]
+mod cursor;
+#[cfg(feature = "tui")]
+mod dashboard;
+#[cfg(all(feature = "disk-buffer", not(target_arch = "wasm32")))]
+mod disk_buffer;
+mod drain;
 mod engine;
+mod enrich;
+mod error;
+#[cfg(feature = "expr")]
+mod expr;
+#[cfg(feature = "requests")]
+mod fetch;
+#[cfg(all(feature = "polars", not(target_arch = "wasm32")))]
+mod frame;
+// only the network sources are guarded
+#[cfg_attr(
+    not(any(
+        feature = "websockets",
+        feature = "requests",
+        all(feature = "wasm", target_arch = "wasm32")
+    )),
+    allow(dead_code)
+)]
+mod guard;
+mod handle;
+mod health;
+mod heartbeat;
+pub mod integrations;
+mod join;
+#[cfg(feature = "json")]
+mod json;
+mod log;
+#[cfg(not(target_arch = "wasm32"))]
+mod manifest;
+pub mod market;
+mod merge;
+pub mod metrics;
+#[cfg(all(feature = "node", not(target_arch = "wasm32")))]
+mod node;
+mod operator;
+mod pipeline;
+mod plan;
+#[cfg(all(feature = "plugins", not(target_arch = "wasm32")))]
+mod plugin;
+mod profile;
+mod proxy;
+#[cfg(feature = "query")]
+mod query;
+// only the websocket sources reconnect through a budget
+#[cfg_attr(
+    not(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32"))),
+    allow(dead_code)
+)]
+mod reconnect;
+#[cfg(feature = "recorders")]
+pub mod recorders;
+mod reorder;
+mod report;
+mod reset;
+mod route;
+mod rt;
+#[cfg(feature = "sbe")]
+mod sbe;
+mod schedule;
+#[cfg(feature = "schema")]
+mod schema;
+#[cfg(any(feature = "rhai", feature = "lua"))]
+mod script;
+mod shared;
+mod signal;
+mod sink;
+mod skew;
 mod source;
 pub mod sources;
+mod throttle;
 
+pub use backpressure::Backpressure;
+pub use burst::{BurstController, BurstMode, ModeChange};
+pub use cache::LatestCache;
+#[cfg(feature = "calendar")]
+pub use calendar::{SessionClock, SessionEvent, SessionInstance, TradingCalendar};
+pub use connect::{AddressFamily, ConnectOptions};
+pub use context::{Context, Envelope};
+#[cfg(feature = "vault")]
+pub use credentials::VaultCredentials;
+pub use credentials::{
+    Credentials, CredentialsProvider, EnvCredentials, FileCredentials, SharedCredentials,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use cursor::{CursorStore, FileCursorStore};
+#[cfg(feature = "tui")]
+pub use dashboard::Dashboard;
+#[cfg(all(feature = "disk-buffer", not(target_arch = "wasm32")))]
+pub use disk_buffer::DiskBuffer;
 pub use engine::{Engine, EngineBuilder, EngineSource};
-pub use source::{Source, Stream};
+pub use enrich::{CachedLookup, HashMapLookup, Lookup, LookupResult};
+pub use error::{BoxError, Error, Result};
+#[cfg(feature = "expr")]
+pub use expr::Expr;
+#[cfg(feature = "requests")]
+pub use fetch::{FetchConfig, FetchError};
+#[cfg(all(feature = "polars", not(target_arch = "wasm32")))]
+pub use frame::{FrameFormat, FrameRow, FrameWriter};
+pub use guard::{FrameGuard, GuardPolicy, GuardViolation, ViolationKind};
+pub use handle::{EngineEvent, EngineHandle};
+pub use health::{HealthCheck, HealthReport};
+pub use heartbeat::{Heartbeat, HeartbeatMonitor};
+pub use join::WindowJoin;
+#[cfg(feature = "json")]
+pub use json::JsonDecodeError;
+pub use log::Level;
+#[cfg(not(target_arch = "wasm32"))]
+pub use log::{LogSink, Logger};
+#[cfg(not(target_arch = "wasm32"))]
+pub use manifest::{BatchFileSink, BatchManifest, CommittedBatch};
+pub use merge::{merge, merge_sorted, stitch};
+pub use operator::{BoxOperator, Operator, OperatorChain, Then};
+pub use pipeline::{Pipeline, PipelineContext, PipelineError};
+pub use plan::{
+    BufferStatus, EnginePlan, EngineStatus, PlannedSource, PlannedTimer, SourceState, SourceStatus,
+};
+#[cfg(all(feature = "plugins", not(target_arch = "wasm32")))]
+pub use plugin::WasmPlugin;
+pub use proxy::{default_proxy, set_default_proxy, ProxyConfig, ProxyKind};
+#[cfg(feature = "query")]
+pub use query::Queryable;
+pub use reconnect::{BreakerState, ReconnectBudget};
+pub use reorder::{ReorderBuffer, Sequenced};
+pub use report::{RunReport, SourceRate, SourceStats, TimerStats};
+pub use reset::{ConnectionEvent, ResetGroup, Resettable};
+pub use route::RouteTable;
+#[cfg(feature = "sbe")]
+pub use sbe::{SbeDecoder, SbeMessage, SbeSchema};
+#[cfg(feature = "schema")]
+pub use schema::Schema;
+#[cfg(any(feature = "rhai", feature = "lua"))]
+pub use script::{Script, ScriptLanguage};
+pub use shared::{SharedSource, SharedStream};
+pub use signal::{Signal, SignalAction};
+pub use sink::{FileSink, Sink, StdoutSink};
+pub use skew::{ClockSkew, SkewEstimator};
+pub use source::{Source, Stream, TapSampling};
 pub use source::{TimedBuffer, TimedEmitter};
+pub use throttle::{Debouncer, Throttler};
+pub use tokio_util::sync::CancellationToken;
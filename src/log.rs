@@ -0,0 +1,513 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub use logger::{LogSink, Logger};
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        })
+    }
+}
+
+type LibraryLog = Box<dyn Fn(Level, &str, &str)>;
+
+thread_local! {
+    // Where this thread's library messages go; stdout when unset.
+    static LIBRARY: RefCell<Option<LibraryLog>> = const { RefCell::new(None) };
+}
+
+// The library's own messages (reconnects, failed writes and the like), with
+// the component they come from.
+pub(crate) fn write(level: Level, target: &str, message: impl Display) {
+    let message = message.to_string();
+    let routed = LIBRARY.with(|library| match &*library.borrow() {
+        Some(log) => {
+            log(level, target, &message);
+            true
+        }
+        None => false,
+    });
+    if !routed {
+        println!("{}", message);
+    }
+}
+
+pub(crate) fn info(target: &str, message: impl Display) {
+    write(Level::Info, target, message);
+}
+
+pub(crate) fn warn(target: &str, message: impl Display) {
+    write(Level::Warn, target, message);
+}
+
+pub(crate) fn error(target: &str, message: impl Display) {
+    write(Level::Error, target, message);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod logger {
+    use super::{Level, LIBRARY};
+    use crate::{Context, Envelope, Error, Result, Sink};
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::fmt::{Display, Write as _};
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, BufWriter, Write};
+    use std::marker::PhantomData;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    const DEFAULT_MAX_FILES: usize = 5;
+
+    // Writes log lines as JSON objects (`ts` in milliseconds since the epoch,
+    // `level`, `stream`, `message`) to stdout or a file. Files rotate past
+    // `with_max_bytes` or `with_rotate_every`: `app.log` moves to `app.log.1`,
+    // and so on up to `with_max_files`. With `with_throttle`, a line repeated
+    // within the window is written once, followed by a count of the repeats
+    // once the window has passed. Streams log through `sink`, and streams of
+    // `Envelope`s through `envelope_sink`, which adds each item's `tenant`,
+    // `trace_id` and `tags`; `install` routes the library's own messages here
+    // too.
+    #[derive(Clone)]
+    pub struct Logger {
+        inner: Rc<LoggerInner>,
+    }
+
+    struct LoggerInner {
+        path: Option<PathBuf>,
+        writer: RefCell<Option<BufWriter<File>>>,
+        written: Cell<u64>,
+        opened: Cell<Instant>,
+        max_bytes: Cell<Option<u64>>,
+        rotate_every: Cell<Option<Duration>>,
+        max_files: Cell<usize>,
+        min_level: Cell<Level>,
+        stream_levels: RefCell<HashMap<String, Level>>,
+        throttle: Cell<Option<Duration>>,
+        // by stream, level, message and context fields
+        repeats: RefCell<HashMap<(String, Level, String, String), Repeat>>,
+        reported: Cell<bool>,
+    }
+
+    struct Repeat {
+        since: Instant,
+        suppressed: u64,
+    }
+
+    impl Logger {
+        pub fn stdout() -> Self {
+            Self::with_writer(None, None)
+        }
+
+        // Appends to `path`, creating it if needed.
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            let file = append(&path)?;
+            let written = file.metadata().map_or(0, |metadata| metadata.len());
+            let logger = Self::with_writer(Some(path), Some(BufWriter::new(file)));
+            logger.inner.written.set(written);
+            Ok(logger)
+        }
+
+        fn with_writer(path: Option<PathBuf>, writer: Option<BufWriter<File>>) -> Self {
+            Self {
+                inner: Rc::new(LoggerInner {
+                    path,
+                    writer: RefCell::new(writer),
+                    written: Cell::new(0),
+                    opened: Cell::new(Instant::now()),
+                    max_bytes: Cell::new(None),
+                    rotate_every: Cell::new(None),
+                    max_files: Cell::new(DEFAULT_MAX_FILES),
+                    min_level: Cell::new(Level::Info),
+                    stream_levels: RefCell::new(HashMap::new()),
+                    throttle: Cell::new(None),
+                    repeats: RefCell::new(HashMap::new()),
+                    reported: Cell::new(false),
+                }),
+            }
+        }
+
+        pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+            self.inner.max_bytes.set(Some(max_bytes));
+            self
+        }
+
+        pub fn with_rotate_every(self, period: Duration) -> Self {
+            self.inner.rotate_every.set(Some(period));
+            self
+        }
+
+        // Rotated files kept besides the current one; 5 by default.
+        pub fn with_max_files(self, max_files: usize) -> Self {
+            self.inner.max_files.set(max_files.max(1));
+            self
+        }
+
+        // Lines below `level` are dropped; info by default.
+        pub fn with_min_level(self, level: Level) -> Self {
+            self.inner.min_level.set(level);
+            self
+        }
+
+        // Logs everything from `stream` at `level`, whatever its sink says,
+        // e.g. to quieten a chatty stream from config.
+        pub fn with_stream_level(self, stream: impl Into<String>, level: Level) -> Self {
+            self.inner
+                .stream_levels
+                .borrow_mut()
+                .insert(stream.into(), level);
+            self
+        }
+
+        pub fn with_throttle(self, window: Duration) -> Self {
+            self.inner.throttle.set(Some(window));
+            self
+        }
+
+        // A sink logging each item's `Display` as `stream` at `level`.
+        pub fn sink<T>(&self, stream: impl Into<String>, level: Level) -> LogSink<T>
+        where
+            T: Display + 'static,
+        {
+            self.sink_with(stream, move |_: &T| level)
+        }
+
+        // Like `sink`, with the level picked per item, e.g. warn for
+        // rejections and info for fills.
+        pub fn sink_with<T, F>(&self, stream: impl Into<String>, level: F) -> LogSink<T>
+        where
+            T: Display + 'static,
+            F: Fn(&T) -> Level + 'static,
+        {
+            LogSink {
+                logger: self.clone(),
+                stream: stream.into(),
+                level: Box::new(level),
+                context: no_context,
+                _item: PhantomData,
+            }
+        }
+
+        // Like `sink`, for items in envelopes: their contexts are logged
+        // with them.
+        pub fn envelope_sink<T>(
+            &self,
+            stream: impl Into<String>,
+            level: Level,
+        ) -> LogSink<Envelope<T>>
+        where
+            T: Display + 'static,
+        {
+            LogSink {
+                logger: self.clone(),
+                stream: stream.into(),
+                level: Box::new(move |_: &Envelope<T>| level),
+                context: envelope_context,
+                _item: PhantomData,
+            }
+        }
+
+        // Routes the library's messages on this thread here, under their
+        // component's name (e.g. "websocket_pool").
+        pub fn install(&self) {
+            let logger = self.clone();
+            LIBRARY.with(|library| {
+                *library.borrow_mut() = Some(Box::new(move |level, target, message| {
+                    logger.log(level, target, message)
+                }));
+            });
+        }
+
+        pub fn log(&self, level: Level, stream: &str, message: &str) {
+            self.log_fields(level, stream, message, String::new());
+        }
+
+        // Logs with the fields of `context`, e.g. from an `Envelope`.
+        pub fn log_in(&self, context: &Context, level: Level, stream: &str, message: &str) {
+            self.log_fields(level, stream, message, context_fields(context));
+        }
+
+        fn log_fields(&self, level: Level, stream: &str, message: &str, fields: String) {
+            let inner = &self.inner;
+            let level = inner
+                .stream_levels
+                .borrow()
+                .get(stream)
+                .copied()
+                .unwrap_or(level);
+            if level < inner.min_level.get() {
+                return;
+            }
+            if let Some(window) = inner.throttle.get() {
+                let key = (
+                    stream.to_string(),
+                    level,
+                    message.to_string(),
+                    fields.clone(),
+                );
+                let mut repeats = inner.repeats.borrow_mut();
+                match repeats.get_mut(&key) {
+                    Some(repeat) if repeat.since.elapsed() < window => {
+                        repeat.suppressed += 1;
+                        return;
+                    }
+                    Some(repeat) => {
+                        let suppressed = std::mem::take(&mut repeat.suppressed);
+                        repeat.since = Instant::now();
+                        drop(repeats);
+                        self.repeated(level, stream, message, &fields, suppressed);
+                    }
+                    None => {
+                        repeats.insert(
+                            key,
+                            Repeat {
+                                since: Instant::now(),
+                                suppressed: 0,
+                            },
+                        );
+                    }
+                }
+            }
+            self.write_line(level, stream, message, &fields, None);
+        }
+
+        pub fn flush(&self) -> Result<()> {
+            self.release_repeats();
+            match self.inner.writer.borrow_mut().as_mut() {
+                Some(writer) => writer.flush().map_err(|err| self.error(err)),
+                None => io::stdout().flush().map_err(|err| Error::io("stdout", err)),
+            }
+        }
+
+        // Writes the counts of lines whose throttle window has passed.
+        fn release_repeats(&self) {
+            let Some(window) = self.inner.throttle.get() else {
+                return;
+            };
+            let mut released = Vec::new();
+            self.inner.repeats.borrow_mut().retain(|key, repeat| {
+                if repeat.since.elapsed() < window {
+                    return true;
+                }
+                if repeat.suppressed > 0 {
+                    released.push((key.clone(), repeat.suppressed));
+                }
+                false
+            });
+            for ((stream, level, message, fields), suppressed) in released {
+                self.repeated(level, &stream, &message, &fields, suppressed);
+            }
+        }
+
+        fn repeated(
+            &self,
+            level: Level,
+            stream: &str,
+            message: &str,
+            fields: &str,
+            suppressed: u64,
+        ) {
+            if suppressed > 0 {
+                self.write_line(level, stream, message, fields, Some(suppressed));
+            }
+        }
+
+        fn write_line(
+            &self,
+            level: Level,
+            stream: &str,
+            message: &str,
+            fields: &str,
+            repeated: Option<u64>,
+        ) {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let mut line = format!(
+                "{{\"ts\":{},\"level\":\"{}\",\"stream\":{},\"message\":{}",
+                ts,
+                level,
+                quote(stream),
+                quote(message)
+            );
+            line.push_str(fields);
+            if let Some(repeated) = repeated {
+                let _ = write!(line, ",\"repeated\":{}", repeated);
+            }
+            line.push_str("}\n");
+            let result = if self.inner.path.is_some() {
+                self.rotate_if_due(line.len() as u64)
+                    .and_then(|()| self.append_line(&line))
+            } else {
+                io::stdout()
+                    .write_all(line.as_bytes())
+                    .map_err(|err| Error::io("stdout", err))
+            };
+            if let Err(err) = result {
+                if !self.inner.reported.replace(true) {
+                    eprintln!("logger failed to write: {}", err);
+                }
+            }
+        }
+
+        fn append_line(&self, line: &str) -> Result<()> {
+            let mut writer = self.inner.writer.borrow_mut();
+            let Some(writer) = writer.as_mut() else {
+                return Ok(());
+            };
+            writer
+                .write_all(line.as_bytes())
+                .map_err(|err| self.error(err))?;
+            self.inner
+                .written
+                .set(self.inner.written.get() + line.len() as u64);
+            Ok(())
+        }
+
+        fn rotate_if_due(&self, next: u64) -> Result<()> {
+            let inner = &self.inner;
+            let written = inner.written.get();
+            let full = inner
+                .max_bytes
+                .get()
+                .is_some_and(|max_bytes| written > 0 && written + next > max_bytes);
+            let old = inner
+                .rotate_every
+                .get()
+                .is_some_and(|period| inner.opened.get().elapsed() >= period);
+            if !full && !old {
+                return Ok(());
+            }
+            let Some(path) = &inner.path else {
+                return Ok(());
+            };
+            if let Some(mut writer) = inner.writer.borrow_mut().take() {
+                writer.flush().map_err(|err| self.error(err))?;
+            }
+            let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+            let max_files = inner.max_files.get();
+            let _ = fs::remove_file(rotated(max_files));
+            for index in (1..max_files).rev() {
+                let _ = fs::rename(rotated(index), rotated(index + 1));
+            }
+            fs::rename(path, rotated(1)).map_err(|err| self.error(err))?;
+            *inner.writer.borrow_mut() = Some(BufWriter::new(append(path)?));
+            inner.written.set(0);
+            inner.opened.set(Instant::now());
+            Ok(())
+        }
+
+        fn error(&self, err: io::Error) -> Error {
+            let label = self.inner.path.as_ref().map(|path| path.display());
+            Error::io(
+                label.map_or("stdout".to_string(), |path| path.to_string()),
+                err,
+            )
+        }
+    }
+
+    fn append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| Error::io(path.display().to_string(), err))
+    }
+
+    fn quote(text: &str) -> String {
+        let mut quoted = String::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if (c as u32) < 0x20 => {
+                    let _ = write!(quoted, "\\u{:04x}", c as u32);
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    // `"tenant"`, `"trace_id"` and `"tags"`, each when set, as JSON members
+    // following a comma.
+    fn context_fields(context: &Context) -> String {
+        let mut fields = String::new();
+        if let Some(tenant) = &context.tenant {
+            let _ = write!(fields, ",\"tenant\":{}", quote(tenant));
+        }
+        if let Some(trace_id) = &context.trace_id {
+            let _ = write!(fields, ",\"trace_id\":{}", quote(trace_id));
+        }
+        if !context.tags.is_empty() {
+            let tags: Vec<String> = context
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{}:{}", quote(key), quote(value)))
+                .collect();
+            let _ = write!(fields, ",\"tags\":{{{}}}", tags.join(","));
+        }
+        fields
+    }
+
+    fn no_context<T>(_: &T) -> Option<&Context> {
+        None
+    }
+
+    fn envelope_context<T>(envelope: &Envelope<T>) -> Option<&Context> {
+        Some(&envelope.context)
+    }
+
+    // Logs a stream's items through a `Logger`; from `Logger::sink` or
+    // `Logger::envelope_sink`.
+    pub struct LogSink<T> {
+        logger: Logger,
+        stream: String,
+        level: Box<dyn Fn(&T) -> Level>,
+        context: fn(&T) -> Option<&Context>,
+        _item: PhantomData<fn(&T)>,
+    }
+
+    impl<T> Sink<T> for LogSink<T>
+    where
+        T: Display + 'static,
+    {
+        fn on_item(&self, item: &T) {
+            let level = (self.level)(item);
+            match (self.context)(item) {
+                Some(context) => {
+                    self.logger
+                        .log_in(context, level, &self.stream, &item.to_string())
+                }
+                None => self.logger.log(level, &self.stream, &item.to_string()),
+            }
+        }
+
+        fn flush(&self) -> Result<()> {
+            self.logger.flush()
+        }
+    }
+}
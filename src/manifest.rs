@@ -0,0 +1,319 @@
+use crate::log;
+use crate::rt::now_millis;
+use crate::{Error, Result, Sink};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_MAX_ITEMS: usize = 100_000;
+
+// A batch whose file is complete, and the source position it covers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommittedBatch {
+    pub batch: u64,
+    pub path: PathBuf,
+    pub cursor: Option<String>,
+}
+
+// A write-ahead log of output files, one tab-separated line per step: a
+// batch is logged `pending` with its path and cursor before its file is
+// written, and `committed` once the file is in place. Opening the manifest
+// rolls back batches left pending by a crash (their files are deleted and
+// logged `aborted`), so resuming the source from `last_cursor` neither
+// duplicates nor loses output.
+pub struct BatchManifest {
+    path: PathBuf,
+    label: String,
+    log: RefCell<File>,
+    batches: RefCell<Vec<CommittedBatch>>,
+    next_batch: Cell<u64>,
+    aborted: u64,
+}
+
+impl BatchManifest {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let label = path.display().to_string();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(Error::io(label, err)),
+        };
+
+        let mut pending = BTreeMap::new();
+        let mut batches = Vec::new();
+        let mut next_batch = 0;
+        for (index, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || Error::decode(&label, format!("line {}: unknown entry", index + 1));
+            let mut fields = line.split('\t');
+            let step = fields.next().unwrap_or_default();
+            let batch = fields
+                .next()
+                .and_then(|batch| batch.parse::<u64>().ok())
+                .ok_or_else(invalid)?;
+            next_batch = next_batch.max(batch + 1);
+            match step {
+                "pending" => {
+                    let file = fields.next().ok_or_else(invalid)?;
+                    let cursor = fields.next().filter(|cursor| !cursor.is_empty());
+                    pending.insert(batch, (PathBuf::from(file), cursor.map(str::to_string)));
+                }
+                "committed" => {
+                    let (path, cursor) = pending.remove(&batch).ok_or_else(invalid)?;
+                    batches.push(CommittedBatch {
+                        batch,
+                        path,
+                        cursor,
+                    });
+                }
+                "aborted" => {
+                    pending.remove(&batch);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| Error::io(&label, err))?;
+        }
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| Error::io(&label, err))?;
+        let mut manifest = Self {
+            path,
+            label,
+            log: RefCell::new(log),
+            batches: RefCell::new(batches),
+            next_batch: Cell::new(next_batch),
+            aborted: 0,
+        };
+        for (batch, (file, _)) in pending {
+            remove_if_exists(&temporary_path(&file))?;
+            remove_if_exists(&file)?;
+            manifest.append(&format!("aborted\t{}", batch))?;
+            manifest.aborted += 1;
+        }
+        Ok(manifest)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn batches(&self) -> Vec<CommittedBatch> {
+        self.batches.borrow().clone()
+    }
+
+    // Where to resume the source: the cursor of the last committed batch
+    // that had one.
+    pub fn last_cursor(&self) -> Option<String> {
+        let batches = self.batches.borrow();
+        batches.iter().rev().find_map(|batch| batch.cursor.clone())
+    }
+
+    // The number the next batch will get; numbers are never reused.
+    pub fn next_batch(&self) -> u64 {
+        self.next_batch.get()
+    }
+
+    // Batches rolled back when the manifest was opened.
+    pub fn aborted(&self) -> u64 {
+        self.aborted
+    }
+
+    // Logs the batch, has `write` fill a temporary file next to `path`,
+    // moves it into place and logs it committed.
+    pub(crate) fn commit(
+        &self,
+        path: &Path,
+        cursor: Option<&str>,
+        write: impl FnOnce(&mut File) -> Result<()>,
+    ) -> Result<CommittedBatch> {
+        if cursor.is_some_and(|cursor| cursor.contains(['\t', '\n'])) {
+            return Err(Error::config(format!(
+                "{}: cursors may not contain tabs or newlines",
+                self.label
+            )));
+        }
+        let batch = self.next_batch.get();
+        self.next_batch.set(batch + 1);
+        self.append(&format!(
+            "pending\t{}\t{}\t{}",
+            batch,
+            path.display(),
+            cursor.unwrap_or_default()
+        ))?;
+        if let Err(err) = write_atomically(path, write) {
+            self.append(&format!("aborted\t{}", batch))?;
+            return Err(err);
+        }
+        self.append(&format!("committed\t{}", batch))?;
+        let committed = CommittedBatch {
+            batch,
+            path: path.to_path_buf(),
+            cursor: cursor.map(str::to_string),
+        };
+        self.batches.borrow_mut().push(committed.clone());
+        Ok(committed)
+    }
+
+    fn append(&self, line: &str) -> Result<()> {
+        let mut log = self.log.borrow_mut();
+        writeln!(log, "{}", line).map_err(|err| Error::io(&self.label, err))?;
+        log.sync_data().map_err(|err| Error::io(&self.label, err))
+    }
+}
+
+// Writes `path` via a hidden temporary file and a rename, so readers never
+// see it half-written.
+pub(crate) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<()>,
+) -> Result<()> {
+    let label = path.display().to_string();
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|err| Error::io(&label, err))?;
+    }
+    let temporary = temporary_path(path);
+    let mut file = File::create(&temporary).map_err(|err| Error::io(&label, err))?;
+    let written =
+        write(&mut file).and_then(|()| file.sync_all().map_err(|err| Error::io(&label, err)));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(err);
+    }
+    fs::rename(&temporary, path).map_err(|err| Error::io(&label, err))
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(Error::io(path.display().to_string(), err))
+        }
+        _ => Ok(()),
+    }
+}
+
+type CursorFn<T> = Box<dyn Fn(&T) -> String>;
+
+// Writes items as lines, a batch per file: lines collect until the sink is
+// flushed (on the engine's flush interval, or `with_max_items`) and are then
+// written as one file, atomically and listed in a `BatchManifest`. `pattern`
+// may contain `{seq}` (the batch number) and `{ts}` (milliseconds since the
+// unix epoch), e.g. "out/trades-{seq}.jsonl".
+pub struct BatchFileSink<T> {
+    pattern: String,
+    manifest: BatchManifest,
+    cursor_fn: Option<CursorFn<T>>,
+    max_items: usize,
+    lines: RefCell<Vec<String>>,
+    cursor: RefCell<Option<String>>,
+}
+
+impl<T> BatchFileSink<T>
+where
+    T: Display + 'static,
+{
+    pub fn new(pattern: impl Into<String>, manifest: impl Into<PathBuf>) -> Result<Self> {
+        let pattern = pattern.into();
+        if !pattern.contains("{seq}") && !pattern.contains("{ts}") {
+            return Err(Error::config(format!(
+                "{:?} needs {{seq}} or {{ts}} to name one file per batch",
+                pattern
+            )));
+        }
+        Ok(Self {
+            pattern,
+            manifest: BatchManifest::open(manifest)?,
+            cursor_fn: None,
+            max_items: DEFAULT_MAX_ITEMS,
+            lines: RefCell::new(Vec::new()),
+            cursor: RefCell::new(None),
+        })
+    }
+
+    // The source position an item was read at, e.g. a sequence number; a
+    // batch records that of its last item.
+    pub fn with_cursor<F>(mut self, cursor: F) -> Self
+    where
+        F: Fn(&T) -> String + 'static,
+    {
+        self.cursor_fn = Some(Box::new(cursor));
+        self
+    }
+
+    // Writes a batch once this many lines are waiting.
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items.max(1);
+        self
+    }
+
+    pub fn manifest(&self) -> &BatchManifest {
+        &self.manifest
+    }
+
+    // Writes the waiting lines as a batch, if there are any.
+    pub fn commit(&self) -> Result<Option<CommittedBatch>> {
+        if self.lines.borrow().is_empty() {
+            return Ok(None);
+        }
+        let path = PathBuf::from(
+            self.pattern
+                .replace("{seq}", &self.manifest.next_batch().to_string())
+                .replace("{ts}", &now_millis().to_string()),
+        );
+        let cursor = self.cursor.borrow().clone();
+        let lines = self.lines.borrow();
+        let committed = self.manifest.commit(&path, cursor.as_deref(), |file| {
+            let label = path.display().to_string();
+            let mut writer = BufWriter::new(file);
+            for line in lines.iter() {
+                writeln!(writer, "{}", line).map_err(|err| Error::io(&label, err))?;
+            }
+            writer.flush().map_err(|err| Error::io(&label, err))
+        })?;
+        drop(lines);
+        self.lines.borrow_mut().clear();
+        Ok(Some(committed))
+    }
+}
+
+impl<T> Sink<T> for BatchFileSink<T>
+where
+    T: Display + 'static,
+{
+    fn on_item(&self, item: &T) {
+        self.lines.borrow_mut().push(item.to_string());
+        if let Some(cursor_fn) = &self.cursor_fn {
+            *self.cursor.borrow_mut() = Some(cursor_fn(item));
+        }
+        if self.lines.borrow().len() >= self.max_items {
+            if let Err(err) = self.commit() {
+                log::error(
+                    "batch_file",
+                    format_args!("failed to write a batch: {}", err),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.commit().map(|_| ())
+    }
+}
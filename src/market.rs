@@ -0,0 +1,34 @@
+pub mod portfolio;
+
+pub use portfolio::{Portfolio, Position};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    // +1 for buys, -1 for sells.
+    pub fn sign(&self) -> f64 {
+        match self {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+        }
+    }
+}
+
+// One execution of an order, e.g. from `OrderEntryClient`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub trade_id: String,
+    pub order_id: String,
+    pub label: Option<String>,
+    pub instrument: String,
+    pub side: Side,
+    pub price: f64,
+    pub amount: f64,
+    pub fee: f64,
+    // milliseconds since the epoch
+    pub timestamp: u64,
+}
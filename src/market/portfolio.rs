@@ -0,0 +1,180 @@
+use super::Fill;
+use crate::{Source, Stream};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+// Quantities smaller than this count as flat.
+const FLAT: f64 = 1e-12;
+
+// Holdings in one instrument. PnL is quantity times price difference, as for
+// linear contracts; fees are kept apart from it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Position {
+    pub instrument: String,
+    // negative when short
+    pub quantity: f64,
+    // of the open quantity; zero when flat
+    pub average_price: f64,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub fees: f64,
+    // the latest mark, or the last fill price before any
+    pub mark: Option<f64>,
+}
+
+impl Position {
+    pub fn is_flat(&self) -> bool {
+        self.quantity.abs() < FLAT
+    }
+
+    // Realized and unrealized, less fees.
+    pub fn total_pnl(&self) -> f64 {
+        self.realized_pnl + self.unrealized_pnl - self.fees
+    }
+
+    fn apply(&mut self, fill: &Fill, marked: bool) {
+        let quantity = fill.amount * fill.side.sign();
+        let held = self.quantity;
+        if self.is_flat() || held.signum() == quantity.signum() {
+            let open = held.abs() + quantity.abs();
+            self.average_price =
+                (self.average_price * held.abs() + fill.price * quantity.abs()) / open;
+        } else {
+            let closed = quantity.abs().min(held.abs());
+            self.realized_pnl += closed * (fill.price - self.average_price) * held.signum();
+            if quantity.abs() > held.abs() {
+                // flipped: the remainder opens at the fill price
+                self.average_price = fill.price;
+            }
+        }
+        self.quantity = held + quantity;
+        if self.is_flat() {
+            self.quantity = 0.0;
+            self.average_price = 0.0;
+        }
+        self.fees += fill.fee;
+        if !marked {
+            self.mark = Some(fill.price);
+        }
+        self.revalue();
+    }
+
+    fn revalue(&mut self) {
+        self.unrealized_pnl = match self.mark {
+            Some(mark) if !self.is_flat() => self.quantity * (mark - self.average_price),
+            _ => 0.0,
+        };
+    }
+}
+
+pub struct Portfolio {
+    inner: Rc<PortfolioInner>,
+}
+
+struct PortfolioInner {
+    positions: RefCell<BTreeMap<String, Position>>,
+    marks: RefCell<HashMap<String, f64>>,
+    updates: Source<Position>,
+}
+
+impl Stream<Fill> {
+    // Keeps a position per instrument from fills, revalued by `marks` of
+    // (instrument, price). Emits the position after every fill, and after
+    // every mark of an instrument that is not flat.
+    pub fn portfolio(&self, marks: &Stream<(String, f64)>) -> Portfolio {
+        let inner = Rc::new(PortfolioInner {
+            positions: RefCell::new(BTreeMap::new()),
+            marks: RefCell::new(HashMap::new()),
+            updates: Source::new(),
+        });
+
+        let inner_fill = inner.clone();
+        self.sink(move |fill: &Fill| {
+            let mark = inner_fill.marks.borrow().get(&fill.instrument).copied();
+            let position = {
+                let mut positions = inner_fill.positions.borrow_mut();
+                let position =
+                    positions
+                        .entry(fill.instrument.clone())
+                        .or_insert_with(|| Position {
+                            instrument: fill.instrument.clone(),
+                            mark,
+                            ..Position::default()
+                        });
+                position.apply(fill, mark.is_some());
+                position.clone()
+            };
+            inner_fill.updates.emit(position);
+        });
+
+        let inner_mark = inner.clone();
+        marks.sink(move |(instrument, price): &(String, f64)| {
+            inner_mark
+                .marks
+                .borrow_mut()
+                .insert(instrument.clone(), *price);
+            let position = {
+                let mut positions = inner_mark.positions.borrow_mut();
+                let Some(position) = positions.get_mut(instrument) else {
+                    return;
+                };
+                position.mark = Some(*price);
+                position.revalue();
+                if position.is_flat() {
+                    return;
+                }
+                position.clone()
+            };
+            inner_mark.updates.emit(position);
+        });
+
+        let inner_done = inner.clone();
+        self.on_complete(move || inner_done.updates.complete());
+
+        Portfolio { inner }
+    }
+}
+
+impl Portfolio {
+    pub fn stream(&self) -> Stream<Position> {
+        self.inner.updates.to_stream()
+    }
+
+    pub fn position(&self, instrument: &str) -> Option<Position> {
+        self.inner.positions.borrow().get(instrument).cloned()
+    }
+
+    // Every instrument traded, flat ones included, by name.
+    pub fn snapshot(&self) -> Vec<Position> {
+        self.inner.positions.borrow().values().cloned().collect()
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.sum(|position| position.realized_pnl)
+    }
+
+    pub fn unrealized_pnl(&self) -> f64 {
+        self.sum(|position| position.unrealized_pnl)
+    }
+
+    pub fn fees(&self) -> f64 {
+        self.sum(|position| position.fees)
+    }
+
+    pub fn total_pnl(&self) -> f64 {
+        self.sum(Position::total_pnl)
+    }
+
+    fn sum(&self, field: impl Fn(&Position) -> f64) -> f64 {
+        self.inner.positions.borrow().values().map(field).sum()
+    }
+}
+
+impl Clone for Portfolio {
+    fn clone(&self) -> Self {
+        Portfolio {
+            inner: self.inner.clone(),
+        }
+    }
+}
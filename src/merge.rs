@@ -0,0 +1,395 @@
+use crate::{Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+struct MergeState<T> {
+    heap: BinaryHeap<Reverse<Buffered<T>>>,
+    next_seq: u64,
+    // last timestamp seen per input, `None` until it emits
+    last_seen: Vec<Option<u64>>,
+    completed: Vec<bool>,
+}
+
+struct Buffered<T> {
+    ts: u64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Buffered<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.ts, self.seq) == (other.ts, other.seq)
+    }
+}
+
+impl<T> Eq for Buffered<T> {}
+
+impl<T> PartialOrd for Buffered<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Buffered<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.ts, self.seq).cmp(&(other.ts, other.seq))
+    }
+}
+
+impl<T> MergeState<T> {
+    // Highest timestamp safe to emit: the slowest active input's position, or
+    // the fastest input's minus `max_skew` when that is later.
+    fn watermark(&self, max_skew: u64) -> Option<u64> {
+        let mut slowest = None::<u64>;
+        let mut fastest = None::<u64>;
+        let mut waiting = false;
+        for (last_seen, completed) in self.last_seen.iter().zip(&self.completed) {
+            if let Some(ts) = last_seen {
+                fastest = Some(fastest.map_or(*ts, |fastest| fastest.max(*ts)));
+            }
+            if *completed {
+                continue;
+            }
+            match last_seen {
+                Some(ts) => slowest = Some(slowest.map_or(*ts, |slowest| slowest.min(*ts))),
+                None => waiting = true,
+            }
+        }
+
+        let skewed = fastest.map(|fastest| fastest.saturating_sub(max_skew));
+        let aligned = if waiting { None } else { slowest.or(fastest) };
+        aligned.max(skewed)
+    }
+
+    fn drain_until(&mut self, watermark: Option<u64>) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(Reverse(next)) = self.heap.peek() {
+            if watermark.is_some_and(|watermark| next.ts <= watermark) {
+                let Reverse(next) = self.heap.pop().unwrap();
+                ready.push(next.item);
+            } else {
+                break;
+            }
+        }
+        ready
+    }
+
+    fn drain_all(&mut self) -> Vec<T> {
+        let mut ready = Vec::with_capacity(self.heap.len());
+        while let Some(Reverse(next)) = self.heap.pop() {
+            ready.push(next.item);
+        }
+        ready
+    }
+}
+
+// Items from all `streams`, in order of arrival; completes when all have.
+pub fn merge<T>(streams: &[Stream<T>]) -> Stream<T>
+where
+    T: 'static,
+{
+    Stream::merged(streams)
+}
+
+// Merges time-ordered inputs into a single stream ordered by
+// `timestamp_fn` (milliseconds, e.g. `RecordedMessage::ts`). Items are held
+// until every input has caught up, but never more than `max_skew` behind the
+// most advanced input. Items arriving behind the watermark are emitted as
+// soon as they arrive.
+pub fn merge_sorted<T, F>(streams: &[Stream<T>], timestamp_fn: F, max_skew: Duration) -> Stream<T>
+where
+    T: Clone + 'static,
+    F: Fn(&T) -> u64 + 'static,
+{
+    let output = Rc::new(Source::new());
+    let state = Rc::new(RefCell::new(MergeState {
+        heap: BinaryHeap::new(),
+        next_seq: 0,
+        last_seen: vec![None; streams.len()],
+        completed: vec![false; streams.len()],
+    }));
+    let timestamp_fn = Rc::new(timestamp_fn);
+    let max_skew = max_skew.as_millis() as u64;
+
+    for (index, stream) in streams.iter().enumerate() {
+        let output_item = output.clone();
+        let state_item = state.clone();
+        let timestamp_fn = timestamp_fn.clone();
+        stream.sink(move |item: &T| {
+            let ready = {
+                let mut state = state_item.borrow_mut();
+                let ts = timestamp_fn(item);
+                let seq = state.next_seq;
+                state.next_seq += 1;
+                state.heap.push(Reverse(Buffered {
+                    ts,
+                    seq,
+                    item: item.clone(),
+                }));
+                let last_seen = &mut state.last_seen[index];
+                *last_seen = Some(last_seen.map_or(ts, |last| last.max(ts)));
+                let watermark = state.watermark(max_skew);
+                state.drain_until(watermark)
+            };
+            for item in ready {
+                output_item.emit(item);
+            }
+        });
+
+        let output_done = output.clone();
+        let state_done = state.clone();
+        stream.on_complete(move || {
+            let (ready, all_done) = {
+                let mut state = state_done.borrow_mut();
+                state.completed[index] = true;
+                if state.completed.iter().all(|completed| *completed) {
+                    (state.drain_all(), true)
+                } else {
+                    let watermark = state.watermark(max_skew);
+                    (state.drain_until(watermark), false)
+                }
+            };
+            for item in ready {
+                output_done.emit(item);
+            }
+            if all_done {
+                output_done.complete();
+            }
+        });
+    }
+
+    output.to_stream()
+}
+
+struct Stitch<T, K> {
+    // highest key emitted so far; live items at or below it are overlap
+    high: Option<K>,
+    backfilled: bool,
+    buffered: Vec<T>,
+    live_done: bool,
+}
+
+impl<T, K: Ord> Stitch<T, K> {
+    fn admit(&mut self, key: K) -> bool {
+        if self.high.as_ref().is_some_and(|high| key <= *high) {
+            return false;
+        }
+        self.high = Some(key);
+        true
+    }
+}
+
+// Replays `historical` (e.g. a REST backfill) and then switches to `live`.
+// Live items arriving before the backfill completes are buffered. Overlap is
+// dropped by `key_fn`, which must increase along both streams, e.g.
+// (timestamp, trade id): any live item whose key is not above the last one
+// emitted is skipped. Completes once both inputs have.
+pub fn stitch<T, K, F>(historical: &Stream<T>, live: &Stream<T>, key_fn: F) -> Stream<T>
+where
+    T: Clone + 'static,
+    K: Ord + 'static,
+    F: Fn(&T) -> K + 'static,
+{
+    let output = Rc::new(Source::new());
+    let state = Rc::new(RefCell::new(Stitch {
+        high: None::<K>,
+        backfilled: false,
+        buffered: Vec::new(),
+        live_done: false,
+    }));
+    let key_fn = Rc::new(key_fn);
+
+    let output_history = output.clone();
+    let state_history = state.clone();
+    let key_history = key_fn.clone();
+    historical.sink(move |item: &T| {
+        {
+            let mut state = state_history.borrow_mut();
+            if state.backfilled || !state.admit(key_history(item)) {
+                return;
+            }
+        }
+        output_history.emit(item.clone());
+    });
+
+    let output_live = output.clone();
+    let state_live = state.clone();
+    let key_live = key_fn.clone();
+    live.sink(move |item: &T| {
+        {
+            let mut state = state_live.borrow_mut();
+            if !state.backfilled {
+                state.buffered.push(item.clone());
+                return;
+            }
+            if !state.admit(key_live(item)) {
+                return;
+            }
+        }
+        output_live.emit(item.clone());
+    });
+
+    let output_backfilled = output.clone();
+    let state_backfilled = state.clone();
+    historical.on_complete(move || {
+        let (buffered, live_done) = {
+            let mut state = state_backfilled.borrow_mut();
+            if state.backfilled {
+                return;
+            }
+            state.backfilled = true;
+            (std::mem::take(&mut state.buffered), state.live_done)
+        };
+        for item in buffered {
+            let fresh = state_backfilled.borrow_mut().admit(key_fn(&item));
+            if fresh {
+                output_backfilled.emit(item);
+            }
+        }
+        if live_done {
+            output_backfilled.complete();
+        }
+    });
+
+    let output_done = output.clone();
+    live.on_complete(move || {
+        let backfilled = {
+            let mut state = state.borrow_mut();
+            state.live_done = true;
+            state.backfilled
+        };
+        if backfilled {
+            output_done.complete();
+        }
+    });
+
+    output.to_stream()
+}
+
+impl<T> Stream<Stream<T>>
+where
+    T: Clone + 'static,
+{
+    // Forwards only the most recent inner stream. Streams cannot be
+    // unsubscribed from, so superseded inner streams stay attached but are
+    // ignored. Completes once the outer and the current inner stream have.
+    pub fn switch(&self) -> Stream<T> {
+        let output = Rc::new(Source::new());
+        let generation = Rc::new(Cell::new(0u64));
+        let outer_done = Rc::new(Cell::new(false));
+        let inner_done = Rc::new(Cell::new(true));
+
+        let finish = {
+            let output = output.clone();
+            let outer_done = outer_done.clone();
+            let inner_done = inner_done.clone();
+            Rc::new(move || {
+                if outer_done.get() && inner_done.get() {
+                    output.complete();
+                }
+            })
+        };
+
+        let output_item = output.clone();
+        let finish_inner = finish.clone();
+        self.sink(move |inner: &Stream<T>| {
+            let current = generation.get() + 1;
+            generation.set(current);
+            inner_done.set(false);
+
+            let generation_item = generation.clone();
+            let output = output_item.clone();
+            inner.sink(move |item: &T| {
+                if generation_item.get() == current {
+                    output.emit(item.clone());
+                }
+            });
+
+            let generation_done = generation.clone();
+            let inner_done = inner_done.clone();
+            let finish = finish_inner.clone();
+            inner.on_complete(move || {
+                if generation_done.get() == current {
+                    inner_done.set(true);
+                    finish();
+                }
+            });
+        });
+
+        self.on_complete(move || {
+            outer_done.set(true);
+            finish();
+        });
+
+        output.to_stream()
+    }
+
+    // Forwards up to `max_concurrent` inner streams at once; further inner
+    // streams wait until an active one completes and only see items emitted
+    // from then on.
+    pub fn merge_all(&self, max_concurrent: usize) -> Stream<T> {
+        let merge = Rc::new(MergeAll {
+            max_concurrent: max_concurrent.max(1),
+            active: Cell::new(0),
+            queued: RefCell::new(VecDeque::new()),
+            outer_done: Cell::new(false),
+            output: Source::new(),
+        });
+
+        let merge_item = merge.clone();
+        self.sink(move |inner: &Stream<T>| {
+            if merge_item.active.get() < merge_item.max_concurrent {
+                merge_item.activate(inner);
+            } else {
+                merge_item.queued.borrow_mut().push_back(inner.clone());
+            }
+        });
+
+        let merge_done = merge.clone();
+        self.on_complete(move || {
+            merge_done.outer_done.set(true);
+            merge_done.finish();
+        });
+
+        merge.output.to_stream()
+    }
+}
+
+struct MergeAll<T> {
+    max_concurrent: usize,
+    active: Cell<usize>,
+    queued: RefCell<VecDeque<Stream<T>>>,
+    outer_done: Cell<bool>,
+    output: Source<T>,
+}
+
+impl<T> MergeAll<T>
+where
+    T: Clone + 'static,
+{
+    fn activate(self: &Rc<Self>, inner: &Stream<T>) {
+        self.active.set(self.active.get() + 1);
+
+        let merge_item = self.clone();
+        inner.sink(move |item: &T| merge_item.output.emit(item.clone()));
+
+        let merge_done = self.clone();
+        inner.on_complete(move || {
+            merge_done.active.set(merge_done.active.get() - 1);
+            let next = merge_done.queued.borrow_mut().pop_front();
+            match next {
+                Some(next) => merge_done.activate(&next),
+                None => merge_done.finish(),
+            }
+        });
+    }
+
+    fn finish(&self) {
+        if self.outer_done.get() && self.active.get() == 0 && self.queued.borrow().is_empty() {
+            self.output.complete();
+        }
+    }
+}
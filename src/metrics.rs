@@ -0,0 +1,188 @@
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::TcpListener;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+struct Family {
+    help: String,
+    kind: MetricKind,
+    series: BTreeMap<String, Arc<AtomicU64>>,
+}
+
+#[derive(Clone, Default)]
+pub struct MetricsRegistry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+#[derive(Clone)]
+pub struct Counter {
+    value: Arc<AtomicU64>,
+}
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct Gauge {
+    bits: Arc<AtomicU64>,
+}
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.bits.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        Counter {
+            value: self.series(name, help, MetricKind::Counter, labels),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        Gauge {
+            bits: self.series(name, help, MetricKind::Gauge, labels),
+        }
+    }
+
+    fn series(
+        &self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        labels: &[(&str, &str)],
+    ) -> Arc<AtomicU64> {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            series: BTreeMap::new(),
+        });
+        assert_eq!(
+            family.kind, kind,
+            "metric {name} registered as both a counter and a gauge"
+        );
+        family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(|| {
+                let initial = match kind {
+                    MetricKind::Counter => 0,
+                    MetricKind::Gauge => 0f64.to_bits(),
+                };
+                Arc::new(AtomicU64::new(initial))
+            })
+            .clone()
+    }
+
+    // Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in &family.series {
+                let value = value.load(Ordering::Relaxed);
+                match family.kind {
+                    MetricKind::Counter => {
+                        let _ = writeln!(out, "{}{} {}", name, labels, value);
+                    }
+                    MetricKind::Gauge => {
+                        let _ = writeln!(out, "{}{} {}", name, labels, f64::from_bits(value));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // Serves `render()` on `GET /metrics` until the returned future is dropped.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn serve(&self, addr: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|err| Error::io("metrics", err))?;
+        loop {
+            let (mut socket, _) = listener
+                .accept()
+                .await
+                .map_err(|err| Error::io("metrics", err))?;
+            let registry = self.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let Ok(read) = socket.read(&mut request).await else {
+                    return;
+                };
+                let request = String::from_utf8_lossy(&request[..read]);
+                let response = if request.starts_with("GET /metrics ") {
+                    let body = registry.render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                } else {
+                    "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_string()
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let rendered: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect();
+    format!("{{{}}}", rendered.join(","))
+}
@@ -0,0 +1,308 @@
+// A Node.js binding (napi-rs) for the TypeScript side: declare websocket and
+// polling http sources on an `Engine`, chain `map` / `filter` / `subscribe`
+// with JS callbacks and `await engine.run()`. Built with
+// `cargo rustc --release --features node --crate-type cdylib` and the
+// library copied to `streamz.node`; `node/index.d.ts` has the types.
+//
+// The engine runs on a libuv worker thread, so connections, decoding and
+// backpressure never touch the JS thread; each message is queued back to it,
+// where the callbacks run in declaration order. A callback that throws drops
+// the item, which is counted in its stream's `errors`, and the first throw
+// is printed.
+use crate::log;
+use crate::sources::http_client::{PollingHttpClient, PollingHttpClientConfig};
+use crate::sources::websocket_client::{WebSocketClient, WebSocketClientConfigBuilder};
+use crate::{CancellationToken, EngineBuilder, Error};
+use napi::bindgen_prelude::{AsyncTask, FnArgs, Function, FunctionRef, Unknown};
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, JsValue, Task};
+use napi_derive::napi;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Builder;
+
+type Callback = FunctionRef<Unknown<'static>, Unknown<'static>>;
+// Queues (source index, message) to the JS thread.
+type Deliver = ThreadsafeFunction<(u32, String), (), FnArgs<(u32, String)>, napi::Status, false>;
+
+enum Op {
+    Source,
+    Map(Callback),
+    Filter(Callback),
+    Subscribe(Callback),
+}
+
+struct Node {
+    op: Op,
+    children: RefCell<Vec<Rc<Node>>>,
+    errors: Cell<u32>,
+}
+
+impl Node {
+    fn new(op: Op) -> Rc<Self> {
+        Rc::new(Self {
+            op,
+            children: RefCell::new(Vec::new()),
+            errors: Cell::new(0),
+        })
+    }
+
+    fn receive(&self, env: &Env, value: Unknown<'static>) {
+        let result = match &self.op {
+            Op::Source => Ok(Some(value)),
+            Op::Map(callback) => callback
+                .borrow_back(env)
+                .and_then(|callback| callback.call(value))
+                .map(Some),
+            Op::Filter(callback) => callback
+                .borrow_back(env)
+                .and_then(|callback| callback.call(value))
+                .and_then(|keep| keep.coerce_to_bool())
+                .map(|keep| keep.then_some(value)),
+            Op::Subscribe(callback) => callback
+                .borrow_back(env)
+                .and_then(|callback| callback.call(value))
+                .map(|_| None),
+        };
+        match result {
+            Ok(Some(value)) => {
+                // Cloned so callbacks may chain new operators onto this stream.
+                let children = self.children.borrow().clone();
+                for child in children {
+                    child.receive(env, value);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                if self.errors.replace(self.errors.get().saturating_add(1)) == 0 {
+                    log::error(
+                        "node",
+                        format_args!("streamz callback failed: {}", err.reason),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[napi]
+pub struct Stream {
+    node: Rc<Node>,
+}
+
+#[napi]
+impl Stream {
+    fn chain(&self, op: Op) -> Stream {
+        let node = Node::new(op);
+        self.node.children.borrow_mut().push(node.clone());
+        Stream { node }
+    }
+
+    #[napi(ts_args_type = "callback: (item: any) => any")]
+    pub fn map(
+        &self,
+        callback: Function<Unknown<'static>, Unknown<'static>>,
+    ) -> napi::Result<Stream> {
+        Ok(self.chain(Op::Map(callback.create_ref()?)))
+    }
+
+    #[napi(ts_args_type = "callback: (item: any) => boolean")]
+    pub fn filter(
+        &self,
+        callback: Function<Unknown<'static>, Unknown<'static>>,
+    ) -> napi::Result<Stream> {
+        Ok(self.chain(Op::Filter(callback.create_ref()?)))
+    }
+
+    #[napi(ts_args_type = "callback: (item: any) => void")]
+    pub fn subscribe(
+        &self,
+        callback: Function<Unknown<'static>, Unknown<'static>>,
+    ) -> napi::Result<()> {
+        self.chain(Op::Subscribe(callback.create_ref()?));
+        Ok(())
+    }
+
+    // Items this stream's callback threw on so far.
+    #[napi(getter)]
+    pub fn errors(&self) -> u32 {
+        self.node.errors.get()
+    }
+}
+
+enum SourceKind {
+    WebSocket { url: String, messages: Vec<String> },
+    Poll { url: String, period: Duration },
+}
+
+struct SourceSpec {
+    name: String,
+    kind: SourceKind,
+}
+
+#[napi]
+pub struct Engine {
+    token: CancellationToken,
+    specs: RefCell<Vec<SourceSpec>>,
+    roots: RefCell<Vec<Rc<Node>>>,
+    started: Cell<bool>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[napi]
+impl Engine {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            specs: RefCell::new(Vec::new()),
+            roots: RefCell::new(Vec::new()),
+            started: Cell::new(false),
+        }
+    }
+
+    fn add(&self, name: String, kind: SourceKind) -> napi::Result<Stream> {
+        if self.started.get() {
+            return Err(reason(Error::config("the engine is already running")));
+        }
+        if self.specs.borrow().iter().any(|spec| spec.name == name) {
+            return Err(reason(Error::config(format!(
+                "duplicate source name {:?}",
+                name
+            ))));
+        }
+        let node = Node::new(Op::Source);
+        self.specs.borrow_mut().push(SourceSpec { name, kind });
+        self.roots.borrow_mut().push(node.clone());
+        Ok(Stream { node })
+    }
+
+    // A websocket source sending `messages` (subscriptions) after each
+    // connect; items are the text frames.
+    #[napi]
+    pub fn websocket(
+        &self,
+        name: String,
+        url: String,
+        messages: Option<Vec<String>>,
+    ) -> napi::Result<Stream> {
+        let messages = messages.unwrap_or_default();
+        self.add(name, SourceKind::WebSocket { url, messages })
+    }
+
+    // GETs `url` every `period_ms`; items are the response bodies.
+    #[napi]
+    pub fn poll(&self, name: String, url: String, period_ms: u32) -> napi::Result<Stream> {
+        let period = Duration::from_millis(period_ms.max(1).into());
+        self.add(name, SourceKind::Poll { url, period })
+    }
+
+    // Resolves when `stop` is called or every source finishes. An engine
+    // runs once.
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn run(&self, env: Env) -> napi::Result<AsyncTask<EngineTask>> {
+        if self.started.replace(true) {
+            return Err(reason(Error::config("the engine has already run")));
+        }
+        let specs = self.specs.take();
+        if specs.is_empty() {
+            return Err(reason(Error::config("the engine has no sources")));
+        }
+        let roots = self.roots.take();
+        let dispatch: Function<FnArgs<(u32, String)>, ()> =
+            env.create_function_from_closure("dispatch", move |ctx| {
+                let index: u32 = ctx.get(0)?;
+                let message: Unknown<'static> = ctx.get(1)?;
+                if let Some(root) = roots.get(index as usize) {
+                    root.receive(ctx.env, message);
+                }
+                Ok(())
+            })?;
+        let deliver = dispatch
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build_callback(|ctx| Ok(FnArgs::from(ctx.value)))?;
+        Ok(AsyncTask::new(EngineTask {
+            specs,
+            deliver: Arc::new(deliver),
+            token: self.token.clone(),
+        }))
+    }
+
+    // Asks a running engine to stop; `run` then resolves.
+    #[napi]
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+}
+
+pub struct EngineTask {
+    specs: Vec<SourceSpec>,
+    deliver: Arc<Deliver>,
+    token: CancellationToken,
+}
+
+impl EngineTask {
+    fn run(&self) -> crate::Result<()> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| Error::other("streamz", err))?;
+        runtime.block_on(async {
+            let mut builder = EngineBuilder::new()
+                .with_cancellation(self.token.clone())
+                .with_signal_handling(false);
+            for (index, spec) in self.specs.iter().enumerate() {
+                let index = index as u32;
+                let deliver = self.deliver.clone();
+                let forward = move |message: &String| {
+                    deliver.call(
+                        (index, message.clone()),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                };
+                builder = match &spec.kind {
+                    SourceKind::WebSocket { url, messages } => {
+                        let config = WebSocketClientConfigBuilder::new(url)
+                            .with_messages(messages.clone())
+                            .build()?;
+                        let client = WebSocketClient::new(config).await?;
+                        client.source().to_stream().sink(forward);
+                        builder.add_source_owned(&spec.name, client)
+                    }
+                    SourceKind::Poll { url, period } => {
+                        let config = PollingHttpClientConfig::new(url, *period);
+                        let client = PollingHttpClient::new(config).await?;
+                        client.source().to_stream().sink(forward);
+                        builder.add_source_owned(&spec.name, client)
+                    }
+                };
+            }
+            builder.build()?.run().await.map(|_| ())
+        })
+    }
+}
+
+impl Task for EngineTask {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> napi::Result<()> {
+        self.run().map_err(reason)
+    }
+
+    fn resolve(&mut self, _env: Env, _output: ()) -> napi::Result<()> {
+        Ok(())
+    }
+}
+
+fn reason(err: Error) -> napi::Error {
+    napi::Error::from_reason(err.to_string())
+}
@@ -0,0 +1,124 @@
+use crate::Stream;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+// A reusable stage from one stream to another, e.g. a filter or a
+// decoder, that can be boxed so chains are put together at runtime (from a
+// config or a plugin registry) and applied to a test `Source` on its own.
+// Closures `Fn(&Stream<I>) -> Stream<O>` are operators.
+pub trait Operator<I, O> {
+    fn apply(&self, input: &Stream<I>) -> Stream<O>;
+
+    // Runs `next` on this operator's output.
+    fn then<P, R>(self, next: P) -> Then<Self, P, O>
+    where
+        Self: Sized,
+        P: Operator<O, R>,
+    {
+        Then {
+            first: self,
+            second: next,
+            _middle: PhantomData,
+        }
+    }
+
+    fn boxed(self) -> BoxOperator<I, O>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+pub type BoxOperator<I, O> = Box<dyn Operator<I, O>>;
+
+impl<I, O, F> Operator<I, O> for F
+where
+    F: Fn(&Stream<I>) -> Stream<O>,
+{
+    fn apply(&self, input: &Stream<I>) -> Stream<O> {
+        self(input)
+    }
+}
+
+impl<I, O> Operator<I, O> for Box<dyn Operator<I, O>> {
+    fn apply(&self, input: &Stream<I>) -> Stream<O> {
+        (**self).apply(input)
+    }
+}
+
+impl<I, O> Operator<I, O> for Rc<dyn Operator<I, O>> {
+    fn apply(&self, input: &Stream<I>) -> Stream<O> {
+        (**self).apply(input)
+    }
+}
+
+// Two operators in sequence; see `Operator::then`.
+pub struct Then<A, B, M> {
+    first: A,
+    second: B,
+    _middle: PhantomData<fn() -> M>,
+}
+
+impl<I, M, O, A, B> Operator<I, O> for Then<A, B, M>
+where
+    A: Operator<I, M>,
+    B: Operator<M, O>,
+{
+    fn apply(&self, input: &Stream<I>) -> Stream<O> {
+        self.second.apply(&self.first.apply(input))
+    }
+}
+
+// Operators of one item type applied in the order added, e.g. one per
+// config entry; an empty chain passes its input through.
+pub struct OperatorChain<T> {
+    operators: Vec<BoxOperator<T, T>>,
+}
+
+impl<T> Default for OperatorChain<T> {
+    fn default() -> Self {
+        Self {
+            operators: Vec::new(),
+        }
+    }
+}
+
+impl<T> OperatorChain<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, operator: impl Operator<T, T> + 'static) -> Self {
+        self.push(operator);
+        self
+    }
+
+    pub fn push(&mut self, operator: impl Operator<T, T> + 'static) {
+        self.operators.push(Box::new(operator));
+    }
+
+    pub fn len(&self) -> usize {
+        self.operators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operators.is_empty()
+    }
+}
+
+impl<T> Operator<T, T> for OperatorChain<T> {
+    fn apply(&self, input: &Stream<T>) -> Stream<T> {
+        let mut stream = input.clone();
+        for operator in &self.operators {
+            stream = operator.apply(&stream);
+        }
+        stream
+    }
+}
+
+impl<T> Stream<T> {
+    pub fn apply<O>(&self, operator: &impl Operator<T, O>) -> Stream<O> {
+        operator.apply(self)
+    }
+}
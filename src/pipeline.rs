@@ -0,0 +1,138 @@
+use crate::source::RetainedStream;
+use crate::{EngineSource, Source, Stream, TimedBuffer, TimedEmitter};
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::sync::Arc;
+
+// A reusable unit of wiring, e.g. a book builder or a candle writer. `build`
+// registers everything the engine has to drive on the context, so callers
+// only deal with the input and output streams.
+pub trait Pipeline {
+    type Input;
+    type Output;
+
+    fn build(&self, input: Self::Input, context: &mut PipelineContext) -> Self::Output;
+
+    // Tags the errors the pipeline reports; the type's name by default.
+    fn name(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+}
+
+// A failure a pipeline stage reported through
+// `PipelineContext::report_errors`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineError {
+    pub pipeline: String,
+    pub stage: String,
+    pub message: String,
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.pipeline, self.stage, self.message)
+    }
+}
+
+// Collects the streams, sources and timers a `Pipeline` creates; see
+// `EngineBuilder::add_pipeline` and `EngineHandle::add_pipeline`.
+pub struct PipelineContext {
+    pub(crate) streams: Vec<Box<dyn RetainedStream>>,
+    pub(crate) sources: Vec<(String, Arc<dyn EngineSource>)>,
+    pub(crate) timed_emitters: Vec<Rc<dyn TimedEmitter>>,
+    pipeline: String,
+    errors: Rc<Source<PipelineError>>,
+    // the engine's `pipeline_errors`, for contexts it created
+    forward: Option<Rc<Source<PipelineError>>>,
+}
+
+impl Default for PipelineContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineContext {
+    // A context of its own, e.g. to build a pipeline in a test and watch
+    // `errors()`.
+    pub fn new() -> Self {
+        Self::named("pipeline", None)
+    }
+
+    pub(crate) fn reporting_to(pipeline: &str, engine: Rc<Source<PipelineError>>) -> Self {
+        Self::named(pipeline, Some(engine))
+    }
+
+    fn named(pipeline: &str, forward: Option<Rc<Source<PipelineError>>>) -> Self {
+        Self {
+            streams: Vec::new(),
+            sources: Vec::new(),
+            timed_emitters: Vec::new(),
+            pipeline: pipeline.to_string(),
+            errors: Rc::new(Source::new()),
+            forward,
+        }
+    }
+
+    // Publishes every error on `errors` as a `PipelineError` of this
+    // pipeline's `stage`, e.g. the second stream of a `try_map`.
+    pub fn report_errors<E>(&mut self, stage: &str, errors: &Stream<E>)
+    where
+        E: Display + 'static,
+    {
+        let (pipeline, stage) = (self.pipeline.clone(), stage.to_string());
+        let (output, forward) = (self.errors.clone(), self.forward.clone());
+        errors.sink(move |err: &E| {
+            let error = PipelineError {
+                pipeline: pipeline.clone(),
+                stage: stage.clone(),
+                message: err.to_string(),
+            };
+            if let Some(forward) = &forward {
+                forward.emit(error.clone());
+            }
+            output.emit(error);
+        });
+    }
+
+    // What the pipeline built with this context reported. An engine's
+    // contexts also forward it to the engine's `pipeline_errors()`.
+    pub fn errors(&self) -> Stream<PipelineError> {
+        self.errors.to_stream()
+    }
+
+    pub fn add_stream<T>(&mut self, stream: Stream<T>)
+    where
+        T: 'static,
+    {
+        self.streams.push(Box::new(stream));
+    }
+
+    pub fn add_source<S>(&mut self, label: impl Into<String>, source: Arc<S>)
+    where
+        S: EngineSource,
+    {
+        self.sources
+            .push((label.into(), source as Arc<dyn EngineSource>));
+    }
+
+    pub fn add_source_owned<S>(&mut self, label: impl Into<String>, source: S)
+    where
+        S: EngineSource,
+    {
+        self.add_source(label, Arc::new(source));
+    }
+
+    pub fn add_timed_buffer<T>(&mut self, buffer: TimedBuffer<T>)
+    where
+        T: Clone + 'static,
+    {
+        self.streams.push(Box::new(buffer.stream()));
+        self.timed_emitters.push(buffer.as_timed_emitter());
+    }
+
+    pub fn add_timed_emitter(&mut self, emitter: Rc<dyn TimedEmitter>) {
+        self.timed_emitters.push(emitter);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::SourceStats;
+use std::fmt;
+use std::time::Duration;
+
+// What an engine would run, from `Engine::validate`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnginePlan {
+    // in registration order
+    pub sources: Vec<PlannedSource>,
+    // in flush order
+    pub timers: Vec<PlannedTimer>,
+    // streams registered to be kept alive
+    pub streams: usize,
+    pub engines: Vec<(String, EnginePlan)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedSource {
+    pub label: String,
+    pub priority: i32,
+    // callbacks on the source's outputs, where the source can tell
+    pub subscribers: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedTimer {
+    pub name: String,
+    pub period: Duration,
+    pub priority: i32,
+}
+
+// A running engine's topology and sources, from `EngineHandle::status`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EngineStatus {
+    pub plan: EnginePlan,
+    // one per label, in registration order
+    pub sources: Vec<SourceStatus>,
+    // bounded stages that reported backpressure, by stream name
+    pub buffers: Vec<BufferStatus>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceStatus {
+    pub label: String,
+    pub state: SourceState,
+    // summed over the sources registered under the label
+    pub stats: SourceStats,
+    // false if any of them is disconnected, where they can tell
+    pub connected: Option<bool>,
+    // since its message count last grew (or the engine started), as seen by
+    // successive status calls
+    pub idle: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferStatus {
+    pub stream: String,
+    // as last reported
+    pub depth: usize,
+    pub dropped: u64,
+    // since the last report
+    pub age: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceState {
+    Running,
+    // stopped with `EngineHandle::pause_source`, outputs left open
+    Paused,
+    // its `run` returned
+    Finished,
+    Removed,
+}
+
+impl fmt::Display for SourceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SourceState::Running => "running",
+            SourceState::Paused => "paused",
+            SourceState::Finished => "finished",
+            SourceState::Removed => "removed",
+        })
+    }
+}
+
+impl EnginePlan {
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let pad = " ".repeat(indent);
+        for source in &self.sources {
+            write!(
+                f,
+                "{}source {} (priority {}",
+                pad, source.label, source.priority
+            )?;
+            match source.subscribers {
+                Some(subscribers) => writeln!(f, ", {} subscribers)", subscribers)?,
+                None => writeln!(f, ")")?,
+            }
+        }
+        for timer in &self.timers {
+            writeln!(
+                f,
+                "{}timer {} every {:?} (priority {})",
+                pad, timer.name, timer.period, timer.priority
+            )?;
+        }
+        writeln!(f, "{}{} registered streams", pad, self.streams)?;
+        for (label, plan) in &self.engines {
+            writeln!(f, "{}engine {}:", pad, label)?;
+            plan.write(f, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EnginePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
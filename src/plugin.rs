@@ -0,0 +1,196 @@
+use crate::log;
+use crate::{Error, Result, Stream};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::path::Path;
+use wasmtime::{
+    Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+// An operator compiled to WebAssembly, exporting
+//
+//   memory
+//   alloc(len: i32) -> i32                  where the host writes the input
+//   process(ptr: i32, len: i32) -> i64      (out_ptr << 32) | out_len
+//   dealloc(ptr: i32, len: i32)             optional, given input and output
+//
+// An empty output drops the item. Modules get no imports (no WASI, no host
+// calls), so all they can touch is their own memory, capped at
+// `max_memory`; each item may burn at most `fuel` instructions' worth
+// before it is aborted. Items the plugin traps on are dropped and counted,
+// and the first trap is printed.
+pub struct WasmPlugin {
+    label: String,
+    fuel: u64,
+    store: RefCell<Store<StoreLimits>>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    process: TypedFunc<(u32, u32), u64>,
+    dealloc: Option<TypedFunc<(u32, u32), ()>>,
+    errors: Cell<u64>,
+    reported: Cell<bool>,
+}
+
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY: usize = 64 << 20;
+
+impl WasmPlugin {
+    // A `.wasm` binary, or `.wat` text.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let bytes = std::fs::read(path).map_err(|err| Error::io(&label, err))?;
+        Self::load(&label, &bytes)
+    }
+
+    pub fn new(bytes: &[u8]) -> Result<Self> {
+        Self::load("wasm plugin", bytes)
+    }
+
+    fn load(label: &str, bytes: &[u8]) -> Result<Self> {
+        let invalid = |err: wasmtime::Error| Error::config(format!("{}: {:#}", label, err));
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(invalid)?;
+        let module = Module::new(&engine, bytes).map_err(invalid)?;
+        let mut store = Store::new(&engine, limits(DEFAULT_MAX_MEMORY));
+        store.limiter(|limits| limits);
+        store.set_fuel(DEFAULT_FUEL).map_err(invalid)?;
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .map_err(invalid)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| Error::config(format!("{}: exports no memory", label)))?;
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(invalid)?;
+        let process = instance
+            .get_typed_func(&mut store, "process")
+            .map_err(invalid)?;
+        let dealloc = match instance.get_func(&mut store, "dealloc") {
+            Some(func) => Some(func.typed(&store).map_err(invalid)?),
+            None => None,
+        };
+        Ok(Self {
+            label: label.to_string(),
+            fuel: DEFAULT_FUEL,
+            store: RefCell::new(store),
+            memory,
+            alloc,
+            process,
+            dealloc,
+            errors: Cell::new(0),
+            reported: Cell::new(false),
+        })
+    }
+
+    // Fuel per item, roughly instructions; 10 million by default.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel.max(1);
+        self
+    }
+
+    // 64 MiB by default.
+    pub fn with_max_memory(self, bytes: usize) -> Self {
+        *self.store.borrow_mut().data_mut() = limits(bytes);
+        self
+    }
+
+    // Items the plugin trapped on so far.
+    pub fn errors(&self) -> u64 {
+        self.errors.get()
+    }
+
+    pub fn process(&self, input: &[u8]) -> Result<Vec<u8>> {
+        let trapped = |err: wasmtime::Error| Error::other(&self.label, format!("{:#}", err));
+        let mut store = self.store.borrow_mut();
+        store.set_fuel(self.fuel).map_err(trapped)?;
+        let len = u32::try_from(input.len())
+            .map_err(|_| Error::other(&self.label, "input larger than 4 GiB"))?;
+        let ptr = self.alloc.call(&mut *store, len).map_err(trapped)?;
+        self.memory
+            .write(&mut *store, ptr as usize, input)
+            .map_err(|err| Error::other(&self.label, err))?;
+        let packed = self
+            .process
+            .call(&mut *store, (ptr, len))
+            .map_err(trapped)?;
+        let (out_ptr, out_len) = ((packed >> 32) as u32, packed as u32);
+        let output = self
+            .memory
+            .data(&*store)
+            .get(out_ptr as usize..out_ptr as usize + out_len as usize)
+            .ok_or_else(|| Error::other(&self.label, "output outside the plugin's memory"))?
+            .to_vec();
+        if let Some(dealloc) = &self.dealloc {
+            dealloc.call(&mut *store, (ptr, len)).map_err(trapped)?;
+            if out_len > 0 {
+                dealloc
+                    .call(&mut *store, (out_ptr, out_len))
+                    .map_err(trapped)?;
+            }
+        }
+        Ok(output)
+    }
+
+    // `process`, counting and reporting failures; `None` drops the item.
+    fn process_item(&self, input: &[u8]) -> Option<Vec<u8>> {
+        match self.process(input) {
+            Ok(output) if output.is_empty() => None,
+            Ok(output) => Some(output),
+            Err(err) => {
+                self.errors.set(self.errors.get() + 1);
+                if !self.reported.replace(true) {
+                    log::warn("plugin", format_args!("{}", err));
+                }
+                None
+            }
+        }
+    }
+}
+
+fn limits(max_memory: usize) -> StoreLimits {
+    StoreLimitsBuilder::new()
+        .memory_size(max_memory)
+        .instances(1)
+        .build()
+}
+
+impl fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WasmPlugin").field(&self.label).finish()
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: AsRef<[u8]> + 'static,
+{
+    pub fn process_wasm(&self, plugin: WasmPlugin) -> Stream<Vec<u8>> {
+        self.filter_map(move |item| plugin.process_item(item.as_ref()))
+    }
+}
+
+impl Stream<Value> {
+    // Passes items to the plugin as JSON and parses what it returns.
+    pub fn process_wasm_json(&self, plugin: WasmPlugin) -> Stream<Value> {
+        self.filter_map(move |item| {
+            let output = plugin.process_item(&serde_json::to_vec(item).ok()?)?;
+            match serde_json::from_slice(&output) {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    plugin.errors.set(plugin.errors.get() + 1);
+                    if !plugin.reported.replace(true) {
+                        log::warn(
+                            "plugin",
+                            format_args!("{} returned invalid json: {}", plugin.label, err),
+                        );
+                    }
+                    None
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,109 @@
+use crate::log;
+use crate::metrics::{Counter, MetricsRegistry};
+use crate::rt::Instant;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+thread_local! {
+    // Installed by `Engine::run` for the lifetime of the engine.
+    static ACTIVE: RefCell<Option<Rc<CallbackProfiler>>> = const { RefCell::new(None) };
+}
+
+pub(crate) struct CallbackProfiler {
+    budget: Option<Duration>,
+    metrics: MetricsRegistry,
+    timings: RefCell<HashMap<String, StreamTimings>>,
+}
+
+struct StreamTimings {
+    calls: Counter,
+    micros: Counter,
+    slow: Counter,
+}
+
+impl CallbackProfiler {
+    pub(crate) fn new(budget: Option<Duration>, metrics: Option<MetricsRegistry>) -> Self {
+        Self {
+            budget,
+            metrics: metrics.unwrap_or_default(),
+            timings: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn record(&self, name: &str, elapsed: Duration) {
+        let slow = self.budget.is_some_and(|budget| elapsed > budget);
+        {
+            let mut timings = self.timings.borrow_mut();
+            let timings = timings
+                .entry(name.to_string())
+                .or_insert_with(|| self.register(name));
+            timings.calls.inc();
+            timings.micros.add(elapsed.as_micros() as u64);
+            if slow {
+                timings.slow.inc();
+            }
+        }
+        if let (true, Some(budget)) = (slow, self.budget) {
+            log::warn(
+                "profiler",
+                format_args!(
+                    "slow callback on stream {:?}: took {:?}, budget {:?}",
+                    name, elapsed, budget
+                ),
+            );
+        }
+    }
+
+    fn register(&self, name: &str) -> StreamTimings {
+        let labels = [("stream", name)];
+        StreamTimings {
+            calls: self.metrics.counter(
+                "streamz_callback_calls_total",
+                "Items dispatched per named stream.",
+                &labels,
+            ),
+            micros: self.metrics.counter(
+                "streamz_callback_micros_total",
+                "Time spent in downstream callbacks per named stream, in microseconds.",
+                &labels,
+            ),
+            slow: self.metrics.counter(
+                "streamz_slow_callbacks_total",
+                "Dispatches per named stream that exceeded the callback budget.",
+                &labels,
+            ),
+        }
+    }
+}
+
+// Restores the previously installed profiler on drop.
+pub(crate) struct ProfilerGuard {
+    previous: Option<Rc<CallbackProfiler>>,
+}
+
+pub(crate) fn install(profiler: Option<Rc<CallbackProfiler>>) -> ProfilerGuard {
+    let previous = ACTIVE.with(|active| active.replace(profiler));
+    ProfilerGuard { previous }
+}
+
+impl Drop for ProfilerGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ACTIVE.with(|active| *active.borrow_mut() = previous);
+    }
+}
+
+pub(crate) fn measure<F>(name: &str, dispatch: F)
+where
+    F: FnOnce(),
+{
+    let profiler = ACTIVE.with(|active| active.borrow().clone());
+    let Some(profiler) = profiler else {
+        return dispatch();
+    };
+    let started = Instant::now();
+    dispatch();
+    profiler.record(name, started.elapsed());
+}
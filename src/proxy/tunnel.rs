@@ -0,0 +1,237 @@
+use super::{default_proxy, ProxyConfig, ProxyKind};
+use crate::connect::{self, ConnectOptions};
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+impl ProxyConfig {
+    // A connection to `host:port` tunnelled through the proxy, which is
+    // reached per `options`.
+    async fn tunnel(
+        &self,
+        host: &str,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> io::Result<TcpStream> {
+        let mut stream = connect::connect(&self.host, self.port, options).await?;
+        stream.set_nodelay(true)?;
+        match self.kind {
+            ProxyKind::Http => self.http_connect(&mut stream, host, port).await?,
+            ProxyKind::Socks5 | ProxyKind::Socks5h => self.socks5(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let target = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+            _ => format!("{}:{}", host, port),
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some((user, pass)) = &self.auth {
+            let token = base64(format!("{}:{}", user, pass).as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // read the response head byte by byte so nothing of the tunnelled
+        // stream is consumed
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() > 8192 {
+                return Err(proxy_error("proxy response head too long"));
+            }
+            head.push(stream.read_u8().await?);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or_default();
+        if status != "200" {
+            let line = head.lines().next().unwrap_or_default();
+            return Err(proxy_error(&format!("proxy refused CONNECT: {}", line)));
+        }
+        Ok(())
+    }
+
+    async fn socks5(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let methods: &[u8] = if self.auth.is_some() { &[0, 2] } else { &[0] };
+        let mut greeting = vec![5, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        match (reply, &self.auth) {
+            ([5, 0], _) => {}
+            ([5, 2], Some((user, pass))) => {
+                if user.len() > 255 || pass.len() > 255 {
+                    return Err(proxy_error("socks5 username or password too long"));
+                }
+                let mut login = vec![1, user.len() as u8];
+                login.extend_from_slice(user.as_bytes());
+                login.push(pass.len() as u8);
+                login.extend_from_slice(pass.as_bytes());
+                stream.write_all(&login).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(proxy_error("socks5 authentication failed"));
+                }
+            }
+            _ => {
+                return Err(proxy_error(
+                    "socks5 proxy accepts no offered authentication",
+                ))
+            }
+        }
+
+        let mut request = vec![5, 1, 0];
+        let address = match host.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) if self.kind == ProxyKind::Socks5 => {
+                let mut resolved = tokio::net::lookup_host((host, port)).await?;
+                let resolved = resolved
+                    .next()
+                    .ok_or_else(|| proxy_error(&format!("{} did not resolve", host)))?;
+                Some(resolved.ip())
+            }
+            Err(_) => None,
+        };
+        match address {
+            Some(IpAddr::V4(address)) => {
+                request.push(1);
+                request.extend_from_slice(&address.octets());
+            }
+            Some(IpAddr::V6(address)) => {
+                request.push(4);
+                request.extend_from_slice(&address.octets());
+            }
+            None if host.len() > 255 => return Err(proxy_error("socks5 host name too long")),
+            None => {
+                request.push(3);
+                request.push(host.len() as u8);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        if head[1] != 0 {
+            return Err(proxy_error(&format!(
+                "socks5 connect failed: {}",
+                socks5_reply(head[1])
+            )));
+        }
+        let bound = match head[3] {
+            1 => 4,
+            4 => 16,
+            3 => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("socks5 reply has an unknown address type")),
+        };
+        let mut rest = vec![0u8; bound + 2];
+        stream.read_exact(&mut rest).await?;
+        Ok(())
+    }
+}
+
+// A TCP connection to `host:port`, through the proxy if one applies.
+pub(crate) async fn connect_tcp(
+    host: &str,
+    port: u16,
+    proxy: Option<&ProxyConfig>,
+    options: Option<&ConnectOptions>,
+) -> io::Result<TcpStream> {
+    let default = ConnectOptions::default();
+    let options = options.unwrap_or(&default);
+    match resolve(proxy, host) {
+        Some(proxy) => proxy.tunnel(host, port, options).await,
+        None => connect::connect(host, port, options).await,
+    }
+}
+
+// `tokio_tungstenite::connect_async`, through the proxy if one applies,
+// with the address connected to: the host's, or the proxy's.
+#[cfg(feature = "websockets")]
+pub(crate) async fn connect_async<R>(
+    request: R,
+    proxy: Option<&ProxyConfig>,
+    options: Option<&ConnectOptions>,
+) -> std::result::Result<
+    (
+        tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+        std::net::SocketAddr,
+    ),
+    tokio_tungstenite::tungstenite::Error,
+>
+where
+    R: tokio_tungstenite::tungstenite::client::IntoClientRequest + Unpin,
+{
+    use tokio_tungstenite::tungstenite::error::UrlError;
+    use tokio_tungstenite::tungstenite::Error as WsError;
+
+    let request = request.into_client_request()?;
+    let uri = request.uri();
+    let host = uri.host().ok_or(WsError::Url(UrlError::NoHostName))?;
+    let port = match uri.port_u16() {
+        Some(port) => port,
+        None if uri.scheme_str() == Some("wss") => 443,
+        None => 80,
+    };
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let stream = connect_tcp(&host, port, proxy, options).await?;
+    let peer = stream.peer_addr()?;
+    let (stream, _) = tokio_tungstenite::client_async_tls(request, stream).await?;
+    Ok((stream, peer))
+}
+
+// `explicit`, or else the default, unless it bypasses `host`.
+fn resolve(explicit: Option<&ProxyConfig>, host: &str) -> Option<ProxyConfig> {
+    explicit
+        .cloned()
+        .or_else(default_proxy)
+        .filter(|proxy| !proxy.bypasses(host))
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(message.to_string())
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let block = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for position in 0..4 {
+            if position <= chunk.len() {
+                encoded.push(ALPHABET[(block >> (18 - 6 * position) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,113 @@
+use crate::market::{Portfolio, Position};
+use crate::LatestCache;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::rc::Rc;
+use tokio::sync::watch;
+
+// State that can be inspected from outside the pipeline by name, through
+// `EngineHandle::query` or over HTTP with `QueryServer`.
+pub trait Queryable: 'static {
+    fn snapshot(&self) -> Value;
+
+    // One entry of a keyed snapshot, e.g. one instrument of a cache.
+    fn get(&self, key: &str) -> Option<Value> {
+        self.snapshot().get(key).cloned()
+    }
+}
+
+// Entries that have not expired, keyed by the key's `Display`.
+impl<K, T> Queryable for LatestCache<K, T>
+where
+    K: Eq + Hash + Clone + Display + 'static,
+    T: Serialize + Clone + 'static,
+{
+    fn snapshot(&self) -> Value {
+        let entries: Map<String, Value> = LatestCache::snapshot(self)
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), to_value(&value)))
+            .collect();
+        Value::Object(entries)
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        LatestCache::snapshot(self)
+            .into_iter()
+            .find(|(entry, _)| entry.to_string() == key)
+            .map(|(_, value)| to_value(&value))
+    }
+}
+
+// The latest item of a stream, from `Stream::to_watch`; null before the first.
+impl<T> Queryable for watch::Receiver<Option<T>>
+where
+    T: Serialize + 'static,
+{
+    fn snapshot(&self) -> Value {
+        to_value(&*self.borrow())
+    }
+}
+
+// Positions by instrument.
+impl Queryable for Portfolio {
+    fn snapshot(&self) -> Value {
+        let positions: Map<String, Value> = Portfolio::snapshot(self)
+            .iter()
+            .map(|position| (position.instrument.clone(), position_value(position)))
+            .collect();
+        Value::Object(positions)
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        self.position(key).map(|position| position_value(&position))
+    }
+}
+
+fn position_value(position: &Position) -> Value {
+    json!({
+        "quantity": position.quantity,
+        "average_price": position.average_price,
+        "realized_pnl": position.realized_pnl,
+        "unrealized_pnl": position.unrealized_pnl,
+        "fees": position.fees,
+        "mark": position.mark,
+        "total_pnl": position.total_pnl(),
+    })
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+// Queryable state by name, shared by an engine builder and its handles.
+#[derive(Clone, Default)]
+pub(crate) struct Queries {
+    entries: Rc<RefCell<BTreeMap<String, Rc<dyn Queryable>>>>,
+}
+
+impl Queries {
+    // Replaces anything registered under `name` before.
+    pub(crate) fn register(&self, name: String, state: Rc<dyn Queryable>) {
+        self.entries.borrow_mut().insert(name, state);
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.entries.borrow().keys().cloned().collect()
+    }
+
+    fn find(&self, name: &str) -> Option<Rc<dyn Queryable>> {
+        self.entries.borrow().get(name).cloned()
+    }
+
+    pub(crate) fn snapshot(&self, name: &str) -> Option<Value> {
+        self.find(name).map(|state| state.snapshot())
+    }
+
+    pub(crate) fn get(&self, name: &str, key: &str) -> Option<Value> {
+        self.find(name)?.get(key)
+    }
+}
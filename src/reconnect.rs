@@ -0,0 +1,259 @@
+use crate::log;
+use crate::rt::{self, Instant};
+use crate::{EngineEvent, Source};
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+const DEFAULT_MAX_CONCURRENT: usize = 4;
+const DEFAULT_JITTER: f64 = 0.2;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 10;
+const DEFAULT_OPEN_FOR: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    // attempts go ahead
+    Closed,
+    // attempts wait until the breaker half-opens
+    Open,
+    // one trial attempt decides whether it closes or opens again
+    HalfOpen,
+}
+
+impl Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        })
+    }
+}
+
+struct BudgetInner {
+    slots: RefCell<Arc<Semaphore>>,
+    jitter: Cell<f64>,
+    failure_threshold: Cell<u32>,
+    open_for: Cell<Duration>,
+    state: Cell<BreakerState>,
+    reopen_at: Cell<Instant>,
+    // a half-open trial attempt is under way
+    trial: Cell<bool>,
+    failures: Cell<u32>,
+    attempts: Cell<u64>,
+    changed: Notify,
+    rng: Cell<u64>,
+    events: RefCell<Vec<Rc<Source<EngineEvent>>>>,
+}
+
+// Connection attempts shared by reconnecting sources (`WebSocketPool`,
+// `RedundantWebSocketClient`, `OrderEntryClient`) given the same budget:
+// at most `with_max_concurrent` attempts run at once, reconnect delays are
+// jittered, and after `with_breaker`'s number of failures in a row the
+// breaker opens and holds every attempt back for a while, then lets a
+// single trial through. Changes are logged and, once the budget is passed
+// to `EngineBuilder::with_reconnect_budget`, published as
+// `EngineEvent::ReconnectBreaker`.
+#[derive(Clone)]
+pub struct ReconnectBudget {
+    inner: Rc<BudgetInner>,
+}
+
+impl Default for ReconnectBudget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReconnectBudget {
+    pub fn new() -> Self {
+        let inner = Rc::new(BudgetInner {
+            slots: RefCell::new(Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT))),
+            jitter: Cell::new(DEFAULT_JITTER),
+            failure_threshold: Cell::new(DEFAULT_FAILURE_THRESHOLD),
+            open_for: Cell::new(DEFAULT_OPEN_FOR),
+            state: Cell::new(BreakerState::Closed),
+            reopen_at: Cell::new(Instant::now()),
+            trial: Cell::new(false),
+            failures: Cell::new(0),
+            attempts: Cell::new(0),
+            changed: Notify::new(),
+            rng: Cell::new(0),
+            events: RefCell::new(Vec::new()),
+        });
+        // xorshift64; seeded per budget so separate processes spread out
+        inner
+            .rng
+            .set(Rc::as_ptr(&inner) as u64 ^ rt::now_millis() | 1);
+        Self { inner }
+    }
+
+    pub fn with_max_concurrent(self, max_concurrent: usize) -> Self {
+        *self.inner.slots.borrow_mut() = Arc::new(Semaphore::new(max_concurrent.max(1)));
+        self
+    }
+
+    // Spreads each reconnect delay over `delay * (1 ± jitter)`.
+    pub fn with_jitter(self, jitter: f64) -> Self {
+        self.inner.jitter.set(jitter.clamp(0.0, 1.0));
+        self
+    }
+
+    // Opens the breaker after `failures` failed attempts in a row, for
+    // `open_for` before a trial attempt.
+    pub fn with_breaker(self, failures: u32, open_for: Duration) -> Self {
+        self.inner.failure_threshold.set(failures.max(1));
+        self.inner.open_for.set(open_for);
+        self
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.state.get()
+    }
+
+    // Failed attempts since the last successful one.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.failures.get()
+    }
+
+    pub fn attempts(&self) -> u64 {
+        self.inner.attempts.get()
+    }
+
+    pub(crate) fn publish_to(&self, events: Rc<Source<EngineEvent>>) {
+        self.inner.events.borrow_mut().push(events);
+    }
+
+    // Waits for the breaker and a free slot. The attempt counts as failed
+    // unless the permit is marked `connected` before it is dropped.
+    pub(crate) async fn acquire(&self) -> ReconnectPermit {
+        let inner = &self.inner;
+        let trial = loop {
+            match inner.state.get() {
+                BreakerState::Closed => break false,
+                BreakerState::Open => {
+                    let reopen_at = inner.reopen_at.get();
+                    if Instant::now() < reopen_at {
+                        rt::sleep_until(reopen_at).await;
+                    } else {
+                        inner.trial.set(false);
+                        inner.transition(BreakerState::HalfOpen);
+                    }
+                }
+                BreakerState::HalfOpen if !inner.trial.get() => {
+                    inner.trial.set(true);
+                    break true;
+                }
+                BreakerState::HalfOpen => inner.changed.notified().await,
+            }
+        };
+        let slots = inner.slots.borrow().clone();
+        let slot = slots.acquire_owned().await.ok();
+        inner.attempts.set(inner.attempts.get() + 1);
+        ReconnectPermit {
+            budget: Some(inner.clone()),
+            slot,
+            trial,
+        }
+    }
+
+    // `delay` with jitter applied.
+    pub(crate) fn delay(&self, delay: Duration) -> Duration {
+        let jitter = self.inner.jitter.get();
+        let mut x = self.inner.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.inner.rng.set(x);
+        let unit = (x >> 11) as f64 / (1u64 << 53) as f64;
+        delay.mul_f64(1.0 - jitter + 2.0 * jitter * unit)
+    }
+}
+
+impl BudgetInner {
+    fn transition(&self, state: BreakerState) {
+        if self.state.replace(state) == state {
+            return;
+        }
+        if state == BreakerState::Open {
+            self.reopen_at.set(Instant::now() + self.open_for.get());
+        }
+        let failures = self.failures.get();
+        log::warn(
+            "reconnect",
+            format_args!(
+                "reconnect breaker {} after {} failed attempts in a row",
+                state, failures
+            ),
+        );
+        for events in self.events.borrow().iter() {
+            events.emit(EngineEvent::ReconnectBreaker { state, failures });
+        }
+        self.changed.notify_waiters();
+    }
+
+    fn settle(&self, connected: bool, trial: bool) {
+        if trial {
+            self.trial.set(false);
+        }
+        if connected {
+            self.failures.set(0);
+            self.transition(BreakerState::Closed);
+            return;
+        }
+        self.failures.set(self.failures.get().saturating_add(1));
+        if trial || self.failures.get() >= self.failure_threshold.get() {
+            self.transition(BreakerState::Open);
+        }
+    }
+}
+
+// A connection attempt in progress, holding one of the budget's slots.
+pub(crate) struct ReconnectPermit {
+    budget: Option<Rc<BudgetInner>>,
+    slot: Option<OwnedSemaphorePermit>,
+    trial: bool,
+}
+
+impl ReconnectPermit {
+    // For sources without a budget.
+    pub(crate) fn unlimited() -> Self {
+        Self {
+            budget: None,
+            slot: None,
+            trial: false,
+        }
+    }
+
+    // The attempt succeeded; frees the slot.
+    pub(crate) fn connected(mut self) {
+        if let Some(budget) = self.budget.take() {
+            budget.settle(true, self.trial);
+        }
+        self.slot = None;
+    }
+}
+
+impl Drop for ReconnectPermit {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget.take() {
+            budget.settle(false, self.trial);
+        }
+    }
+}
+
+// Waits for `budget`, if any, before a connection attempt.
+pub(crate) async fn permit(budget: Option<&ReconnectBudget>) -> ReconnectPermit {
+    match budget {
+        Some(budget) => budget.acquire().await,
+        None => ReconnectPermit::unlimited(),
+    }
+}
+
+// The delay before reconnecting, jittered by `budget`, if any.
+pub(crate) fn delay(budget: Option<&ReconnectBudget>, delay: Duration) -> Duration {
+    budget.map_or(delay, |budget| budget.delay(delay))
+}
@@ -0,0 +1,435 @@
+use crate::log;
+use crate::rt::now_millis;
+use crate::sources::replay::RecordedMessage;
+use crate::{Error, Result, Sink, Stream};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const HOUR_MS: u64 = 3_600_000;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+// When a tape file is closed and the next one started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    // on the UTC hour / day boundary
+    Hourly,
+    Daily,
+    // once a file holds this many (uncompressed) bytes
+    MaxBytes(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapeCompression {
+    None,
+    Gzip,
+}
+
+// One closed tape file, as listed in the manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TapeSegment {
+    pub path: PathBuf,
+    pub source: String,
+    // milliseconds since the unix epoch
+    pub first_ts: u64,
+    pub last_ts: u64,
+    pub items: u64,
+    // uncompressed
+    pub bytes: u64,
+}
+
+type InstrumentFn<T> = Rc<dyn Fn(&T) -> String>;
+
+// Records a stream to rotating files in the recording format, so
+// `ReplaySource` can play them back. `pattern` may contain `{date}`
+// (YYYY-MM-DD, UTC), `{hour}`, `{instrument}` and `{name}`, e.g.
+// "tapes/{date}/{instrument}.jsonl". Every closed file is appended to a
+// manifest (JSON lines of `TapeSegment`) next to the tapes. Existing files
+// are never appended to; a numbered part is started instead.
+pub struct TapeRecorder<T> {
+    name: String,
+    pattern: String,
+    instrument: Option<InstrumentFn<T>>,
+    rotation: Rotation,
+    compression: TapeCompression,
+    manifest: PathBuf,
+    state: Rc<TapeState>,
+}
+
+#[derive(Default)]
+struct TapeState {
+    // keyed by the rendered pattern
+    open: RefCell<HashMap<PathBuf, OpenSegment>>,
+    segments: RefCell<Vec<TapeSegment>>,
+}
+
+impl<T> Clone for TapeRecorder<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            pattern: self.pattern.clone(),
+            instrument: self.instrument.clone(),
+            rotation: self.rotation,
+            compression: self.compression,
+            manifest: self.manifest.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> TapeRecorder<T>
+where
+    T: Serialize + 'static,
+{
+    pub fn new(pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        Self {
+            name: "tape".to_string(),
+            manifest: default_manifest(&pattern),
+            pattern,
+            instrument: None,
+            rotation: Rotation::Daily,
+            compression: TapeCompression::None,
+            state: Rc::new(TapeState::default()),
+        }
+    }
+
+    // Recorded as the source of items without an instrument, and `{name}`.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    // Splits the tape per instrument; also the recorded source label.
+    pub fn with_instrument<F>(mut self, instrument: F) -> Self
+    where
+        F: Fn(&T) -> String + 'static,
+    {
+        self.instrument = Some(Rc::new(instrument));
+        self
+    }
+
+    // Daily by default.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    // Gzip appends ".gz" to file names that do not end in it already.
+    pub fn with_compression(mut self, compression: TapeCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_manifest(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest = path.into();
+        self
+    }
+
+    pub fn manifest(&self) -> &Path {
+        &self.manifest
+    }
+
+    // Files closed so far.
+    pub fn segments(&self) -> Vec<TapeSegment> {
+        self.state.segments.borrow().clone()
+    }
+
+    pub fn record(&self, stream: &Stream<T>) {
+        stream.sink_to(self.clone());
+    }
+
+    pub fn write(&self, item: &T) -> Result<()> {
+        let ts = now_millis();
+        let source = match &self.instrument {
+            Some(instrument) => instrument(item),
+            None => self.name.clone(),
+        };
+        let data = serde_json::to_string(item).map_err(|err| Error::decode(&self.name, err))?;
+        let mut line = serde_json::to_string(&RecordedMessage {
+            ts,
+            source: source.clone(),
+            data,
+        })
+        .map_err(|err| Error::decode(&self.name, err))?;
+        line.push('\n');
+
+        let key = self.render(ts, &source);
+        let mut open = self.state.open.borrow_mut();
+        let expired = open
+            .get(&key)
+            .is_some_and(|segment| segment.expired(self.rotation, ts));
+        if expired {
+            if let Some(segment) = open.remove(&key) {
+                self.finish(segment)?;
+            }
+        }
+        let segment = match open.get_mut(&key) {
+            Some(segment) => segment,
+            None => {
+                let segment =
+                    OpenSegment::create(&key, source, ts, self.rotation, self.compression)?;
+                open.entry(key).or_insert(segment)
+            }
+        };
+        segment.write(line.as_bytes(), ts)
+    }
+
+    // Closes every open file and lists it in the manifest.
+    pub fn close(&self) -> Result<()> {
+        let open: Vec<OpenSegment> = self
+            .state
+            .open
+            .borrow_mut()
+            .drain()
+            .map(|(_, segment)| segment)
+            .collect();
+        let mut result = Ok(());
+        for segment in open {
+            if let Err(err) = self.finish(segment) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    fn finish(&self, segment: OpenSegment) -> Result<()> {
+        let label = segment.path.display().to_string();
+        segment
+            .writer
+            .finish()
+            .map_err(|err| Error::io(&label, err))?;
+        let entry = TapeSegment {
+            path: segment.path,
+            source: segment.source,
+            first_ts: segment.first_ts,
+            last_ts: segment.last_ts,
+            items: segment.items,
+            bytes: segment.bytes,
+        };
+        let manifest_label = self.manifest.display().to_string();
+        let io_error = |err| Error::io(&manifest_label, err);
+        if let Some(dir) = self
+            .manifest
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.manifest)
+            .map_err(io_error)?;
+        let mut line =
+            serde_json::to_string(&entry).map_err(|err| Error::decode(&manifest_label, err))?;
+        line.push('\n');
+        manifest.write_all(line.as_bytes()).map_err(io_error)?;
+        self.state.segments.borrow_mut().push(entry);
+        Ok(())
+    }
+
+    fn render(&self, ts: u64, source: &str) -> PathBuf {
+        let (year, month, day) = civil_date(ts / DAY_MS);
+        let instrument: String = source
+            .chars()
+            .map(|c| if matches!(c, '/' | '\\') { '_' } else { c })
+            .collect();
+        let mut path = self
+            .pattern
+            .replace("{date}", &format!("{:04}-{:02}-{:02}", year, month, day))
+            .replace("{hour}", &format!("{:02}", ts % DAY_MS / HOUR_MS))
+            .replace("{instrument}", &instrument)
+            .replace("{name}", &self.name);
+        if self.compression == TapeCompression::Gzip && !path.ends_with(".gz") {
+            path.push_str(".gz");
+        }
+        PathBuf::from(path)
+    }
+}
+
+impl<T> Sink<T> for TapeRecorder<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        if let Err(err) = self.write(item) {
+            log::error(
+                "recorder",
+                format_args!("{} failed to record: {}", self.name, err),
+            );
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        for segment in self.state.open.borrow_mut().values_mut() {
+            segment
+                .writer
+                .flush()
+                .map_err(|err| Error::io(segment.path.display().to_string(), err))?;
+        }
+        Ok(())
+    }
+
+    fn close(&self) -> Result<()> {
+        TapeRecorder::close(self)
+    }
+}
+
+struct OpenSegment {
+    path: PathBuf,
+    source: String,
+    // the hour or day the file belongs to, for time-based rotation
+    period: u64,
+    first_ts: u64,
+    last_ts: u64,
+    items: u64,
+    bytes: u64,
+    writer: TapeWriter,
+}
+
+impl OpenSegment {
+    fn create(
+        key: &Path,
+        source: String,
+        ts: u64,
+        rotation: Rotation,
+        compression: TapeCompression,
+    ) -> Result<Self> {
+        let label = key.display().to_string();
+        let io_error = |err| Error::io(&label, err);
+        if let Some(dir) = key.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let mut part = 0;
+        let path = loop {
+            let path = part_path(key, part);
+            if !path.exists() {
+                break path;
+            }
+            part += 1;
+        };
+        let file = File::create(&path).map_err(io_error)?;
+        let writer = match compression {
+            TapeCompression::None => TapeWriter::Plain(BufWriter::new(file)),
+            TapeCompression::Gzip => TapeWriter::Gzip(Box::new(GzEncoder::new(
+                BufWriter::new(file),
+                Compression::default(),
+            ))),
+        };
+        Ok(Self {
+            path,
+            source,
+            period: period(rotation, ts),
+            first_ts: ts,
+            last_ts: ts,
+            items: 0,
+            bytes: 0,
+            writer,
+        })
+    }
+
+    fn expired(&self, rotation: Rotation, ts: u64) -> bool {
+        match rotation {
+            Rotation::Never => false,
+            Rotation::Hourly | Rotation::Daily => period(rotation, ts) != self.period,
+            Rotation::MaxBytes(max) => self.bytes >= max,
+        }
+    }
+
+    fn write(&mut self, line: &[u8], ts: u64) -> Result<()> {
+        self.writer
+            .write_all(line)
+            .map_err(|err| Error::io(self.path.display().to_string(), err))?;
+        self.items += 1;
+        self.bytes += line.len() as u64;
+        self.last_ts = ts;
+        Ok(())
+    }
+}
+
+enum TapeWriter {
+    Plain(BufWriter<File>),
+    Gzip(Box<GzEncoder<BufWriter<File>>>),
+}
+
+impl TapeWriter {
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            TapeWriter::Plain(mut writer) => writer.flush(),
+            TapeWriter::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for TapeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            TapeWriter::Plain(writer) => writer.write(buf),
+            TapeWriter::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            TapeWriter::Plain(writer) => writer.flush(),
+            TapeWriter::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+fn period(rotation: Rotation, ts: u64) -> u64 {
+    match rotation {
+        Rotation::Hourly => ts / HOUR_MS,
+        Rotation::Daily => ts / DAY_MS,
+        Rotation::Never | Rotation::MaxBytes(_) => 0,
+    }
+}
+
+// "trades.jsonl" part 2 is "trades.2.jsonl".
+fn part_path(path: &Path, part: u32) -> PathBuf {
+    if part == 0 {
+        return path.to_path_buf();
+    }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match name.split_once('.') {
+        Some((stem, extensions)) => format!("{}.{}.{}", stem, part, extensions),
+        None => format!("{}.{}", name, part),
+    };
+    path.with_file_name(name)
+}
+
+// The directory above the first placeholder.
+fn default_manifest(pattern: &str) -> PathBuf {
+    let fixed = &pattern[..pattern.find('{').unwrap_or(pattern.len())];
+    let dir = match fixed.rfind('/') {
+        Some(end) => &fixed[..end],
+        None => "",
+    };
+    Path::new(dir).join("manifest.jsonl")
+}
+
+// Days since the unix epoch to a (year, month, day) in the proleptic
+// Gregorian calendar.
+fn civil_date(days: u64) -> (i64, u32, u32) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,246 @@
+use crate::rt::Instant;
+use crate::source::track_timed_emitter;
+use crate::{Resettable, Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ops::{Deref, Range};
+use std::rc::Rc;
+use std::time::Duration;
+
+pub struct ReorderBuffer<T> {
+    inner: Rc<ReorderBufferInner<T>>,
+}
+
+struct ReorderBufferInner<T> {
+    max_wait: Duration,
+    next_seq: Cell<Option<u64>>,
+    held: RefCell<BTreeMap<u64, (T, Instant)>>,
+    ordered: Source<T>,
+    ordered_stream: Stream<T>,
+    gaps: Source<Range<u64>>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Emits items in sequence order starting from the first sequence number
+    // seen. Items ahead of a gap are held for up to `max_wait`, after which
+    // the missing range is published on `gaps()` and the held items are
+    // released. Duplicates and items older than the current position are
+    // dropped.
+    pub fn reorder_by_seq<F>(&self, seq_fn: F, max_wait: Duration) -> ReorderBuffer<T>
+    where
+        F: Fn(&T) -> u64 + 'static,
+    {
+        self.reorder_from("reorder_by_seq", None, seq_fn, max_wait)
+    }
+
+    // Stamps each item with its position in this stream, from 0, for
+    // `restore_order` after a stage that may finish items out of order.
+    pub fn sequenced(&self) -> Stream<Sequenced<T>> {
+        let next = Cell::new(0);
+        self.map(move |item: &T| Sequenced {
+            seq: next.replace(next.get() + 1),
+            item: item.clone(),
+        })
+    }
+
+    fn reorder_from<F>(
+        &self,
+        kind: &'static str,
+        first: Option<u64>,
+        seq_fn: F,
+        max_wait: Duration,
+    ) -> ReorderBuffer<T>
+    where
+        F: Fn(&T) -> u64 + 'static,
+    {
+        let ordered = Source::new();
+        let inner = Rc::new(ReorderBufferInner {
+            max_wait,
+            next_seq: Cell::new(first),
+            held: RefCell::new(BTreeMap::new()),
+            ordered_stream: ordered.to_stream(),
+            ordered,
+            gaps: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter(kind, max_wait, &inner.registered);
+
+        let inner_item = inner.clone();
+        self.sink(move |item: &T| {
+            let seq = seq_fn(item);
+            let next = inner_item.next_seq.get().unwrap_or(seq);
+            if seq < next {
+                return;
+            }
+            if seq > next {
+                inner_item
+                    .held
+                    .borrow_mut()
+                    .entry(seq)
+                    .or_insert_with(|| (item.clone(), Instant::now()));
+                return;
+            }
+            inner_item.ordered.emit(item.clone());
+            inner_item.next_seq.set(Some(seq + 1));
+            inner_item.release();
+        });
+
+        let inner_done = inner.clone();
+        self.on_complete(move || {
+            while inner_done.skip_gap() {}
+            inner_done.gaps.complete();
+            inner_done.ordered.complete();
+        });
+
+        ReorderBuffer { inner }
+    }
+}
+
+// An item and its position in the stream it came from; see
+// `Stream::sequenced`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sequenced<T> {
+    pub seq: u64,
+    pub item: T,
+}
+
+impl<T> Sequenced<T> {
+    // The result of processing `item`, at the same position.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Sequenced<U> {
+        Sequenced {
+            seq: self.seq,
+            item: f(self.item),
+        }
+    }
+}
+
+impl<T> Stream<Sequenced<T>>
+where
+    T: Clone + 'static,
+{
+    // Back in `sequenced` order, from position 0 (`reorder_by_seq` would
+    // start from whichever item finished first). Register the buffer's
+    // timed emitter so a lost item holds the rest back for no more than
+    // `max_wait`.
+    pub fn restore_order(&self, max_wait: Duration) -> ReorderBuffer<Sequenced<T>> {
+        self.reorder_from(
+            "restore_order",
+            Some(0),
+            |sequenced| sequenced.seq,
+            max_wait,
+        )
+    }
+}
+
+impl<T> ReorderBuffer<T>
+where
+    T: Clone + 'static,
+{
+    pub fn stream(&self) -> Stream<T> {
+        self.inner.ordered_stream.clone()
+    }
+
+    pub fn gaps(&self) -> Stream<Range<u64>> {
+        self.inner.gaps.to_stream()
+    }
+
+    pub fn max_wait(&self) -> Duration {
+        self.inner.max_wait
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+
+    // Drops held items and starts over from the next sequence number seen,
+    // e.g. for a feed that renumbers on reconnect.
+    pub fn as_resettable(&self) -> Rc<dyn Resettable> {
+        self.inner.clone() as Rc<dyn Resettable>
+    }
+}
+
+impl<T> Clone for ReorderBuffer<T> {
+    fn clone(&self) -> Self {
+        ReorderBuffer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Deref for ReorderBuffer<T> {
+    type Target = Stream<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner.ordered_stream
+    }
+}
+
+impl<T> ReorderBufferInner<T>
+where
+    T: Clone + 'static,
+{
+    // Emits held items that have become contiguous with the current position.
+    fn release(&self) {
+        loop {
+            let Some(next) = self.next_seq.get() else {
+                return;
+            };
+            let Some((item, _)) = self.held.borrow_mut().remove(&next) else {
+                return;
+            };
+            self.ordered.emit(item);
+            self.next_seq.set(Some(next + 1));
+        }
+    }
+
+    // Gives up on the gap before the oldest held item, if there is one.
+    fn skip_gap(&self) -> bool {
+        let Some(first_held) = self.held.borrow().keys().next().copied() else {
+            return false;
+        };
+        if let Some(next) = self.next_seq.get() {
+            self.gaps.emit(next..first_held);
+        }
+        self.next_seq.set(Some(first_held));
+        self.release();
+        true
+    }
+}
+
+impl<T> Resettable for ReorderBufferInner<T>
+where
+    T: Clone + 'static,
+{
+    fn reset(&self) {
+        self.held.borrow_mut().clear();
+        self.next_seq.set(None);
+    }
+}
+
+impl<T> TimedEmitter for ReorderBufferInner<T>
+where
+    T: Clone + 'static,
+{
+    fn period(&self) -> Duration {
+        self.max_wait
+    }
+
+    fn flush(&self) {
+        loop {
+            let expired = self
+                .held
+                .borrow()
+                .values()
+                .any(|(_, held_since)| held_since.elapsed() >= self.max_wait);
+            if !expired || !self.skip_gap() {
+                return;
+            }
+        }
+    }
+}
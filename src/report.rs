@@ -0,0 +1,190 @@
+use crate::metrics::MetricsRegistry;
+use crate::rt::Instant;
+use crate::{EngineSource, Source};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Counters an `EngineSource` exposes for the run report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SourceStats {
+    pub messages: u64,
+    // payload bytes received, where the source can tell
+    pub bytes: u64,
+    // recoverable failures the source skipped over, e.g. undecodable frames
+    pub errors: u64,
+}
+
+impl AddAssign for SourceStats {
+    fn add_assign(&mut self, other: Self) {
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.errors += other.errors;
+    }
+}
+
+// Published per source label every `EngineBuilder::with_stats_interval`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceRate {
+    pub source: String,
+    pub totals: SourceStats,
+    pub messages_per_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+pub(crate) struct StatsSampler {
+    period: Duration,
+    pub(crate) next_tick: Instant,
+    last_sample: Instant,
+    previous: BTreeMap<String, SourceStats>,
+    rates: Rc<Source<SourceRate>>,
+    metrics: Option<MetricsRegistry>,
+}
+
+impl StatsSampler {
+    pub(crate) fn new(
+        period: Duration,
+        rates: Rc<Source<SourceRate>>,
+        metrics: Option<MetricsRegistry>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            period,
+            next_tick: now + period,
+            last_sample: now,
+            previous: BTreeMap::new(),
+            rates,
+            metrics,
+        }
+    }
+
+    pub(crate) fn sample(&mut self, sources: &[(String, Arc<dyn EngineSource>)]) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_sample).as_secs_f64();
+        self.last_sample = now;
+        while self.next_tick <= now {
+            self.next_tick += self.period;
+        }
+
+        let mut totals: BTreeMap<String, SourceStats> = BTreeMap::new();
+        for (label, source) in sources {
+            *totals.entry(label.clone()).or_default() += source.stats();
+        }
+        for (label, stats) in totals {
+            let previous = self
+                .previous
+                .insert(label.clone(), stats)
+                .unwrap_or_default();
+            let messages = stats.messages.saturating_sub(previous.messages);
+            let bytes = stats.bytes.saturating_sub(previous.bytes);
+            let rate = SourceRate {
+                messages_per_sec: messages as f64 / elapsed,
+                bytes_per_sec: bytes as f64 / elapsed,
+                totals: stats,
+                source: label,
+            };
+            if let Some(metrics) = &self.metrics {
+                let labels = [("source", rate.source.as_str())];
+                metrics
+                    .counter(
+                        "streamz_source_bytes_total",
+                        "Payload bytes received per source.",
+                        &labels,
+                    )
+                    .add(bytes);
+                metrics
+                    .gauge(
+                        "streamz_source_messages_per_second",
+                        "Messages per second over the last stats interval.",
+                        &labels,
+                    )
+                    .set(rate.messages_per_sec);
+                metrics
+                    .gauge(
+                        "streamz_source_bytes_per_second",
+                        "Bytes per second over the last stats interval.",
+                        &labels,
+                    )
+                    .set(rate.bytes_per_sec);
+            }
+            self.rates.emit(rate);
+        }
+    }
+}
+
+// Flush timings of the timed emitters sharing a name.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimerStats {
+    pub flushes: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl TimerStats {
+    pub(crate) fn record(&mut self, took: Duration) {
+        self.flushes += 1;
+        self.total += took;
+        self.max = self.max.max(took);
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.flushes {
+            0 => Duration::ZERO,
+            flushes => self.total / flushes as u32,
+        }
+    }
+}
+
+// Returned by `Engine::run` once the engine stops.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunReport {
+    pub runtime: Duration,
+    pub sources: BTreeMap<String, SourceStats>,
+    pub timer_flushes: u64,
+    // ticks skipped because a flush ran later than a whole period
+    pub timer_overruns: u64,
+    // per `TimedEmitter::name`
+    pub timers: BTreeMap<String, TimerStats>,
+}
+
+impl RunReport {
+    pub fn total_messages(&self) -> u64 {
+        self.sources.values().map(|stats| stats.messages).sum()
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.sources.values().map(|stats| stats.errors).sum()
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ran for {:.3?}", self.runtime)?;
+        for (label, stats) in &self.sources {
+            writeln!(
+                f,
+                "  source {}: {} messages, {} bytes, {} errors",
+                label, stats.messages, stats.bytes, stats.errors
+            )?;
+        }
+        write!(
+            f,
+            "  timers: {} flushes, {} overruns",
+            self.timer_flushes, self.timer_overruns
+        )?;
+        for (name, stats) in &self.timers {
+            write!(
+                f,
+                "\n  timer {}: {} flushes, mean {:.3?}, max {:.3?}",
+                name,
+                stats.flushes,
+                stats.mean(),
+                stats.max
+            )?;
+        }
+        Ok(())
+    }
+}
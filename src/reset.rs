@@ -0,0 +1,135 @@
+use crate::{EngineEvent, EngineHandle, Stream};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+// State built from a feed that must be dropped when the feed loses messages,
+// e.g. a book or running total spanning a reconnect.
+pub trait Resettable: 'static {
+    fn reset(&self);
+}
+
+impl<F> Resettable for F
+where
+    F: Fn() + 'static,
+{
+    fn reset(&self) {
+        self()
+    }
+}
+
+// A change in a websocket source's connection, from `connection_events()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    // connected again after a drop; messages in between were missed
+    Reconnected,
+    Disconnected,
+}
+
+// Resets every registered operator together when its feed reconnects or its
+// source is restarted, so no state blends messages from before and after the
+// gap. Stateful operators built through the group (`accumulate`,
+// `scan_map`) start over from their initial state; others are added with
+// `add`, e.g. `cache.as_resettable()` or a closure clearing a book.
+#[derive(Clone, Default)]
+pub struct ResetGroup {
+    inner: Rc<ResetGroupInner>,
+}
+
+#[derive(Default)]
+struct ResetGroupInner {
+    members: RefCell<Vec<Rc<dyn Resettable>>>,
+    resets: Cell<u64>,
+}
+
+impl ResetGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, member: Rc<dyn Resettable>) {
+        self.inner.members.borrow_mut().push(member);
+    }
+
+    pub fn reset(&self) {
+        self.inner.resets.set(self.inner.resets.get() + 1);
+        let members = self.inner.members.borrow().clone();
+        for member in members {
+            member.reset();
+        }
+    }
+
+    pub fn resets(&self) -> u64 {
+        self.inner.resets.get()
+    }
+
+    pub fn reset_on_reconnect(&self, events: &Stream<ConnectionEvent>) {
+        self.reset_when(events, |event| *event == ConnectionEvent::Reconnected);
+    }
+
+    // Restarting a source through the handle or the admin API reconnects it.
+    pub fn reset_on_restart(&self, handle: &EngineHandle, label: impl Into<String>) {
+        let label = label.into();
+        self.reset_when(&handle.events(), move |event| {
+            matches!(event, EngineEvent::SourceRestarted(restarted) if *restarted == label)
+        });
+    }
+
+    pub fn reset_when<E, F>(&self, events: &Stream<E>, when: F)
+    where
+        E: 'static,
+        F: Fn(&E) -> bool + 'static,
+    {
+        let group = self.clone();
+        events.sink(move |event: &E| {
+            if when(event) {
+                group.reset();
+            }
+        });
+    }
+
+    // `Stream::accumulate`, back to `initial_state` on reset.
+    pub fn accumulate<T, State, F>(
+        &self,
+        stream: &Stream<T>,
+        initial_state: State,
+        f: F,
+    ) -> Stream<State>
+    where
+        T: 'static,
+        State: Clone + 'static,
+        F: Fn(State, &T) -> State + 'static,
+    {
+        let state = Rc::new(RefCell::new(initial_state.clone()));
+        let state_reset = state.clone();
+        self.add(Rc::new(move || {
+            *state_reset.borrow_mut() = initial_state.clone();
+        }));
+        stream.scan_map((), move |_, item: &T| {
+            let next = f(state.borrow().clone(), item);
+            *state.borrow_mut() = next.clone();
+            Some(next)
+        })
+    }
+
+    // `Stream::scan_map`, back to `initial_state` on reset.
+    pub fn scan_map<T, State, U, F>(
+        &self,
+        stream: &Stream<T>,
+        initial_state: State,
+        f: F,
+    ) -> Stream<U>
+    where
+        T: 'static,
+        State: Clone + 'static,
+        U: 'static,
+        F: Fn(&mut State, &T) -> Option<U> + 'static,
+    {
+        let state = Rc::new(RefCell::new(initial_state.clone()));
+        let state_reset = state.clone();
+        self.add(Rc::new(move || {
+            *state_reset.borrow_mut() = initial_state.clone();
+        }));
+        stream.scan_map((), move |_, item: &T| f(&mut state.borrow_mut(), item))
+    }
+}
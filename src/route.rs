@@ -0,0 +1,87 @@
+use crate::{Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+pub struct RouteTable<K, T> {
+    inner: Rc<RouteTableInner<K, T>>,
+}
+
+struct RouteTableInner<K, T> {
+    routes: RefCell<HashMap<K, Rc<Source<T>>>>,
+    default: Source<T>,
+    completed: Cell<bool>,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // Dispatches each item to the route registered for `selector(item)` with a
+    // single lookup; items without a registered route go to `default_route()`.
+    pub fn route<K, F>(&self, selector: F) -> RouteTable<K, T>
+    where
+        K: Eq + Hash + 'static,
+        F: Fn(&T) -> K + 'static,
+    {
+        let inner = Rc::new(RouteTableInner {
+            routes: RefCell::new(HashMap::new()),
+            default: Source::new(),
+            completed: Cell::new(false),
+        });
+
+        let inner_item = inner.clone();
+        self.sink(move |item: &T| {
+            let key = selector(item);
+            // release the table before emitting so downstreams may add routes
+            let route = inner_item.routes.borrow().get(&key).cloned();
+            match route {
+                Some(route) => route.emit(item.clone()),
+                None => inner_item.default.emit(item.clone()),
+            }
+        });
+
+        let inner_done = inner.clone();
+        self.on_complete(move || {
+            inner_done.completed.set(true);
+            for route in inner_done.routes.borrow().values() {
+                route.complete();
+            }
+            inner_done.default.complete();
+        });
+
+        RouteTable { inner }
+    }
+}
+
+impl<K, T> RouteTable<K, T>
+where
+    K: Eq + Hash + 'static,
+    T: Clone + 'static,
+{
+    // Registering the same key twice returns the same stream.
+    pub fn route(&self, key: K) -> Stream<T> {
+        let mut routes = self.inner.routes.borrow_mut();
+        let route = routes.entry(key).or_insert_with(|| {
+            let source = Rc::new(Source::new());
+            if self.inner.completed.get() {
+                source.complete();
+            }
+            source
+        });
+        route.to_stream()
+    }
+
+    pub fn default_route(&self) -> Stream<T> {
+        self.inner.default.to_stream()
+    }
+}
+
+impl<K, T> Clone for RouteTable<K, T> {
+    fn clone(&self) -> Self {
+        RouteTable {
+            inner: self.inner.clone(),
+        }
+    }
+}
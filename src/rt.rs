@@ -0,0 +1,133 @@
+// Runtime shims so the engine and operators run on tokio natively and on
+// wasm-bindgen-futures in the browser.
+
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("enable the `wasm` feature when targeting wasm32");
+
+use crate::signal::Signal;
+use crate::Result;
+use std::future::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::{SystemTime, UNIX_EPOCH};
+
+// Wall-clock milliseconds since the unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep_until(deadline: Instant) {
+    tokio::time::sleep_until(deadline).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep_until(deadline: Instant) {
+    gloo_timers::future::sleep(deadline.saturating_duration_since(Instant::now())).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn_local<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    tokio::task::spawn_local(future);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn spawn_local<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+// Drives `future` somewhere `spawn_local` is allowed.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn run_local<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    tokio::task::LocalSet::new().run_until(future).await
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn run_local<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    future.await
+}
+
+// Listens for the process signals the engine reacts to.
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+pub(crate) struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+
+#[cfg(all(unix, not(target_arch = "wasm32")))]
+impl Signals {
+    pub(crate) fn new() -> Result<Self> {
+        use crate::Error;
+        use tokio::signal::unix::{signal, SignalKind};
+        let listen = |kind| signal(kind).map_err(|err| Error::io("signals", err));
+        Ok(Self {
+            interrupt: listen(SignalKind::interrupt())?,
+            terminate: listen(SignalKind::terminate())?,
+            hangup: listen(SignalKind::hangup())?,
+        })
+    }
+
+    pub(crate) async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.interrupt.recv() => Signal::Interrupt,
+            _ = self.terminate.recv() => Signal::Terminate,
+            _ = self.hangup.recv() => Signal::Hangup,
+        }
+    }
+}
+
+// Only Ctrl+C is available outside unix.
+#[cfg(all(not(unix), not(target_arch = "wasm32")))]
+pub(crate) struct Signals;
+
+#[cfg(all(not(unix), not(target_arch = "wasm32")))]
+impl Signals {
+    pub(crate) fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    pub(crate) async fn recv(&mut self) -> Signal {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => Signal::Interrupt,
+            Err(_) => futures_util::future::pending().await,
+        }
+    }
+}
+
+// A browser tab has no signals; the engine runs until its sources finish, it
+// is cancelled or the page goes away.
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct Signals;
+
+#[cfg(target_arch = "wasm32")]
+impl Signals {
+    pub(crate) fn new() -> Result<Self> {
+        Ok(Self)
+    }
+
+    pub(crate) async fn recv(&mut self) -> Signal {
+        futures_util::future::pending().await
+    }
+}
@@ -0,0 +1,973 @@
+use crate::log;
+use crate::{Error, Result, Source, Stream};
+use quick_xml::events::{BytesStart, Event};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+const SOURCE: &str = "sbe";
+// Deepest nesting of `ref`s and composites followed when resolving types.
+const MAX_DEPTH: usize = 32;
+
+// A decoded SBE message. `fields` is an object keyed by field name: decimal
+// composites (mantissa and exponent) become numbers, char arrays strings,
+// enums and sets their names, repeating groups arrays of objects and
+// optional fields holding their null value `null`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SbeMessage {
+    pub template_id: u16,
+    pub schema_id: u16,
+    pub version: u16,
+    pub name: String,
+    pub fields: Value,
+}
+
+impl SbeMessage {
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+}
+
+// e.g. "MDIncrementalRefreshTradeSummary48 {\"TransactTime\":...}"
+impl Display for SbeMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.fields)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Primitive {
+    Char,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float,
+    Double,
+}
+
+impl Primitive {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" => Primitive::Char,
+            "int8" => Primitive::Int8,
+            "int16" => Primitive::Int16,
+            "int32" => Primitive::Int32,
+            "int64" => Primitive::Int64,
+            "uint8" => Primitive::UInt8,
+            "uint16" => Primitive::UInt16,
+            "uint32" => Primitive::UInt32,
+            "uint64" => Primitive::UInt64,
+            "float" => Primitive::Float,
+            "double" => Primitive::Double,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Primitive::Char | Primitive::Int8 | Primitive::UInt8 => 1,
+            Primitive::Int16 | Primitive::UInt16 => 2,
+            Primitive::Int32 | Primitive::UInt32 | Primitive::Float => 4,
+            Primitive::Int64 | Primitive::UInt64 | Primitive::Double => 8,
+        }
+    }
+
+    // The spec's null value for optional fields of this type.
+    fn null(self) -> Raw {
+        match self {
+            Primitive::Char => Raw::UInt(0),
+            Primitive::Int8 => Raw::Int(i8::MIN.into()),
+            Primitive::Int16 => Raw::Int(i16::MIN.into()),
+            Primitive::Int32 => Raw::Int(i32::MIN.into()),
+            Primitive::Int64 => Raw::Int(i64::MIN),
+            Primitive::UInt8 => Raw::UInt(u8::MAX.into()),
+            Primitive::UInt16 => Raw::UInt(u16::MAX.into()),
+            Primitive::UInt32 => Raw::UInt(u32::MAX.into()),
+            Primitive::UInt64 => Raw::UInt(u64::MAX),
+            Primitive::Float | Primitive::Double => Raw::Float(f64::NAN),
+        }
+    }
+
+    // A value written in the schema, e.g. a `nullValue` or an enum's
+    // `validValue`; chars are written as the character.
+    fn literal(self, text: &str) -> Option<Raw> {
+        let text = text.trim();
+        match self {
+            Primitive::Char => text.bytes().next().map(|byte| Raw::UInt(byte.into())),
+            Primitive::Int8 | Primitive::Int16 | Primitive::Int32 | Primitive::Int64 => {
+                text.parse().ok().map(Raw::Int)
+            }
+            Primitive::UInt8 | Primitive::UInt16 | Primitive::UInt32 | Primitive::UInt64 => {
+                text.parse().ok().map(Raw::UInt)
+            }
+            Primitive::Float | Primitive::Double => text.parse().ok().map(Raw::Float),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Raw {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+impl Raw {
+    fn is(self, other: Raw) -> bool {
+        match (self, other) {
+            (Raw::Float(value), Raw::Float(null)) => {
+                value == null || (value.is_nan() && null.is_nan())
+            }
+            _ => self == other,
+        }
+    }
+
+    fn as_u64(self) -> u64 {
+        match self {
+            Raw::Int(value) => value as u64,
+            Raw::UInt(value) => value,
+            Raw::Float(value) => value as u64,
+        }
+    }
+
+    fn to_json(self, primitive: Primitive) -> Value {
+        match (self, primitive) {
+            (Raw::UInt(byte), Primitive::Char) => Value::from(char::from(byte as u8).to_string()),
+            (Raw::Int(value), _) => Value::from(value),
+            (Raw::UInt(value), _) => Value::from(value),
+            (Raw::Float(value), _) => Value::from(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Encoding {
+    primitive: Primitive,
+    length: usize,
+    null: Option<Raw>,
+    constant: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    name: String,
+    offset: Option<usize>,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Encoded(Encoding),
+    Composite(Vec<Member>),
+    Enum {
+        encoding: Encoding,
+        values: Vec<(String, Raw)>,
+    },
+    Set {
+        encoding: Encoding,
+        choices: Vec<(String, u32)>,
+    },
+}
+
+impl Kind {
+    fn size(&self) -> usize {
+        match self {
+            Kind::Encoded(encoding) if encoding.constant.is_some() => 0,
+            Kind::Encoded(encoding) => encoding.primitive.size() * encoding.length,
+            Kind::Composite(members) => members.iter().fold(0, |end, member| {
+                member.offset.unwrap_or(end) + member.kind.size()
+            }),
+            Kind::Enum { encoding, .. } | Kind::Set { encoding, .. } => encoding.primitive.size(),
+        }
+    }
+
+    fn member(&self, name: &str) -> Option<(usize, &Member)> {
+        let Kind::Composite(members) = self else {
+            return None;
+        };
+        let mut end = 0;
+        for member in members {
+            let offset = member.offset.unwrap_or(end);
+            if member.name == name {
+                return Some((offset, member));
+            }
+            end = offset + member.kind.size();
+        }
+        None
+    }
+
+    fn make_optional(&mut self) {
+        match self {
+            Kind::Encoded(encoding) | Kind::Enum { encoding, .. } => {
+                encoding.null.get_or_insert(encoding.primitive.null());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Field {
+    name: String,
+    offset: Option<usize>,
+    since_version: u16,
+    kind: Kind,
+    constant: Option<Value>,
+}
+
+#[derive(Clone, Debug)]
+struct Group {
+    name: String,
+    since_version: u16,
+    dimension: Kind,
+    body: Body,
+}
+
+#[derive(Clone, Debug)]
+struct Data {
+    name: String,
+    since_version: u16,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Body {
+    fields: Vec<Field>,
+    groups: Vec<Group>,
+    data: Vec<Data>,
+}
+
+#[derive(Clone, Debug)]
+struct Message {
+    name: String,
+    body: Body,
+}
+
+// A message schema read from its XML definition at runtime, so a venue's
+// schema update needs a new file rather than a rebuild. Understands
+// encoded types, composites, enums, sets and refs, optional and constant
+// presence, `sinceVersion`, repeating groups (nested too) and var data.
+#[derive(Clone, Debug)]
+pub struct SbeSchema {
+    id: u16,
+    version: u16,
+    big_endian: bool,
+    header: Kind,
+    messages: HashMap<u16, Message>,
+}
+
+impl SbeSchema {
+    pub fn parse(xml: &str) -> Result<Self> {
+        let document = parse_xml(xml)?;
+        let root = document
+            .children
+            .iter()
+            .find(|element| element.name == "messageSchema")
+            .ok_or_else(|| Error::config("SBE schema has no messageSchema element"))?;
+        let types = Types::new(root);
+        let number = |name: &str| -> Result<u16> {
+            root.attr(name)
+                .map(|value| {
+                    value.trim().parse().map_err(|_| {
+                        Error::config(format!("SBE schema: {} {:?} is not a number", name, value))
+                    })
+                })
+                .unwrap_or(Ok(0))
+        };
+
+        let mut messages = HashMap::new();
+        for element in root.children.iter().filter(|child| child.name == "message") {
+            let name = element.attr("name").unwrap_or_default().to_string();
+            let id = element
+                .attr("id")
+                .and_then(|id| id.trim().parse().ok())
+                .ok_or_else(|| {
+                    Error::config(format!("SBE schema: message {:?} needs an id", name))
+                })?;
+            let body = types.body(element, 0)?;
+            messages.insert(id, Message { name, body });
+        }
+
+        let header_type = root.attr("headerType").unwrap_or("messageHeader");
+        let header = types.named(header_type, 0)?;
+        for member in ["blockLength", "templateId", "schemaId", "version"] {
+            if header.member(member).is_none() {
+                return Err(Error::config(format!(
+                    "SBE schema: {} has no {}",
+                    header_type, member
+                )));
+            }
+        }
+        Ok(Self {
+            id: number("id")?,
+            version: number("version")?,
+            big_endian: root.attr("byteOrder") == Some("bigEndian"),
+            header,
+            messages,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let xml =
+            fs::read_to_string(path).map_err(|err| Error::io(path.display().to_string(), err))?;
+        Self::parse(&xml)
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn message_name(&self, template_id: u16) -> Option<&str> {
+        self.messages
+            .get(&template_id)
+            .map(|message| message.name.as_str())
+    }
+
+    // Decodes one message, header first.
+    pub fn decode(&self, bytes: &[u8]) -> Result<SbeMessage> {
+        let buffer = Buffer {
+            bytes,
+            big_endian: self.big_endian,
+        };
+        let header = |name: &str| buffer.member(&self.header, name, 0);
+        let block_length = header("blockLength")? as usize;
+        let template_id = header("templateId")? as u16;
+        let schema_id = header("schemaId")? as u16;
+        let version = header("version")? as u16;
+        let message = self
+            .messages
+            .get(&template_id)
+            .ok_or_else(|| Error::decode(SOURCE, format!("unknown template id {}", template_id)))?;
+        let (fields, _) = buffer.body(&message.body, self.header.size(), block_length, version)?;
+        Ok(SbeMessage {
+            template_id,
+            schema_id,
+            version,
+            name: message.name.clone(),
+            fields: Value::Object(fields),
+        })
+    }
+}
+
+// The type definitions of a schema, resolved by name on demand.
+struct Types<'a> {
+    named: HashMap<&'a str, &'a Element>,
+}
+
+impl<'a> Types<'a> {
+    fn new(root: &'a Element) -> Self {
+        let named = root
+            .children
+            .iter()
+            .filter(|child| child.name == "types")
+            .flat_map(|types| &types.children)
+            .filter_map(|element| Some((element.attr("name")?, element)))
+            .collect();
+        Self { named }
+    }
+
+    fn named(&self, name: &str, depth: usize) -> Result<Kind> {
+        if let Some(primitive) = Primitive::parse(name) {
+            return Ok(Kind::Encoded(Encoding {
+                primitive,
+                length: 1,
+                null: None,
+                constant: None,
+            }));
+        }
+        if depth > MAX_DEPTH {
+            return Err(Error::config(format!(
+                "SBE schema: type {:?} nests too deeply",
+                name
+            )));
+        }
+        let element = self
+            .named
+            .get(name)
+            .ok_or_else(|| Error::config(format!("SBE schema: unknown type {:?}", name)))?;
+        self.define(element, depth + 1)
+    }
+
+    fn define(&self, element: &Element, depth: usize) -> Result<Kind> {
+        match element.name.as_str() {
+            "type" => self.encoding(element, depth).map(Kind::Encoded),
+            "ref" => self.named(element.attr("type").unwrap_or_default(), depth),
+            "composite" => {
+                let mut members = Vec::new();
+                for child in &element.children {
+                    members.push(Member {
+                        name: child.attr("name").unwrap_or_default().to_string(),
+                        offset: child.attr("offset").and_then(|offset| offset.parse().ok()),
+                        kind: self.define(child, depth + 1)?,
+                    });
+                }
+                Ok(Kind::Composite(members))
+            }
+            "enum" => {
+                let encoding = self.encoding_of(element, depth)?;
+                let mut values = Vec::new();
+                for value in element
+                    .children
+                    .iter()
+                    .filter(|child| child.name == "validValue")
+                {
+                    let raw = encoding.primitive.literal(&value.text).ok_or_else(|| {
+                        Error::config(format!("SBE schema: bad enum value {:?}", value.text))
+                    })?;
+                    values.push((value.attr("name").unwrap_or_default().to_string(), raw));
+                }
+                Ok(Kind::Enum { encoding, values })
+            }
+            "set" => {
+                let encoding = self.encoding_of(element, depth)?;
+                let choices = element
+                    .children
+                    .iter()
+                    .filter(|child| child.name == "choice")
+                    .filter_map(|choice| {
+                        let bit = choice.text.trim().parse().ok()?;
+                        Some((choice.attr("name").unwrap_or_default().to_string(), bit))
+                    })
+                    .collect();
+                Ok(Kind::Set { encoding, choices })
+            }
+            other => Err(Error::config(format!(
+                "SBE schema: unexpected element {:?}",
+                other
+            ))),
+        }
+    }
+
+    // An enum's or set's `encodingType`: a primitive or a named encoded type.
+    fn encoding_of(&self, element: &Element, depth: usize) -> Result<Encoding> {
+        match self.named(element.attr("encodingType").unwrap_or_default(), depth)? {
+            Kind::Encoded(encoding) => Ok(encoding),
+            _ => Err(Error::config(format!(
+                "SBE schema: {:?} must be encoded as a primitive",
+                element.attr("name").unwrap_or_default()
+            ))),
+        }
+    }
+
+    fn encoding(&self, element: &Element, depth: usize) -> Result<Encoding> {
+        let primitive = element.attr("primitiveType").unwrap_or_default();
+        let mut encoding = match self.named(primitive, depth)? {
+            Kind::Encoded(encoding) => encoding,
+            _ => {
+                return Err(Error::config(format!(
+                    "SBE schema: unknown primitive type {:?}",
+                    primitive
+                )))
+            }
+        };
+        encoding.length = element
+            .attr("length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(1);
+        match element.attr("presence") {
+            Some("optional") => {
+                let null = element
+                    .attr("nullValue")
+                    .and_then(|null| encoding.primitive.literal(null));
+                encoding.null = Some(null.unwrap_or(encoding.primitive.null()));
+            }
+            Some("constant") => encoding.constant = Some(element.text.clone()),
+            _ => {}
+        }
+        Ok(encoding)
+    }
+
+    fn body(&self, element: &Element, depth: usize) -> Result<Body> {
+        let mut body = Body::default();
+        for child in &element.children {
+            let name = child.attr("name").unwrap_or_default().to_string();
+            let since_version = child
+                .attr("sinceVersion")
+                .and_then(|version| version.parse().ok())
+                .unwrap_or(0);
+            match child.name.as_str() {
+                "field" => {
+                    let mut kind = self.named(child.attr("type").unwrap_or_default(), depth)?;
+                    let constant = match child.attr("presence") {
+                        Some("constant") => Some(constant(child, &kind)),
+                        Some("optional") => {
+                            kind.make_optional();
+                            None
+                        }
+                        _ => match &kind {
+                            Kind::Encoded(encoding) => encoding.constant.as_ref().map(|text| {
+                                literal_value(encoding.primitive, text, encoding.length)
+                            }),
+                            _ => None,
+                        },
+                    };
+                    body.fields.push(Field {
+                        name,
+                        offset: child.attr("offset").and_then(|offset| offset.parse().ok()),
+                        since_version,
+                        kind,
+                        constant,
+                    });
+                }
+                "group" => {
+                    if depth > MAX_DEPTH {
+                        return Err(Error::config("SBE schema: groups nest too deeply"));
+                    }
+                    let dimension_type = child.attr("dimensionType").unwrap_or("groupSizeEncoding");
+                    let dimension = self.named(dimension_type, depth)?;
+                    if dimension.member("blockLength").is_none()
+                        || dimension.member("numInGroup").is_none()
+                    {
+                        return Err(Error::config(format!(
+                            "SBE schema: {} needs blockLength and numInGroup",
+                            dimension_type
+                        )));
+                    }
+                    body.groups.push(Group {
+                        name,
+                        since_version,
+                        dimension,
+                        body: self.body(child, depth + 1)?,
+                    });
+                }
+                "data" => {
+                    let kind = self.named(child.attr("type").unwrap_or_default(), depth)?;
+                    if kind.member("length").is_none() {
+                        return Err(Error::config(format!(
+                            "SBE schema: data {:?} needs a length",
+                            name
+                        )));
+                    }
+                    body.data.push(Data {
+                        name,
+                        since_version,
+                        kind,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(body)
+    }
+}
+
+// A constant field: the element's text, or the value a `valueRef` such as
+// "SecurityUpdateAction.Add" names.
+fn constant(element: &Element, kind: &Kind) -> Value {
+    if let Some(value_ref) = element.attr("valueRef") {
+        let name = value_ref.rsplit('.').next().unwrap_or(value_ref);
+        return Value::from(name);
+    }
+    match kind {
+        Kind::Encoded(encoding) => {
+            literal_value(encoding.primitive, &element.text, encoding.length)
+        }
+        _ => Value::from(element.text.trim()),
+    }
+}
+
+fn literal_value(primitive: Primitive, text: &str, length: usize) -> Value {
+    if primitive == Primitive::Char || length > 1 {
+        return Value::from(text.trim());
+    }
+    primitive
+        .literal(text)
+        .map_or_else(|| Value::from(text.trim()), |raw| raw.to_json(primitive))
+}
+
+struct Buffer<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl Buffer<'_> {
+    fn slice(&self, at: usize, len: usize) -> Result<&[u8]> {
+        self.bytes.get(at..at + len).ok_or_else(|| {
+            Error::decode(
+                SOURCE,
+                format!(
+                    "message truncated: {} bytes needed at offset {}, {} available",
+                    len,
+                    at,
+                    self.bytes.len()
+                ),
+            )
+        })
+    }
+
+    fn raw(&self, primitive: Primitive, at: usize) -> Result<Raw> {
+        let bytes = self.slice(at, primitive.size())?;
+        let mut word = [0u8; 8];
+        if self.big_endian {
+            word[8 - bytes.len()..].copy_from_slice(bytes);
+        } else {
+            word[..bytes.len()].copy_from_slice(bytes);
+        }
+        let unsigned = if self.big_endian {
+            u64::from_be_bytes(word)
+        } else {
+            u64::from_le_bytes(word)
+        };
+        let bits = primitive.size() as u32 * 8;
+        Ok(match primitive {
+            Primitive::Char
+            | Primitive::UInt8
+            | Primitive::UInt16
+            | Primitive::UInt32
+            | Primitive::UInt64 => Raw::UInt(unsigned),
+            Primitive::Int8 | Primitive::Int16 | Primitive::Int32 | Primitive::Int64 => {
+                // sign-extend from the field's width
+                let shift = 64 - bits;
+                Raw::Int(((unsigned << shift) as i64) >> shift)
+            }
+            Primitive::Float => Raw::Float(f32::from_bits(unsigned as u32).into()),
+            Primitive::Double => Raw::Float(f64::from_bits(unsigned)),
+        })
+    }
+
+    fn member(&self, kind: &Kind, name: &str, at: usize) -> Result<u64> {
+        let Some((offset, member)) = kind.member(name) else {
+            return Ok(0);
+        };
+        match &member.kind {
+            Kind::Encoded(encoding) => match &encoding.constant {
+                Some(text) => Ok(encoding.primitive.literal(text).map_or(0, Raw::as_u64)),
+                None => self.raw(encoding.primitive, at + offset).map(Raw::as_u64),
+            },
+            _ => Ok(0),
+        }
+    }
+
+    fn value(&self, kind: &Kind, at: usize) -> Result<Value> {
+        match kind {
+            Kind::Encoded(encoding) => self.encoded(encoding, at),
+            Kind::Enum { encoding, values } => {
+                let raw = self.raw(encoding.primitive, at)?;
+                if let Some((name, _)) = values.iter().find(|(_, value)| raw.is(*value)) {
+                    return Ok(Value::from(name.as_str()));
+                }
+                if encoding.null.is_some_and(|null| raw.is(null)) {
+                    return Ok(Value::Null);
+                }
+                Ok(raw.to_json(encoding.primitive))
+            }
+            Kind::Set { encoding, choices } => {
+                let bits = self.raw(encoding.primitive, at)?.as_u64();
+                Ok(Value::from(
+                    choices
+                        .iter()
+                        .filter(|(_, bit)| *bit < 64 && bits & (1 << bit) != 0)
+                        .map(|(name, _)| Value::from(name.as_str()))
+                        .collect::<Vec<_>>(),
+                ))
+            }
+            Kind::Composite(members) => {
+                if let (Some((mantissa_at, mantissa)), Some((exponent_at, exponent))) =
+                    (kind.member("mantissa"), kind.member("exponent"))
+                {
+                    if members.len() == 2 {
+                        let mantissa = self.value(&mantissa.kind, at + mantissa_at)?;
+                        let exponent = self.value(&exponent.kind, at + exponent_at)?;
+                        return Ok(match (mantissa.as_f64(), exponent.as_i64()) {
+                            (Some(mantissa), Some(exponent)) => {
+                                Value::from(mantissa * 10f64.powi(exponent as i32))
+                            }
+                            _ => Value::Null,
+                        });
+                    }
+                }
+                let mut object = Map::new();
+                let mut end = 0;
+                for member in members {
+                    let offset = member.offset.unwrap_or(end);
+                    end = offset + member.kind.size();
+                    object.insert(member.name.clone(), self.value(&member.kind, at + offset)?);
+                }
+                Ok(Value::Object(object))
+            }
+        }
+    }
+
+    fn encoded(&self, encoding: &Encoding, at: usize) -> Result<Value> {
+        let primitive = encoding.primitive;
+        if let Some(text) = &encoding.constant {
+            return Ok(literal_value(primitive, text, encoding.length));
+        }
+        if primitive == Primitive::Char && encoding.length > 1 {
+            let bytes = self.slice(at, encoding.length)?;
+            let end = bytes
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(bytes.len());
+            if end == 0 && encoding.null.is_some() {
+                return Ok(Value::Null);
+            }
+            return Ok(Value::from(
+                String::from_utf8_lossy(&bytes[..end])
+                    .trim_end()
+                    .to_string(),
+            ));
+        }
+        let single = |at: usize| -> Result<Value> {
+            let raw = self.raw(primitive, at)?;
+            if encoding.null.is_some_and(|null| raw.is(null)) {
+                return Ok(Value::Null);
+            }
+            Ok(raw.to_json(primitive))
+        };
+        if encoding.length == 1 {
+            return single(at);
+        }
+        (0..encoding.length)
+            .map(|index| single(at + index * primitive.size()))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::from)
+    }
+
+    // Decodes a message or group entry whose root block starts at `at`;
+    // returns its fields and where the next entry starts.
+    fn body(
+        &self,
+        body: &Body,
+        at: usize,
+        block_length: usize,
+        version: u16,
+    ) -> Result<(Map<String, Value>, usize)> {
+        let mut fields = Map::new();
+        let mut end = 0;
+        for field in &body.fields {
+            let offset = field.offset.unwrap_or(end);
+            end = offset + field.kind.size();
+            let value = if let Some(constant) = &field.constant {
+                constant.clone()
+            } else if version < field.since_version || end > block_length {
+                // added after the sender's schema version
+                Value::Null
+            } else {
+                self.value(&field.kind, at + offset)?
+            };
+            fields.insert(field.name.clone(), value);
+        }
+
+        let mut cursor = at + block_length;
+        for group in &body.groups {
+            let mut entries = Vec::new();
+            if version >= group.since_version {
+                let entry_length = self.member(&group.dimension, "blockLength", cursor)? as usize;
+                let count = self.member(&group.dimension, "numInGroup", cursor)?;
+                cursor += group.dimension.size();
+                for _ in 0..count {
+                    let (entry, next) = self.body(&group.body, cursor, entry_length, version)?;
+                    entries.push(Value::Object(entry));
+                    cursor = next;
+                }
+            }
+            fields.insert(group.name.clone(), Value::from(entries));
+        }
+
+        for data in &body.data {
+            if version < data.since_version {
+                fields.insert(data.name.clone(), Value::Null);
+                continue;
+            }
+            let length = self.member(&data.kind, "length", cursor)? as usize;
+            cursor += data
+                .kind
+                .member("length")
+                .map_or(0, |(_, member)| member.kind.size());
+            let bytes = self.slice(cursor, length)?;
+            cursor += length;
+            let value = match std::str::from_utf8(bytes) {
+                Ok(text) => Value::from(text),
+                Err(_) => Value::from(bytes.to_vec()),
+            };
+            fields.insert(data.name.clone(), value);
+        }
+        Ok((fields, cursor))
+    }
+}
+
+// How to find messages in a buffer and which schema to read them with. By
+// default a buffer holds one message; `with_packet_header` and
+// `with_length_prefix` describe packet framing such as CME MDP 3.0's (a
+// 12-byte packet header, then messages each preceded by a two-byte size).
+#[derive(Clone)]
+pub struct SbeDecoder {
+    schema: Rc<SbeSchema>,
+    packet_header: usize,
+    length_prefixed: bool,
+    errors: Rc<Cell<u64>>,
+}
+
+impl SbeDecoder {
+    pub fn new(schema: SbeSchema) -> Self {
+        Self {
+            schema: Rc::new(schema),
+            packet_header: 0,
+            length_prefixed: false,
+            errors: Rc::new(Cell::new(0)),
+        }
+    }
+
+    // Bytes to skip at the start of every buffer.
+    pub fn with_packet_header(mut self, bytes: usize) -> Self {
+        self.packet_header = bytes;
+        self
+    }
+
+    // Messages are each preceded by their size, including the two size
+    // bytes, as an unsigned 16-bit integer in the schema's byte order.
+    pub fn with_length_prefix(mut self) -> Self {
+        self.length_prefixed = true;
+        self
+    }
+
+    pub fn schema(&self) -> &SbeSchema {
+        &self.schema
+    }
+
+    // Buffers that failed to decode through `decode_sbe`.
+    pub fn errors(&self) -> u64 {
+        self.errors.get()
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<SbeMessage>> {
+        let bytes = bytes
+            .get(self.packet_header..)
+            .ok_or_else(|| Error::decode(SOURCE, "buffer shorter than its packet header"))?;
+        if !self.length_prefixed {
+            return self.schema.decode(bytes).map(|message| vec![message]);
+        }
+        let buffer = Buffer {
+            bytes,
+            big_endian: self.schema.big_endian,
+        };
+        let mut messages = Vec::new();
+        let mut at = 0;
+        while at < bytes.len() {
+            let size = buffer.raw(Primitive::UInt16, at)?.as_u64() as usize;
+            if size < 2 {
+                return Err(Error::decode(
+                    SOURCE,
+                    format!("message size {} at offset {}", size, at),
+                ));
+            }
+            messages.push(self.schema.decode(buffer.slice(at + 2, size - 2)?)?);
+            at += size;
+        }
+        Ok(messages)
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: AsRef<[u8]> + 'static,
+{
+    // Decodes every buffer into its messages. Buffers that fail are dropped
+    // and counted in `decoder.errors()`; the first failure is logged.
+    pub fn decode_sbe(&self, decoder: &SbeDecoder) -> Stream<SbeMessage> {
+        let source = Rc::new(Source::new());
+        let output = source.to_stream();
+        let decoder = decoder.clone();
+        let emitter = source.clone();
+        self.sink(move |bytes: &T| match decoder.decode(bytes.as_ref()) {
+            Ok(messages) => {
+                for message in messages {
+                    emitter.emit(message);
+                }
+            }
+            Err(err) => {
+                let errors = decoder.errors.get() + 1;
+                decoder.errors.set(errors);
+                if errors == 1 {
+                    log::warn(SOURCE, format_args!("dropping undecodable buffer: {}", err));
+                }
+            }
+        });
+        self.on_complete(move || source.complete());
+        output
+    }
+}
+
+// Just enough of an XML document to read a schema: elements by local name
+// (so "sbe:message" is "message"), their attributes and text.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+fn parse_xml(xml: &str) -> Result<Element> {
+    let invalid =
+        |err: &dyn Display| Error::config(format!("SBE schema is not valid XML: {}", err));
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut stack = vec![Element::default()];
+    loop {
+        match reader.read_event().map_err(|err| invalid(&err))? {
+            Event::Start(start) => stack.push(element(&start).map_err(|err| invalid(&err))?),
+            Event::Empty(start) => {
+                let element = element(&start).map_err(|err| invalid(&err))?;
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(element);
+                }
+            }
+            Event::End(_) => {
+                let closed = stack.pop();
+                match (closed, stack.last_mut()) {
+                    (Some(closed), Some(parent)) => parent.children.push(closed),
+                    _ => return Err(invalid(&"unbalanced end tag")),
+                }
+            }
+            Event::Text(text) => {
+                let text = text.decode().map_err(|err| invalid(&err))?;
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (Some(document), true) => Ok(document),
+        _ => Err(invalid(&"unclosed element")),
+    }
+}
+
+fn element(start: &BytesStart<'_>) -> std::result::Result<Element, quick_xml::Error> {
+    let mut element = Element {
+        name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+        ..Element::default()
+    };
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(attribute.key.local_name().as_ref()).into_owned();
+        let value = attribute.unescape_value()?.into_owned();
+        element.attributes.insert(key, value);
+    }
+    Ok(element)
+}
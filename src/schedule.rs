@@ -0,0 +1,137 @@
+use crate::metrics::{Counter, Gauge, MetricsRegistry};
+use crate::rt::Instant;
+use crate::Error;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
+use std::collections::BTreeMap;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+pub(crate) type SourceTask = Pin<Box<dyn Future<Output = Result<(), (String, Error)>>>>;
+
+// Source tasks grouped by priority. Whenever the engine wakes up the higher
+// priorities are polled first, so their messages are dispatched before those
+// of lower priority sources that became ready at the same time.
+#[derive(Default)]
+pub(crate) struct SourceTasks {
+    buckets: BTreeMap<i32, FuturesUnordered<SourceTask>>,
+}
+
+impl SourceTasks {
+    pub(crate) fn push(&mut self, priority: i32, task: SourceTask) {
+        self.buckets.entry(priority).or_default().push(task);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buckets.values().all(FuturesUnordered::is_empty)
+    }
+
+    pub(crate) async fn next(&mut self) -> Option<Result<(), (String, Error)>> {
+        poll_fn(|cx| {
+            for bucket in self.buckets.values_mut().rev() {
+                if let Poll::Ready(Some(result)) = bucket.poll_next_unpin(cx) {
+                    return Poll::Ready(Some(result));
+                }
+            }
+            if self.is_empty() {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+// Measures how long a source waits between being woken and being polled,
+// i.e. how much busier or higher priority sources starve it.
+pub(crate) struct PollDelay {
+    task: SourceTask,
+    tracker: Option<Arc<WakeTracker>>,
+    wakeups: Counter,
+    delay_micros: Counter,
+    max_delay_micros: Gauge,
+    max_delay: Duration,
+}
+
+struct WakeTracker {
+    waker: Waker,
+    woken_at: Mutex<Option<Instant>>,
+}
+
+impl Wake for WakeTracker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Ok(mut woken_at) = self.woken_at.lock() {
+            woken_at.get_or_insert_with(Instant::now);
+        }
+        self.waker.wake_by_ref();
+    }
+}
+
+impl PollDelay {
+    pub(crate) fn new(task: SourceTask, label: &str, metrics: &MetricsRegistry) -> Self {
+        let labels = [("source", label)];
+        Self {
+            task,
+            tracker: None,
+            wakeups: metrics.counter(
+                "streamz_source_wakeups_total",
+                "Times a source was woken to make progress.",
+                &labels,
+            ),
+            delay_micros: metrics.counter(
+                "streamz_source_poll_delay_micros_total",
+                "Time sources spent woken but waiting to be polled.",
+                &labels,
+            ),
+            max_delay_micros: metrics.gauge(
+                "streamz_source_poll_delay_max_micros",
+                "Longest wait between a source being woken and polled.",
+                &labels,
+            ),
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Future for PollDelay {
+    type Output = Result<(), (String, Error)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let woken_at = this
+            .tracker
+            .as_ref()
+            .and_then(|tracker| tracker.woken_at.lock().ok()?.take());
+        if let Some(woken_at) = woken_at {
+            let delay = woken_at.elapsed();
+            this.wakeups.inc();
+            this.delay_micros.add(delay.as_micros() as u64);
+            if delay > this.max_delay {
+                this.max_delay = delay;
+                this.max_delay_micros.set(delay.as_micros() as f64);
+            }
+        }
+
+        let tracker = match &this.tracker {
+            Some(tracker) if tracker.waker.will_wake(cx.waker()) => tracker.clone(),
+            _ => {
+                let tracker = Arc::new(WakeTracker {
+                    waker: cx.waker().clone(),
+                    woken_at: Mutex::new(None),
+                });
+                this.tracker = Some(tracker.clone());
+                tracker
+            }
+        };
+        let waker = Waker::from(tracker);
+        this.task.poll_unpin(&mut Context::from_waker(&waker))
+    }
+}
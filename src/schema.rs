@@ -0,0 +1,375 @@
+use crate::log;
+use crate::source::{current_builder, owned_by};
+use crate::Stream;
+use serde::Serialize;
+use serde_json::Value;
+use std::any::{self, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::mem;
+
+// The shape of a named stream's items: a JSON schema (the `type`,
+// `properties`, `required`, `additionalProperties`, `items` and `enum`
+// keywords are understood), or the Rust type carried, for streams that
+// never leave the process.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schema {
+    Json(Value),
+    Type { name: &'static str, id: TypeId },
+}
+
+impl Schema {
+    pub fn json(schema: Value) -> Self {
+        Schema::Json(schema)
+    }
+
+    pub fn of<T: 'static>() -> Self {
+        Schema::Type {
+            name: any::type_name::<T>(),
+            id: TypeId::of::<T>(),
+        }
+    }
+
+    // Where `value` breaks the schema, e.g. "$.price: expected number, got
+    // string"; empty when it conforms. Type schemas accept anything.
+    pub fn violations(&self, value: &Value) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Schema::Json(schema) = self {
+            check(schema, value, "$", &mut violations);
+        }
+        violations
+    }
+
+    // Whether everything `required` asks of an item is guaranteed by this
+    // schema; lists what is not.
+    pub fn satisfies(&self, required: &Schema) -> Vec<String> {
+        let mut problems = Vec::new();
+        match (self, required) {
+            (Schema::Json(provided), Schema::Json(required)) => {
+                compare(provided, required, "$", &mut problems)
+            }
+            (
+                Schema::Type { id, name },
+                Schema::Type {
+                    id: other,
+                    name: other_name,
+                },
+            ) => {
+                if id != other {
+                    problems.push(format!("type {} is not {}", name, other_name));
+                }
+            }
+            _ => problems.push("a type and a JSON schema cannot be compared".to_string()),
+        }
+        problems
+    }
+}
+
+impl Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schema::Json(schema) => write!(f, "{}", schema),
+            Schema::Type { name, .. } => f.write_str(name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Producer,
+    Consumer,
+}
+
+thread_local! {
+    // Schemas declared with `produces` and `consumes` on this thread, checked
+    // and forgotten by the `EngineBuilder::build` they were declared for.
+    static DECLARED: RefCell<Vec<Declaration>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Declaration {
+    name: String,
+    role: Role,
+    schema: Schema,
+    // the builder it was made for, see `BuilderScope`
+    owner: Option<u64>,
+}
+
+// Disagreements between the registry and what producers and consumers of
+// each named stream declared for `builders`, forgetting those declarations;
+// see `owned_by`.
+pub(crate) fn take_problems(registry: &HashMap<String, Schema>, builders: &[u64]) -> Vec<String> {
+    let (declared, kept): (Vec<Declaration>, Vec<Declaration>) = DECLARED
+        .with(|declared| mem::take(&mut *declared.borrow_mut()))
+        .into_iter()
+        .partition(|declaration| owned_by(declaration.owner, builders));
+    DECLARED.with(|declared| *declared.borrow_mut() = kept);
+    let mut streams: BTreeMap<&str, Vec<(Role, &Schema)>> = BTreeMap::new();
+    for declaration in &declared {
+        streams
+            .entry(&declaration.name)
+            .or_default()
+            .push((declaration.role, &declaration.schema));
+    }
+
+    let mut problems = Vec::new();
+    for (name, declarations) in streams {
+        let producers: Vec<&Schema> = declarations
+            .iter()
+            .filter(|(role, _)| *role == Role::Producer)
+            .map(|(_, schema)| *schema)
+            .collect();
+        let Some(canonical) = registry.get(name).or(producers.first().copied()) else {
+            problems.push(format!(
+                "stream {:?} is consumed but nothing produces it",
+                name
+            ));
+            continue;
+        };
+        let mut report = |role: &str, found: Vec<String>| {
+            for problem in found {
+                problems.push(format!("stream {:?}: {} {}", name, role, problem));
+            }
+        };
+        for producer in &producers {
+            report("producer", producer.satisfies(canonical));
+        }
+        for (role, consumer) in &declarations {
+            if *role == Role::Consumer {
+                report("consumer", canonical.satisfies(consumer));
+            }
+        }
+    }
+    problems
+}
+
+fn declare(name: String, role: Role, schema: Schema) {
+    let declaration = Declaration {
+        name,
+        role,
+        schema,
+        owner: current_builder(),
+    };
+    DECLARED.with(|declared| declared.borrow_mut().push(declaration));
+}
+
+impl<T> Stream<T>
+where
+    T: 'static,
+{
+    // Declares that this stream publishes the named stream `name` in the
+    // shape `schema`. `EngineBuilder::build` fails if producers disagree
+    // with each other or with the schema registered for the name.
+    pub fn produces(&self, name: impl Into<String>, schema: Schema) -> Stream<T> {
+        declare(name.into(), Role::Producer, schema);
+        self.clone()
+    }
+
+    // Declares that what follows reads the named stream `name` and relies on
+    // `schema`. `EngineBuilder::build` fails if the stream's producers do
+    // not guarantee it.
+    pub fn consumes(&self, name: impl Into<String>, schema: Schema) -> Stream<T> {
+        declare(name.into(), Role::Consumer, schema);
+        self.clone()
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: Serialize + Clone + 'static,
+{
+    // Checks every item against `schema` and passes it on regardless. Each
+    // distinct violation is logged once and all are counted; meant for
+    // debug deployments, as every item is serialized.
+    pub fn validate_schema(&self, schema: Schema) -> Stream<T> {
+        let seen = RefCell::new(HashMap::<String, u64>::new());
+        let invalid = Cell::new(0u64);
+        self.tap(move |item: &T| {
+            let value = match serde_json::to_value(item) {
+                Ok(value) => value,
+                Err(err) => {
+                    log::error("schema", format_args!("item not serializable: {}", err));
+                    return;
+                }
+            };
+            let violations = schema.violations(&value);
+            if violations.is_empty() {
+                return;
+            }
+            invalid.set(invalid.get() + 1);
+            let mut seen = seen.borrow_mut();
+            for violation in violations {
+                let count = seen.entry(violation.clone()).or_default();
+                *count += 1;
+                if *count == 1 {
+                    log::warn(
+                        "schema",
+                        format_args!("{} (invalid items so far: {})", violation, invalid.get()),
+                    );
+                }
+            }
+        })
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// The `type` keyword as a list; none when absent.
+fn types(schema: &Value) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(name) => Some(vec![name.as_str()]),
+        Value::Array(names) => Some(names.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn allows(types: &[&str], found: &str) -> bool {
+    types
+        .iter()
+        .any(|name| *name == found || (*name == "number" && found == "integer"))
+}
+
+fn required(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn check(schema: &Value, value: &Value, path: &str, violations: &mut Vec<String>) {
+    let found = type_of(value);
+    if let Some(types) = types(schema) {
+        if !allows(&types, found) {
+            violations.push(format!(
+                "{}: expected {}, got {}",
+                path,
+                types.join(" or "),
+                found
+            ));
+            return;
+        }
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            violations.push(format!(
+                "{}: {} is not one of {}",
+                path,
+                value,
+                Value::from(options.clone())
+            ));
+        }
+    }
+    match value {
+        Value::Object(fields) => {
+            for name in required(schema) {
+                if !fields.contains_key(name) {
+                    violations.push(format!("{}.{}: missing", path, name));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let closed = schema.get("additionalProperties") == Some(&Value::Bool(false));
+            for (name, field) in fields {
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => {
+                        check(property, field, &format!("{}.{}", path, name), violations)
+                    }
+                    None if closed => violations.push(format!("{}.{}: unexpected", path, name)),
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    check(
+                        item_schema,
+                        item,
+                        &format!("{}[{}]", path, index),
+                        violations,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn compare(provided: &Value, required_schema: &Value, path: &str, problems: &mut Vec<String>) {
+    if let Some(wanted) = types(required_schema) {
+        match types(provided) {
+            Some(offered) => {
+                let stray: Vec<&str> = offered
+                    .iter()
+                    .copied()
+                    .filter(|offered| !allows(&wanted, offered))
+                    .collect();
+                if !stray.is_empty() {
+                    problems.push(format!(
+                        "{}: may be {}, expected {}",
+                        path,
+                        stray.join(" or "),
+                        wanted.join(" or ")
+                    ));
+                    return;
+                }
+            }
+            None => problems.push(format!(
+                "{}: type unspecified, expected {}",
+                path,
+                wanted.join(" or ")
+            )),
+        }
+    }
+    if let Some(wanted) = required_schema.get("enum").and_then(Value::as_array) {
+        match provided.get("enum").and_then(Value::as_array) {
+            Some(offered) => {
+                for option in offered.iter().filter(|option| !wanted.contains(option)) {
+                    problems.push(format!(
+                        "{}: may be {}, not in the expected enum",
+                        path, option
+                    ));
+                }
+            }
+            None => problems.push(format!("{}: not limited to the expected enum", path)),
+        }
+    }
+
+    let offered_required = required(provided);
+    for name in required(required_schema) {
+        if !offered_required.contains(&name) {
+            problems.push(format!("{}.{}: required but not guaranteed", path, name));
+        }
+    }
+    let offered_properties = provided.get("properties").and_then(Value::as_object);
+    if let Some(wanted_properties) = required_schema.get("properties").and_then(Value::as_object) {
+        for (name, wanted) in wanted_properties {
+            if let Some(offered) = offered_properties.and_then(|properties| properties.get(name)) {
+                compare(offered, wanted, &format!("{}.{}", path, name), problems);
+            }
+        }
+        if required_schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+            for name in offered_properties
+                .into_iter()
+                .flat_map(|properties| properties.keys())
+            {
+                if !wanted_properties.contains_key(name) {
+                    problems.push(format!("{}.{}: not allowed", path, name));
+                }
+            }
+        }
+    }
+    if let (Some(offered), Some(wanted)) = (provided.get("items"), required_schema.get("items")) {
+        compare(offered, wanted, &format!("{}[]", path), problems);
+    }
+}
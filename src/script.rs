@@ -0,0 +1,261 @@
+use crate::log;
+use crate::rt::Instant;
+use crate::{Error, Result, Stream};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptLanguage {
+    #[cfg(feature = "rhai")]
+    Rhai,
+    #[cfg(feature = "lua")]
+    Lua,
+}
+
+impl ScriptLanguage {
+    // By file extension, `.rhai` or `.lua`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "rhai")]
+            "rhai" => Some(ScriptLanguage::Rhai),
+            #[cfg(feature = "lua")]
+            "lua" => Some(ScriptLanguage::Lua),
+            _ => None,
+        }
+    }
+}
+
+enum Compiled {
+    #[cfg(feature = "rhai")]
+    Rhai(Box<rhai::Engine>, rhai::AST),
+    #[cfg(feature = "lua")]
+    Lua(mlua::Lua, mlua::Function),
+}
+
+// Operations a Rhai script may run per item before it is stopped, so a
+// runaway loop fails the item instead of hanging the engine.
+#[cfg(feature = "rhai")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+impl Compiled {
+    fn compile(language: ScriptLanguage, label: &str, source: &str) -> Result<Self> {
+        let invalid = |message: String| Error::InvalidExpression {
+            expression: label.to_string(),
+            message,
+        };
+        match language {
+            #[cfg(feature = "rhai")]
+            ScriptLanguage::Rhai => {
+                let mut engine = rhai::Engine::new();
+                engine.set_max_operations(MAX_OPERATIONS);
+                let ast = engine
+                    .compile(source)
+                    .map_err(|err| invalid(err.to_string()))?;
+                Ok(Compiled::Rhai(Box::new(engine), ast))
+            }
+            #[cfg(feature = "lua")]
+            ScriptLanguage::Lua => {
+                let lua = mlua::Lua::new();
+                let function = lua
+                    .load(source)
+                    .set_name(label)
+                    .into_function()
+                    .map_err(|err| invalid(err.to_string()))?;
+                Ok(Compiled::Lua(lua, function))
+            }
+        }
+    }
+
+    fn eval(&self, item: &Value) -> std::result::Result<Value, String> {
+        match self {
+            #[cfg(feature = "rhai")]
+            Compiled::Rhai(engine, ast) => {
+                let mut scope = rhai::Scope::new();
+                let item = rhai::serde::to_dynamic(item).map_err(|err| err.to_string())?;
+                scope.push_dynamic("item", item);
+                let result: rhai::Dynamic = engine
+                    .eval_ast_with_scope(&mut scope, ast)
+                    .map_err(|err| err.to_string())?;
+                rhai::serde::from_dynamic(&result).map_err(|err| err.to_string())
+            }
+            #[cfg(feature = "lua")]
+            Compiled::Lua(lua, function) => {
+                use mlua::LuaSerdeExt;
+                let item = lua.to_value(item).map_err(|err| err.to_string())?;
+                lua.globals()
+                    .set("item", item)
+                    .map_err(|err| err.to_string())?;
+                let result: mlua::Value = function.call(()).map_err(|err| err.to_string())?;
+                lua.from_value(result).map_err(|err| err.to_string())
+            }
+        }
+    }
+}
+
+// A Rhai or Lua script run per item with the item bound to `item` as JSON
+// (maps, arrays, numbers, strings, bools, `()` / `nil` for null); its value
+// is the result (Lua chunks `return` it).
+//
+// Scripts loaded with `from_file` are reloaded when the file changes,
+// checked at most once per `reload_interval`. A reload that fails to compile
+// is reported and the previous version kept. Items the script fails on are
+// dropped and counted, and the first failure after each load is printed.
+pub struct Script {
+    language: ScriptLanguage,
+    label: String,
+    path: Option<PathBuf>,
+    reload_interval: Duration,
+    compiled: RefCell<Compiled>,
+    modified: Cell<Option<SystemTime>>,
+    checked: Cell<Instant>,
+    reported: Cell<bool>,
+    errors: Cell<u64>,
+}
+
+impl Script {
+    pub fn new(language: ScriptLanguage, source: &str) -> Result<Self> {
+        let label = format!("{:?} script", language).to_ascii_lowercase();
+        let compiled = Compiled::compile(language, &label, source)?;
+        Ok(Self::with_compiled(language, label, None, compiled))
+    }
+
+    // The language comes from the extension, `.rhai` or `.lua`.
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let language = ScriptLanguage::from_path(&path).ok_or_else(|| {
+            Error::config(format!(
+                "{} is not a script this build supports (by extension)",
+                path.display()
+            ))
+        })?;
+        Self::from_file_as(language, path)
+    }
+
+    pub fn from_file_as(language: ScriptLanguage, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let label = path.display().to_string();
+        let modified = modified_at(&path);
+        let source = std::fs::read_to_string(&path).map_err(|err| Error::io(&label, err))?;
+        let compiled = Compiled::compile(language, &label, &source)?;
+        let script = Self::with_compiled(language, label, Some(path), compiled);
+        script.modified.set(modified);
+        Ok(script)
+    }
+
+    fn with_compiled(
+        language: ScriptLanguage,
+        label: String,
+        path: Option<PathBuf>,
+        compiled: Compiled,
+    ) -> Self {
+        Self {
+            language,
+            label,
+            path,
+            reload_interval: Duration::from_secs(1),
+            compiled: RefCell::new(compiled),
+            modified: Cell::new(None),
+            checked: Cell::new(Instant::now()),
+            reported: Cell::new(false),
+            errors: Cell::new(0),
+        }
+    }
+
+    // 1s by default.
+    pub fn with_reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    pub fn language(&self) -> ScriptLanguage {
+        self.language
+    }
+
+    // Items the script failed on so far.
+    pub fn errors(&self) -> u64 {
+        self.errors.get()
+    }
+
+    pub fn eval(&self, item: &Value) -> Result<Value> {
+        self.reload();
+        self.compiled
+            .borrow()
+            .eval(item)
+            .map_err(|message| Error::other(&self.label, message))
+    }
+
+    // `eval`, counting and reporting failures.
+    fn eval_item(&self, item: &Value) -> Option<Value> {
+        match self.eval(item) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.errors.set(self.errors.get() + 1);
+                if !self.reported.replace(true) {
+                    log::warn("script", format_args!("{}", err));
+                }
+                None
+            }
+        }
+    }
+
+    fn reload(&self) {
+        let Some(path) = &self.path else { return };
+        let now = Instant::now();
+        if now.duration_since(self.checked.get()) < self.reload_interval {
+            return;
+        }
+        self.checked.set(now);
+        let modified = modified_at(path);
+        if modified == self.modified.get() {
+            return;
+        }
+        self.modified.set(modified);
+        let compiled = std::fs::read_to_string(path)
+            .map_err(|err| Error::io(&self.label, err))
+            .and_then(|source| Compiled::compile(self.language, &self.label, &source));
+        match compiled {
+            Ok(compiled) => {
+                *self.compiled.borrow_mut() = compiled;
+                self.reported.set(false);
+                log::info("script", format_args!("reloaded {}", self.label));
+            }
+            Err(err) => log::warn(
+                "script",
+                format_args!("kept the previous {}: {}", self.label, err),
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("language", &self.language)
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl Stream<Value> {
+    // Keeps the items the script returns `true` for.
+    pub fn filter_script(&self, script: Script) -> Stream<Value> {
+        self.filter(move |item| matches!(script.eval_item(item), Some(Value::Bool(true))))
+    }
+
+    pub fn map_script(&self, script: Script) -> Stream<Value> {
+        self.filter_map(move |item| script.eval_item(item))
+    }
+}
@@ -0,0 +1,629 @@
+use crate::Stream;
+use std::collections::VecDeque;
+use std::mem;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{broadcast, watch};
+
+type SharedCallback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+type SharedCompletion = Arc<dyn Fn() + Send + Sync>;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Copied on write, so a callback can subscribe while items are delivered.
+struct Subscribers<T> {
+    callbacks: Mutex<Arc<Vec<SharedCallback<T>>>>,
+}
+
+impl<T> Subscribers<T> {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            callbacks: Mutex::new(Arc::new(Vec::new())),
+        })
+    }
+
+    fn push(&self, callback: SharedCallback<T>) {
+        let mut callbacks = lock(&self.callbacks);
+        let mut updated = Vec::clone(&callbacks);
+        updated.push(callback);
+        *callbacks = Arc::new(updated);
+    }
+
+    fn deliver(&self, item: &T) {
+        let callbacks = lock(&self.callbacks).clone();
+        for callback in callbacks.iter() {
+            callback(item);
+        }
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.callbacks).len()
+    }
+}
+
+#[derive(Default)]
+struct CompletionState {
+    completed: AtomicBool,
+    callbacks: Mutex<Vec<SharedCompletion>>,
+}
+
+impl CompletionState {
+    fn subscribe(&self, callback: SharedCompletion) {
+        {
+            let mut callbacks = lock(&self.callbacks);
+            if !self.completed.load(Ordering::Acquire) {
+                callbacks.push(callback);
+                return;
+            }
+        }
+        callback();
+    }
+
+    fn complete(&self) {
+        let callbacks = {
+            let mut callbacks = lock(&self.callbacks);
+            if self.completed.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            mem::take(&mut *callbacks)
+        };
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.completed.load(Ordering::Acquire)
+    }
+}
+
+struct Delivery<T> {
+    emitting: bool,
+    queued: VecDeque<T>,
+    completing: bool,
+}
+
+// A `Source` that can be shared with and emitted on from other threads,
+// e.g. tasks `tokio::spawn`ed on the multi-thread runtime, so CPU-heavy
+// stages run on worker threads. Items are delivered one at a time, in the
+// order `emit` was called: an item emitted while another is being
+// delivered, from a callback or another thread, is handed to the thread
+// delivering it. Callbacks run on whichever thread that is.
+pub struct SharedSource<T> {
+    callbacks: Arc<Subscribers<T>>,
+    completion: Arc<CompletionState>,
+    emitted: AtomicU64,
+    delivery: Mutex<Delivery<T>>,
+}
+
+impl<T> Default for SharedSource<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SharedSource<T> {
+    pub fn new() -> Self {
+        Self {
+            callbacks: Subscribers::new(),
+            completion: Arc::new(CompletionState::default()),
+            emitted: AtomicU64::new(0),
+            delivery: Mutex::new(Delivery {
+                emitting: false,
+                queued: VecDeque::new(),
+                completing: false,
+            }),
+        }
+    }
+
+    // Items emitted after `complete` are dropped.
+    pub fn emit(&self, item: T) {
+        {
+            let mut delivery = lock(&self.delivery);
+            if delivery.completing || self.completion.is_complete() {
+                return;
+            }
+            if delivery.emitting {
+                delivery.queued.push_back(item);
+                return;
+            }
+            delivery.emitting = true;
+        }
+        let mut next = item;
+        loop {
+            self.emitted.fetch_add(1, Ordering::Relaxed);
+            self.callbacks.deliver(&next);
+            let mut delivery = lock(&self.delivery);
+            if delivery.completing {
+                delivery.queued.clear();
+                delivery.emitting = false;
+                drop(delivery);
+                self.completion.complete();
+                return;
+            }
+            match delivery.queued.pop_front() {
+                Some(item) => next = item,
+                None => {
+                    delivery.emitting = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    // Once the item being delivered, if any, has reached every subscriber;
+    // items still queued behind it are dropped.
+    pub fn complete(&self) {
+        {
+            let mut delivery = lock(&self.delivery);
+            if delivery.emitting {
+                delivery.completing = true;
+                return;
+            }
+        }
+        self.completion.complete();
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.is_complete()
+    }
+
+    pub fn emitted(&self) -> u64 {
+        self.emitted.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.callbacks.len()
+    }
+
+    pub fn to_stream(&self) -> SharedStream<T> {
+        SharedStream {
+            callbacks: self.callbacks.clone(),
+            completion: self.completion.clone(),
+        }
+    }
+}
+
+// `Stream`'s operators for a `SharedSource`; the stream and every closure
+// handed to it are `Send + Sync`. Engine-bound operators (timed buffers,
+// profiling, metrics) stay on `Stream`: bridge with `Stream::to_shared` and
+// `SharedStream::to_broadcast` into a `BroadcastSource`.
+pub struct SharedStream<T> {
+    callbacks: Arc<Subscribers<T>>,
+    completion: Arc<CompletionState>,
+}
+
+impl<T> Clone for SharedStream<T> {
+    fn clone(&self) -> Self {
+        SharedStream {
+            callbacks: self.callbacks.clone(),
+            completion: self.completion.clone(),
+        }
+    }
+}
+
+impl<T> SharedStream<T>
+where
+    T: 'static,
+{
+    pub fn map<U, F>(&self, f: F) -> SharedStream<U>
+    where
+        U: 'static,
+        F: Fn(&T) -> U + Send + Sync + 'static,
+    {
+        self.filter_map(move |item: &T| Some(f(item)))
+    }
+
+    pub fn filter<F>(&self, predicate: F) -> SharedStream<T>
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.push(Arc::new(move |item: &T| {
+            if predicate(item) {
+                downstream_clone.deliver(item);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    pub fn filter_map<U, F>(&self, f: F) -> SharedStream<U>
+    where
+        U: 'static,
+        F: Fn(&T) -> Option<U> + Send + Sync + 'static,
+    {
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.push(Arc::new(move |item: &T| {
+            if let Some(mapped) = f(item) {
+                downstream_clone.deliver(&mapped);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    pub fn try_map<U, E, F>(&self, f: F) -> (SharedStream<U>, SharedStream<E>)
+    where
+        U: 'static,
+        E: 'static,
+        F: Fn(&T) -> Result<U, E> + Send + Sync + 'static,
+    {
+        self.try_filter_map(move |item: &T| f(item).map(Some))
+    }
+
+    pub fn try_filter<E, F>(&self, f: F) -> (SharedStream<T>, SharedStream<E>)
+    where
+        T: Clone,
+        E: 'static,
+        F: Fn(&T) -> Result<bool, E> + Send + Sync + 'static,
+    {
+        self.try_filter_map(move |item: &T| Ok(f(item)?.then(|| item.clone())))
+    }
+
+    pub fn try_filter_map<U, E, F>(&self, f: F) -> (SharedStream<U>, SharedStream<E>)
+    where
+        U: 'static,
+        E: 'static,
+        F: Fn(&T) -> Result<Option<U>, E> + Send + Sync + 'static,
+    {
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+        let errors = Subscribers::new();
+        let errors_clone = errors.clone();
+
+        self.callbacks.push(Arc::new(move |item: &T| match f(item) {
+            Ok(Some(mapped)) => downstream_clone.deliver(&mapped),
+            Ok(None) => {}
+            Err(err) => errors_clone.deliver(&err),
+        }));
+
+        (self.chain(downstream), self.chain(errors))
+    }
+
+    pub fn accumulate<State, F>(&self, initial_state: State, f: F) -> SharedStream<State>
+    where
+        State: Clone + Send + 'static,
+        F: Fn(State, &T) -> State + Send + Sync + 'static,
+    {
+        self.scan_map(initial_state, move |state: &mut State, item: &T| {
+            *state = f(state.clone(), item);
+            Some(state.clone())
+        })
+    }
+
+    // The state is locked while `f` runs, so concurrent deliveries from
+    // several upstreams update it one at a time.
+    pub fn scan_map<State, U, F>(&self, initial_state: State, f: F) -> SharedStream<U>
+    where
+        State: Send + 'static,
+        U: 'static,
+        F: Fn(&mut State, &T) -> Option<U> + Send + Sync + 'static,
+    {
+        let state = Mutex::new(initial_state);
+        self.filter_map(move |item: &T| f(&mut lock(&state), item))
+    }
+
+    pub fn tap<F>(&self, f: F) -> SharedStream<T>
+    where
+        F: Fn(&T) + Send + Sync + 'static,
+    {
+        self.filter(move |item: &T| {
+            f(item);
+            true
+        })
+    }
+
+    // See `Stream::zip`: the latest of `other` paired with every item of
+    // `self`, once `other` has emitted.
+    pub fn zip<U>(&self, other: &SharedStream<U>) -> SharedStream<(T, U)>
+    where
+        T: Clone + Send,
+        U: Clone + Send + 'static,
+    {
+        let right = Arc::new(Mutex::new(None::<U>));
+        let right_clone = right.clone();
+        other.callbacks.push(Arc::new(move |item: &U| {
+            *lock(&right_clone) = Some(item.clone());
+        }));
+        self.filter_map(move |item: &T| {
+            let right = lock(&right).clone()?;
+            Some((item.clone(), right))
+        })
+    }
+
+    // See `Stream::combine_latest`.
+    pub fn combine_latest<U>(&self, other: &SharedStream<U>) -> SharedStream<(T, U)>
+    where
+        T: Clone + Send,
+        U: Clone + Send + 'static,
+    {
+        let downstream = Subscribers::new();
+        let latest = Arc::new(Mutex::new((None::<T>, None::<U>)));
+
+        let downstream_left = downstream.clone();
+        let latest_left = latest.clone();
+        self.callbacks.push(Arc::new(move |item: &T| {
+            let pair = {
+                let mut latest = lock(&latest_left);
+                latest.0 = Some(item.clone());
+                latest.1.clone().map(|right| (item.clone(), right))
+            };
+            if let Some(pair) = pair {
+                downstream_left.deliver(&pair);
+            }
+        }));
+
+        let downstream_right = downstream.clone();
+        other.callbacks.push(Arc::new(move |item: &U| {
+            let pair = {
+                let mut latest = lock(&latest);
+                latest.1 = Some(item.clone());
+                latest.0.clone().map(|left| (left, item.clone()))
+            };
+            if let Some(pair) = pair {
+                downstream_right.deliver(&pair);
+            }
+        }));
+
+        SharedStream::completing_after(
+            downstream,
+            &[self.completion.clone(), other.completion.clone()],
+        )
+    }
+
+    // Items from both streams as they arrive; completes when both have.
+    // Items delivered on different threads at once reach subscribers
+    // concurrently.
+    pub fn merge(&self, other: &SharedStream<T>) -> SharedStream<T> {
+        let downstream = Subscribers::new();
+        for upstream in [self, other] {
+            let downstream = downstream.clone();
+            upstream
+                .callbacks
+                .push(Arc::new(move |item: &T| downstream.deliver(item)));
+        }
+        SharedStream::completing_after(
+            downstream,
+            &[self.completion.clone(), other.completion.clone()],
+        )
+    }
+
+    pub fn sample_with<U>(&self, sampler: &SharedStream<U>) -> SharedStream<T>
+    where
+        T: Clone + Send,
+        U: 'static,
+    {
+        let latest = Arc::new(Mutex::new(None::<T>));
+        let latest_clone = latest.clone();
+        self.callbacks.push(Arc::new(move |item: &T| {
+            *lock(&latest_clone) = Some(item.clone());
+        }));
+
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+        sampler.callbacks.push(Arc::new(move |_: &U| {
+            let sampled = lock(&latest).clone();
+            if let Some(sampled) = sampled {
+                downstream_clone.deliver(&sampled);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // See `Stream::chunk_by`.
+    pub fn chunk_by<F>(&self, is_boundary: F) -> SharedStream<Vec<T>>
+    where
+        T: Clone + Send,
+        F: Fn(&T, &T) -> bool + Send + Sync + 'static,
+    {
+        let chunk = Arc::new(Mutex::new(Vec::<T>::new()));
+        let chunk_clone = chunk.clone();
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.push(Arc::new(move |item: &T| {
+            let complete = {
+                let mut chunk = lock(&chunk_clone);
+                let boundary = chunk
+                    .last()
+                    .is_some_and(|previous| is_boundary(previous, item));
+                let complete = boundary.then(|| mem::take(&mut *chunk));
+                chunk.push(item.clone());
+                complete
+            };
+            if let Some(complete) = complete {
+                downstream_clone.deliver(&complete);
+            }
+        }));
+
+        self.chain_flushing(downstream, chunk)
+    }
+
+    // See `Stream::window_count`.
+    pub fn window_count(&self, n: usize) -> SharedStream<Vec<T>>
+    where
+        T: Clone + Send,
+    {
+        let n = n.max(1);
+        let window = Arc::new(Mutex::new(Vec::<T>::with_capacity(n)));
+        let window_clone = window.clone();
+        let downstream = Subscribers::new();
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.push(Arc::new(move |item: &T| {
+            let full = {
+                let mut window = lock(&window_clone);
+                window.push(item.clone());
+                if window.len() < n {
+                    return;
+                }
+                mem::replace(&mut *window, Vec::with_capacity(n))
+            };
+            downstream_clone.deliver(&full);
+        }));
+
+        self.chain_flushing(downstream, window)
+    }
+
+    // See `Stream::sliding_window`.
+    pub fn sliding_window(&self, n: usize) -> SharedStream<Vec<T>>
+    where
+        T: Clone + Send,
+    {
+        let n = n.max(1);
+        self.scan_map(
+            VecDeque::<T>::with_capacity(n),
+            move |window: &mut VecDeque<T>, item: &T| {
+                if window.len() == n {
+                    window.pop_front();
+                }
+                window.push_back(item.clone());
+                (window.len() == n).then(|| window.iter().cloned().collect())
+            },
+        )
+    }
+
+    pub fn sink<F>(&self, f: F)
+    where
+        F: Fn(&T) + Send + Sync + 'static,
+    {
+        self.callbacks.push(Arc::new(f));
+    }
+
+    pub fn on_complete<F>(&self, f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.completion.subscribe(Arc::new(f));
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.is_complete()
+    }
+
+    // Into a `BroadcastSource` to carry on as a `Stream` on an engine's
+    // thread; the sender is dropped, closing the channel, on completion.
+    pub fn to_broadcast(&self, capacity: usize) -> broadcast::Sender<T>
+    where
+        T: Clone + Send,
+    {
+        let (sender, _) = broadcast::channel(capacity);
+        let open = Arc::new(Mutex::new(Some(sender.clone())));
+        let open_clone = open.clone();
+        self.sink(move |item: &T| {
+            if let Some(sender) = lock(&open_clone).as_ref() {
+                let _ = sender.send(item.clone());
+            }
+        });
+        self.on_complete(move || drop(lock(&open).take()));
+        sender
+    }
+
+    pub fn to_watch(&self) -> watch::Receiver<Option<T>>
+    where
+        T: Clone + Send + Sync,
+    {
+        let (sender, receiver) = watch::channel(None);
+        self.sink(move |item: &T| {
+            let _ = sender.send(Some(item.clone()));
+        });
+        receiver
+    }
+
+    fn chain<U>(&self, callbacks: Arc<Subscribers<U>>) -> SharedStream<U> {
+        SharedStream::completing_after(callbacks, slice::from_ref(&self.completion))
+    }
+
+    // Emits the batch still collecting before completing.
+    fn chain_flushing(
+        &self,
+        callbacks: Arc<Subscribers<Vec<T>>>,
+        batch: Arc<Mutex<Vec<T>>>,
+    ) -> SharedStream<Vec<T>>
+    where
+        T: Send,
+    {
+        let completion = Arc::new(CompletionState::default());
+        let completion_clone = completion.clone();
+        let callbacks_remaining = callbacks.clone();
+        self.completion.subscribe(Arc::new(move || {
+            let remaining = mem::take(&mut *lock(&batch));
+            if !remaining.is_empty() {
+                callbacks_remaining.deliver(&remaining);
+            }
+            completion_clone.complete();
+        }));
+        SharedStream {
+            callbacks,
+            completion,
+        }
+    }
+}
+
+impl<T> SharedStream<T> {
+    fn completing_after(
+        callbacks: Arc<Subscribers<T>>,
+        upstreams: &[Arc<CompletionState>],
+    ) -> SharedStream<T> {
+        let completion = Arc::new(CompletionState::default());
+        let remaining = Arc::new(AtomicU64::new(upstreams.len() as u64));
+        for upstream in upstreams {
+            let completion = completion.clone();
+            let remaining = remaining.clone();
+            upstream.subscribe(Arc::new(move || {
+                if remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+                    completion.complete();
+                }
+            }));
+        }
+        SharedStream {
+            callbacks,
+            completion,
+        }
+    }
+}
+
+impl<T, E> SharedStream<Result<T, E>>
+where
+    T: Clone + 'static,
+    E: Clone + 'static,
+{
+    pub fn split_result(&self) -> (SharedStream<T>, SharedStream<E>) {
+        self.try_map(|item: &Result<T, E>| item.clone())
+    }
+
+    pub fn ok(&self) -> SharedStream<T> {
+        self.filter_map(|item: &Result<T, E>| item.as_ref().ok().cloned())
+    }
+
+    pub fn err(&self) -> SharedStream<E> {
+        self.filter_map(|item: &Result<T, E>| item.as_ref().err().cloned())
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + Send + 'static,
+{
+    // Re-emits every item on a `SharedSource`, completing with `self`, so a
+    // pipeline on the engine's thread can hand off to worker threads.
+    pub fn to_shared(&self) -> SharedStream<T> {
+        let source = Arc::new(SharedSource::new());
+        let output = source.to_stream();
+        let source_clone = source.clone();
+        self.sink(move |item: &T| source_clone.emit(item.clone()));
+        self.on_complete(move || source.complete());
+        output
+    }
+}
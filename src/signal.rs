@@ -0,0 +1,28 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+    Interrupt, // Ctrl+C / SIGINT
+    Terminate, // SIGTERM, unix only
+    Hangup,    // SIGHUP, unix only
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalAction {
+    Shutdown,
+    Ignore,
+    // Keep running and publish the signal on `EngineBuilder::signals()`, e.g.
+    // to reload config on SIGHUP.
+    Notify,
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Signal::Interrupt => "interrupt",
+            Signal::Terminate => "SIGTERM",
+            Signal::Hangup => "SIGHUP",
+        };
+        f.write_str(name)
+    }
+}
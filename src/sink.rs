@@ -0,0 +1,301 @@
+use crate::log;
+use crate::source::{current_builder, owned_by};
+use crate::{Error, Result, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+
+// Where a stream ends up. Attached with `Stream::sink_to`, a sink is closed
+// once when its stream completes, and flushed and closed by the engine on
+// shutdown (and flushed every `EngineBuilder::with_sink_flush_interval`).
+pub trait Sink<T>: 'static {
+    fn on_item(&self, item: &T);
+
+    // Batches from `timed_buffer` and the like, via `sink_batches_to`.
+    fn on_batch(&self, items: &[T]) {
+        for item in items {
+            self.on_item(item);
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // No items follow; flushes by default.
+    fn close(&self) -> Result<()> {
+        self.flush()
+    }
+}
+
+thread_local! {
+    // Sinks attached on this thread, with the builder they were attached
+    // for, for its engine to flush and close.
+    static ATTACHED: RefCell<Vec<AttachedSink>> = const { RefCell::new(Vec::new()) };
+}
+
+struct AttachedSink {
+    sink: Weak<dyn Lifecycle>,
+    owner: Option<u64>,
+}
+
+trait Lifecycle {
+    fn flush(&self);
+    fn close(&self);
+}
+
+// A sink as attached to a stream; items after `close` are dropped.
+struct Attached<S, T> {
+    sink: S,
+    closed: Cell<bool>,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<S, T> Lifecycle for Attached<S, T>
+where
+    S: Sink<T>,
+{
+    fn flush(&self) {
+        if self.closed.get() {
+            return;
+        }
+        if let Err(err) = self.sink.flush() {
+            log::error("sink", format_args!("sink failed to flush: {}", err));
+        }
+    }
+
+    fn close(&self) {
+        if self.closed.replace(true) {
+            return;
+        }
+        if let Err(err) = self.sink.close() {
+            log::error("sink", format_args!("sink failed to close: {}", err));
+        }
+    }
+}
+
+fn attach<S, T>(sink: S) -> Rc<Attached<S, T>>
+where
+    S: Sink<T>,
+    T: 'static,
+{
+    let attached = Rc::new(Attached {
+        sink,
+        closed: Cell::new(false),
+        _item: PhantomData,
+    });
+    let lifecycle: Rc<dyn Lifecycle> = attached.clone();
+    ATTACHED.with(|sinks| {
+        let mut sinks = sinks.borrow_mut();
+        sinks.retain(|sink| sink.sink.strong_count() > 0);
+        sinks.push(AttachedSink {
+            sink: Rc::downgrade(&lifecycle),
+            owner: current_builder(),
+        });
+    });
+    attached
+}
+
+// The live sinks of `builders`, see `owned_by`.
+fn attached(builders: &[u64]) -> Vec<Rc<dyn Lifecycle>> {
+    ATTACHED.with(|sinks| {
+        sinks
+            .borrow()
+            .iter()
+            .filter(|sink| owned_by(sink.owner, builders))
+            .filter_map(|sink| sink.sink.upgrade())
+            .collect()
+    })
+}
+
+pub(crate) fn flush_all(builders: &[u64]) {
+    for sink in attached(builders) {
+        sink.flush();
+    }
+}
+
+// Leaves other engines' sinks, and unowned ones while another engine could
+// still be feeding them, open.
+pub(crate) fn close_all(builders: &[u64]) {
+    for sink in attached(builders) {
+        sink.close();
+    }
+    ATTACHED.with(|sinks| {
+        sinks
+            .borrow_mut()
+            .retain(|sink| sink.sink.strong_count() > 0 && !owned_by(sink.owner, builders))
+    });
+}
+
+// Flushes the sinks of `builders` on the engine's timer.
+pub(crate) struct SinkFlusher {
+    pub(crate) period: Duration,
+    pub(crate) builders: Vec<u64>,
+}
+
+impl TimedEmitter for SinkFlusher {
+    fn period(&self) -> Duration {
+        self.period
+    }
+
+    fn name(&self) -> String {
+        "sink_flush".to_string()
+    }
+
+    fn flush(&self) {
+        flush_all(&self.builders);
+    }
+}
+
+impl<T> Stream<T>
+where
+    T: 'static,
+{
+    pub fn sink_to<S>(&self, sink: S)
+    where
+        S: Sink<T>,
+    {
+        let attached = attach(sink);
+        let attached_item = attached.clone();
+        self.sink(move |item: &T| {
+            if !attached_item.closed.get() {
+                attached_item.sink.on_item(item);
+            }
+        });
+        self.on_complete(move || attached.close());
+    }
+}
+
+impl<T> Stream<Vec<T>>
+where
+    T: 'static,
+{
+    // Hands each batch to `Sink::on_batch` whole.
+    pub fn sink_batches_to<S>(&self, sink: S)
+    where
+        S: Sink<T>,
+    {
+        let attached = attach(sink);
+        let attached_batch = attached.clone();
+        self.sink(move |items: &Vec<T>| {
+            if !attached_batch.closed.get() {
+                attached_batch.sink.on_batch(items);
+            }
+        });
+        self.on_complete(move || attached.close());
+    }
+}
+
+// Prints each item on its own line.
+pub struct StdoutSink {
+    prefix: String,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self::with_prefix("")
+    }
+
+    pub fn with_prefix(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Sink<T> for StdoutSink
+where
+    T: Display,
+{
+    fn on_item(&self, item: &T) {
+        println!("{}{}", self.prefix, item);
+    }
+
+    fn flush(&self) -> Result<()> {
+        io::stdout().flush().map_err(|err| Error::io("stdout", err))
+    }
+}
+
+// Appends each item to a file as a line; lines reach the file as they are
+// written, so `flush` only matters for a partial last line.
+pub struct FileSink {
+    label: String,
+    writer: RefCell<Option<LineWriter<File>>>,
+}
+
+impl FileSink {
+    pub fn append(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| Error::io(&label, err))?;
+        Ok(Self {
+            label,
+            writer: RefCell::new(Some(LineWriter::new(file))),
+        })
+    }
+}
+
+impl<T> Sink<T> for FileSink
+where
+    T: Display,
+{
+    fn on_item(&self, item: &T) {
+        if let Some(writer) = self.writer.borrow_mut().as_mut() {
+            if let Err(err) = writeln!(writer, "{}", item) {
+                log::error(
+                    "file_sink",
+                    format_args!("{} write failed: {}", self.label, err),
+                );
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        match self.writer.borrow_mut().as_mut() {
+            Some(writer) => writer.flush().map_err(|err| Error::io(&self.label, err)),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        match self.writer.borrow_mut().take() {
+            Some(mut writer) => writer.flush().map_err(|err| Error::io(&self.label, err)),
+            None => Ok(()),
+        }
+    }
+}
+
+// No receivers is not an error; they may subscribe later.
+impl<T> Sink<T> for broadcast::Sender<T>
+where
+    T: Clone + 'static,
+{
+    fn on_item(&self, item: &T) {
+        let _ = self.send(item.clone());
+    }
+}
+
+impl<T> Sink<T> for watch::Sender<Option<T>>
+where
+    T: Clone + 'static,
+{
+    fn on_item(&self, item: &T) {
+        self.send_replace(Some(item.clone()));
+    }
+}
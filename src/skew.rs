@@ -0,0 +1,245 @@
+use crate::metrics::{Counter, Gauge, MetricsRegistry};
+use crate::rt;
+use crate::{Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+const DEFAULT_WINDOW: usize = 256;
+const DEFAULT_SMOOTHING: f64 = 0.05;
+const DEFAULT_OUTLIER_THRESHOLD: f64 = 5.0;
+// Samples needed before outliers are rejected.
+const MIN_SAMPLES: usize = 8;
+// Scales a median absolute deviation to a standard deviation.
+const MAD_SCALE: f64 = 1.4826;
+// Clocks are read in milliseconds, so deviations below one are noise.
+const MIN_DEVIATION: f64 = 1.0;
+
+// How far local receive time runs ahead of exchange time, in milliseconds:
+// clock skew plus one-way latency, which one clock cannot tell apart. The
+// median is the estimate to correct timestamps with; `min_millis` bounds
+// the skew when latency is mostly queueing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClockSkew {
+    pub median_millis: f64,
+    pub ewma_millis: f64,
+    pub min_millis: f64,
+    pub jitter_millis: f64,
+    pub samples: u64,
+    pub outliers: u64,
+}
+
+impl ClockSkew {
+    // An exchange timestamp on the local clock.
+    pub fn to_local(&self, exchange_millis: u64) -> u64 {
+        (exchange_millis as f64 + self.median_millis).max(0.0) as u64
+    }
+
+    // A local timestamp on the exchange clock.
+    pub fn to_exchange(&self, local_millis: u64) -> u64 {
+        (local_millis as f64 - self.median_millis).max(0.0) as u64
+    }
+}
+
+// e.g. "skew 12.0ms (ewma 11.8ms, min 9.0ms, jitter 0.7ms, 2 outliers)"
+impl Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "skew {:.1}ms (ewma {:.1}ms, min {:.1}ms, jitter {:.1}ms, {} outliers)",
+            self.median_millis,
+            self.ewma_millis,
+            self.min_millis,
+            self.jitter_millis,
+            self.outliers
+        )
+    }
+}
+
+struct SkewMetrics {
+    skew: Gauge,
+    jitter: Gauge,
+    outliers: Counter,
+}
+
+struct SkewInner {
+    window: Cell<usize>,
+    smoothing: Cell<f64>,
+    outlier_threshold: Cell<f64>,
+    samples: RefCell<VecDeque<f64>>,
+    // outliers in a row; as many as `MIN_SAMPLES` means the clock stepped
+    rejected_run: RefCell<Vec<f64>>,
+    latest: RefCell<Option<ClockSkew>>,
+    metrics: RefCell<Option<SkewMetrics>>,
+    output: Source<ClockSkew>,
+}
+
+// Estimates clock skew from the exchange timestamps of a stream's items,
+// emitting the estimate after every item. Samples further than
+// `with_outlier_threshold` deviations from the median of the window are
+// counted and left out, unless enough arrive in a row to show the clock
+// stepped, when the window restarts from them.
+#[derive(Clone)]
+pub struct SkewEstimator {
+    inner: Rc<SkewInner>,
+}
+
+impl<T> Stream<T>
+where
+    T: 'static,
+{
+    // `timestamp_fn` gives an item's exchange time in milliseconds since the
+    // unix epoch; items are compared with the local clock as they arrive, so
+    // this is not for replays.
+    pub fn estimate_skew<F>(&self, timestamp_fn: F) -> SkewEstimator
+    where
+        F: Fn(&T) -> u64 + 'static,
+    {
+        let inner = Rc::new(SkewInner {
+            window: Cell::new(DEFAULT_WINDOW),
+            smoothing: Cell::new(DEFAULT_SMOOTHING),
+            outlier_threshold: Cell::new(DEFAULT_OUTLIER_THRESHOLD),
+            samples: RefCell::new(VecDeque::new()),
+            rejected_run: RefCell::new(Vec::new()),
+            latest: RefCell::new(None),
+            metrics: RefCell::new(None),
+            output: Source::new(),
+        });
+        let inner_item = inner.clone();
+        let inner_complete = inner.clone();
+
+        self.sink(move |item: &T| {
+            let sample = rt::now_millis() as f64 - timestamp_fn(item) as f64;
+            let estimate = inner_item.observe(sample);
+            inner_item.output.emit(estimate);
+        });
+        self.on_complete(move || inner_complete.output.complete());
+
+        SkewEstimator { inner }
+    }
+}
+
+impl SkewEstimator {
+    // Samples the median is taken over.
+    pub fn with_window(self, window: usize) -> Self {
+        self.inner.window.set(window.max(1));
+        self
+    }
+
+    // Weight of each new sample in the EWMA.
+    pub fn with_smoothing(self, alpha: f64) -> Self {
+        self.inner.smoothing.set(alpha.clamp(f64::EPSILON, 1.0));
+        self
+    }
+
+    // How many (robust) standard deviations from the median make a sample
+    // an outlier.
+    pub fn with_outlier_threshold(self, deviations: f64) -> Self {
+        self.inner.outlier_threshold.set(deviations.max(0.0));
+        self
+    }
+
+    // Publishes `streamz_clock_skew_millis`, `streamz_clock_skew_jitter_millis`
+    // and `streamz_clock_skew_outliers_total` under `name`.
+    pub fn with_metrics(self, metrics: &MetricsRegistry, name: &str) -> Self {
+        let labels = [("stream", name)];
+        *self.inner.metrics.borrow_mut() = Some(SkewMetrics {
+            skew: metrics.gauge(
+                "streamz_clock_skew_millis",
+                "Median of local receive time minus exchange time.",
+                &labels,
+            ),
+            jitter: metrics.gauge(
+                "streamz_clock_skew_jitter_millis",
+                "Robust standard deviation of the clock skew samples.",
+                &labels,
+            ),
+            outliers: metrics.counter(
+                "streamz_clock_skew_outliers_total",
+                "Clock skew samples rejected as outliers.",
+                &labels,
+            ),
+        });
+        self
+    }
+
+    pub fn stream(&self) -> Stream<ClockSkew> {
+        self.inner.output.to_stream()
+    }
+
+    // The estimate after the latest item, if any has arrived.
+    pub fn latest(&self) -> Option<ClockSkew> {
+        self.inner.latest.borrow().clone()
+    }
+}
+
+impl SkewInner {
+    fn observe(&self, sample: f64) -> ClockSkew {
+        let mut latest = self.latest.borrow_mut();
+        let mut estimate = latest.clone().unwrap_or(ClockSkew {
+            ewma_millis: sample,
+            ..ClockSkew::default()
+        });
+        let mut samples = self.samples.borrow_mut();
+        let mut rejected_run = self.rejected_run.borrow_mut();
+
+        let outlier = samples.len() >= MIN_SAMPLES && {
+            let deviation = estimate.jitter_millis.max(MIN_DEVIATION);
+            (sample - estimate.median_millis).abs() > self.outlier_threshold.get() * deviation
+        };
+        if outlier {
+            estimate.outliers += 1;
+            if let Some(metrics) = &*self.metrics.borrow() {
+                metrics.outliers.inc();
+            }
+            rejected_run.push(sample);
+            if rejected_run.len() < MIN_SAMPLES {
+                *latest = Some(estimate.clone());
+                return estimate;
+            }
+            // the clock stepped: start over from the run
+            samples.clear();
+            estimate.ewma_millis = f64::NAN;
+            estimate.samples += rejected_run.len() as u64 - 1;
+            samples.extend(rejected_run.drain(..));
+        } else {
+            rejected_run.clear();
+            samples.push_back(sample);
+            let alpha = self.smoothing.get();
+            estimate.ewma_millis += alpha * (sample - estimate.ewma_millis);
+        }
+        while samples.len() > self.window.get() {
+            samples.pop_front();
+        }
+
+        let mut sorted: Vec<f64> = samples.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let middle = median(&sorted);
+        let mut deviations: Vec<f64> = sorted.iter().map(|value| (value - middle).abs()).collect();
+        deviations.sort_by(f64::total_cmp);
+        estimate.median_millis = middle;
+        if estimate.ewma_millis.is_nan() {
+            estimate.ewma_millis = middle;
+        }
+        estimate.min_millis = sorted[0];
+        estimate.jitter_millis = median(&deviations) * MAD_SCALE;
+        estimate.samples += 1;
+
+        if let Some(metrics) = &*self.metrics.borrow() {
+            metrics.skew.set(estimate.median_millis);
+            metrics.jitter.set(estimate.jitter_millis);
+        }
+        *latest = Some(estimate.clone());
+        estimate
+    }
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
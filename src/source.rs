@@ -1,13 +1,245 @@
-use std::cell::RefCell;
+use crate::backpressure;
+use crate::metrics::MetricsRegistry;
+use crate::profile;
+use crate::rt::{self, Instant};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::mem;
 use std::ops::Deref;
-use std::rc::Rc;
+use std::ptr;
+use std::rc::{Rc, Weak};
 use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
 
 type Callback<T> = Rc<dyn Fn(&T)>;
 
+// Which items `Stream::tap_sampled` passes to its callback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TapSampling {
+    // The first item and every `n`th after it.
+    OneIn(u64),
+    // Each item independently, with this probability.
+    Probability(f64),
+}
+type Completion = Rc<dyn Fn()>;
+
+thread_local! {
+    // Timed buffers and other timer-driven operators created on this
+    // thread, so `EngineBuilder::build` can report the ones nothing will
+    // ever flush.
+    static TIMED_BUFFERS: RefCell<Vec<TrackedBuffer>> = const { RefCell::new(Vec::new()) };
+    // Engine builders (and the engines built from them) alive on this
+    // thread, and the builder running `add_pipeline`, which owns the
+    // buffers and sinks created meanwhile.
+    // Each is marked once built.
+    static BUILDERS: RefCell<Vec<(u64, bool)>> = const { RefCell::new(Vec::new()) };
+    static PIPELINE_BUILDER: Cell<Option<u64>> = const { Cell::new(None) };
+    static NEXT_BUILDER: Cell<u64> = const { Cell::new(1) };
+    // `subscribe_once` labels per upstream, with how often each was attached.
+    static SUBSCRIPTIONS: RefCell<Vec<Subscription>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Subscription {
+    upstream: Weak<dyn Any>,
+    label: String,
+    attached: u32,
+    // the builder it was made for, see `BuilderScope`
+    owner: Option<u64>,
+}
+
+// Labels of `builders` attached more than once to a live stream, with the
+// count, forgetting their subscriptions; see `owned_by`.
+pub(crate) fn take_duplicate_subscriptions(builders: &[u64]) -> Vec<(String, u32)> {
+    SUBSCRIPTIONS.with(|subscriptions| {
+        let (taken, kept) = mem::take(&mut *subscriptions.borrow_mut())
+            .into_iter()
+            .filter(|subscription| subscription.upstream.strong_count() > 0)
+            .partition::<Vec<_>, _>(|subscription| owned_by(subscription.owner, builders));
+        *subscriptions.borrow_mut() = kept;
+        taken
+            .into_iter()
+            .filter(|subscription| subscription.attached > 1)
+            .map(|subscription| (subscription.label, subscription.attached))
+            .collect()
+    })
+}
+
+struct TrackedBuffer {
+    // e.g. "timed buffer" or "debounce"
+    kind: &'static str,
+    period: Duration,
+    registered: Weak<Cell<bool>>,
+    // the builder it was created for, if that is clear
+    owner: Option<u64>,
+}
+
+impl TrackedBuffer {
+    fn unregistered(&self) -> bool {
+        self.registered
+            .upgrade()
+            .is_some_and(|registered| !registered.get())
+    }
+
+    fn belongs_to(&self, builders: &[u64]) -> bool {
+        owned_by(self.owner, builders)
+    }
+}
+
+// Whether something created for `owner` is `builders`': unowned things are
+// unless another builder or engine could still claim them.
+pub(crate) fn owned_by(owner: Option<u64>, builders: &[u64]) -> bool {
+    match owner {
+        Some(owner) => builders.contains(&owner),
+        None => BUILDERS.with(|live| live.borrow().iter().all(|(id, _)| builders.contains(id))),
+    }
+}
+
+// An engine builder's claim on the timed buffers and sinks created for it:
+// those created by its pipelines, or while it is the only builder on the
+// thread not built yet. The engine it builds keeps it.
+pub(crate) struct BuilderScope {
+    id: u64,
+}
+
+impl BuilderScope {
+    pub(crate) fn new() -> Self {
+        let id = NEXT_BUILDER.with(|next| next.replace(next.get() + 1));
+        BUILDERS.with(|live| live.borrow_mut().push((id, false)));
+        Self { id }
+    }
+
+    pub(crate) fn built(&self) {
+        BUILDERS.with(|live| {
+            for (id, built) in live.borrow_mut().iter_mut() {
+                if *id == self.id {
+                    *built = true;
+                }
+            }
+        });
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn building<R>(&self, f: impl FnOnce() -> R) -> R {
+        let outer = PIPELINE_BUILDER.with(|current| current.replace(Some(self.id)));
+        let output = f();
+        PIPELINE_BUILDER.with(|current| current.set(outer));
+        output
+    }
+}
+
+impl Drop for BuilderScope {
+    fn drop(&mut self) {
+        BUILDERS.with(|live| live.borrow_mut().retain(|(id, _)| *id != self.id));
+    }
+}
+
+pub(crate) fn current_builder() -> Option<u64> {
+    PIPELINE_BUILDER.with(Cell::get).or_else(|| {
+        BUILDERS.with(|live| {
+            let live = live.borrow();
+            let mut building = live.iter().filter(|(_, built)| !built);
+            match (building.next(), building.next()) {
+                (Some((only, _)), None) => Some(*only),
+                _ => None,
+            }
+        })
+    })
+}
+
+// Tracks a timer-driven operator until `registered` is set by its
+// `as_timed_emitter()`.
+pub(crate) fn track_timed_emitter(
+    kind: &'static str,
+    period: Duration,
+    registered: &Rc<Cell<bool>>,
+) {
+    TIMED_BUFFERS.with(|buffers| {
+        let mut buffers = buffers.borrow_mut();
+        buffers.retain(|buffer| buffer.registered.strong_count() > 0);
+        buffers.push(TrackedBuffer {
+            kind,
+            period,
+            registered: Rc::downgrade(registered),
+            owner: current_builder(),
+        });
+    });
+}
+
+// Kinds and periods of the live timed buffers and emitters of `builders`
+// never handed to an engine, forgetting them; see
+// `TrackedBuffer::belongs_to`.
+pub(crate) fn take_unregistered_buffers(builders: &[u64]) -> Vec<(&'static str, Duration)> {
+    let periods = unregistered_buffers(builders);
+    TIMED_BUFFERS.with(|buffers| {
+        buffers
+            .borrow_mut()
+            .retain(|buffer| buffer.unregistered() && !buffer.belongs_to(builders))
+    });
+    periods
+}
+
+pub(crate) fn unregistered_buffers(builders: &[u64]) -> Vec<(&'static str, Duration)> {
+    TIMED_BUFFERS.with(|buffers| {
+        buffers
+            .borrow()
+            .iter()
+            .filter(|buffer| buffer.unregistered() && buffer.belongs_to(builders))
+            .map(|buffer| (buffer.kind, buffer.period))
+            .collect()
+    })
+}
+
+// Streams an engine keeps alive, checked for sinks when it is built.
+pub(crate) trait RetainedStream {
+    fn has_sinks(&self) -> bool;
+}
+
+impl<T> RetainedStream for Stream<T> {
+    fn has_sinks(&self) -> bool {
+        !self.callbacks.borrow().is_empty()
+    }
+}
+
+#[derive(Default)]
+struct CompletionState {
+    completed: Cell<bool>,
+    callbacks: RefCell<Vec<Completion>>,
+}
+
+impl CompletionState {
+    fn subscribe(&self, callback: Completion) {
+        if self.completed.get() {
+            callback();
+        } else {
+            self.callbacks.borrow_mut().push(callback);
+        }
+    }
+
+    fn complete(&self) {
+        if self.completed.replace(true) {
+            return;
+        }
+        let callbacks = mem::take(&mut *self.callbacks.borrow_mut());
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
+
 pub struct Source<T> {
     callbacks: Rc<RefCell<Vec<Callback<T>>>>,
+    completion: Rc<CompletionState>,
+    emitted: Cell<u64>,
+    emitting: Cell<bool>,
+    // items emitted from within a callback, delivered once the current one
+    // has reached every subscriber
+    queued: RefCell<VecDeque<T>>,
 }
 
 impl<T> Default for Source<T> {
@@ -20,25 +252,73 @@ impl<T> Source<T> {
     pub fn new() -> Self {
         Self {
             callbacks: Rc::new(RefCell::new(Vec::new())),
+            completion: Rc::new(CompletionState::default()),
+            emitted: Cell::new(0),
+            emitting: Cell::new(false),
+            queued: RefCell::new(VecDeque::new()),
         }
     }
 
+    // Items emitted after `complete` are dropped. Every subscriber sees
+    // items in the order they were emitted, subscribers in the order they
+    // were attached, even when a callback emits on this source again: such
+    // items wait until the current one has reached every subscriber.
     pub fn emit(&self, item: T) {
-        let callbacks = self.callbacks.borrow();
-        for callback in callbacks.iter() {
-            callback(&item);
+        if self.completion.completed.get() {
+            return;
+        }
+        if self.emitting.get() {
+            self.queued.borrow_mut().push_back(item);
+            return;
         }
+        self.emitting.set(true);
+        let mut next = Some(item);
+        while let Some(item) = next {
+            if self.completion.completed.get() {
+                self.queued.borrow_mut().clear();
+                break;
+            }
+            self.emitted.set(self.emitted.get() + 1);
+            let callbacks = self.callbacks.borrow();
+            for callback in callbacks.iter() {
+                callback(&item);
+            }
+            drop(callbacks);
+            next = self.queued.borrow_mut().pop_front();
+        }
+        self.emitting.set(false);
+    }
+
+    // Signals end-of-stream; operators propagate it and flush any buffered
+    // state before completing their own outputs.
+    pub fn complete(&self) {
+        self.completion.complete();
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.completed.get()
+    }
+
+    pub fn emitted(&self) -> u64 {
+        self.emitted.get()
+    }
+
+    // Callbacks attached directly to this source.
+    pub fn subscribers(&self) -> usize {
+        self.callbacks.borrow().len()
     }
 
     pub fn to_stream(&self) -> Stream<T> {
         Stream {
             callbacks: self.callbacks.clone(),
+            completion: self.completion.clone(),
         }
     }
 }
 
 pub struct Stream<T> {
     callbacks: Rc<RefCell<Vec<Callback<T>>>>,
+    completion: Rc<CompletionState>,
 }
 
 impl<T> Stream<T> {
@@ -57,9 +337,7 @@ impl<T> Stream<T> {
             }
         }));
 
-        Stream {
-            callbacks: downstream,
-        }
+        self.chain(downstream)
     }
 
     pub fn filter<F>(&self, predicate: F) -> Stream<T>
@@ -78,9 +356,7 @@ impl<T> Stream<T> {
             }
         }));
 
-        Stream {
-            callbacks: downstream,
-        }
+        self.chain(downstream)
     }
 
     pub fn filter_map<U, F>(&self, f: F) -> Stream<U>
@@ -99,9 +375,60 @@ impl<T> Stream<T> {
             }
         }));
 
-        Stream {
-            callbacks: downstream,
-        }
+        self.chain(downstream)
+    }
+
+    // Results of `f` on the first stream, its errors on the second, e.g. a
+    // parse whose failures go to a dead-letter sink rather than being
+    // dropped inside the closure.
+    pub fn try_map<U, E, F>(&self, f: F) -> (Stream<U>, Stream<E>)
+    where
+        U: 'static,
+        E: 'static,
+        F: Fn(&T) -> Result<U, E> + 'static,
+    {
+        self.try_filter_map(move |item: &T| f(item).map(Some))
+    }
+
+    // Items `f` accepts, and the errors it returns for those it could not
+    // judge, e.g. failed validations.
+    pub fn try_filter<E, F>(&self, f: F) -> (Stream<T>, Stream<E>)
+    where
+        T: Clone + 'static,
+        E: 'static,
+        F: Fn(&T) -> Result<bool, E> + 'static,
+    {
+        self.try_filter_map(move |item: &T| Ok(f(item)?.then(|| item.clone())))
+    }
+
+    pub fn try_filter_map<U, E, F>(&self, f: F) -> (Stream<U>, Stream<E>)
+    where
+        U: 'static,
+        E: 'static,
+        F: Fn(&T) -> Result<Option<U>, E> + 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<U>>::new()));
+        let downstream_clone = downstream.clone();
+        let errors = Rc::new(RefCell::new(Vec::<Callback<E>>::new()));
+        let errors_clone = errors.clone();
+
+        self.callbacks
+            .borrow_mut()
+            .push(Rc::new(move |item: &T| match f(item) {
+                Ok(Some(mapped)) => {
+                    for callback in downstream_clone.borrow().iter() {
+                        callback(&mapped);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    for callback in errors_clone.borrow().iter() {
+                        callback(&err);
+                    }
+                }
+            }));
+
+        (self.chain(downstream), self.chain(errors))
     }
 
     pub fn timed_buffer(&self, period: Duration) -> TimedBuffer<T>
@@ -109,17 +436,18 @@ impl<T> Stream<T> {
         T: Clone + 'static,
     {
         let callbacks: Rc<RefCell<Vec<Callback<Vec<T>>>>> = Rc::new(RefCell::new(Vec::new()));
-        let stream = Stream {
-            callbacks: callbacks.clone(),
-        };
-        let buffer = Rc::new(RefCell::new(Vec::<T>::new()));
-        let buffer_clone = buffer.clone();
+        let state = Rc::new(BufferState::new(callbacks.clone()));
+        let state_clone = state.clone();
+        let state_remaining = state.clone();
+        // emit whatever is left rather than waiting for the next tick
+        let stream = self.chain_with(callbacks, move || state_remaining.flush());
 
         self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
-            buffer_clone.borrow_mut().push(item.clone());
+            state_clone.push(item);
         }));
 
-        TimedBuffer::new(period, buffer, callbacks, stream)
+        track_timed_emitter("timed buffer", period, &state.registered);
+        TimedBuffer::new(period, state, stream)
     }
 
     pub fn accumulate<State, F>(&self, initial_state: State, f: F) -> Stream<State>
@@ -141,9 +469,119 @@ impl<T> Stream<T> {
             }
         }));
 
-        Stream {
-            callbacks: downstream,
-        }
+        self.chain(downstream)
+    }
+
+    pub fn try_accumulate<State, E, F>(
+        &self,
+        initial_state: State,
+        f: F,
+    ) -> (Stream<State>, Stream<E>)
+    where
+        State: Clone + 'static,
+        E: 'static,
+        F: Fn(State, &T) -> Result<State, E> + 'static,
+    {
+        let reset_state = initial_state.clone();
+        self.try_accumulate_with(initial_state, f, move |_, _| reset_state.clone())
+    }
+
+    // On error the state is replaced by `recover(previous_state, &error)` and
+    // the error is published on the second stream.
+    pub fn try_accumulate_with<State, E, F, R>(
+        &self,
+        initial_state: State,
+        f: F,
+        recover: R,
+    ) -> (Stream<State>, Stream<E>)
+    where
+        State: Clone + 'static,
+        E: 'static,
+        F: Fn(State, &T) -> Result<State, E> + 'static,
+        R: Fn(State, &E) -> State + 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<State>>::new()));
+        let downstream_clone = downstream.clone();
+        let errors = Rc::new(RefCell::new(Vec::<Callback<E>>::new()));
+        let errors_clone = errors.clone();
+        let state_cell = Rc::new(RefCell::new(initial_state));
+        let state_cell_clone = state_cell.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let current = state_cell_clone.borrow().clone();
+            match f(current.clone(), item) {
+                Ok(next) => {
+                    *state_cell_clone.borrow_mut() = next.clone();
+                    for callback in downstream_clone.borrow().iter() {
+                        callback(&next);
+                    }
+                }
+                Err(err) => {
+                    *state_cell_clone.borrow_mut() = recover(current, &err);
+                    for callback in errors_clone.borrow().iter() {
+                        callback(&err);
+                    }
+                }
+            }
+        }));
+
+        (self.chain(downstream), self.chain(errors))
+    }
+
+    // Like `accumulate`, but the state is updated in place and only what `f`
+    // returns is emitted, so the state never has to be cloned per item.
+    pub fn scan_map<State, U, F>(&self, initial_state: State, f: F) -> Stream<U>
+    where
+        State: 'static,
+        U: 'static,
+        F: Fn(&mut State, &T) -> Option<U> + 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<U>>::new()));
+        let downstream_clone = downstream.clone();
+        let state_cell = RefCell::new(initial_state);
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let output = f(&mut state_cell.borrow_mut(), item);
+            if let Some(output) = output {
+                for callback in downstream_clone.borrow().iter() {
+                    callback(&output);
+                }
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Streams are push-based with no subscription step, so the seed values are
+    // emitted immediately before the first upstream item.
+    pub fn start_with<I>(&self, values: I) -> Stream<T>
+    where
+        T: 'static,
+        I: IntoIterator<Item = T>,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+        let pending = RefCell::new(Some(values.into_iter().collect::<Vec<T>>()));
+        let emit_seeds = Rc::new(move || {
+            let seeds = pending.borrow_mut().take();
+            for seed in seeds.iter().flatten() {
+                for callback in downstream_clone.borrow().iter() {
+                    callback(seed);
+                }
+            }
+        });
+        let emit_seeds_clone = emit_seeds.clone();
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            emit_seeds_clone();
+            for callback in downstream_clone.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        // an upstream that completes without emitting still delivers the seeds
+        self.chain_with(downstream, move || emit_seeds())
     }
 
     pub fn tap<F>(&self, f: F) -> Stream<T>
@@ -162,9 +600,161 @@ impl<T> Stream<T> {
             }
         }));
 
-        Stream {
-            callbacks: downstream,
-        }
+        self.chain(downstream)
+    }
+
+    // Like `tap`, but only calls `f` for a sample of the items.
+    pub fn tap_sampled<F>(&self, sampling: TapSampling, f: F) -> Stream<T>
+    where
+        T: 'static,
+        F: Fn(&T) + 'static,
+    {
+        let seen = Cell::new(0u64);
+        // xorshift64; seeded per stream so parallel taps don't sample in step
+        let state = Cell::new(Rc::as_ptr(&self.callbacks) as u64 | 1);
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let sampled = match sampling {
+                TapSampling::OneIn(n) => {
+                    let count = seen.get();
+                    seen.set(count + 1);
+                    count.is_multiple_of(n.max(1))
+                }
+                TapSampling::Probability(p) => {
+                    let mut x = state.get();
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    state.set(x);
+                    ((x >> 11) as f64 / (1u64 << 53) as f64) < p
+                }
+            };
+            if sampled {
+                f(item);
+            }
+            for callback in downstream_clone.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Labels this point in the pipeline. While an engine with a callback
+    // budget or metrics is running, the time spent dispatching each item to
+    // everything downstream of it is recorded under `name`.
+    pub fn named(&self, name: impl Into<String>) -> Stream<T>
+    where
+        T: 'static,
+    {
+        let name = name.into();
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            profile::measure(&name, || {
+                for callback in downstream_clone.borrow().iter() {
+                    callback(item);
+                }
+            });
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Publishes `streamz_stream_items_total`, `streamz_stream_items_per_second`
+    // and `streamz_stream_last_value` (from `value`) under `name`, passing
+    // items through unchanged. The rate is refreshed about once a second
+    // while items arrive.
+    pub fn inspect_metrics<F>(&self, metrics: &MetricsRegistry, name: &str, value: F) -> Stream<T>
+    where
+        T: 'static,
+        F: Fn(&T) -> f64 + 'static,
+    {
+        let labels = [("stream", name)];
+        let items = metrics.counter(
+            "streamz_stream_items_total",
+            "Items passed through an inspected stream.",
+            &labels,
+        );
+        let rate = metrics.gauge(
+            "streamz_stream_items_per_second",
+            "Item rate of an inspected stream.",
+            &labels,
+        );
+        let last = metrics.gauge(
+            "streamz_stream_last_value",
+            "Value of the latest item of an inspected stream.",
+            &labels,
+        );
+        let window = Cell::new((Instant::now(), 0u64));
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            items.inc();
+            last.set(value(item));
+            let (started, count) = window.get();
+            let elapsed = started.elapsed();
+            if elapsed >= RATE_WINDOW {
+                rate.set((count + 1) as f64 / elapsed.as_secs_f64());
+                window.set((Instant::now(), 0));
+            } else {
+                window.set((started, count + 1));
+            }
+            for callback in downstream_clone.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Drops items more than `max_age` old by `timestamp_fn` (milliseconds since
+    // the unix epoch, e.g. the exchange time), so a burst costs stale updates
+    // rather than a growing lag. Drops are reported as backpressure under
+    // `name` when the next fresh item passes. Not for replays, where every
+    // item is old.
+    pub fn shed_if_older_than<F>(
+        &self,
+        name: impl Into<String>,
+        max_age: Duration,
+        timestamp_fn: F,
+    ) -> Stream<T>
+    where
+        T: 'static,
+        F: Fn(&T) -> u64 + 'static,
+    {
+        let name = Rc::new(name.into());
+        let name_remaining = name.clone();
+        let max_age = max_age.as_millis() as u64;
+        let shed = Rc::new(Cell::new(0u64));
+        let shed_remaining = shed.clone();
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            if rt::now_millis().saturating_sub(timestamp_fn(item)) > max_age {
+                shed.set(shed.get() + 1);
+                return;
+            }
+            let dropped = shed.replace(0);
+            if dropped > 0 {
+                backpressure::report(&name, 0, dropped);
+            }
+            for callback in downstream_clone.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        self.chain_with(downstream, move || {
+            let dropped = shed_remaining.replace(0);
+            if dropped > 0 {
+                backpressure::report(&name_remaining, 0, dropped);
+            }
+        })
     }
 
     pub fn zip<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
@@ -202,11 +792,368 @@ impl<T> Stream<T> {
             *right_state_right.borrow_mut() = Some(item.clone());
         }));
 
+        self.chain(downstream)
+    }
+
+    // Emits the latest of both sides whenever either updates, once both have
+    // emitted; unlike `zip`, updates from `other` emit too. Completes once
+    // both have completed.
+    pub fn combine_latest<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
+    where
+        T: Clone + 'static,
+        U: Clone + 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<(T, U)>>::new()));
+        let completion = Rc::new(CompletionState::default());
+        let left_state = Rc::new(RefCell::new(None::<T>));
+        let right_state = Rc::new(RefCell::new(None::<U>));
+
+        let emit = {
+            let downstream = downstream.clone();
+            let left_state = left_state.clone();
+            let right_state = right_state.clone();
+            Rc::new(move || {
+                let pair = match (&*left_state.borrow(), &*right_state.borrow()) {
+                    (Some(left), Some(right)) => (left.clone(), right.clone()),
+                    _ => return,
+                };
+                let callbacks = downstream.borrow();
+                for callback in callbacks.iter() {
+                    callback(&pair);
+                }
+            })
+        };
+
+        let emit_left = emit.clone();
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            *left_state.borrow_mut() = Some(item.clone());
+            emit_left();
+        }));
+
+        other.callbacks.borrow_mut().push(Rc::new(move |item: &U| {
+            *right_state.borrow_mut() = Some(item.clone());
+            emit();
+        }));
+
+        let remaining = Rc::new(Cell::new(2));
+        for upstream in [&self.completion, &other.completion] {
+            let completion = completion.clone();
+            let remaining = remaining.clone();
+            upstream.subscribe(Rc::new(move || {
+                remaining.set(remaining.get() - 1);
+                if remaining.get() == 0 {
+                    completion.complete();
+                }
+            }));
+        }
+
         Stream {
             callbacks: downstream,
+            completion,
         }
     }
 
+    // Items from both streams, in order of arrival; completes when both have.
+    pub fn merge(&self, other: &Stream<T>) -> Stream<T>
+    where
+        T: 'static,
+    {
+        Stream::merged(&[self.clone(), other.clone()])
+    }
+
+    pub(crate) fn merged(streams: &[Stream<T>]) -> Stream<T>
+    where
+        T: 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let completion = Rc::new(CompletionState::default());
+        let remaining = Rc::new(Cell::new(streams.len()));
+        if streams.is_empty() {
+            completion.complete();
+        }
+
+        for upstream in streams {
+            let downstream = downstream.clone();
+            upstream
+                .callbacks
+                .borrow_mut()
+                .push(Rc::new(move |item: &T| {
+                    for callback in downstream.borrow().iter() {
+                        callback(item);
+                    }
+                }));
+
+            let completion = completion.clone();
+            let remaining = remaining.clone();
+            upstream.completion.subscribe(Rc::new(move || {
+                remaining.set(remaining.get() - 1);
+                if remaining.get() == 0 {
+                    completion.complete();
+                }
+            }));
+        }
+
+        Stream {
+            callbacks: downstream,
+            completion,
+        }
+    }
+
+    // Forwards `self` until it completes, then `other`. Items `other` emits
+    // before that are dropped; the result completes once both have completed.
+    pub fn concat(&self, other: &Stream<T>) -> Stream<T>
+    where
+        T: 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_first = downstream.clone();
+        let downstream_second = downstream.clone();
+        let first_completion = self.completion.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            for callback in downstream_first.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        other.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            if first_completion.completed.get() {
+                for callback in downstream_second.borrow().iter() {
+                    callback(item);
+                }
+            }
+        }));
+
+        let completion = Rc::new(CompletionState::default());
+        for upstream in [&self.completion, &other.completion] {
+            let first = self.completion.clone();
+            let second = other.completion.clone();
+            let completion = completion.clone();
+            upstream.subscribe(Rc::new(move || {
+                if first.completed.get() && second.completed.get() {
+                    completion.complete();
+                }
+            }));
+        }
+
+        Stream {
+            callbacks: downstream,
+            completion,
+        }
+    }
+
+    // Emits the latest item from `self` every time `sampler` fires, e.g. to
+    // snapshot book state on each trade.
+    pub fn sample_with<U>(&self, sampler: &Stream<U>) -> Stream<T>
+    where
+        T: Clone + 'static,
+        U: 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+        let latest = Rc::new(RefCell::new(None::<T>));
+        let latest_clone = latest.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            *latest_clone.borrow_mut() = Some(item.clone());
+        }));
+
+        sampler.callbacks.borrow_mut().push(Rc::new(move |_: &U| {
+            let sampled = latest.borrow().clone();
+            if let Some(sampled) = sampled {
+                for callback in downstream_clone.borrow().iter() {
+                    callback(&sampled);
+                }
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Holds items back until `signal` first fires (e.g. once a depth snapshot
+    // has been applied), then releases them in order and passes later items
+    // straight through. Items still held when `self` completes are dropped.
+    pub fn buffer_until<U>(&self, signal: &Stream<U>) -> Stream<T>
+    where
+        T: Clone + 'static,
+        U: 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let downstream_clone = downstream.clone();
+        let held = Rc::new(RefCell::new(VecDeque::<T>::new()));
+        let held_clone = held.clone();
+        let held_dropped = held.clone();
+        let released = Rc::new(Cell::new(false));
+        let released_clone = released.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            if !released_clone.get() {
+                held_clone.borrow_mut().push_back(item.clone());
+                return;
+            }
+            for callback in downstream_clone.borrow().iter() {
+                callback(item);
+            }
+        }));
+
+        let downstream_clone = downstream.clone();
+        signal.callbacks.borrow_mut().push(Rc::new(move |_: &U| {
+            if released.get() {
+                return;
+            }
+            // items arriving while the held ones are released queue behind them
+            loop {
+                let item = held.borrow_mut().pop_front();
+                let Some(item) = item else { break };
+                for callback in downstream_clone.borrow().iter() {
+                    callback(&item);
+                }
+            }
+            released.set(true);
+        }));
+
+        self.chain_with(downstream, move || held_dropped.borrow_mut().clear())
+    }
+
+    // Collects items into batches, emitting one whenever
+    // `is_boundary(previous, current)` holds (e.g. the minute, trading
+    // session or key changed); `current` starts the next batch. The last
+    // batch is emitted when `self` completes.
+    pub fn chunk_by<F>(&self, is_boundary: F) -> Stream<Vec<T>>
+    where
+        T: Clone + 'static,
+        F: Fn(&T, &T) -> bool + 'static,
+    {
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<Vec<T>>>::new()));
+        let downstream_clone = downstream.clone();
+        let downstream_remaining = downstream.clone();
+        let chunk = Rc::new(RefCell::new(Vec::<T>::new()));
+        let chunk_clone = chunk.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let boundary = chunk_clone
+                .borrow()
+                .last()
+                .is_some_and(|previous| is_boundary(previous, item));
+            if boundary {
+                let complete = mem::take(&mut *chunk_clone.borrow_mut());
+                for callback in downstream_clone.borrow().iter() {
+                    callback(&complete);
+                }
+            }
+            chunk_clone.borrow_mut().push(item.clone());
+        }));
+
+        self.chain_with(downstream, move || {
+            let remaining = mem::take(&mut *chunk.borrow_mut());
+            if !remaining.is_empty() {
+                for callback in downstream_remaining.borrow().iter() {
+                    callback(&remaining);
+                }
+            }
+        })
+    }
+
+    // Batches of `n` items, back to back; a shorter last batch is emitted
+    // when `self` completes.
+    pub fn window_count(&self, n: usize) -> Stream<Vec<T>>
+    where
+        T: Clone + 'static,
+    {
+        let n = n.max(1);
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<Vec<T>>>::new()));
+        let downstream_clone = downstream.clone();
+        let downstream_remaining = downstream.clone();
+        let window = Rc::new(RefCell::new(Vec::<T>::with_capacity(n)));
+        let window_clone = window.clone();
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let full = {
+                let mut window = window_clone.borrow_mut();
+                window.push(item.clone());
+                if window.len() < n {
+                    return;
+                }
+                mem::replace(&mut *window, Vec::with_capacity(n))
+            };
+            for callback in downstream_clone.borrow().iter() {
+                callback(&full);
+            }
+        }));
+
+        self.chain_with(downstream, move || {
+            let remaining = mem::take(&mut *window.borrow_mut());
+            if !remaining.is_empty() {
+                for callback in downstream_remaining.borrow().iter() {
+                    callback(&remaining);
+                }
+            }
+        })
+    }
+
+    // The last `n` items, oldest first, on every item once `n` have arrived;
+    // e.g. rolling statistics over the last N trades.
+    pub fn sliding_window(&self, n: usize) -> Stream<Vec<T>>
+    where
+        T: Clone + 'static,
+    {
+        let n = n.max(1);
+        let downstream = Rc::new(RefCell::new(Vec::<Callback<Vec<T>>>::new()));
+        let downstream_clone = downstream.clone();
+        let window = RefCell::new(VecDeque::<T>::with_capacity(n));
+
+        self.callbacks.borrow_mut().push(Rc::new(move |item: &T| {
+            let current = {
+                let mut window = window.borrow_mut();
+                if window.len() == n {
+                    window.pop_front();
+                }
+                window.push_back(item.clone());
+                if window.len() < n {
+                    return;
+                }
+                window.iter().cloned().collect::<Vec<T>>()
+            };
+            for callback in downstream_clone.borrow().iter() {
+                callback(&current);
+            }
+        }));
+
+        self.chain(downstream)
+    }
+
+    // Marks what follows as the subscriber `label`, e.g. the name of the setup
+    // function wiring it. Attaching the same label to the same stream again,
+    // say by calling that function twice, fails `EngineBuilder::build` rather
+    // than processing every item twice.
+    pub fn subscribe_once(&self, label: impl Into<String>) -> Stream<T>
+    where
+        T: 'static,
+    {
+        let label = label.into();
+        let upstream: Rc<dyn Any> = self.callbacks.clone();
+        let owner = current_builder();
+        SUBSCRIPTIONS.with(|subscriptions| {
+            let mut subscriptions = subscriptions.borrow_mut();
+            subscriptions.retain(|subscription| subscription.upstream.strong_count() > 0);
+            let existing = subscriptions.iter_mut().find(|subscription| {
+                subscription.label == label
+                    && subscription.owner == owner
+                    && ptr::addr_eq(subscription.upstream.as_ptr(), Rc::as_ptr(&upstream))
+            });
+            match existing {
+                Some(subscription) => subscription.attached += 1,
+                None => subscriptions.push(Subscription {
+                    upstream: Rc::downgrade(&upstream),
+                    label,
+                    attached: 1,
+                    owner,
+                }),
+            }
+        });
+        self.clone()
+    }
+
     pub fn sink<F>(&self, f: F)
     where
         F: Fn(&T) + 'static,
@@ -215,19 +1162,125 @@ impl<T> Stream<T> {
             .borrow_mut()
             .push(Rc::new(move |item: &T| f(item)));
     }
+
+    pub fn on_complete<F>(&self, f: F)
+    where
+        F: Fn() + 'static,
+    {
+        self.completion.subscribe(Rc::new(f));
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completion.completed.get()
+    }
+
+    pub fn to_broadcast(&self, capacity: usize) -> broadcast::Sender<T>
+    where
+        T: Clone + 'static,
+    {
+        let (sender, _) = broadcast::channel(capacity);
+        self.sink_to(sender.clone());
+        sender
+    }
+
+    pub fn to_watch(&self) -> watch::Receiver<Option<T>>
+    where
+        T: Clone + 'static,
+    {
+        let (sender, receiver) = watch::channel(None);
+        self.sink_to(sender);
+        receiver
+    }
+
+    fn chain<U>(&self, callbacks: Rc<RefCell<Vec<Callback<U>>>>) -> Stream<U> {
+        self.chain_with(callbacks, || {})
+    }
+
+    // Wraps an operator's output so it completes when `self` does, running
+    // `before_complete` first.
+    fn chain_with<U, F>(
+        &self,
+        callbacks: Rc<RefCell<Vec<Callback<U>>>>,
+        before_complete: F,
+    ) -> Stream<U>
+    where
+        F: Fn() + 'static,
+    {
+        let completion = Rc::new(CompletionState::default());
+        let completion_clone = completion.clone();
+        self.completion.subscribe(Rc::new(move || {
+            before_complete();
+            completion_clone.complete();
+        }));
+        Stream {
+            callbacks,
+            completion,
+        }
+    }
 }
 
 impl<T> Clone for Stream<T> {
     fn clone(&self) -> Self {
         Stream {
             callbacks: self.callbacks.clone(),
+            completion: self.completion.clone(),
         }
     }
 }
 
+impl<T, E> Stream<Result<T, E>>
+where
+    T: Clone + 'static,
+    E: Clone + 'static,
+{
+    // Successes on the first stream, errors on the second, e.g. decoded
+    // messages onwards and decode failures to a dead-letter sink.
+    pub fn split_result(&self) -> (Stream<T>, Stream<E>) {
+        let oks = Rc::new(RefCell::new(Vec::<Callback<T>>::new()));
+        let oks_clone = oks.clone();
+        let errors = Rc::new(RefCell::new(Vec::<Callback<E>>::new()));
+        let errors_clone = errors.clone();
+
+        self.callbacks
+            .borrow_mut()
+            .push(Rc::new(move |item: &Result<T, E>| match item {
+                Ok(value) => {
+                    for callback in oks_clone.borrow().iter() {
+                        callback(value);
+                    }
+                }
+                Err(err) => {
+                    for callback in errors_clone.borrow().iter() {
+                        callback(err);
+                    }
+                }
+            }));
+
+        (self.chain(oks), self.chain(errors))
+    }
+
+    pub fn ok(&self) -> Stream<T> {
+        self.filter_map(|item: &Result<T, E>| item.as_ref().ok().cloned())
+    }
+
+    pub fn err(&self) -> Stream<E> {
+        self.filter_map(|item: &Result<T, E>| item.as_ref().err().cloned())
+    }
+}
+
 pub trait TimedEmitter: 'static {
     fn period(&self) -> Duration;
     fn flush(&self);
+
+    // Emitters due in the same tick flush highest priority first.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    // Label for per-emitter flush timings.
+    fn name(&self) -> String {
+        "timed_emitter".to_string()
+    }
 }
 
 pub struct TimedBuffer<T> {
@@ -236,31 +1289,118 @@ pub struct TimedBuffer<T> {
 
 struct TimedBufferInner<T> {
     period: Duration,
-    buffer: Rc<RefCell<Vec<T>>>,
-    callbacks: Rc<RefCell<Vec<Callback<Vec<T>>>>>,
+    name: RefCell<String>,
+    priority: Cell<i32>,
+    state: Rc<BufferState<T>>,
     stream: Stream<Vec<T>>,
 }
 
+type SizeFn<T> = Box<dyn Fn(&T) -> usize>;
+
+// Items waiting for the next flush, plus the size limits that flush early.
+struct BufferState<T> {
+    items: RefCell<Vec<T>>,
+    bytes: Cell<usize>,
+    max_items: Cell<Option<usize>>,
+    max_bytes: RefCell<Option<(usize, SizeFn<T>)>>,
+    callbacks: Rc<RefCell<Vec<Callback<Vec<T>>>>>,
+    // set once something will drive the flushes
+    registered: Rc<Cell<bool>>,
+}
+
+impl<T> BufferState<T>
+where
+    T: Clone,
+{
+    fn new(callbacks: Rc<RefCell<Vec<Callback<Vec<T>>>>>) -> Self {
+        Self {
+            items: RefCell::new(Vec::new()),
+            bytes: Cell::new(0),
+            max_items: Cell::new(None),
+            max_bytes: RefCell::new(None),
+            callbacks,
+            registered: Rc::new(Cell::new(false)),
+        }
+    }
+
+    fn push(&self, item: &T) {
+        let len = {
+            let mut items = self.items.borrow_mut();
+            items.push(item.clone());
+            items.len()
+        };
+        let mut full = self.max_items.get().is_some_and(|max| len >= max);
+        if let Some((max, size_fn)) = &*self.max_bytes.borrow() {
+            self.bytes.set(self.bytes.get() + size_fn(item));
+            full |= self.bytes.get() >= *max;
+        }
+        if full {
+            self.flush();
+        }
+    }
+
+    fn flush(&self) {
+        let chunk = {
+            let mut items = self.items.borrow_mut();
+            if items.is_empty() {
+                return;
+            }
+            mem::take(&mut *items)
+        };
+        self.bytes.set(0);
+
+        let callbacks = self.callbacks.borrow();
+        for callback in callbacks.iter() {
+            callback(&chunk);
+        }
+    }
+}
+
 impl<T> TimedBuffer<T>
 where
     T: Clone + 'static,
 {
-    fn new(
-        period: Duration,
-        buffer: Rc<RefCell<Vec<T>>>,
-        callbacks: Rc<RefCell<Vec<Callback<Vec<T>>>>>,
-        stream: Stream<Vec<T>>,
-    ) -> Self {
+    fn new(period: Duration, state: Rc<BufferState<T>>, stream: Stream<Vec<T>>) -> Self {
         Self {
             inner: Rc::new(TimedBufferInner {
                 period,
-                buffer,
-                callbacks,
+                name: RefCell::new("timed_buffer".to_string()),
+                priority: Cell::new(0),
+                state,
                 stream,
             }),
         }
     }
 
+    // Also flushes as soon as `max_items` are buffered.
+    pub fn with_max_items(self, max_items: usize) -> Self {
+        self.inner.state.max_items.set(Some(max_items.max(1)));
+        self
+    }
+
+    // Also flushes once the buffered items add up to `max_bytes`, as
+    // estimated by `size_fn`.
+    pub fn with_max_bytes<F>(self, max_bytes: usize, size_fn: F) -> Self
+    where
+        F: Fn(&T) -> usize + 'static,
+    {
+        *self.inner.state.max_bytes.borrow_mut() = Some((max_bytes, Box::new(size_fn)));
+        self
+    }
+
+    // Flushes before lower-priority emitters due in the same tick, e.g. a
+    // book conflation buffer ahead of a bulk file writer. The default is 0.
+    pub fn with_priority(self, priority: i32) -> Self {
+        self.inner.priority.set(priority);
+        self
+    }
+
+    // Label for the engine's per-emitter flush timings.
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        *self.inner.name.borrow_mut() = name.into();
+        self
+    }
+
     pub fn stream(&self) -> Stream<Vec<T>> {
         self.inner.stream.clone()
     }
@@ -270,6 +1410,7 @@ where
     }
 
     pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.state.registered.set(true);
         self.inner.clone() as Rc<dyn TimedEmitter>
     }
 }
@@ -305,17 +1446,14 @@ where
     }
 
     fn flush(&self) {
-        let chunk = {
-            let mut buffer = self.buffer.borrow_mut();
-            if buffer.is_empty() {
-                return;
-            }
-            mem::take(&mut *buffer)
-        };
+        self.state.flush();
+    }
 
-        let callbacks = self.callbacks.borrow();
-        for callback in callbacks.iter() {
-            callback(&chunk);
-        }
+    fn priority(&self) -> i32 {
+        self.priority.get()
+    }
+
+    fn name(&self) -> String {
+        self.name.borrow().clone()
     }
 }
@@ -0,0 +1,388 @@
+use crate::backpressure;
+use crate::guard::Guarded;
+use crate::proxy;
+use crate::sources::http_client::{PollingHttpClient, PollingHttpClientConfig};
+use crate::sources::websocket_client::WebSocketClientConfig;
+use crate::{Error, GuardViolation, Result, Source, Stream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+// Price levels of one side of a book as (price, size), best first.
+pub type Levels = Vec<(f64, f64)>;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OrderBook {
+    // venue sequence of the snapshot or last diff applied
+    pub sequence: u64,
+    pub bids: Levels,
+    pub asks: Levels,
+}
+
+impl OrderBook {
+    pub fn best_bid(&self) -> Option<(f64, f64)> {
+        self.bids.first().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<(f64, f64)> {
+        self.asks.first().copied()
+    }
+
+    // Sizes are absolute; a size of zero removes the level.
+    pub fn apply(&mut self, diff: &BookDiff) {
+        for &(price, size) in &diff.bids {
+            update(&mut self.bids, price, size, true);
+        }
+        for &(price, size) in &diff.asks {
+            update(&mut self.asks, price, size, false);
+        }
+        self.sequence = diff.last_sequence;
+    }
+
+    fn sort(&mut self) {
+        self.bids.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.asks.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+}
+
+fn update(levels: &mut Levels, price: f64, size: f64, descending: bool) {
+    let position = levels.binary_search_by(|(level, _)| {
+        let order = level.total_cmp(&price);
+        if descending {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+    match (position, size == 0.0) {
+        (Ok(index), true) => {
+            levels.remove(index);
+        }
+        (Ok(index), false) => levels[index].1 = size,
+        (Err(index), false) => levels.insert(index, (price, size)),
+        (Err(_), true) => {}
+    }
+}
+
+// Changes covering venue sequences `first_sequence..=last_sequence`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BookDiff {
+    pub first_sequence: u64,
+    pub last_sequence: u64,
+    pub bids: Levels,
+    pub asks: Levels,
+}
+
+// How a venue's snapshot responses and websocket diffs are read. Diffs sit
+// on one sequence line: the first diff after a snapshot at `sequence` must
+// cover `sequence + 1`, every later one must start right after the last.
+// Venues that chain updates by a previous id report `previous + 1` as the
+// first sequence.
+pub trait BookVenue: 'static {
+    fn parse_snapshot(&self, body: &str) -> Result<OrderBook>;
+
+    // `None` for messages without a diff: subscription acks, heartbeats.
+    fn parse_diff(&self, message: &str) -> Result<Option<BookDiff>>;
+}
+
+// `GET /api/v3/depth` (or `/fapi/v1/depth`) snapshots and `<symbol>@depth`
+// diffs, raw or from a combined stream. Futures diffs chain by `pu`.
+pub struct BinanceDepth;
+
+impl BookVenue for BinanceDepth {
+    fn parse_snapshot(&self, body: &str) -> Result<OrderBook> {
+        let label = "binance depth snapshot";
+        let value: Value = serde_json::from_str(body).map_err(|err| Error::decode(label, err))?;
+        Ok(OrderBook {
+            sequence: sequence(&value["lastUpdateId"], label)?,
+            bids: levels(&value["bids"], label)?,
+            asks: levels(&value["asks"], label)?,
+        })
+    }
+
+    fn parse_diff(&self, message: &str) -> Result<Option<BookDiff>> {
+        let label = "binance depth update";
+        let value: Value =
+            serde_json::from_str(message).map_err(|err| Error::decode(label, err))?;
+        let data = value.get("data").unwrap_or(&value);
+        if data["e"] != "depthUpdate" {
+            return Ok(None);
+        }
+        let first_sequence = match data.get("pu") {
+            Some(previous) => sequence(previous, label)? + 1,
+            None => sequence(&data["U"], label)?,
+        };
+        Ok(Some(BookDiff {
+            first_sequence,
+            last_sequence: sequence(&data["u"], label)?,
+            bids: levels(&data["b"], label)?,
+            asks: levels(&data["a"], label)?,
+        }))
+    }
+}
+
+// `public/get_order_book` snapshots and `book.{instrument}.{interval}`
+// changes, chained by `prev_change_id`. Snapshots pushed on the websocket
+// are skipped; the REST snapshot is the one synchronised against.
+pub struct DeribitBook;
+
+impl BookVenue for DeribitBook {
+    fn parse_snapshot(&self, body: &str) -> Result<OrderBook> {
+        let label = "deribit book snapshot";
+        let value: Value = serde_json::from_str(body).map_err(|err| Error::decode(label, err))?;
+        let result = &value["result"];
+        Ok(OrderBook {
+            sequence: sequence(&result["change_id"], label)?,
+            bids: levels(&result["bids"], label)?,
+            asks: levels(&result["asks"], label)?,
+        })
+    }
+
+    fn parse_diff(&self, message: &str) -> Result<Option<BookDiff>> {
+        let label = "deribit book change";
+        let value: Value =
+            serde_json::from_str(message).map_err(|err| Error::decode(label, err))?;
+        let data = &value["params"]["data"];
+        if data["type"] != "change" {
+            return Ok(None);
+        }
+        Ok(Some(BookDiff {
+            first_sequence: sequence(&data["prev_change_id"], label)? + 1,
+            last_sequence: sequence(&data["change_id"], label)?,
+            bids: levels(&data["bids"], label)?,
+            asks: levels(&data["asks"], label)?,
+        }))
+    }
+}
+
+fn sequence(value: &Value, label: &str) -> Result<u64> {
+    value
+        .as_u64()
+        .ok_or_else(|| Error::protocol(label, format!("expected a sequence, got {}", value)))
+}
+
+// Prices and sizes as numbers or strings; the last two entries of each level,
+// so Deribit's `["change", price, amount]` reads like `[price, amount]`.
+fn levels(value: &Value, label: &str) -> Result<Levels> {
+    let invalid = || Error::protocol(label, format!("expected price levels, got {}", value));
+    let Some(entries) = value.as_array() else {
+        return Err(invalid());
+    };
+    let number = |value: &Value| match value {
+        Value::String(text) => text.parse::<f64>().ok(),
+        value => value.as_f64(),
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let entry = entry.as_array().filter(|entry| entry.len() >= 2);
+            let (price, size) = entry
+                .map(|entry| (&entry[entry.len() - 2], &entry[entry.len() - 1]))
+                .ok_or_else(invalid)?;
+            Ok((
+                number(price).ok_or_else(invalid)?,
+                number(size).ok_or_else(invalid)?,
+            ))
+        })
+        .collect()
+}
+
+enum Continuity {
+    Stale,
+    Apply,
+    Gap,
+}
+
+fn continuity(sequence: u64, diff: &BookDiff, after_snapshot: bool) -> Continuity {
+    let next = sequence + 1;
+    if diff.last_sequence < next {
+        Continuity::Stale
+    } else if diff.first_sequence == next || (after_snapshot && diff.first_sequence < next) {
+        Continuity::Apply
+    } else {
+        Continuity::Gap
+    }
+}
+
+// Keeps an order book in sync from a REST snapshot and a websocket diff
+// stream. Diffs are held from the moment the websocket is subscribed until
+// the snapshot arrives, the ones it already covers are dropped, and a gap in
+// the sequence fetches a fresh snapshot (at most one per polling period)
+// without emitting the broken book. Held diffs are bounded by the websocket
+// config's `buffer_size`, oldest dropped first. A diff the websocket
+// config's guard rejects is skipped, so the next one resyncs.
+pub struct SyncedBookSource {
+    venue: Box<dyn BookVenue>,
+    snapshots: PollingHttpClient,
+    period: Duration,
+    updates: WebSocketClientConfig,
+    source: Source<OrderBook>,
+    resyncs: Cell<u64>,
+    bytes: Cell<u64>,
+    guarded: Guarded,
+}
+
+impl SyncedBookSource {
+    pub async fn new(
+        venue: impl BookVenue,
+        snapshot: PollingHttpClientConfig,
+        updates: WebSocketClientConfig,
+    ) -> Result<Self> {
+        let period = snapshot.period;
+        Ok(Self {
+            venue: Box::new(venue),
+            snapshots: PollingHttpClient::new(snapshot).await?,
+            period,
+            guarded: Guarded::new(updates.guard),
+            updates,
+            source: Source::new(),
+            resyncs: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn source(&self) -> &Source<OrderBook> {
+        &self.source
+    }
+
+    // Snapshots fetched again after a gap in the diffs.
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs.get()
+    }
+
+    // Snapshot and websocket payload bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get() + self.snapshots.bytes_received()
+    }
+
+    // Diffs and snapshots rejected by the configs' guards, as they happen.
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.guarded
+            .violations()
+            .merge(&self.snapshots.violations())
+    }
+
+    // Diffs and snapshots rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.guarded.rejected() + self.snapshots.rejected()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let label = &self.updates.url;
+        let (ws_stream, _) = proxy::connect_async(
+            label,
+            self.updates.proxy.as_ref(),
+            Some(&self.updates.connect_options),
+        )
+        .await
+        .map_err(|err| Error::connect(label, err))?;
+        let (mut write, mut read) = ws_stream.split();
+        for message in &self.updates.init_messages {
+            write
+                .send(Message::Text(message.clone().into()))
+                .await
+                .map_err(|err| Error::connect(label, err))?;
+        }
+
+        let mut book: Option<OrderBook> = None;
+        let mut after_snapshot = false;
+        let mut held = VecDeque::new();
+        let mut requested = Instant::now();
+        let mut snapshot = Some(Box::pin(self.snapshot(requested)));
+
+        loop {
+            tokio::select! {
+                message = read.next() => {
+                    let text = match message {
+                        None => break,
+                        Some(message) => match message.map_err(|err| Error::connect(label, err))? {
+                            Message::Text(text) => text.to_string(),
+                            Message::Binary(data) => match String::from_utf8(data.to_vec()) {
+                                Ok(text) => text,
+                                Err(_) => continue,
+                            },
+                            Message::Close(_) => break,
+                            _ => continue,
+                        },
+                    };
+                    self.bytes.set(self.bytes.get() + text.len() as u64);
+                    if !self.guarded.check(label, text.as_bytes())? {
+                        continue;
+                    }
+                    let Some(diff) = self.venue.parse_diff(&text)? else {
+                        continue;
+                    };
+                    let Some(current) = book.as_mut() else {
+                        self.hold(&mut held, diff);
+                        continue;
+                    };
+                    match continuity(current.sequence, &diff, after_snapshot) {
+                        Continuity::Stale => {}
+                        Continuity::Apply => {
+                            current.apply(&diff);
+                            after_snapshot = false;
+                            self.source.emit(current.clone());
+                        }
+                        Continuity::Gap => {
+                            book = None;
+                            held.push_back(diff);
+                            self.resyncs.set(self.resyncs.get() + 1);
+                            requested = (requested + self.period).max(Instant::now());
+                            snapshot = Some(Box::pin(self.snapshot(requested)));
+                        }
+                    }
+                }
+                body = async { snapshot.as_mut().expect("guarded").await }, if snapshot.is_some() => {
+                    snapshot = None;
+                    let mut fresh = self.venue.parse_snapshot(&body?)?;
+                    fresh.sort();
+                    after_snapshot = true;
+                    let mut synced = true;
+                    while let Some(diff) = held.pop_front() {
+                        match continuity(fresh.sequence, &diff, after_snapshot) {
+                            Continuity::Stale => {}
+                            Continuity::Apply => {
+                                fresh.apply(&diff);
+                                after_snapshot = false;
+                            }
+                            Continuity::Gap => {
+                                held.push_front(diff);
+                                synced = false;
+                                break;
+                            }
+                        }
+                    }
+                    if synced {
+                        self.source.emit(fresh.clone());
+                        book = Some(fresh);
+                    } else {
+                        self.resyncs.set(self.resyncs.get() + 1);
+                        requested = (requested + self.period).max(Instant::now());
+                        snapshot = Some(Box::pin(self.snapshot(requested)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&self, not_before: Instant) -> Result<String> {
+        sleep_until(not_before).await;
+        self.snapshots
+            .fetch_once()
+            .await?
+            .ok_or_else(|| Error::protocol(&self.updates.url, "book snapshot was not modified"))
+    }
+
+    fn hold(&self, held: &mut VecDeque<BookDiff>, diff: BookDiff) {
+        if held.len() >= self.updates.buffer_size {
+            held.pop_front();
+            backpressure::report(&self.updates.url, held.len(), 1);
+        }
+        held.push_back(diff);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::backpressure;
+use crate::log;
+use crate::{Error, Result, Source};
+use std::cell::RefCell;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+pub struct BroadcastSource<T> {
+    name: String,
+    receiver: RefCell<Option<broadcast::Receiver<T>>>,
+    source: Source<T>,
+}
+
+impl<T> BroadcastSource<T>
+where
+    T: Clone + 'static,
+{
+    pub fn new(receiver: broadcast::Receiver<T>) -> Self {
+        Self {
+            name: "broadcast".to_string(),
+            receiver: RefCell::new(Some(receiver)),
+            source: Source::new(),
+        }
+    }
+
+    // Label used for backpressure events when the receiver lags.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver =
+            self.receiver
+                .borrow_mut()
+                .take()
+                .ok_or_else(|| Error::AlreadyStarted {
+                    source: self.name.clone(),
+                })?;
+
+        loop {
+            match receiver.recv().await {
+                Ok(item) => self.source.emit(item),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn(
+                        "channel",
+                        format_args!("{} source lagged; skipped {} items", self.name, skipped),
+                    );
+                    backpressure::report(&self.name, receiver.len(), skipped);
+                }
+                Err(RecvError::Closed) => {
+                    self.source.complete();
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub struct WatchSource<T> {
+    receiver: RefCell<Option<watch::Receiver<T>>>,
+    source: Source<T>,
+}
+
+impl<T> WatchSource<T>
+where
+    T: Clone + 'static,
+{
+    pub fn new(receiver: watch::Receiver<T>) -> Self {
+        Self {
+            receiver: RefCell::new(Some(receiver)),
+            source: Source::new(),
+        }
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    // Emits the current value first, then every change until the sender is
+    // dropped, at which point the source completes.
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver =
+            self.receiver
+                .borrow_mut()
+                .take()
+                .ok_or_else(|| Error::AlreadyStarted {
+                    source: "watch".to_string(),
+                })?;
+
+        let current = receiver.borrow_and_update().clone();
+        self.source.emit(current);
+        while receiver.changed().await.is_ok() {
+            let current = receiver.borrow_and_update().clone();
+            self.source.emit(current);
+        }
+        self.source.complete();
+        Ok(())
+    }
+}
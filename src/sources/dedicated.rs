@@ -0,0 +1,182 @@
+use crate::backpressure;
+use crate::{EngineSource, Error, Result, Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::oneshot;
+use tokio::task::LocalSet;
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+type Segment<T> = Box<dyn FnOnce(Link<T>) -> Result<()> + Send>;
+
+// What the dedicated thread shares with the engine side.
+struct Link<T> {
+    items: Sender<T>,
+    cancel: CancellationToken,
+    dropped: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+}
+
+// Runs a source, and the pipeline segment built on it, on a thread of its own
+// with a current-thread runtime, so e.g. a feed that spends most of its time
+// parsing JSON does not hold up every other source. Only the segment's output
+// crosses to the engine, over a bounded channel: when the engine falls behind
+// items are dropped and reported as backpressure rather than stalling the
+// thread.
+pub struct DedicatedThreadSource<T> {
+    label: String,
+    capacity: usize,
+    segment: RefCell<Option<Segment<T>>>,
+    cancel: CancellationToken,
+    source: Source<T>,
+    pending_drops: Arc<AtomicU64>,
+    dropped: Cell<u64>,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<T> DedicatedThreadSource<T>
+where
+    T: Clone + Send + 'static,
+{
+    // `factory` runs on the new thread once the engine starts, returning the
+    // source to drive there and the stream whose items come back.
+    pub fn new<S, F, Fut>(label: impl Into<String>, factory: F) -> Self
+    where
+        S: EngineSource,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(S, Stream<T>)>> + 'static,
+    {
+        let label = label.into();
+        let thread_label = label.clone();
+        let segment: Segment<T> = Box::new(move |link| run_segment(&thread_label, factory, link));
+        Self {
+            label,
+            capacity: DEFAULT_CAPACITY,
+            segment: RefCell::new(Some(segment)),
+            cancel: CancellationToken::new(),
+            source: Source::new(),
+            pending_drops: Arc::new(AtomicU64::new(0)),
+            dropped: Cell::new(0),
+            bytes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // Items in flight between the thread and the engine.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    // Items dropped because the engine fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.get() + self.pending_drops.load(Ordering::Relaxed)
+    }
+
+    // As counted by the source on the thread.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let segment = self
+            .segment
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| Error::AlreadyStarted {
+                source: self.label.clone(),
+            })?;
+        let (items, mut receiver) = channel(self.capacity);
+        let (done, result) = oneshot::channel();
+        let link = Link {
+            items,
+            cancel: self.cancel.clone(),
+            dropped: self.pending_drops.clone(),
+            bytes: self.bytes.clone(),
+        };
+        thread::Builder::new()
+            .name(self.label.clone())
+            .spawn(move || {
+                let _ = done.send(segment(link));
+            })
+            .map_err(|err| Error::io(&self.label, err))?;
+
+        while let Some(item) = receiver.recv().await {
+            let dropped = self.pending_drops.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                self.dropped.set(self.dropped.get() + dropped);
+                backpressure::report(&self.label, self.capacity, dropped);
+            }
+            self.source.emit(item);
+        }
+        self.source.complete();
+        match result.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::other(&self.label, "dedicated thread panicked")),
+        }
+    }
+
+    // Stops the thread; its source is closed there.
+    pub fn close(&self) {
+        self.cancel.cancel();
+        self.source.complete();
+    }
+}
+
+impl<T> Drop for DedicatedThreadSource<T> {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+fn run_segment<T, S, F, Fut>(label: &str, factory: F, link: Link<T>) -> Result<()>
+where
+    T: Clone + Send + 'static,
+    S: EngineSource,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(S, Stream<T>)>>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| Error::io(label, err))?;
+    // the segment may use operators that spawn local tasks
+    LocalSet::new().block_on(&runtime, async move {
+        let (source, stream) = factory().await?;
+        let source = Rc::new(source);
+        let Link {
+            items,
+            cancel,
+            dropped,
+            bytes,
+        } = link;
+
+        // weak, as the source's own outputs end up holding this callback
+        let counted = Rc::downgrade(&source);
+        stream.sink(move |item: &T| {
+            if let Err(TrySendError::Full(_)) = items.try_send(item.clone()) {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(source) = counted.upgrade() {
+                bytes.store(source.stats().bytes, Ordering::Relaxed);
+            }
+        });
+
+        let result = tokio::select! {
+            result = source.run() => result,
+            _ = cancel.cancelled() => Ok(()),
+        };
+        source.close();
+        result
+    })
+}
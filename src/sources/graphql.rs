@@ -0,0 +1,292 @@
+use super::http_client::{check_poll, client, insert_header, request_error};
+use crate::proxy;
+use crate::{Error, Result, Source};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+// An entry of a response's `errors` list. `path` points at the field that
+// failed, e.g. `["trades", 3, "price"]`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct GraphQlError {
+    pub message: String,
+    #[serde(default)]
+    pub path: Vec<Value>,
+}
+
+impl GraphQlError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            path: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    data: Option<Value>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+// Emits `data` (which may be partial) and reports every error separately.
+fn dispatch<T>(response: Response, source: &Source<T>, errors: &Source<GraphQlError>)
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    for error in response.errors {
+        errors.emit(error);
+    }
+    match response.data {
+        None | Some(Value::Null) => {}
+        Some(data) => match serde_json::from_value::<T>(data) {
+            Ok(value) => source.emit(value),
+            Err(err) => errors.emit(GraphQlError::new(format!("failed to decode data: {}", err))),
+        },
+    }
+}
+
+type VariablesFn = Box<dyn Fn() -> Value>;
+
+// Runs a query every `period`, with variables computed per tick.
+pub struct GraphQlPollingClient<T> {
+    client: reqwest::Client,
+    url: String,
+    query: String,
+    period: Duration,
+    headers: HeaderMap,
+    variables: Option<VariablesFn>,
+    source: Source<T>,
+    errors: Source<GraphQlError>,
+    bytes: Cell<u64>,
+}
+
+impl<T> GraphQlPollingClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    pub async fn new(url: &str, query: &str, period: Duration) -> Result<Self> {
+        check_poll(url, period)?;
+        Ok(Self {
+            client: client(url)?,
+            url: url.to_string(),
+            query: query.to_string(),
+            period,
+            headers: HeaderMap::new(),
+            variables: None,
+            source: Source::new(),
+            errors: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Result<Self> {
+        insert_header(&mut self.headers, &self.url, key, value)?;
+        Ok(self)
+    }
+
+    // Called before every request, e.g. to page from the last seen cursor.
+    pub fn with_variables<F>(mut self, variables: F) -> Self
+    where
+        F: Fn() -> Value + 'static,
+    {
+        self.variables = Some(Box::new(variables));
+        self
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    // GraphQL errors and undecodable `data`; the client keeps polling.
+    pub fn errors(&self) -> &Source<GraphQlError> {
+        &self.errors
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut ticker = interval(self.period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.poll_once().await?;
+        }
+    }
+
+    async fn poll_once(&self) -> Result<()> {
+        let variables = self.variables.as_ref().map_or(Value::Null, |f| f());
+        let label = &self.url;
+        let response = self
+            .client
+            .post(label)
+            .headers(self.headers.clone())
+            .json(&json!({ "query": self.query, "variables": variables }))
+            .send()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        self.bytes.set(self.bytes.get() + body.len() as u64);
+        // servers commonly answer query errors with a 4xx and a GraphQL body
+        match serde_json::from_slice::<Response>(&body) {
+            Ok(response) => dispatch(response, &self.source, &self.errors),
+            Err(_) if !status.is_success() => {
+                return Err(Error::protocol(
+                    label,
+                    format!("request failed: {}", status),
+                ))
+            }
+            Err(err) => self
+                .errors
+                .emit(GraphQlError::new(format!("invalid response: {}", err))),
+        }
+        Ok(())
+    }
+}
+
+// A subscription over the `graphql-transport-ws` protocol. The source
+// completes when the server completes the subscription.
+pub struct GraphQlSubscriptionClient<T> {
+    url: String,
+    query: String,
+    variables: Value,
+    init_payload: Value,
+    source: Source<T>,
+    errors: Source<GraphQlError>,
+    bytes: Cell<u64>,
+}
+
+impl<T> GraphQlSubscriptionClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    pub async fn new(url: &str, query: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            query: query.to_string(),
+            variables: Value::Null,
+            init_payload: json!({}),
+            source: Source::new(),
+            errors: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn with_variables(mut self, variables: Value) -> Self {
+        self.variables = variables;
+        self
+    }
+
+    // Sent with `connection_init`, typically auth tokens.
+    pub fn with_init_payload(mut self, payload: Value) -> Self {
+        self.init_payload = payload;
+        self
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub fn errors(&self) -> &Source<GraphQlError> {
+        &self.errors
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let label = &self.url;
+        let connect_error = |err| Error::connect(label, err);
+        let mut request = label
+            .as_str()
+            .into_client_request()
+            .map_err(connect_error)?;
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static("graphql-transport-ws"),
+        );
+        let (ws_stream, _) = proxy::connect_async(request, None, None)
+            .await
+            .map_err(connect_error)?;
+        let (mut write, mut read) = ws_stream.split();
+
+        let init = json!({ "type": "connection_init", "payload": self.init_payload });
+        write
+            .send(Message::Text(init.to_string().into()))
+            .await
+            .map_err(connect_error)?;
+
+        while let Some(message) = read.next().await {
+            let text = match message.map_err(connect_error)? {
+                Message::Text(text) => text.to_string(),
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            self.bytes.set(self.bytes.get() + text.len() as u64);
+            let mut message: Value =
+                serde_json::from_str(&text).map_err(|err| Error::decode(label, err))?;
+            let payload = message["payload"].take();
+            match message["type"].as_str() {
+                Some("connection_ack") => {
+                    let subscribe = json!({
+                        "id": "1",
+                        "type": "subscribe",
+                        "payload": { "query": self.query, "variables": self.variables },
+                    });
+                    write
+                        .send(Message::Text(subscribe.to_string().into()))
+                        .await
+                        .map_err(connect_error)?;
+                }
+                Some("ping") => {
+                    let pong = json!({ "type": "pong" });
+                    write
+                        .send(Message::Text(pong.to_string().into()))
+                        .await
+                        .map_err(connect_error)?;
+                }
+                Some("next") => match serde_json::from_value::<Response>(payload) {
+                    Ok(response) => dispatch(response, &self.source, &self.errors),
+                    Err(err) => self
+                        .errors
+                        .emit(GraphQlError::new(format!("invalid payload: {}", err))),
+                },
+                // the subscription was rejected as a whole
+                Some("error") => {
+                    let errors: Vec<GraphQlError> =
+                        serde_json::from_value(payload).unwrap_or_default();
+                    let messages: Vec<String> =
+                        errors.iter().map(|error| error.message.clone()).collect();
+                    return Err(Error::protocol(
+                        label,
+                        format!("subscription failed: {}", messages.join("; ")),
+                    ));
+                }
+                Some("complete") => {
+                    self.source.complete();
+                    self.errors.complete();
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
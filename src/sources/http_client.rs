@@ -1,9 +1,17 @@
-use crate::Source;
-use anyhow::Result;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use crate::guard::Guarded;
+use crate::proxy;
+use crate::{
+    CursorStore, Error, FrameGuard, GuardViolation, ProxyConfig, Result, Source, Stream,
+    ViolationKind,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ETAG, IF_NONE_MATCH};
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde::de::DeserializeOwned;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::Duration;
+use tokio::sync::Notify;
 use tokio::time::{interval, MissedTickBehavior};
 
 #[derive(Clone, Debug)]
@@ -13,6 +21,9 @@ pub struct PollingHttpClientConfig {
     pub headers: HeaderMap,
     pub method: HttpMethod,
     pub body: Option<String>,
+    // the default proxy, if any, when `None`
+    pub proxy: Option<ProxyConfig>,
+    pub guard: Option<FrameGuard>,
 }
 
 impl PollingHttpClientConfig {
@@ -23,21 +34,19 @@ impl PollingHttpClientConfig {
             headers: HeaderMap::new(),
             method: HttpMethod::Get,
             body: None,
+            proxy: None,
+            guard: None,
         }
     }
 
     pub fn with_header(mut self, key: &str, value: &str) -> Result<Self> {
-        let name = HeaderName::from_bytes(key.as_bytes())?;
-        let value = HeaderValue::from_str(value)?;
-        self.headers.insert(name, value);
+        insert_header(&mut self.headers, &self.url, key, value)?;
         Ok(self)
     }
 
     pub fn with_headers(mut self, headers: HashMap<String, String>) -> Result<Self> {
         for (key, value) in headers {
-            let name = HeaderName::from_bytes(key.as_bytes())?;
-            let value = HeaderValue::from_str(&value)?;
-            self.headers.insert(name, value);
+            insert_header(&mut self.headers, &self.url, &key, &value)?;
         }
         Ok(self)
     }
@@ -51,6 +60,19 @@ impl PollingHttpClientConfig {
         self.body = Some(body.into());
         self
     }
+
+    // Requests go through `proxy` rather than the default one (see
+    // `set_default_proxy`).
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    // Limits on every response body, checked before it is emitted.
+    pub fn with_guard(mut self, guard: FrameGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -62,24 +84,54 @@ pub enum HttpMethod {
 pub struct PollingHttpClient {
     client: reqwest::Client,
     config: PollingHttpClientConfig,
+    cursors: Option<Rc<dyn CursorStore>>,
     source: Source<String>,
+    bytes: Cell<u64>,
+    guarded: Guarded,
 }
 
 impl PollingHttpClient {
     pub async fn new(config: PollingHttpClientConfig) -> Result<Self> {
-        let client = reqwest::Client::builder().no_proxy().build()?;
+        check_poll(&config.url, config.period)?;
+        let client = client_with_proxy(&config.url, config.proxy.as_ref())?;
 
         Ok(Self {
             client,
+            guarded: Guarded::new(config.guard),
             config,
+            cursors: None,
             source: Source::new(),
+            bytes: Cell::new(0),
         })
     }
 
+    // Keeps the ETag of the last response (under the url) and sends it as
+    // `If-None-Match`, so unchanged resources are not emitted again, not
+    // even after a restart.
+    pub fn with_cursor_store(mut self, cursors: Rc<dyn CursorStore>) -> Self {
+        self.cursors = Some(cursors);
+        self
+    }
+
     pub fn source(&self) -> &Source<String> {
         &self.source
     }
 
+    // Response body bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Responses the config's guard rejected, as they happen.
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.guarded.violations()
+    }
+
+    // Responses the config's guard rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.guarded.rejected()
+    }
+
     pub async fn start(&self) -> Result<()> {
         let mut ticker = interval(self.config.period);
         ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
@@ -93,23 +145,61 @@ impl PollingHttpClient {
         }
     }
 
+    // A single request outside the polling loop, returned rather than
+    // emitted; `None` if the resource is unchanged since the saved ETag. A
+    // response the guard rejects is an error, there is nothing to skip to.
+    pub async fn fetch_once(&self) -> Result<Option<String>> {
+        let Some(response) = self.send().await? else {
+            return Ok(None);
+        };
+        match body(response, &self.config.url, &self.guarded, &self.bytes).await? {
+            Some(body) => Ok(Some(text(body))),
+            None => Err(Error::protocol(&self.config.url, "response was rejected")),
+        }
+    }
+
     async fn poll_once(&self) -> Result<()> {
-        let mut request = match self.config.method {
-            HttpMethod::Get => self.client.get(&self.config.url),
-            HttpMethod::Post => self.client.post(&self.config.url),
+        let Some(response) = self.send().await? else {
+            return Ok(());
         };
+        let etag = response.headers().get(ETAG).cloned();
+        // a rejected body is not fetched again until it changes
+        if let Some(body) = body(response, &self.config.url, &self.guarded, &self.bytes).await? {
+            self.source.emit(text(body));
+        }
+        self.save_etag(etag)
+    }
 
-        if !self.config.headers.is_empty() {
-            request = request.headers(self.config.headers.clone());
+    // `None` if the resource is unchanged since the saved ETag.
+    async fn send(&self) -> Result<Option<Response>> {
+        let mut request = request(&self.client, &self.config);
+        if let Some(etag) = self
+            .cursors
+            .as_ref()
+            .and_then(|cursors| cursors.load(&self.config.url))
+        {
+            request = request.header(IF_NONE_MATCH, etag);
         }
-        if let Some(body) = &self.config.body {
-            request = request.body(body.clone());
+
+        let response = request
+            .send()
+            .await
+            .map_err(|err| request_error(&self.config.url, err))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
         }
+        Ok(Some(response))
+    }
 
-        let response = request.send().await?;
-        let text = response.text().await?;
-        self.source.emit(text);
-        Ok(())
+    // Only once the response was emitted, so a failure in between re-fetches.
+    fn save_etag(&self, etag: Option<HeaderValue>) -> Result<()> {
+        let (Some(cursors), Some(etag)) = (&self.cursors, etag) else {
+            return Ok(());
+        };
+        match etag.to_str() {
+            Ok(etag) => cursors.save(&self.config.url, etag),
+            Err(_) => Ok(()),
+        }
     }
 }
 
@@ -131,10 +221,27 @@ where
         })
     }
 
+    pub fn with_cursor_store(mut self, cursors: Rc<dyn CursorStore>) -> Self {
+        self.inner = self.inner.with_cursor_store(cursors);
+        self
+    }
+
     pub fn source(&self) -> &Source<T> {
         &self.source
     }
 
+    pub fn bytes_received(&self) -> u64 {
+        self.inner.bytes_received()
+    }
+
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.inner.violations()
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.inner.rejected()
+    }
+
     pub async fn start(&self) -> Result<()> {
         let mut ticker = interval(self.inner.config.period);
         ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
@@ -146,20 +253,233 @@ where
     }
 
     async fn poll_once(&self) -> Result<()> {
-        let mut request = match self.inner.config.method {
-            HttpMethod::Get => self.inner.client.get(&self.inner.config.url),
-            HttpMethod::Post => self.inner.client.post(&self.inner.config.url),
+        let label = &self.inner.config.url;
+        let Some(response) = self.inner.send().await? else {
+            return Ok(());
         };
+        let etag = response.headers().get(ETAG).cloned();
+        if let Some(body) = body(response, label, &self.inner.guarded, &self.inner.bytes).await? {
+            let value =
+                serde_json::from_slice::<T>(&body).map_err(|err| Error::decode(label, err))?;
+            self.source.emit(value);
+        }
+        self.inner.save_etag(etag)
+    }
+}
 
-        if !self.inner.config.headers.is_empty() {
-            request = request.headers(self.inner.config.headers.clone());
+// A single request, made when the engine starts or on the first item of a
+// trigger stream; the response is emitted and the source completes. Built
+// from a polling config, whose period is not used.
+pub struct OneShotRequest {
+    client: reqwest::Client,
+    config: PollingHttpClientConfig,
+    trigger: Option<Trigger>,
+    source: Source<String>,
+    bytes: Cell<u64>,
+    guarded: Guarded,
+}
+
+struct Trigger {
+    fired: Rc<Cell<bool>>,
+    completed: Rc<Cell<bool>>,
+    notify: Rc<Notify>,
+}
+
+impl OneShotRequest {
+    pub async fn new(config: PollingHttpClientConfig) -> Result<Self> {
+        if config.url.trim().is_empty() {
+            return Err(Error::config("request url is empty"));
         }
-        if let Some(body) = &self.inner.config.body {
-            request = request.body(body.clone());
+        Ok(Self {
+            client: client_with_proxy(&config.url, config.proxy.as_ref())?,
+            guarded: Guarded::new(config.guard),
+            config,
+            trigger: None,
+            source: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    // Waits for the first item of `trigger` instead of requesting right away;
+    // if it completes without one, so does this source, without a request.
+    pub fn with_trigger<U: 'static>(mut self, trigger: &Stream<U>) -> Self {
+        let fired = Rc::new(Cell::new(false));
+        let completed = Rc::new(Cell::new(false));
+        let notify = Rc::new(Notify::new());
+        let (fired_clone, notify_clone) = (fired.clone(), notify.clone());
+        trigger.sink(move |_| {
+            fired_clone.set(true);
+            notify_clone.notify_one();
+        });
+        let (completed_clone, notify_clone) = (completed.clone(), notify.clone());
+        trigger.on_complete(move || {
+            completed_clone.set(true);
+            notify_clone.notify_one();
+        });
+        self.trigger = Some(Trigger {
+            fired,
+            completed,
+            notify,
+        });
+        self
+    }
+
+    pub fn source(&self) -> &Source<String> {
+        &self.source
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.guarded.violations()
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.guarded.rejected()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if let Some(trigger) = &self.trigger {
+            while !trigger.fired.get() {
+                if trigger.completed.get() {
+                    self.source.complete();
+                    return Ok(());
+                }
+                trigger.notify.notified().await;
+            }
+        }
+
+        let label = &self.config.url;
+        let response = request(&self.client, &self.config)
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(|err| request_error(label, err))?;
+        // a rejected response completes the source without an item
+        if let Some(body) = body(response, label, &self.guarded, &self.bytes).await? {
+            self.source.emit(text(body));
         }
-        let response = request.send().await?;
-        let value = response.json::<T>().await?;
-        self.source.emit(value);
+        self.source.complete();
         Ok(())
     }
 }
+
+// The body, unless `guarded` rejects it; a body over the size limit is read
+// no further. Counts the bytes read into `bytes`.
+async fn body(
+    mut response: Response,
+    label: &str,
+    guarded: &Guarded,
+    bytes: &Cell<u64>,
+) -> Result<Option<Vec<u8>>> {
+    let add_bytes = |len: usize| bytes.set(bytes.get() + len as u64);
+    let Some(limit) = guarded.max_frame_bytes() else {
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        add_bytes(body.len());
+        return Ok(guarded.check(label, &body)?.then(|| body.into()));
+    };
+    if let Some(size) = response
+        .content_length()
+        .filter(|&size| size > limit as u64)
+    {
+        let size = size as usize;
+        guarded.reject(label, &[], ViolationKind::FrameTooLarge { size, limit })?;
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| request_error(label, err))?
+    {
+        add_bytes(chunk.len());
+        body.extend_from_slice(&chunk);
+        if body.len() > limit {
+            let size = body.len();
+            guarded.reject(label, &body, ViolationKind::FrameTooLarge { size, limit })?;
+            return Ok(None);
+        }
+    }
+    Ok(guarded.check(label, &body)?.then_some(body))
+}
+
+// Invalid UTF-8 replaced, as reqwest's `text` does.
+fn text(body: Vec<u8>) -> String {
+    String::from_utf8(body)
+        .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+}
+
+fn request(client: &reqwest::Client, config: &PollingHttpClientConfig) -> RequestBuilder {
+    let mut request = match config.method {
+        HttpMethod::Get => client.get(&config.url),
+        HttpMethod::Post => client.post(&config.url),
+    };
+    if !config.headers.is_empty() {
+        request = request.headers(config.headers.clone());
+    }
+    if let Some(body) = &config.body {
+        request = request.body(body.clone());
+    }
+    request
+}
+
+// `interval` panics on a zero period, and an empty url only fails once the
+// engine runs.
+pub(crate) fn check_poll(url: &str, period: Duration) -> Result<()> {
+    if url.trim().is_empty() {
+        return Err(Error::config("polling url is empty"));
+    }
+    if period.is_zero() {
+        return Err(Error::config(format!(
+            "polling {}: period must be non-zero",
+            url
+        )));
+    }
+    Ok(())
+}
+
+// Through the default proxy, if any; reqwest's own environment lookup stays
+// off.
+pub(crate) fn client(label: &str) -> Result<reqwest::Client> {
+    client_with_proxy(label, None)
+}
+
+pub(crate) fn client_with_proxy(
+    label: &str,
+    proxy: Option<&ProxyConfig>,
+) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().no_proxy();
+    if let Some(proxy) = proxy::reqwest_proxy(proxy).map_err(|err| Error::other(label, err))? {
+        builder = builder.proxy(proxy);
+    }
+    builder.build().map_err(|err| Error::other(label, err))
+}
+
+pub(crate) fn insert_header(
+    headers: &mut HeaderMap,
+    label: &str,
+    key: &str,
+    value: &str,
+) -> Result<()> {
+    let name = HeaderName::from_bytes(key.as_bytes()).map_err(|err| Error::other(label, err))?;
+    let value = HeaderValue::from_str(value).map_err(|err| Error::other(label, err))?;
+    headers.insert(name, value);
+    Ok(())
+}
+
+// Sorts a reqwest failure into connection trouble, an error status or an
+// undecodable body.
+pub(crate) fn request_error(label: &str, err: reqwest::Error) -> Error {
+    if err.is_decode() {
+        Error::decode(label, err)
+    } else if let Some(status) = err.status() {
+        Error::protocol(label, format!("request failed: {}", status))
+    } else {
+        Error::connect(label, err)
+    }
+}
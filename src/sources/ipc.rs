@@ -0,0 +1,283 @@
+use crate::backpressure;
+use crate::log;
+use crate::{Error, Result, Sink, Source, Stream};
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Sender};
+
+#[cfg(feature = "shm")]
+mod shm;
+#[cfg(feature = "shm")]
+pub use shm::{ShmPublisher, ShmSubscriber, ShmWaitMode};
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+// One line on the socket: a JSON object terminated by '\n'.
+#[derive(Serialize)]
+struct FrameRef<'a, T> {
+    seq: u64,
+    item: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Frame<T> {
+    seq: u64,
+    item: T,
+}
+
+// Serves a stream to other processes over a Unix socket. Every published item
+// gets the next sequence number; a subscriber that falls more than `capacity`
+// items behind misses items rather than slowing the publisher down, and sees
+// the hole in the sequence. Register it with the engine so it can accept
+// subscribers.
+pub struct IpcPublisher<T> {
+    inner: Rc<PublisherInner>,
+    _item: PhantomData<fn(&T)>,
+}
+
+struct PublisherInner {
+    name: String,
+    path: PathBuf,
+    listener: UnixListener,
+    capacity: Cell<usize>,
+    seq: Cell<u64>,
+    subscribers: RefCell<Vec<Sender<Rc<str>>>>,
+    published: Cell<u64>,
+    dropped: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl<T> Clone for IpcPublisher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T> IpcPublisher<T>
+where
+    T: Serialize + 'static,
+{
+    // Replaces a socket file left behind by a previous run.
+    pub async fn bind(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let name = format!("ipc:{}", path.display());
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|err| Error::io(&name, err))?;
+        }
+        let listener = UnixListener::bind(&path).map_err(|err| Error::io(&name, err))?;
+        Ok(Self {
+            inner: Rc::new(PublisherInner {
+                name,
+                path,
+                listener,
+                capacity: Cell::new(DEFAULT_CAPACITY),
+                seq: Cell::new(0),
+                subscribers: RefCell::new(Vec::new()),
+                published: Cell::new(0),
+                dropped: Cell::new(0),
+                bytes: Cell::new(0),
+            }),
+            _item: PhantomData,
+        })
+    }
+
+    // Items queued per subscriber before it starts missing them.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.inner.capacity.set(capacity.max(1));
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.inner.subscribers.borrow().len()
+    }
+
+    pub fn published(&self) -> u64 {
+        self.inner.published.get()
+    }
+
+    // Items not delivered to a lagging subscriber, summed over subscribers.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.get()
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.inner.bytes.get()
+    }
+
+    pub fn publish(&self, item: &T) -> Result<()> {
+        let inner = &self.inner;
+        let seq = inner.seq.get() + 1;
+        inner.seq.set(seq);
+        let mut line = serde_json::to_string(&FrameRef { seq, item })
+            .map_err(|err| Error::decode(&inner.name, err))?;
+        line.push('\n');
+        let line: Rc<str> = line.into();
+        inner.published.set(inner.published.get() + 1);
+
+        inner.subscribers.borrow_mut().retain(|subscriber| {
+            match subscriber.try_send(line.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    inner.dropped.set(inner.dropped.get() + 1);
+                    backpressure::report(&inner.name, inner.capacity.get(), 1);
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+        Ok(())
+    }
+
+    // Publishes every item of `stream`; serialization errors are logged.
+    // Subscribers are disconnected when `stream` completes.
+    pub fn publish_stream(&self, stream: &Stream<T>) {
+        stream.sink_to(self.clone());
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut writers: FuturesUnordered<Pin<Box<dyn Future<Output = ()>>>> =
+            FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = self.inner.listener.accept() => {
+                    let (stream, _) = accepted.map_err(|err| Error::io(&self.inner.name, err))?;
+                    writers.push(Box::pin(self.serve(stream)));
+                }
+                Some(()) = writers.next(), if !writers.is_empty() => {}
+            }
+        }
+    }
+
+    fn serve(&self, mut stream: UnixStream) -> impl Future<Output = ()> + 'static {
+        let (sender, mut receiver) = channel::<Rc<str>>(self.inner.capacity.get());
+        self.inner.subscribers.borrow_mut().push(sender);
+        let inner = self.inner.clone();
+        async move {
+            while let Some(line) = receiver.recv().await {
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+                inner.bytes.set(inner.bytes.get() + line.len() as u64);
+            }
+        }
+    }
+
+    // Disconnects every subscriber, which completes their sources.
+    pub fn close(&self) {
+        self.inner.subscribers.borrow_mut().clear();
+    }
+}
+
+impl<T> Sink<T> for IpcPublisher<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        if let Err(err) = self.publish(item) {
+            log::error(
+                "ipc",
+                format_args!("{} failed to publish: {}", self.inner.name, err),
+            );
+        }
+    }
+
+    fn close(&self) -> Result<()> {
+        IpcPublisher::close(self);
+        Ok(())
+    }
+}
+
+// Receives what an `IpcPublisher` serves. Sequence numbers missed while
+// lagging are published on `gaps()`; the source completes when the publisher
+// goes away.
+pub struct IpcSubscriber<T> {
+    path: PathBuf,
+    source: Source<T>,
+    gaps: Source<Range<u64>>,
+    decode_errors: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl<T> IpcSubscriber<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            source: Source::new(),
+            gaps: Source::new(),
+            decode_errors: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub fn gaps(&self) -> &Source<Range<u64>> {
+        &self.gaps
+    }
+
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let label = format!("ipc:{}", self.path.display());
+        let stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(|err| Error::connect(&label, err))?;
+        let mut lines = BufReader::new(stream).lines();
+        // a subscriber joins mid-stream, so the first frame sets the baseline
+        let mut expected: Option<u64> = None;
+
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|err| Error::io(&label, err))?
+        {
+            self.bytes.set(self.bytes.get() + line.len() as u64 + 1);
+            let frame: Frame<T> = match serde_json::from_str(&line) {
+                Ok(frame) => frame,
+                Err(_) => {
+                    self.decode_errors.set(self.decode_errors.get() + 1);
+                    continue;
+                }
+            };
+            if let Some(next) = expected.filter(|next| frame.seq > *next) {
+                self.gaps.emit(next..frame.seq);
+            }
+            expected = Some(frame.seq + 1);
+            self.source.emit(frame.item);
+        }
+
+        self.source.complete();
+        self.gaps.complete();
+        Ok(())
+    }
+}
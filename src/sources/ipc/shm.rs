@@ -0,0 +1,367 @@
+use crate::log;
+use crate::rt::{self, Instant};
+use crate::{Error, Result, Sink, Source, Stream};
+use memmap2::MmapRaw;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::Cell;
+use std::fs::OpenOptions;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::Duration;
+
+// "STRMZRNG"
+const MAGIC: u64 = 0x5354_524d_5a52_4e47;
+// magic, capacity and slot size on the first cache line, the write cursor on
+// its own second one
+const HEADER_LEN: usize = 128;
+const CURSOR_OFFSET: usize = 64;
+// a slot is its sequence number, the payload length and the payload, padded
+// to whole cache lines
+const SLOT_HEADER_LEN: usize = 16;
+const CACHE_LINE: usize = 64;
+// busy-spin rounds between yields to the rest of the engine
+const SPINS_PER_YIELD: u32 = 1024;
+
+// How a subscriber waits on an empty ring.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShmWaitMode {
+    // Spins on the cursor, yielding to other sources between rounds. Lowest
+    // hand-off latency, at the cost of a core.
+    BusySpin,
+    // Sleeps this long between checks.
+    Blocking(Duration),
+}
+
+// A ring of fixed-size slots in a memory-mapped file (put it on /dev/shm).
+// Any number of processes may publish into it, each claiming slots from the
+// shared cursor, and every subscriber reads the whole stream independently.
+// The ring never waits for readers: a subscriber lapped by the writers skips
+// to the oldest item still in the ring and reports the skipped range.
+struct Ring {
+    label: String,
+    map: MmapRaw,
+    capacity: u64,
+    slot_size: usize,
+    stride: usize,
+}
+
+impl Ring {
+    fn create(path: &Path, capacity: usize, slot_size: usize) -> Result<Self> {
+        let label = format!("shm:{}", path.display());
+        if capacity == 0 || slot_size == 0 {
+            return Err(Error::other(
+                label,
+                "shm ring needs a non-zero capacity and slot size",
+            ));
+        }
+        let stride = Self::stride(slot_size);
+        let io_error = |err| Error::io(&label, err);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(io_error)?;
+        file.set_len((HEADER_LEN + capacity * stride) as u64)
+            .map_err(io_error)?;
+        let map = MmapRaw::map_raw(&file).map_err(io_error)?;
+        let ring = Self {
+            label,
+            map,
+            capacity: capacity as u64,
+            slot_size,
+            stride,
+        };
+        ring.word(8).store(capacity as u64, Ordering::Relaxed);
+        ring.word(16).store(slot_size as u64, Ordering::Relaxed);
+        ring.cursor().store(0, Ordering::Relaxed);
+        ring.word(0).store(MAGIC, Ordering::Release);
+        Ok(ring)
+    }
+
+    fn open(path: &Path) -> Result<Self> {
+        let label = format!("shm:{}", path.display());
+        let io_error = |err| Error::io(&label, err);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(io_error)?;
+        let map = MmapRaw::map_raw(&file).map_err(io_error)?;
+        if map.len() < HEADER_LEN {
+            return Err(Error::protocol(label, "not a shm ring"));
+        }
+        let mut ring = Self {
+            label,
+            map,
+            capacity: 0,
+            slot_size: 0,
+            stride: 0,
+        };
+        if ring.word(0).load(Ordering::Acquire) != MAGIC {
+            return Err(Error::protocol(ring.label, "not a shm ring"));
+        }
+        ring.capacity = ring.word(8).load(Ordering::Relaxed);
+        ring.slot_size = ring.word(16).load(Ordering::Relaxed) as usize;
+        ring.stride = Self::stride(ring.slot_size);
+        if ring.map.len() < HEADER_LEN + ring.capacity as usize * ring.stride {
+            return Err(Error::protocol(ring.label, "ring file is truncated"));
+        }
+        Ok(ring)
+    }
+
+    fn stride(slot_size: usize) -> usize {
+        (SLOT_HEADER_LEN + slot_size).div_ceil(CACHE_LINE) * CACHE_LINE
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: offsets are 8-aligned and inside the mapping, which is
+        // page-aligned and lives as long as `self`.
+        unsafe { &*(self.map.as_mut_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    fn cursor(&self) -> &AtomicU64 {
+        self.word(CURSOR_OFFSET)
+    }
+
+    fn slot(&self, seq: u64) -> usize {
+        HEADER_LEN + (seq % self.capacity) as usize * self.stride
+    }
+
+    // Slots store `seq + 1`, so 0 marks one being (or never) written.
+    fn write(&self, payload: &[u8]) -> Result<u64> {
+        if payload.len() > self.slot_size {
+            return Err(Error::other(
+                &self.label,
+                format!(
+                    "item of {} bytes does not fit a {} byte slot",
+                    payload.len(),
+                    self.slot_size
+                ),
+            ));
+        }
+        let seq = self.cursor().fetch_add(1, Ordering::AcqRel);
+        let slot = self.slot(seq);
+        self.word(slot).store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+        // SAFETY: the payload fits the slot, which this writer claimed.
+        unsafe {
+            let base = self.map.as_mut_ptr().add(slot);
+            (base.add(8) as *mut u32).write_volatile(payload.len() as u32);
+            std::ptr::copy_nonoverlapping(
+                payload.as_ptr(),
+                base.add(SLOT_HEADER_LEN),
+                payload.len(),
+            );
+        }
+        self.word(slot).store(seq + 1, Ordering::Release);
+        Ok(seq)
+    }
+
+    // Copies out item `seq`, if it is there and was not overwritten mid-read.
+    fn read(&self, seq: u64, buffer: &mut Vec<u8>) -> bool {
+        let slot = self.slot(seq);
+        if self.word(slot).load(Ordering::Acquire) != seq + 1 {
+            return false;
+        }
+        // SAFETY: the length is clamped to the slot; a concurrent overwrite
+        // is detected by re-checking the sequence below.
+        unsafe {
+            let base = self.map.as_mut_ptr().add(slot);
+            let len = ((base.add(8) as *const u32).read_volatile() as usize).min(self.slot_size);
+            buffer.clear();
+            buffer.extend_from_slice(std::slice::from_raw_parts(base.add(SLOT_HEADER_LEN), len));
+        }
+        fence(Ordering::Acquire);
+        self.word(slot).load(Ordering::Relaxed) == seq + 1
+    }
+}
+
+// Publishes into a shared-memory ring. Several publishers, in this process or
+// others, may share one ring.
+pub struct ShmPublisher<T> {
+    ring: Rc<Ring>,
+    published: Rc<Cell<u64>>,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T> Clone for ShmPublisher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ring: self.ring.clone(),
+            published: self.published.clone(),
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T> ShmPublisher<T>
+where
+    T: Serialize + 'static,
+{
+    // Creates (or resets) the ring at `path` with `capacity` slots of
+    // `slot_size` bytes each.
+    pub fn create(path: impl AsRef<Path>, capacity: usize, slot_size: usize) -> Result<Self> {
+        Ok(Self::with_ring(Ring::create(
+            path.as_ref(),
+            capacity,
+            slot_size,
+        )?))
+    }
+
+    // Attaches to a ring another publisher created.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::with_ring(Ring::open(path.as_ref())?))
+    }
+
+    fn with_ring(ring: Ring) -> Self {
+        Self {
+            ring: Rc::new(ring),
+            published: Rc::new(Cell::new(0)),
+            _item: PhantomData,
+        }
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published.get()
+    }
+
+    pub fn publish(&self, item: &T) -> Result<()> {
+        let payload =
+            serde_json::to_vec(item).map_err(|err| Error::decode(&self.ring.label, err))?;
+        self.ring.write(&payload)?;
+        self.published.set(self.published.get() + 1);
+        Ok(())
+    }
+
+    // Publishes every item of `stream`; items that fail to publish are logged.
+    pub fn publish_stream(&self, stream: &Stream<T>) {
+        stream.sink_to(self.clone());
+    }
+}
+
+impl<T> Sink<T> for ShmPublisher<T>
+where
+    T: Serialize + 'static,
+{
+    fn on_item(&self, item: &T) {
+        if let Err(err) = self.publish(item) {
+            log::error("shm", format_args!("shm ring failed to publish: {}", err));
+        }
+    }
+}
+
+// Reads a shared-memory ring from the current write position on. Ranges
+// overwritten before they were read are published on `gaps()`.
+pub struct ShmSubscriber<T> {
+    ring: Ring,
+    wait: ShmWaitMode,
+    next: Cell<u64>,
+    source: Source<T>,
+    gaps: Source<Range<u64>>,
+    decode_errors: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl<T> ShmSubscriber<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    pub fn open(path: impl AsRef<Path>, wait: ShmWaitMode) -> Result<Self> {
+        let ring = Ring::open(path.as_ref())?;
+        let next = ring.cursor().load(Ordering::Acquire);
+        Ok(Self {
+            ring,
+            wait,
+            next: Cell::new(next),
+            source: Source::new(),
+            gaps: Source::new(),
+            decode_errors: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub fn gaps(&self) -> &Source<Range<u64>> {
+        &self.gaps
+    }
+
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut buffer = Vec::with_capacity(self.ring.slot_size);
+        let mut spins = 0;
+        loop {
+            if self.poll(&mut buffer)? {
+                spins = 0;
+                continue;
+            }
+            match self.wait {
+                ShmWaitMode::BusySpin => {
+                    spins += 1;
+                    if spins < SPINS_PER_YIELD {
+                        std::hint::spin_loop();
+                    } else {
+                        spins = 0;
+                        tokio::task::yield_now().await;
+                    }
+                }
+                ShmWaitMode::Blocking(period) => rt::sleep_until(Instant::now() + period).await,
+            }
+        }
+    }
+
+    // Emits the next item if it has been written; false if the ring is empty.
+    fn poll(&self, buffer: &mut Vec<u8>) -> Result<bool> {
+        let next = self.next.get();
+        let written = self.ring.cursor().load(Ordering::Acquire);
+        // the ring was created again under us
+        if written < next {
+            return Err(Error::SourceRestarted {
+                source: self.ring.label.clone(),
+            });
+        }
+        if next == written {
+            return Ok(false);
+        }
+        let oldest = written.saturating_sub(self.ring.capacity);
+        if next < oldest {
+            self.skip_to(oldest);
+            return Ok(true);
+        }
+        if !self.ring.read(next, buffer) {
+            // either still being written, or lapped while reading
+            if self.ring.cursor().load(Ordering::Acquire) >= next + self.ring.capacity {
+                self.skip_to(next + 1);
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        self.next.set(next + 1);
+        self.bytes.set(self.bytes.get() + buffer.len() as u64);
+        match serde_json::from_slice::<T>(buffer) {
+            Ok(item) => self.source.emit(item),
+            Err(_) => self.decode_errors.set(self.decode_errors.get() + 1),
+        }
+        Ok(true)
+    }
+
+    fn skip_to(&self, seq: u64) {
+        self.gaps.emit(self.next.get()..seq);
+        self.next.set(seq);
+    }
+}
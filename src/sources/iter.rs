@@ -0,0 +1,45 @@
+use crate::{Error, Result, Source};
+use std::cell::RefCell;
+
+// Emits a fixed set of items and then completes; handy for seeding reference
+// data or driving a pipeline from a batch.
+pub struct IterSource<T> {
+    items: RefCell<Option<Box<dyn Iterator<Item = T>>>>,
+    source: Source<T>,
+}
+
+impl<T> IterSource<T>
+where
+    T: 'static,
+{
+    pub fn new<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: 'static,
+    {
+        Self {
+            items: RefCell::new(Some(Box::new(items.into_iter()))),
+            source: Source::new(),
+        }
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let items = self
+            .items
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| Error::AlreadyStarted {
+                source: "iter".to_string(),
+            })?;
+
+        for item in items {
+            self.source.emit(item);
+        }
+        self.source.complete();
+        Ok(())
+    }
+}
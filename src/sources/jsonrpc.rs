@@ -0,0 +1,217 @@
+use super::http_client::{check_poll, client, insert_header, request_error};
+use crate::{Error, Result, Source};
+use reqwest::header::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+
+// JSON-RPC "internal error", used for failures on our side of a call.
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonRpcCall {
+    pub method: String,
+    pub params: Value,
+}
+
+impl JsonRpcCall {
+    pub fn new(method: impl Into<String>, params: Value) -> Self {
+        Self {
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonRpcError {
+    pub method: String,
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Option<u64>,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<ErrorObject>,
+}
+
+#[derive(Deserialize)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+    #[serde(default)]
+    data: Option<Value>,
+}
+
+type CallsFn = Box<dyn Fn() -> Vec<JsonRpcCall>>;
+
+// Sends the calls returned by `calls` as one JSON-RPC batch every `period`
+// and emits the results in call order, whatever order the server answers
+// in. Failed calls are reported on `errors`.
+pub struct JsonRpcBatchClient<T> {
+    client: reqwest::Client,
+    url: String,
+    period: Duration,
+    headers: HeaderMap,
+    calls: CallsFn,
+    next_id: Cell<u64>,
+    source: Source<T>,
+    errors: Source<JsonRpcError>,
+    bytes: Cell<u64>,
+}
+
+impl<T> JsonRpcBatchClient<T>
+where
+    T: DeserializeOwned + Clone + 'static,
+{
+    pub async fn new<F>(url: &str, period: Duration, calls: F) -> Result<Self>
+    where
+        F: Fn() -> Vec<JsonRpcCall> + 'static,
+    {
+        check_poll(url, period)?;
+        Ok(Self {
+            client: client(url)?,
+            url: url.to_string(),
+            period,
+            headers: HeaderMap::new(),
+            calls: Box::new(calls),
+            next_id: Cell::new(1),
+            source: Source::new(),
+            errors: Source::new(),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Result<Self> {
+        insert_header(&mut self.headers, &self.url, key, value)?;
+        Ok(self)
+    }
+
+    pub fn source(&self) -> &Source<T> {
+        &self.source
+    }
+
+    pub fn errors(&self) -> &Source<JsonRpcError> {
+        &self.errors
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut ticker = interval(self.period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.poll_once().await?;
+        }
+    }
+
+    async fn poll_once(&self) -> Result<()> {
+        let calls = (self.calls)();
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let first_id = self.next_id.get();
+        self.next_id.set(first_id + calls.len() as u64);
+        let batch: Vec<Value> = calls
+            .iter()
+            .zip(first_id..)
+            .map(|(call, id)| {
+                json!({ "jsonrpc": "2.0", "id": id, "method": call.method, "params": call.params })
+            })
+            .collect();
+
+        let label = &self.url;
+        let response = self
+            .client
+            .post(label)
+            .headers(self.headers.clone())
+            .json(&batch)
+            .send()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| request_error(label, err))?;
+        self.bytes.set(self.bytes.get() + body.len() as u64);
+
+        // a rejected batch comes back as a single error object
+        let responses: Vec<Response> = match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Array(items)) => items
+                .into_iter()
+                .filter_map(|item| serde_json::from_value(item).ok())
+                .collect(),
+            Ok(single) => match serde_json::from_value::<Response>(single) {
+                Ok(Response {
+                    error: Some(error), ..
+                }) => {
+                    return Err(Error::protocol(
+                        label,
+                        format!("batch rejected: {} ({})", error.message, error.code),
+                    ))
+                }
+                _ => return Err(Error::protocol(label, "batch response is not an array")),
+            },
+            Err(err) if status.is_success() => return Err(Error::decode(label, err)),
+            Err(_) => {
+                return Err(Error::protocol(
+                    label,
+                    format!("request failed: {}", status),
+                ))
+            }
+        };
+        let mut by_id: HashMap<u64, Response> = responses
+            .into_iter()
+            .filter_map(|response| Some((response.id?, response)))
+            .collect();
+
+        for (call, id) in calls.into_iter().zip(first_id..) {
+            let error = |code, message: String, data| JsonRpcError {
+                method: call.method.clone(),
+                code,
+                message,
+                data,
+            };
+            match by_id.remove(&id) {
+                Some(Response {
+                    error: Some(error_object),
+                    ..
+                }) => self.errors.emit(error(
+                    error_object.code,
+                    error_object.message,
+                    error_object.data,
+                )),
+                Some(response) => {
+                    let result = response.result.unwrap_or(Value::Null);
+                    match serde_json::from_value::<T>(result) {
+                        Ok(value) => self.source.emit(value),
+                        Err(err) => self.errors.emit(error(
+                            INTERNAL_ERROR,
+                            format!("failed to decode result: {}", err),
+                            None,
+                        )),
+                    }
+                }
+                None => self.errors.emit(error(
+                    INTERNAL_ERROR,
+                    "no response for call".to_string(),
+                    None,
+                )),
+            }
+        }
+        Ok(())
+    }
+}
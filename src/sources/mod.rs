@@ -1,7 +1,43 @@
+#[cfg(feature = "books")]
+pub mod book;
+pub mod channel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dedicated;
+#[cfg(feature = "graphql")]
+pub mod graphql;
 #[cfg(feature = "requests")]
 pub mod http_client;
-#[cfg(feature = "websockets")]
+#[cfg(all(feature = "ipc", unix))]
+pub mod ipc;
+pub mod iter;
+#[cfg(feature = "requests")]
+pub mod jsonrpc;
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+pub mod order_entry;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+pub mod simulated;
+#[cfg(any(feature = "websockets", all(feature = "wasm", target_arch = "wasm32")))]
 pub mod websocket_client;
 
+#[cfg(feature = "books")]
+pub use book::{BinanceDepth, BookDiff, BookVenue, DeribitBook, OrderBook, SyncedBookSource};
+pub use channel::{BroadcastSource, WatchSource};
+#[cfg(not(target_arch = "wasm32"))]
+pub use dedicated::DedicatedThreadSource;
+#[cfg(feature = "graphql")]
+pub use graphql::{GraphQlError, GraphQlPollingClient, GraphQlSubscriptionClient};
+#[cfg(feature = "requests")]
+pub use http_client::{OneShotRequest, PollingHttpClient, PollingHttpClientConfig};
+#[cfg(all(feature = "ipc", unix))]
+pub use ipc::{IpcPublisher, IpcSubscriber};
+#[cfg(all(feature = "shm", unix))]
+pub use ipc::{ShmPublisher, ShmSubscriber, ShmWaitMode};
+pub use iter::IterSource;
 #[cfg(feature = "requests")]
-pub use http_client::{PollingHttpClient, PollingHttpClientConfig};
+pub use jsonrpc::{JsonRpcBatchClient, JsonRpcCall, JsonRpcError};
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+pub use order_entry::{ExecutionReport, OrderEntryClient, OrderRequest, OrderState, OrderUpdate};
+#[cfg(all(feature = "orders", not(target_arch = "wasm32")))]
+pub use simulated::{MarketEvent, MatchingModel, OrderCommand, SimulatedExchangeSource};
@@ -0,0 +1,644 @@
+use super::jsonrpc::JsonRpcError;
+use crate::log;
+use crate::market::{Fill, Side};
+use crate::proxy;
+use crate::reconnect::{self, ReconnectBudget, ReconnectPermit};
+use crate::rt::{self, Instant};
+use crate::{ConnectionEvent, Error, Result, SharedCredentials, Source, Stream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_LABEL_WINDOW: usize = 10_000;
+const ORDERS_CHANNEL: &str = "user.orders.any.any.raw";
+const TRADES_CHANNEL: &str = "user.trades.any.any.raw";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderState {
+    Open,
+    Filled,
+    Rejected,
+    Cancelled,
+    Untriggered,
+}
+
+// An order as the venue reports it after every change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderUpdate {
+    pub order_id: String,
+    pub label: Option<String>,
+    pub instrument: String,
+    pub side: Side,
+    pub state: OrderState,
+    // none for market orders
+    pub price: Option<f64>,
+    pub amount: f64,
+    pub filled_amount: f64,
+    pub average_price: Option<f64>,
+    // milliseconds since the epoch
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExecutionReport {
+    Order(OrderUpdate),
+    Fill(Fill),
+    // the venue refused a request; its label may be used again
+    Rejected {
+        label: Option<String>,
+        error: JsonRpcError,
+    },
+    // the connection dropped before the venue answered; the order stream
+    // shows whether it was placed once reconnected
+    Lost {
+        label: Option<String>,
+        method: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderRequest {
+    pub instrument: String,
+    pub amount: f64,
+    // none for market orders
+    pub price: Option<f64>,
+    pub label: Option<String>,
+    pub post_only: bool,
+    pub reduce_only: bool,
+    pub time_in_force: Option<String>,
+}
+
+impl OrderRequest {
+    pub fn limit(instrument: impl Into<String>, amount: f64, price: f64) -> Self {
+        Self {
+            instrument: instrument.into(),
+            amount,
+            price: Some(price),
+            label: None,
+            post_only: false,
+            reduce_only: false,
+            time_in_force: None,
+        }
+    }
+
+    pub fn market(instrument: impl Into<String>, amount: f64) -> Self {
+        Self {
+            price: None,
+            ..Self::limit(instrument, amount, 0.0)
+        }
+    }
+
+    // The idempotency key: an order is sent once per label, however often
+    // it is submitted. One is generated when not set.
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn post_only(mut self) -> Self {
+        self.post_only = true;
+        self
+    }
+
+    pub fn reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+
+    // "good_til_cancelled", "good_til_day", "fill_or_kill" or
+    // "immediate_or_cancel".
+    pub fn with_time_in_force(mut self, time_in_force: impl Into<String>) -> Self {
+        self.time_in_force = Some(time_in_force.into());
+        self
+    }
+
+    fn params(&self, label: &str) -> Value {
+        let mut params = json!({
+            "instrument_name": self.instrument,
+            "amount": self.amount,
+            "type": if self.price.is_some() { "limit" } else { "market" },
+            "label": label,
+        });
+        if let Some(price) = self.price {
+            params["price"] = json!(price);
+        }
+        if self.post_only {
+            params["post_only"] = json!(true);
+        }
+        if self.reduce_only {
+            params["reduce_only"] = json!(true);
+        }
+        if let Some(time_in_force) = &self.time_in_force {
+            params["time_in_force"] = json!(time_in_force);
+        }
+        params
+    }
+}
+
+struct InFlight {
+    method: String,
+    label: Option<String>,
+    // order requests are reported; session setup calls are logged
+    order: bool,
+    sent: Instant,
+}
+
+// Places and cancels Deribit orders over an authenticated JSON-RPC
+// websocket, and reports what happens to them from the private order and
+// trade channels. Requests are tracked until answered; those still in
+// flight when the connection drops are reported `Lost`. Labels act as
+// idempotency keys: a label submitted again is not resent, unless the
+// venue rejected it. With `with_cancel_on_disconnect` the venue cancels
+// the session's open orders when the connection drops. Reconnects on its
+// own.
+pub struct OrderEntryClient {
+    url: String,
+    client_id: String,
+    client_secret: String,
+    credentials: Option<SharedCredentials>,
+    cancel_on_disconnect: bool,
+    heartbeat: Option<Duration>,
+    reconnect_delay: Duration,
+    reconnect_budget: Option<ReconnectBudget>,
+    label_prefix: String,
+    next_id: Cell<u64>,
+    next_label: Cell<u64>,
+    outbox: RefCell<Option<UnboundedSender<String>>>,
+    in_flight: RefCell<HashMap<u64, InFlight>>,
+    labels: RefCell<RecentLabels>,
+    source: Source<ExecutionReport>,
+    events: Source<ConnectionEvent>,
+    ever_connected: Cell<bool>,
+    rejected: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl OrderEntryClient {
+    pub async fn new(url: &str, client_id: &str, client_secret: &str) -> Result<Self> {
+        if !url.starts_with("ws://") && !url.starts_with("wss://") {
+            return Err(Error::config(format!(
+                "order entry url {:?} must start with ws:// or wss://",
+                url
+            )));
+        }
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Ok(Self {
+            url: url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            credentials: None,
+            cancel_on_disconnect: false,
+            heartbeat: None,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            reconnect_budget: None,
+            label_prefix: format!("sz{}", started),
+            next_id: Cell::new(1),
+            next_label: Cell::new(1),
+            outbox: RefCell::new(None),
+            in_flight: RefCell::new(HashMap::new()),
+            labels: RefCell::new(RecentLabels::new(DEFAULT_LABEL_WINDOW)),
+            source: Source::new(),
+            events: Source::new(),
+            ever_connected: Cell::new(false),
+            rejected: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    // Logs in with the `client_id` and `client_secret` that `credentials`
+    // hold at the time, instead of those given to `new`, so rotated keys
+    // are used from the next session on.
+    pub fn with_credentials(mut self, credentials: &SharedCredentials) -> Self {
+        self.credentials = Some(credentials.clone());
+        self
+    }
+
+    // Arms `private/enable_cancel_on_disconnect` for every session.
+    pub fn with_cancel_on_disconnect(mut self) -> Self {
+        self.cancel_on_disconnect = true;
+        self
+    }
+
+    // Asks the venue to probe the connection this often (at least 10s), so
+    // a dead connection is noticed, and cancelled on, sooner.
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval.max(Duration::from_secs(10)));
+        self
+    }
+
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    // Connects and reconnects within `budget`, shared with other sources.
+    pub fn with_reconnect_budget(mut self, budget: &ReconnectBudget) -> Self {
+        self.reconnect_budget = Some(budget.clone());
+        self
+    }
+
+    // Prefix of generated labels; defaults to one unique per client.
+    pub fn with_label_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.label_prefix = prefix.into();
+        self
+    }
+
+    pub fn source(&self) -> &Source<ExecutionReport> {
+        &self.source
+    }
+
+    pub fn connection_events(&self) -> Stream<ConnectionEvent> {
+        self.events.to_stream()
+    }
+
+    // Authenticated and ready for orders.
+    pub fn connected(&self) -> bool {
+        self.outbox.borrow().is_some()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        let in_flight = self.in_flight.borrow();
+        in_flight.values().filter(|request| request.order).count()
+    }
+
+    // How long the oldest unanswered order request has waited.
+    pub fn oldest_in_flight(&self) -> Option<Duration> {
+        let in_flight = self.in_flight.borrow();
+        in_flight
+            .values()
+            .filter(|request| request.order)
+            .map(|request| request.sent.elapsed())
+            .max()
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Returns the order's label.
+    pub fn buy(&self, order: OrderRequest) -> Result<String> {
+        self.place(Side::Buy, order)
+    }
+
+    pub fn sell(&self, order: OrderRequest) -> Result<String> {
+        self.place(Side::Sell, order)
+    }
+
+    pub fn cancel(&self, order_id: &str) -> Result<()> {
+        self.send("private/cancel", json!({ "order_id": order_id }), None)
+    }
+
+    // Cancels by idempotency key, e.g. an order reported `Lost`.
+    pub fn cancel_by_label(&self, label: &str) -> Result<()> {
+        let params = json!({ "label": label });
+        self.send("private/cancel_by_label", params, Some(label.to_string()))
+    }
+
+    pub fn cancel_all(&self) -> Result<()> {
+        self.send("private/cancel_all", json!({}), None)
+    }
+
+    fn place(&self, side: Side, order: OrderRequest) -> Result<String> {
+        let label = order.label.clone().unwrap_or_else(|| {
+            let next = self.next_label.get();
+            self.next_label.set(next + 1);
+            format!("{}-{}", self.label_prefix, next)
+        });
+        if !self.labels.borrow_mut().insert(label.clone()) {
+            return Ok(label);
+        }
+        let method = match side {
+            Side::Buy => "private/buy",
+            Side::Sell => "private/sell",
+        };
+        match self.send(method, order.params(&label), Some(label.clone())) {
+            Ok(()) => Ok(label),
+            Err(err) => {
+                self.labels.borrow_mut().remove(&label);
+                Err(err)
+            }
+        }
+    }
+
+    fn send(&self, method: &str, params: Value, label: Option<String>) -> Result<()> {
+        let outbox = self.outbox.borrow();
+        let Some(outbox) = outbox.as_ref() else {
+            return Err(Error::connect(&self.url, "not connected"));
+        };
+        let (id, request) = self.request(method, params, label, true);
+        if outbox.send(request).is_err() {
+            self.in_flight.borrow_mut().remove(&id);
+            return Err(Error::connect(&self.url, "not connected"));
+        }
+        Ok(())
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        params: Value,
+        label: Option<String>,
+        order: bool,
+    ) -> (u64, String) {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.in_flight.borrow_mut().insert(
+            id,
+            InFlight {
+                method: method.to_string(),
+                label,
+                order,
+                sent: Instant::now(),
+            },
+        );
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        (id, request.to_string())
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let budget = self.reconnect_budget.as_ref();
+        loop {
+            let permit = reconnect::permit(budget).await;
+            match self.session(permit).await {
+                Ok(()) => log::warn(
+                    "order_entry",
+                    format_args!("{} closed; reconnecting", self.url),
+                ),
+                Err(err) => log::warn(
+                    "order_entry",
+                    format_args!("{} failed: {}; reconnecting", self.url, err),
+                ),
+            }
+            self.disconnected();
+            rt::sleep_until(Instant::now() + reconnect::delay(budget, self.reconnect_delay)).await;
+        }
+    }
+
+    async fn session(&self, permit: ReconnectPermit) -> Result<()> {
+        let label = &self.url;
+        let params = match &self.credentials {
+            Some(credentials) => {
+                let credentials = credentials.get().await?;
+                json!({
+                    "grant_type": "client_credentials",
+                    "client_id": credentials.require("client_id")?,
+                    "client_secret": credentials.require("client_secret")?,
+                })
+            }
+            None => json!({
+                "grant_type": "client_credentials",
+                "client_id": self.client_id,
+                "client_secret": self.client_secret,
+            }),
+        };
+        let (ws_stream, _) = proxy::connect_async(label, None, None)
+            .await
+            .map_err(|err| Error::connect(label, err))?;
+        permit.connected();
+        let (mut write, mut read) = ws_stream.split();
+
+        let (auth_id, auth) = self.request("public/auth", params, None, false);
+        let mut setup = vec![auth];
+        if self.cancel_on_disconnect {
+            let params = json!({ "scope": "connection" });
+            let method = "private/enable_cancel_on_disconnect";
+            setup.push(self.request(method, params, None, false).1);
+        }
+        if let Some(interval) = self.heartbeat {
+            let params = json!({ "interval": interval.as_secs() });
+            setup.push(self.request("public/set_heartbeat", params, None, false).1);
+        }
+        let params = json!({ "channels": [ORDERS_CHANNEL, TRADES_CHANNEL] });
+        setup.push(self.request("private/subscribe", params, None, false).1);
+        // pipelined: requests after the auth one are handled once it succeeds
+        for request in setup {
+            write
+                .send(Message::Text(request.into()))
+                .await
+                .map_err(|err| Error::connect(label, err))?;
+        }
+
+        let (outbox, mut pending) = unbounded_channel();
+        let mut outbox = Some(outbox);
+        loop {
+            tokio::select! {
+                message = read.next() => {
+                    let text = match message {
+                        None => break,
+                        Some(message) => match message.map_err(|err| Error::connect(label, err))? {
+                            Message::Text(text) => text.to_string(),
+                            Message::Close(_) => break,
+                            _ => continue,
+                        },
+                    };
+                    self.bytes.set(self.bytes.get() + text.len() as u64);
+                    let Ok(message) = serde_json::from_str::<Value>(&text) else {
+                        continue;
+                    };
+                    if message["id"].as_u64() == Some(auth_id) {
+                        if let Some(error) = message.get("error") {
+                            return Err(Error::connect(
+                                label,
+                                format!("authentication failed: {}", error["message"]),
+                            ));
+                        }
+                        *self.outbox.borrow_mut() = outbox.take();
+                        self.events.emit(if self.ever_connected.replace(true) {
+                            ConnectionEvent::Reconnected
+                        } else {
+                            ConnectionEvent::Connected
+                        });
+                    }
+                    if let Some(reply) = self.handle(message) {
+                        write
+                            .send(Message::Text(reply.into()))
+                            .await
+                            .map_err(|err| Error::connect(label, err))?;
+                    }
+                }
+                Some(request) = pending.recv() => {
+                    write
+                        .send(Message::Text(request.into()))
+                        .await
+                        .map_err(|err| Error::connect(label, err))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns a message to send back, for heartbeat probes.
+    fn handle(&self, mut message: Value) -> Option<String> {
+        match message["method"].as_str() {
+            Some("subscription") => {
+                let params = &message["params"];
+                let channel = params["channel"].as_str().unwrap_or_default();
+                let data = &params["data"];
+                let items = data
+                    .as_array()
+                    .map_or(std::slice::from_ref(data), Vec::as_slice);
+                for item in items {
+                    let report = if channel.starts_with("user.orders") {
+                        order_update(item).map(ExecutionReport::Order)
+                    } else if channel.starts_with("user.trades") {
+                        fill(item).map(ExecutionReport::Fill)
+                    } else {
+                        None
+                    };
+                    if let Some(report) = report {
+                        self.source.emit(report);
+                    }
+                }
+                return None;
+            }
+            Some("heartbeat") => {
+                if message["params"]["type"] == "test_request" {
+                    return Some(self.request("public/test", json!({}), None, false).1);
+                }
+                return None;
+            }
+            _ => {}
+        }
+        let id = message["id"].as_u64()?;
+        let request = self.in_flight.borrow_mut().remove(&id)?;
+        let error = message.get_mut("error").map(Value::take)?;
+        let error = JsonRpcError {
+            method: request.method,
+            code: error["code"].as_i64().unwrap_or_default(),
+            message: error["message"].as_str().unwrap_or_default().to_string(),
+            data: error.get("data").cloned(),
+        };
+        if !request.order {
+            log::error(
+                "order_entry",
+                format_args!("{} {} failed: {}", self.url, error.method, error.message),
+            );
+            return None;
+        }
+        self.rejected.set(self.rejected.get() + 1);
+        if let Some(label) = &request.label {
+            if error.method != "private/cancel_by_label" {
+                self.labels.borrow_mut().remove(label);
+            }
+        }
+        self.source.emit(ExecutionReport::Rejected {
+            label: request.label,
+            error,
+        });
+        None
+    }
+
+    fn disconnected(&self) {
+        let was_connected = self.outbox.borrow_mut().take().is_some();
+        let mut lost: Vec<(u64, InFlight)> = self.in_flight.borrow_mut().drain().collect();
+        lost.sort_by_key(|(id, _)| *id);
+        for (_, request) in lost.into_iter().filter(|(_, request)| request.order) {
+            self.source.emit(ExecutionReport::Lost {
+                label: request.label,
+                method: request.method,
+            });
+        }
+        if was_connected {
+            self.events.emit(ConnectionEvent::Disconnected);
+        }
+    }
+}
+
+fn side(value: &Value) -> Option<Side> {
+    match value.as_str()? {
+        "buy" => Some(Side::Buy),
+        "sell" => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+fn text(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+fn order_update(order: &Value) -> Option<OrderUpdate> {
+    let state = match order["order_state"].as_str()? {
+        "open" => OrderState::Open,
+        "filled" => OrderState::Filled,
+        "rejected" => OrderState::Rejected,
+        "cancelled" => OrderState::Cancelled,
+        "untriggered" => OrderState::Untriggered,
+        _ => return None,
+    };
+    Some(OrderUpdate {
+        order_id: text(&order["order_id"])?,
+        label: text(&order["label"]).filter(|label| !label.is_empty()),
+        instrument: text(&order["instrument_name"])?,
+        side: side(&order["direction"])?,
+        state,
+        // "market_price" for market orders
+        price: order["price"].as_f64(),
+        amount: order["amount"].as_f64()?,
+        filled_amount: order["filled_amount"].as_f64().unwrap_or_default(),
+        average_price: order["average_price"].as_f64().filter(|price| *price > 0.0),
+        timestamp: order["last_update_timestamp"].as_u64().unwrap_or_default(),
+    })
+}
+
+fn fill(trade: &Value) -> Option<Fill> {
+    Some(Fill {
+        trade_id: text(&trade["trade_id"])?,
+        order_id: text(&trade["order_id"])?,
+        label: text(&trade["label"]).filter(|label| !label.is_empty()),
+        instrument: text(&trade["instrument_name"])?,
+        side: side(&trade["direction"])?,
+        price: trade["price"].as_f64()?,
+        amount: trade["amount"].as_f64()?,
+        fee: trade["fee"].as_f64().unwrap_or_default(),
+        timestamp: trade["timestamp"].as_u64().unwrap_or_default(),
+    })
+}
+
+// The last `capacity` labels submitted.
+struct RecentLabels {
+    capacity: usize,
+    order: VecDeque<String>,
+    labels: HashSet<String>,
+}
+
+impl RecentLabels {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::new(),
+            labels: HashSet::new(),
+        }
+    }
+
+    // Returns false for a label that is still remembered.
+    fn insert(&mut self, label: String) -> bool {
+        if !self.labels.insert(label.clone()) {
+            return false;
+        }
+        self.order.push_back(label);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.labels.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn remove(&mut self, label: &str) {
+        if self.labels.remove(label) {
+            self.order.retain(|known| known != label);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::log;
+use crate::rt::now_millis;
+use crate::{Error, Result, Sink, Source, Stream};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+// One line of a recording file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    pub ts: u64, // milliseconds since the unix epoch
+    pub source: String,
+    pub data: String,
+}
+
+pub struct Recorder {
+    writer: Rc<RefCell<LineWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| Error::io(path.display().to_string(), err))?;
+        Ok(Self {
+            writer: Rc::new(RefCell::new(LineWriter::new(file))),
+        })
+    }
+
+    pub fn record(&self, label: &str, stream: &Stream<String>) {
+        stream.sink_to(RecordSink {
+            writer: self.writer.clone(),
+            label: label.to_string(),
+        });
+    }
+}
+
+// One recorded source; all of a recorder's sources share its file.
+struct RecordSink {
+    writer: Rc<RefCell<LineWriter<File>>>,
+    label: String,
+}
+
+impl Sink<String> for RecordSink {
+    fn on_item(&self, data: &String) {
+        let message = RecordedMessage {
+            ts: now_millis(),
+            source: self.label.clone(),
+            data: data.clone(),
+        };
+        let line = match serde_json::to_string(&message) {
+            Ok(line) => line,
+            Err(err) => {
+                log::error(
+                    "recorder",
+                    format_args!("recorder failed to encode message: {}", err),
+                );
+                return;
+            }
+        };
+        if let Err(err) = writeln!(self.writer.borrow_mut(), "{}", line) {
+            log::error(
+                "recorder",
+                format_args!("recorder failed to write message: {}", err),
+            );
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.writer
+            .borrow_mut()
+            .flush()
+            .map_err(|err| Error::io(&self.label, err))
+    }
+}
+
+const MANIFEST_FILE: &str = "manifest.jsonl";
+const DEFAULT_MAX_FILE_GAP: Duration = Duration::from_secs(60);
+
+// What a replay reads: one recording, every recording in a directory (in
+// file name order, as daily or rotated captures are named), or the files a
+// manifest lists (in order of their first message).
+enum ReplayInput {
+    Path(PathBuf),
+    Manifest(PathBuf),
+}
+
+// A manifest line; `TapeRecorder` writes more fields than these.
+#[derive(Deserialize)]
+struct ManifestEntry {
+    path: PathBuf,
+    #[serde(default)]
+    first_ts: Option<u64>,
+}
+
+// Time between the last message of one file and the first of the next, when
+// longer than `ReplaySource::with_max_file_gap`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayGap {
+    pub before: PathBuf,
+    pub after: PathBuf,
+    pub last_ts: u64,
+    pub next_ts: u64,
+}
+
+impl ReplayGap {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.next_ts.saturating_sub(self.last_ts))
+    }
+}
+
+// Replays recordings as one stream; across several files, timestamps must
+// not go backwards and gaps between files are published on `gaps()`.
+pub struct ReplaySource {
+    input: ReplayInput,
+    speed: Option<f64>,
+    max_file_gap: Duration,
+    sources: RefCell<HashMap<String, Source<String>>>,
+    gaps: Source<ReplayGap>,
+    files: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl ReplaySource {
+    // A recording file, or a directory of them.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self::with_input(ReplayInput::Path(path.into()))
+    }
+
+    // The files listed in a manifest, e.g. one written by `TapeRecorder`.
+    // Relative paths that do not exist as given are taken relative to the
+    // manifest.
+    pub fn from_manifest(path: impl Into<PathBuf>) -> Self {
+        Self::with_input(ReplayInput::Manifest(path.into()))
+    }
+
+    fn with_input(input: ReplayInput) -> Self {
+        Self {
+            input,
+            speed: None,
+            max_file_gap: DEFAULT_MAX_FILE_GAP,
+            sources: RefCell::new(HashMap::new()),
+            gaps: Source::new(),
+            files: Cell::new(0),
+            bytes: Cell::new(0),
+        }
+    }
+
+    // 1.0 replays at the recorded pace, 2.0 twice as fast; without a speed
+    // messages are replayed as fast as possible.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = (speed > 0.0).then_some(speed);
+        self
+    }
+
+    // Gaps between files up to this long are not reported; a minute by
+    // default.
+    pub fn with_max_file_gap(mut self, max_file_gap: Duration) -> Self {
+        self.max_file_gap = max_file_gap;
+        self
+    }
+
+    pub fn source(&self, label: &str) -> Stream<String> {
+        self.sources
+            .borrow_mut()
+            .entry(label.to_string())
+            .or_default()
+            .to_stream()
+    }
+
+    pub fn gaps(&self) -> &Source<ReplayGap> {
+        &self.gaps
+    }
+
+    // Callbacks on all subscribed labels.
+    pub fn subscribers(&self) -> usize {
+        self.sources
+            .borrow()
+            .values()
+            .map(Source::subscribers)
+            .sum()
+    }
+
+    // Messages delivered to subscribed labels so far.
+    pub fn replayed(&self) -> u64 {
+        self.sources.borrow().values().map(Source::emitted).sum()
+    }
+
+    // Bytes of the messages replayed so far.
+    pub fn bytes_replayed(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Files read to the end so far.
+    pub fn files_replayed(&self) -> u64 {
+        self.files.get()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let files = self.files().await?;
+        let mut previous = None;
+        for path in files {
+            self.replay_file(&path, &mut previous).await?;
+            self.files.set(self.files.get() + 1);
+        }
+
+        self.complete();
+        Ok(())
+    }
+
+    async fn files(&self) -> Result<Vec<PathBuf>> {
+        match &self.input {
+            ReplayInput::Path(path) => {
+                let label = path.display().to_string();
+                let metadata = tokio::fs::metadata(path)
+                    .await
+                    .map_err(|err| Error::io(&label, err))?;
+                if !metadata.is_dir() {
+                    return Ok(vec![path.clone()]);
+                }
+                let mut files = Vec::new();
+                let mut entries = tokio::fs::read_dir(path)
+                    .await
+                    .map_err(|err| Error::io(&label, err))?;
+                while let Some(entry) = entries
+                    .next_entry()
+                    .await
+                    .map_err(|err| Error::io(&label, err))?
+                {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.ends_with(".jsonl") && name != MANIFEST_FILE {
+                        files.push(entry.path());
+                    }
+                }
+                files.sort();
+                Ok(files)
+            }
+            ReplayInput::Manifest(path) => {
+                let label = path.display().to_string();
+                let manifest = tokio::fs::read_to_string(path)
+                    .await
+                    .map_err(|err| Error::io(&label, err))?;
+                let dir = path.parent().unwrap_or(Path::new(""));
+                let mut entries = Vec::new();
+                for (index, line) in manifest.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let mut entry: ManifestEntry = serde_json::from_str(line).map_err(|err| {
+                        Error::decode(
+                            &label,
+                            format!("line {}: invalid manifest entry: {}", index + 1, err),
+                        )
+                    })?;
+                    if entry.path.is_relative() && !entry.path.exists() {
+                        entry.path = dir.join(&entry.path);
+                    }
+                    entries.push(entry);
+                }
+                // manifests list files as they are closed, not as they start
+                entries.sort_by_key(|entry| entry.first_ts);
+                Ok(entries.into_iter().map(|entry| entry.path).collect())
+            }
+        }
+    }
+
+    // `previous` is the file and timestamp of the last message replayed.
+    async fn replay_file(&self, path: &Path, previous: &mut Option<(PathBuf, u64)>) -> Result<()> {
+        let label = path.display().to_string();
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| Error::io(&label, err))?;
+        let mut lines = BufReader::new(file).lines();
+        let mut line_number = 0;
+        let mut first = true;
+
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .map_err(|err| Error::io(&label, err))?
+        {
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: RecordedMessage = serde_json::from_str(&line).map_err(|err| {
+                Error::decode(
+                    &label,
+                    format!("line {}: invalid record: {}", line_number, err),
+                )
+            })?;
+
+            if let Some((previous_path, previous_ts)) = previous.as_ref() {
+                if message.ts < *previous_ts {
+                    return Err(Error::protocol(
+                        &label,
+                        format!(
+                            "line {}: timestamp {} is before {} of {}",
+                            line_number,
+                            message.ts,
+                            previous_ts,
+                            previous_path.display()
+                        ),
+                    ));
+                }
+                if first && previous_path != path {
+                    let gap = ReplayGap {
+                        before: previous_path.clone(),
+                        after: path.to_path_buf(),
+                        last_ts: *previous_ts,
+                        next_ts: message.ts,
+                    };
+                    if gap.duration() > self.max_file_gap {
+                        self.gaps.emit(gap);
+                    }
+                }
+            }
+            first = false;
+
+            if let (Some(speed), Some((_, previous_ts))) = (self.speed, previous.as_ref()) {
+                let gap = message.ts.saturating_sub(*previous_ts);
+                if gap > 0 {
+                    tokio::time::sleep(Duration::from_millis(gap).div_f64(speed)).await;
+                }
+            }
+            *previous = Some((path.to_path_buf(), message.ts));
+
+            if let Some(source) = self.sources.borrow().get(&message.source) {
+                self.bytes.set(self.bytes.get() + message.data.len() as u64);
+                source.emit(message.data);
+            }
+        }
+        Ok(())
+    }
+
+    // Completes every subscribed label.
+    pub fn complete(&self) {
+        for source in self.sources.borrow().values() {
+            source.complete();
+        }
+        self.gaps.complete();
+    }
+}
@@ -0,0 +1,490 @@
+use super::jsonrpc::JsonRpcError;
+use super::order_entry::{ExecutionReport, OrderRequest, OrderState, OrderUpdate};
+use crate::market::{Fill, Side};
+use crate::{Source, Stream};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// Remaining amounts smaller than this count as filled.
+const FILLED: f64 = 1e-12;
+const GENERIC_ERROR: i64 = 10001;
+const QTY_TOO_LOW: i64 = 10002;
+const ORDER_NOT_FOUND: i64 = 10004;
+
+// Best (bid, ask) as (price, size).
+type Touch = (Option<(f64, f64)>, Option<(f64, f64)>);
+
+// Public market data the simulated venue matches against, e.g. mapped from
+// a replayed recording.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MarketEvent {
+    // top of book as (price, size); none when that side is empty
+    Quote {
+        instrument: String,
+        bid: Option<(f64, f64)>,
+        ask: Option<(f64, f64)>,
+        timestamp: u64,
+    },
+    Trade {
+        instrument: String,
+        price: f64,
+        amount: f64,
+        timestamp: u64,
+    },
+}
+
+// What a strategy would call on `OrderEntryClient`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderCommand {
+    Buy(OrderRequest),
+    Sell(OrderRequest),
+    // by order id
+    Cancel(String),
+    CancelByLabel(String),
+    CancelAll,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchingModel {
+    // Resting orders fill in full once the opposite touch reaches their
+    // price or a trade prints at or through it.
+    #[default]
+    CrossAtTouch,
+    // Resting orders join the back of the displayed size at their price.
+    // Trades at the price work through the queue ahead first, and the queue
+    // shrinks with the displayed size; a crossing touch or a trade through
+    // the price fills in full.
+    QueuePosition,
+}
+
+struct Resting {
+    update: OrderUpdate,
+    // displayed size ahead at the order's price; infinite while the order
+    // sits behind the touch
+    queue_ahead: f64,
+}
+
+impl Resting {
+    fn remaining(&self) -> f64 {
+        self.update.amount - self.update.filled_amount
+    }
+
+    // Whether `price` on the other side reaches this order.
+    fn crossed_by(&self, price: f64) -> bool {
+        let limit = self.update.price.unwrap_or(price);
+        match self.update.side {
+            Side::Buy => price <= limit,
+            Side::Sell => price >= limit,
+        }
+    }
+}
+
+struct Exchange {
+    model: MatchingModel,
+    maker_fee: f64,
+    taker_fee: f64,
+    touch: HashMap<String, Touch>,
+    // in arrival order
+    resting: Vec<Resting>,
+    labels: HashSet<String>,
+    next_order: u64,
+    next_trade: u64,
+    now: u64,
+    fills: u64,
+}
+
+// A venue stub for strategy tests: matches orders from a command stream
+// against replayed market data and reports what happens to them as
+// `OrderEntryClient` would, so the same strategy runs against both.
+// Marketable orders fill in full at the opposite touch as takers; resting
+// orders fill at their limit price as makers, per the `MatchingModel`.
+// Time is that of the latest market event.
+pub struct SimulatedExchangeSource {
+    inner: Rc<SimulatedInner>,
+}
+
+struct SimulatedInner {
+    exchange: RefCell<Exchange>,
+    source: Source<ExecutionReport>,
+}
+
+impl SimulatedExchangeSource {
+    pub fn new(market: &Stream<MarketEvent>, commands: &Stream<OrderCommand>) -> Self {
+        let inner = Rc::new(SimulatedInner {
+            exchange: RefCell::new(Exchange {
+                model: MatchingModel::default(),
+                maker_fee: 0.0,
+                taker_fee: 0.0,
+                touch: HashMap::new(),
+                resting: Vec::new(),
+                labels: HashSet::new(),
+                next_order: 1,
+                next_trade: 1,
+                now: 0,
+                fills: 0,
+            }),
+            source: Source::new(),
+        });
+
+        let inner_market = inner.clone();
+        market.sink(move |event: &MarketEvent| {
+            let reports = inner_market.exchange.borrow_mut().on_market(event);
+            inner_market.report(reports);
+        });
+
+        let inner_command = inner.clone();
+        commands.sink(move |command: &OrderCommand| {
+            let reports = inner_command.exchange.borrow_mut().on_command(command);
+            inner_command.report(reports);
+        });
+
+        let inner_done = inner.clone();
+        market.on_complete(move || inner_done.source.complete());
+
+        Self { inner }
+    }
+
+    pub fn with_model(self, model: MatchingModel) -> Self {
+        self.inner.exchange.borrow_mut().model = model;
+        self
+    }
+
+    // Fees as fractions of notional, e.g. 0.0002 for 2bp.
+    pub fn with_fees(self, maker: f64, taker: f64) -> Self {
+        {
+            let mut exchange = self.inner.exchange.borrow_mut();
+            exchange.maker_fee = maker;
+            exchange.taker_fee = taker;
+        }
+        self
+    }
+
+    pub fn source(&self) -> &Source<ExecutionReport> {
+        &self.inner.source
+    }
+
+    pub fn open_orders(&self) -> Vec<OrderUpdate> {
+        let exchange = self.inner.exchange.borrow();
+        exchange
+            .resting
+            .iter()
+            .map(|order| order.update.clone())
+            .collect()
+    }
+
+    pub fn fills(&self) -> u64 {
+        self.inner.exchange.borrow().fills
+    }
+}
+
+impl Clone for SimulatedExchangeSource {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl SimulatedInner {
+    fn report(&self, reports: Vec<ExecutionReport>) {
+        for report in reports {
+            self.source.emit(report);
+        }
+    }
+}
+
+impl Exchange {
+    fn on_command(&mut self, command: &OrderCommand) -> Vec<ExecutionReport> {
+        let mut reports = Vec::new();
+        match command {
+            OrderCommand::Buy(order) => self.submit(Side::Buy, order, &mut reports),
+            OrderCommand::Sell(order) => self.submit(Side::Sell, order, &mut reports),
+            OrderCommand::Cancel(order_id) => {
+                let found = self.cancel(|order| order.order_id == *order_id, &mut reports);
+                if !found {
+                    reports.push(reject(
+                        None,
+                        "private/cancel",
+                        ORDER_NOT_FOUND,
+                        "order_not_found",
+                    ));
+                }
+            }
+            OrderCommand::CancelByLabel(label) => {
+                let found = self.cancel(|order| order.label.as_ref() == Some(label), &mut reports);
+                if !found {
+                    reports.push(reject(
+                        Some(label.clone()),
+                        "private/cancel_by_label",
+                        ORDER_NOT_FOUND,
+                        "order_not_found",
+                    ));
+                }
+            }
+            OrderCommand::CancelAll => {
+                self.cancel(|_| true, &mut reports);
+            }
+        }
+        reports
+    }
+
+    fn on_market(&mut self, event: &MarketEvent) -> Vec<ExecutionReport> {
+        let mut reports = Vec::new();
+        match event {
+            MarketEvent::Quote {
+                instrument,
+                bid,
+                ask,
+                timestamp,
+            } => {
+                self.now = *timestamp;
+                self.touch.insert(instrument.clone(), (*bid, *ask));
+                let queue = self.model == MatchingModel::QueuePosition;
+                let mut index = 0;
+                while index < self.resting.len() {
+                    let order = &mut self.resting[index];
+                    if order.update.instrument != *instrument {
+                        index += 1;
+                        continue;
+                    }
+                    let (same, opposite) = match order.update.side {
+                        Side::Buy => (*bid, *ask),
+                        Side::Sell => (*ask, *bid),
+                    };
+                    if opposite.is_some_and(|(price, _)| order.crossed_by(price)) {
+                        let amount = order.remaining();
+                        self.fill(index, amount, true, &mut reports);
+                        continue;
+                    }
+                    if queue {
+                        let limit = order.update.price.unwrap_or_default();
+                        order.queue_ahead = match same {
+                            Some((price, size)) if price == limit => order.queue_ahead.min(size),
+                            Some((price, _))
+                                if (price - limit) * order.update.side.sign() > 0.0 =>
+                            {
+                                order.queue_ahead
+                            }
+                            // the levels ahead are gone
+                            _ => 0.0,
+                        };
+                    }
+                    index += 1;
+                }
+            }
+            MarketEvent::Trade {
+                instrument,
+                price,
+                amount,
+                timestamp,
+            } => {
+                self.now = *timestamp;
+                let mut left = *amount;
+                let mut index = 0;
+                while index < self.resting.len() {
+                    let order = &mut self.resting[index];
+                    let limit = order.update.price.unwrap_or(*price);
+                    if order.update.instrument != *instrument || !order.crossed_by(*price) {
+                        index += 1;
+                        continue;
+                    }
+                    let amount = if *price != limit || self.model == MatchingModel::CrossAtTouch {
+                        order.remaining()
+                    } else {
+                        let ahead = order.queue_ahead.min(left);
+                        order.queue_ahead -= ahead;
+                        left -= ahead;
+                        let amount = order.remaining().min(left);
+                        left -= amount;
+                        amount
+                    };
+                    if amount < FILLED {
+                        index += 1;
+                        continue;
+                    }
+                    let before = self.resting.len();
+                    self.fill(index, amount, true, &mut reports);
+                    if self.resting.len() == before {
+                        index += 1;
+                    }
+                }
+            }
+        }
+        reports
+    }
+
+    fn submit(&mut self, side: Side, order: &OrderRequest, reports: &mut Vec<ExecutionReport>) {
+        let method = match side {
+            Side::Buy => "private/buy",
+            Side::Sell => "private/sell",
+        };
+        let label = order
+            .label
+            .clone()
+            .unwrap_or_else(|| format!("sim-{}", self.next_order));
+        if !self.labels.insert(label.clone()) {
+            return;
+        }
+
+        let (bid, ask) = self
+            .touch
+            .get(&order.instrument)
+            .copied()
+            .unwrap_or_default();
+        let opposite = match side {
+            Side::Buy => ask,
+            Side::Sell => bid,
+        };
+        let marketable = opposite.is_some_and(|(touch, _)| match order.price {
+            Some(limit) => (touch - limit) * side.sign() <= 0.0,
+            None => true,
+        });
+        let rejection = if order.amount <= 0.0 {
+            Some((QTY_TOO_LOW, "qty_too_low"))
+        } else if order.post_only && marketable {
+            Some((GENERIC_ERROR, "post_only_reject"))
+        } else if order.price.is_none() && opposite.is_none() {
+            Some((GENERIC_ERROR, "not_enough_liquidity"))
+        } else {
+            None
+        };
+        if let Some((code, message)) = rejection {
+            self.labels.remove(&label);
+            reports.push(reject(Some(label), method, code, message));
+            return;
+        }
+
+        let order_id = format!("SIM-{}", self.next_order);
+        self.next_order += 1;
+        let update = OrderUpdate {
+            order_id,
+            label: Some(label),
+            instrument: order.instrument.clone(),
+            side,
+            state: OrderState::Open,
+            price: order.price,
+            amount: order.amount,
+            filled_amount: 0.0,
+            average_price: None,
+            timestamp: self.now,
+        };
+        reports.push(ExecutionReport::Order(update.clone()));
+
+        let same = match side {
+            Side::Buy => bid,
+            Side::Sell => ask,
+        };
+        let queue_ahead = match (self.model, same, order.price) {
+            (MatchingModel::QueuePosition, Some((price, size)), Some(limit)) if price == limit => {
+                size
+            }
+            (MatchingModel::QueuePosition, Some((price, _)), Some(limit))
+                if (limit - price) * side.sign() < 0.0 =>
+            {
+                f64::INFINITY
+            }
+            _ => 0.0,
+        };
+        self.resting.push(Resting {
+            update,
+            queue_ahead,
+        });
+        let index = self.resting.len() - 1;
+
+        if marketable {
+            let (touch, _) = opposite.unwrap();
+            self.fill_at(index, order.amount, touch, false, reports);
+        } else if matches!(
+            order.time_in_force.as_deref(),
+            Some("immediate_or_cancel" | "fill_or_kill")
+        ) {
+            self.close(index, OrderState::Cancelled, reports);
+        }
+    }
+
+    // Fills at the order's limit price.
+    fn fill(&mut self, index: usize, amount: f64, maker: bool, reports: &mut Vec<ExecutionReport>) {
+        let price = self.resting[index].update.price.unwrap_or_default();
+        self.fill_at(index, amount, price, maker, reports);
+    }
+
+    fn fill_at(
+        &mut self,
+        index: usize,
+        amount: f64,
+        price: f64,
+        maker: bool,
+        reports: &mut Vec<ExecutionReport>,
+    ) {
+        let rate = if maker {
+            self.maker_fee
+        } else {
+            self.taker_fee
+        };
+        let trade_id = format!("SIMT-{}", self.next_trade);
+        self.next_trade += 1;
+        self.fills += 1;
+
+        let update = &mut self.resting[index].update;
+        let filled = update.filled_amount + amount;
+        let average = update.average_price.unwrap_or_default();
+        update.average_price = Some((average * update.filled_amount + price * amount) / filled);
+        update.filled_amount = filled;
+        update.timestamp = self.now;
+        reports.push(ExecutionReport::Fill(Fill {
+            trade_id,
+            order_id: update.order_id.clone(),
+            label: update.label.clone(),
+            instrument: update.instrument.clone(),
+            side: update.side,
+            price,
+            amount,
+            fee: amount * price * rate,
+            timestamp: self.now,
+        }));
+
+        if self.resting[index].remaining() < FILLED {
+            self.close(index, OrderState::Filled, reports);
+        } else {
+            reports.push(ExecutionReport::Order(self.resting[index].update.clone()));
+        }
+    }
+
+    fn close(&mut self, index: usize, state: OrderState, reports: &mut Vec<ExecutionReport>) {
+        let mut order = self.resting.remove(index);
+        order.update.state = state;
+        order.update.timestamp = self.now;
+        reports.push(ExecutionReport::Order(order.update));
+    }
+
+    fn cancel(
+        &mut self,
+        matches: impl Fn(&OrderUpdate) -> bool,
+        reports: &mut Vec<ExecutionReport>,
+    ) -> bool {
+        let mut found = false;
+        let mut index = 0;
+        while index < self.resting.len() {
+            if matches(&self.resting[index].update) {
+                self.close(index, OrderState::Cancelled, reports);
+                found = true;
+            } else {
+                index += 1;
+            }
+        }
+        found
+    }
+}
+
+fn reject(label: Option<String>, method: &str, code: i64, message: &str) -> ExecutionReport {
+    ExecutionReport::Rejected {
+        label,
+        error: JsonRpcError {
+            method: method.to_string(),
+            code,
+            message: message.to_string(),
+            data: None,
+        },
+    }
+}
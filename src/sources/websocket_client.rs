@@ -1,19 +1,59 @@
-use crate::Source;
-use anyhow::Result;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::guard::Guarded;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{proxy, ConnectionEvent, GuardViolation, Source, Stream};
+use crate::{ConnectOptions, Error, FrameGuard, ProxyConfig, Result};
+#[cfg(not(target_arch = "wasm32"))]
 use futures_util::{SinkExt, StreamExt};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::cell::Cell;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::TcpStream;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+#[cfg(not(target_arch = "wasm32"))]
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[cfg(target_arch = "wasm32")]
+mod browser;
+#[cfg(target_arch = "wasm32")]
+pub use browser::WebSocketClient;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fanout;
+#[cfg(not(target_arch = "wasm32"))]
+mod pool;
+mod redundant;
+#[cfg(all(feature = "requests", not(target_arch = "wasm32")))]
+mod universe;
+#[cfg(not(target_arch = "wasm32"))]
+pub use fanout::InstrumentFanout;
+#[cfg(not(target_arch = "wasm32"))]
+pub use pool::WebSocketPool;
+pub use redundant::{LegHealth, RedundantWebSocketClient};
+#[cfg(all(feature = "requests", not(target_arch = "wasm32")))]
+pub use universe::{InstrumentUniverse, UniverseChange};
 
 #[derive(Clone, Debug)]
 pub struct WebSocketClientConfig {
     pub url: String,
     pub init_messages: Vec<String>,
     pub buffer_size: usize,
+    // the default proxy, if any, when `None`
+    pub proxy: Option<ProxyConfig>,
+    pub connect_options: ConnectOptions,
+    pub guard: Option<FrameGuard>,
 }
 
 pub struct WebSocketClientConfigBuilder {
     url: String,
     init_messages: Vec<String>,
     buffer_size: usize,
+    proxy: Option<ProxyConfig>,
+    connect_options: ConnectOptions,
+    guard: Option<FrameGuard>,
 }
 
 impl WebSocketClientConfigBuilder {
@@ -22,6 +62,9 @@ impl WebSocketClientConfigBuilder {
             url: url.to_string(),
             init_messages: Vec::new(),
             buffer_size: 256,
+            proxy: None,
+            connect_options: ConnectOptions::default(),
+            guard: None,
         }
     }
 
@@ -40,25 +83,83 @@ impl WebSocketClientConfigBuilder {
         self
     }
 
-    pub fn build(self) -> WebSocketClientConfig {
-        WebSocketClientConfig {
+    // Connects through `proxy` rather than the default one (see
+    // `set_default_proxy`). Ignored in the browser.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    // Address family preference and racing; names are resolved on every
+    // connect either way. Ignored in the browser.
+    pub fn with_connect_options(mut self, options: ConnectOptions) -> Self {
+        self.connect_options = options;
+        self
+    }
+
+    // Limits on every frame received, checked before it is emitted.
+    pub fn with_guard(mut self, guard: FrameGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    pub fn build(self) -> Result<WebSocketClientConfig> {
+        if self.url.trim().is_empty() {
+            return Err(Error::config("websocket url is empty"));
+        }
+        if !self.url.starts_with("ws://") && !self.url.starts_with("wss://") {
+            return Err(Error::config(format!(
+                "websocket url {:?} must start with ws:// or wss://",
+                self.url
+            )));
+        }
+        if self.buffer_size == 0 {
+            return Err(Error::config(format!(
+                "websocket {}: buffer size must be non-zero",
+                self.url
+            )));
+        }
+        Ok(WebSocketClientConfig {
             url: self.url,
             init_messages: self.init_messages,
             buffer_size: self.buffer_size,
-        }
+            proxy: self.proxy,
+            connect_options: self.connect_options,
+            guard: self.guard,
+        })
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub struct WebSocketClient {
     config: WebSocketClientConfig,
     source: Source<String>,
+    decode_errors: Cell<u64>,
+    bytes: Cell<u64>,
+    connected: Cell<bool>,
+    ever_connected: Cell<bool>,
+    events: Source<ConnectionEvent>,
+    endpoint: Cell<Option<SocketAddr>>,
+    endpoints: Source<SocketAddr>,
+    guarded: Guarded,
+    running: Cell<bool>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl WebSocketClient {
     pub async fn new(config: WebSocketClientConfig) -> Result<Self> {
         Ok(Self {
+            guarded: Guarded::new(config.guard),
             config,
             source: Source::new(),
+            decode_errors: Cell::new(0),
+            bytes: Cell::new(0),
+            connected: Cell::new(false),
+            ever_connected: Cell::new(false),
+            events: Source::new(),
+            endpoint: Cell::new(None),
+            endpoints: Source::new(),
+            running: Cell::new(false),
         })
     }
 
@@ -66,25 +167,103 @@ impl WebSocketClient {
         &self.source
     }
 
+    pub fn connected(&self) -> bool {
+        self.connected.get()
+    }
+
+    // Connects and drops, including reconnects when the source is started
+    // again, e.g. for a `ResetGroup`.
+    pub fn connection_events(&self) -> Stream<ConnectionEvent> {
+        self.events.to_stream()
+    }
+
+    // The address of the current or last connection: the host's, or the
+    // proxy's when connecting through one.
+    pub fn endpoint(&self) -> Option<SocketAddr> {
+        self.endpoint.get()
+    }
+
+    // The address of every connection, just before its `ConnectionEvent`,
+    // e.g. to see a failover to new addresses.
+    pub fn endpoints(&self) -> Stream<SocketAddr> {
+        self.endpoints.to_stream()
+    }
+
+    // Binary frames dropped because they were not valid UTF-8.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.get()
+    }
+
+    // Payload bytes of all text and binary frames received.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Frames the config's guard rejected, as they happen.
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.guarded.violations()
+    }
+
+    // Frames the config's guard rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.guarded.rejected()
+    }
+
+    // Fails with `Error::AlreadyStarted` while another `start` of this
+    // client is running, rather than opening a second connection.
     pub async fn start(&self) -> Result<()> {
-        let (ws_stream, _) = connect_async(&self.config.url).await?;
+        let label = &self.config.url;
+        let _running = Running::claim(&self.running, label)?;
+        let (ws_stream, endpoint) = proxy::connect_async(
+            label,
+            self.config.proxy.as_ref(),
+            Some(&self.config.connect_options),
+        )
+        .await
+        .map_err(|err| Error::connect(label, err))?;
+        self.endpoint.set(Some(endpoint));
+        self.endpoints.emit(endpoint);
+        self.connected.set(true);
+        self.events.emit(if self.ever_connected.replace(true) {
+            ConnectionEvent::Reconnected
+        } else {
+            ConnectionEvent::Connected
+        });
+        let result = self.read(ws_stream).await;
+        self.connected.set(false);
+        self.events.emit(ConnectionEvent::Disconnected);
+        result
+    }
+
+    async fn read(&self, ws_stream: WsStream) -> Result<()> {
+        let label = &self.config.url;
         let (mut write, mut read) = ws_stream.split();
 
         let _ = self.config.buffer_size;
 
         for message in &self.config.init_messages {
-            write.send(Message::Text(message.clone().into())).await?;
+            write
+                .send(Message::Text(message.clone().into()))
+                .await
+                .map_err(|err| Error::connect(label, err))?;
         }
 
         while let Some(message) = read.next().await {
-            match message? {
+            match message.map_err(|err| Error::connect(label, err))? {
                 Message::Text(text) => {
-                    let text = text.to_string();
-                    self.source.emit(text);
+                    self.add_bytes(text.len());
+                    if self.guarded.check(label, text.as_bytes())? {
+                        self.source.emit(text.to_string());
+                    }
                 }
                 Message::Binary(data) => {
-                    if let Ok(text) = String::from_utf8(data.to_vec()) {
-                        self.source.emit(text);
+                    self.add_bytes(data.len());
+                    if !self.guarded.check(label, &data)? {
+                        continue;
+                    }
+                    match String::from_utf8(data.to_vec()) {
+                        Ok(text) => self.source.emit(text),
+                        Err(_) => self.decode_errors.set(self.decode_errors.get() + 1),
                     }
                 }
                 Message::Close(_) => break,
@@ -94,4 +273,28 @@ impl WebSocketClient {
 
         Ok(())
     }
+
+    fn add_bytes(&self, len: usize) {
+        self.bytes.set(self.bytes.get() + len as u64);
+    }
+}
+
+// Marks a client as started until the `start` future ends or is dropped.
+pub(crate) struct Running<'a>(&'a Cell<bool>);
+
+impl<'a> Running<'a> {
+    pub(crate) fn claim(running: &'a Cell<bool>, label: &str) -> Result<Self> {
+        if running.replace(true) {
+            return Err(Error::AlreadyStarted {
+                source: label.to_string(),
+            });
+        }
+        Ok(Self(running))
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
 }
@@ -0,0 +1,175 @@
+use super::{Running, WebSocketClientConfig};
+use crate::guard::Guarded;
+use crate::{ConnectionEvent, Error, GuardViolation, Result, Source, Stream};
+use js_sys::{ArrayBuffer, Uint8Array};
+use std::cell::Cell;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+enum SocketEvent {
+    Open,
+    Message(String),
+    // size of a binary frame that was not valid UTF-8
+    Undecodable(usize),
+    Error(String),
+    Close,
+}
+
+pub struct WebSocketClient {
+    config: WebSocketClientConfig,
+    source: Source<String>,
+    decode_errors: Cell<u64>,
+    bytes: Cell<u64>,
+    ever_connected: Cell<bool>,
+    events: Source<ConnectionEvent>,
+    guarded: Guarded,
+    running: Cell<bool>,
+}
+
+impl WebSocketClient {
+    pub async fn new(config: WebSocketClientConfig) -> Result<Self> {
+        Ok(Self {
+            guarded: Guarded::new(config.guard),
+            config,
+            source: Source::new(),
+            decode_errors: Cell::new(0),
+            bytes: Cell::new(0),
+            ever_connected: Cell::new(false),
+            events: Source::new(),
+            running: Cell::new(false),
+        })
+    }
+
+    pub fn source(&self) -> &Source<String> {
+        &self.source
+    }
+
+    // Connects and drops, including reconnects when the source is started
+    // again, e.g. for a `ResetGroup`.
+    pub fn connection_events(&self) -> Stream<ConnectionEvent> {
+        self.events.to_stream()
+    }
+
+    // Binary frames dropped because they were not valid UTF-8.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.get()
+    }
+
+    // Payload bytes of all text and binary frames received.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Frames the config's guard rejected, as they happen.
+    pub fn violations(&self) -> Stream<GuardViolation> {
+        self.guarded.violations()
+    }
+
+    // Frames the config's guard rejected so far.
+    pub fn rejected(&self) -> u64 {
+        self.guarded.rejected()
+    }
+
+    // Fails with `Error::AlreadyStarted` while another `start` of this
+    // client is running, rather than opening a second connection.
+    pub async fn start(&self) -> Result<()> {
+        let label = &self.config.url;
+        let _running = Running::claim(&self.running, label)?;
+        let socket = WebSocket::new(label).map_err(|err| Error::connect(label, js_error(err)))?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+
+        // Browser callbacks push into a channel so messages are emitted from
+        // this future rather than from inside the JS event handlers.
+        let (tx, mut rx) = unbounded_channel();
+        let url = self.config.url.clone();
+        let on_open = callback(&tx, |_: JsValue| Some(SocketEvent::Open));
+        let on_message = callback(&tx, |event: MessageEvent| {
+            let data = event.data();
+            if let Some(text) = data.as_string() {
+                Some(SocketEvent::Message(text))
+            } else {
+                data.dyn_ref::<ArrayBuffer>().map(|buffer| {
+                    let data = Uint8Array::new(buffer).to_vec();
+                    let len = data.len();
+                    String::from_utf8(data)
+                        .map_or(SocketEvent::Undecodable(len), SocketEvent::Message)
+                })
+            }
+        });
+        // the browser deliberately hides error details from scripts
+        let on_error = callback(&tx, move |_: Event| {
+            Some(SocketEvent::Error(format!("connection to {} failed", url)))
+        });
+        let on_close = callback(&tx, |_: CloseEvent| Some(SocketEvent::Close));
+
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let mut opened = false;
+        let result = loop {
+            match rx.recv().await {
+                Some(SocketEvent::Open) => {
+                    opened = true;
+                    self.events.emit(if self.ever_connected.replace(true) {
+                        ConnectionEvent::Reconnected
+                    } else {
+                        ConnectionEvent::Connected
+                    });
+                    let sent: std::result::Result<(), JsValue> = self
+                        .config
+                        .init_messages
+                        .iter()
+                        .try_for_each(|message| socket.send_with_str(message));
+                    if let Err(err) = sent {
+                        break Err(Error::connect(label, js_error(err)));
+                    }
+                }
+                Some(SocketEvent::Message(text)) => {
+                    self.bytes.set(self.bytes.get() + text.len() as u64);
+                    match self.guarded.check(label, text.as_bytes()) {
+                        Ok(true) => self.source.emit(text),
+                        Ok(false) => {}
+                        Err(err) => break Err(err),
+                    }
+                }
+                Some(SocketEvent::Undecodable(len)) => {
+                    self.bytes.set(self.bytes.get() + len as u64);
+                    self.decode_errors.set(self.decode_errors.get() + 1);
+                }
+                Some(SocketEvent::Error(message)) => break Err(Error::connect(label, message)),
+                Some(SocketEvent::Close) | None => break Ok(()),
+            }
+        };
+
+        socket.set_onopen(None);
+        socket.set_onmessage(None);
+        socket.set_onerror(None);
+        socket.set_onclose(None);
+        let _ = socket.close();
+        if opened {
+            self.events.emit(ConnectionEvent::Disconnected);
+        }
+        result
+    }
+}
+
+fn callback<E, F>(tx: &UnboundedSender<SocketEvent>, f: F) -> Closure<dyn FnMut(E)>
+where
+    E: wasm_bindgen::convert::FromWasmAbi + 'static,
+    F: Fn(E) -> Option<SocketEvent> + 'static,
+{
+    let tx = tx.clone();
+    Closure::new(move |event: E| {
+        if let Some(event) = f(event) {
+            let _ = tx.send(event);
+        }
+    })
+}
+
+fn js_error(value: JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
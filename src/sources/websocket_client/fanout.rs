@@ -0,0 +1,227 @@
+use super::WebSocketPool;
+use crate::log;
+use crate::rt::Instant;
+use crate::{Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+type ChannelsFn = Box<dyn Fn(&str) -> Vec<String>>;
+type KeyFn = Box<dyn Fn(&str) -> Option<String>>;
+type AddedFn = Box<dyn Fn(&str, Stream<String>)>;
+
+// One keyed stream per live instrument on top of a `WebSocketPool`. Each new
+// name on the instruments stream (e.g. parsed from an instruments poller)
+// subscribes the pool to `channels(name)` and gets a stream of the messages
+// `key` attributes to it, handed to `on_added` callbacks to build its
+// pipeline. An instrument is torn down on `expire`, or when it has not been
+// announced for `with_expiry`: its channels are unsubscribed (the pool needs
+// `with_unsubscribe`) and its stream completed, flushing what is downstream.
+// Register the pool as a source and `as_timed_emitter()` with the engine.
+pub struct InstrumentFanout {
+    inner: Rc<FanoutInner>,
+}
+
+struct FanoutInner {
+    pool: Arc<WebSocketPool>,
+    channels: ChannelsFn,
+    key: KeyFn,
+    expiry: Cell<Option<Duration>>,
+    instruments: RefCell<HashMap<String, Instrument>>,
+    on_added: RefCell<Vec<AddedFn>>,
+    added: Source<String>,
+    removed: Source<String>,
+    unrouted: Cell<u64>,
+    reported: Cell<bool>,
+}
+
+struct Instrument {
+    output: Rc<Source<String>>,
+    channels: Vec<String>,
+    last_seen: Instant,
+}
+
+impl InstrumentFanout {
+    // `key` picks the instrument a pool message belongs to, e.g. from the
+    // channel name it carries; messages of unknown instruments are counted
+    // in `unrouted`.
+    pub fn new<C, K>(
+        pool: Arc<WebSocketPool>,
+        instruments: &Stream<String>,
+        channels: C,
+        key: K,
+    ) -> Self
+    where
+        C: Fn(&str) -> Vec<String> + 'static,
+        K: Fn(&str) -> Option<String> + 'static,
+    {
+        let inner = Rc::new(FanoutInner {
+            pool,
+            channels: Box::new(channels),
+            key: Box::new(key),
+            expiry: Cell::new(None),
+            instruments: RefCell::new(HashMap::new()),
+            on_added: RefCell::new(Vec::new()),
+            added: Source::new(),
+            removed: Source::new(),
+            unrouted: Cell::new(0),
+            reported: Cell::new(false),
+        });
+
+        let announced = inner.clone();
+        instruments.sink(move |name: &String| announced.announce(name));
+
+        let messages = inner.clone();
+        let pool = inner.pool.clone();
+        let stream = pool.source().to_stream();
+        stream.sink(move |message: &String| messages.dispatch(message));
+        let completed = inner.clone();
+        stream.on_complete(move || completed.teardown_all());
+
+        Self { inner }
+    }
+
+    // Tears down instruments not announced for `expiry`, checked every
+    // `expiry` / 4 once registered with `as_timed_emitter()`.
+    pub fn with_expiry(self, expiry: Duration) -> Self {
+        self.inner.expiry.set(Some(expiry));
+        self
+    }
+
+    // Called for every instrument added from now on, with its stream.
+    pub fn on_added<F>(&self, callback: F)
+    where
+        F: Fn(&str, Stream<String>) + 'static,
+    {
+        self.inner.on_added.borrow_mut().push(Box::new(callback));
+    }
+
+    pub fn stream(&self, instrument: &str) -> Option<Stream<String>> {
+        let instruments = self.inner.instruments.borrow();
+        instruments
+            .get(instrument)
+            .map(|instrument| instrument.output.to_stream())
+    }
+
+    pub fn instruments(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.inner.instruments.borrow().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Names of instruments as they are added.
+    pub fn added(&self) -> Stream<String> {
+        self.inner.added.to_stream()
+    }
+
+    // Names of instruments as they are torn down.
+    pub fn removed(&self) -> Stream<String> {
+        self.inner.removed.to_stream()
+    }
+
+    pub fn expire(&self, instrument: &str) {
+        self.inner.teardown(instrument);
+    }
+
+    pub fn unrouted(&self) -> u64 {
+        self.inner.unrouted.get()
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl FanoutInner {
+    fn announce(&self, name: &str) {
+        if let Some(instrument) = self.instruments.borrow_mut().get_mut(name) {
+            instrument.last_seen = Instant::now();
+            return;
+        }
+        let output = Rc::new(Source::new());
+        let channels = (self.channels)(name);
+        self.instruments.borrow_mut().insert(
+            name.to_string(),
+            Instrument {
+                output: output.clone(),
+                channels: channels.clone(),
+                last_seen: Instant::now(),
+            },
+        );
+        // wired before subscribing so no message is missed
+        for callback in self.on_added.borrow().iter() {
+            callback(name, output.to_stream());
+        }
+        self.added.emit(name.to_string());
+        self.pool.add_channels(&channels);
+    }
+
+    fn dispatch(&self, message: &str) {
+        let output = (self.key)(message).and_then(|name| {
+            let instruments = self.instruments.borrow();
+            instruments
+                .get(&name)
+                .map(|instrument| instrument.output.clone())
+        });
+        match output {
+            Some(output) => output.emit(message.to_string()),
+            None => self.unrouted.set(self.unrouted.get() + 1),
+        }
+    }
+
+    fn teardown(&self, name: &str) {
+        let Some(instrument) = self.instruments.borrow_mut().remove(name) else {
+            return;
+        };
+        if let Err(err) = self.pool.remove_channels(&instrument.channels) {
+            if !self.reported.replace(true) {
+                log::warn(
+                    "instrument_fanout",
+                    format_args!("instrument {} could not be unsubscribed: {}", name, err),
+                );
+            }
+        }
+        instrument.output.complete();
+        self.removed.emit(name.to_string());
+    }
+
+    fn teardown_all(&self) {
+        let names: Vec<String> = self.instruments.borrow().keys().cloned().collect();
+        for name in names {
+            if let Some(instrument) = self.instruments.borrow_mut().remove(&name) {
+                instrument.output.complete();
+                self.removed.emit(name);
+            }
+        }
+    }
+}
+
+impl TimedEmitter for FanoutInner {
+    fn period(&self) -> Duration {
+        self.expiry.get().map_or(Duration::from_secs(1), |expiry| {
+            (expiry / 4).max(Duration::from_millis(1))
+        })
+    }
+
+    fn name(&self) -> String {
+        "instrument_fanout".to_string()
+    }
+
+    fn flush(&self) {
+        let Some(expiry) = self.expiry.get() else {
+            return;
+        };
+        let expired: Vec<String> = self
+            .instruments
+            .borrow()
+            .iter()
+            .filter(|(_, instrument)| instrument.last_seen.elapsed() > expiry)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            self.teardown(&name);
+        }
+    }
+}
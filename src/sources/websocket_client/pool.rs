@@ -0,0 +1,406 @@
+use crate::log;
+use crate::proxy;
+use crate::reconnect::{self, ReconnectBudget, ReconnectPermit};
+use crate::rt::{self, Instant};
+use crate::{ConnectionEvent, Error, Result, Source, Stream};
+use futures_util::stream::FuturesUnordered;
+use futures_util::{SinkExt, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+type SubscribeFn = Box<dyn Fn(&[String]) -> Vec<String>>;
+
+// Spreads subscriptions over as many connections as `max_per_connection`
+// requires and merges what they receive into one source. Channels of a
+// dropped connection move to connected ones with room to spare; whatever is
+// left is picked up again when a connection comes back. Channels can be
+// added and removed while running; the pool opens connections as needed.
+pub struct WebSocketPool {
+    name: String,
+    url: String,
+    max_per_connection: usize,
+    spare_connections: usize,
+    reconnect_delay: Duration,
+    reconnect_budget: Option<ReconnectBudget>,
+    subscribe: SubscribeFn,
+    unsubscribe: Option<SubscribeFn>,
+    channels: RefCell<Vec<String>>,
+    slots: RefCell<Vec<Slot>>,
+    // set while running, to start connections for added channels
+    grow: RefCell<Option<UnboundedSender<usize>>>,
+    source: Source<String>,
+    // connections that have been up before, across restarts
+    connected_once: RefCell<HashSet<usize>>,
+    events: Source<ConnectionEvent>,
+    decode_errors: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+#[derive(Default)]
+struct Slot {
+    channels: Vec<String>,
+    // set while the connection is up, to send (un)subscribe messages on it
+    outbox: Option<UnboundedSender<Vec<String>>>,
+}
+
+impl WebSocketPool {
+    // `subscribe` turns a batch of channels into the messages that subscribe
+    // to them, e.g. one Deribit `public/subscribe` request.
+    pub async fn new<F>(url: &str, max_per_connection: usize, subscribe: F) -> Result<Self>
+    where
+        F: Fn(&[String]) -> Vec<String> + 'static,
+    {
+        if max_per_connection == 0 {
+            return Err(Error::other(url, "max_per_connection must be at least 1"));
+        }
+        Ok(Self {
+            name: "websocket_pool".to_string(),
+            url: url.to_string(),
+            max_per_connection,
+            spare_connections: 0,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            reconnect_budget: None,
+            subscribe: Box::new(subscribe),
+            unsubscribe: None,
+            channels: RefCell::new(Vec::new()),
+            slots: RefCell::new(Vec::new()),
+            grow: RefCell::new(None),
+            source: Source::new(),
+            connected_once: RefCell::new(HashSet::new()),
+            events: Source::new(),
+            decode_errors: Cell::new(0),
+            bytes: Cell::new(0),
+        })
+    }
+
+    pub fn with_channels<I, S>(mut self, channels: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let known = self.channels.get_mut();
+        for channel in channels {
+            let channel = channel.into();
+            if !known.contains(&channel) {
+                known.push(channel);
+            }
+        }
+        self
+    }
+
+    // Turns channels into the messages that unsubscribe from them; needed to
+    // remove channels while running.
+    pub fn with_unsubscribe<F>(mut self, unsubscribe: F) -> Self
+    where
+        F: Fn(&[String]) -> Vec<String> + 'static,
+    {
+        self.unsubscribe = Some(Box::new(unsubscribe));
+        self
+    }
+
+    // Extra connections that give dropped channels somewhere to go while
+    // their connection is reconnecting.
+    pub fn with_spare_connections(mut self, spare: usize) -> Self {
+        self.spare_connections = spare;
+        self
+    }
+
+    // Label used in log lines.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    // Connects and reconnects within `budget`, shared with other sources.
+    pub fn with_reconnect_budget(mut self, budget: &ReconnectBudget) -> Self {
+        self.reconnect_budget = Some(budget.clone());
+        self
+    }
+
+    pub fn source(&self) -> &Source<String> {
+        &self.source
+    }
+
+    pub fn connections(&self) -> usize {
+        let needed = self
+            .channels
+            .borrow()
+            .len()
+            .div_ceil(self.max_per_connection);
+        (needed + self.spare_connections).max(self.slots.borrow().len())
+    }
+
+    // Connections currently up.
+    pub fn connected(&self) -> usize {
+        let slots = self.slots.borrow();
+        // a stopped pool leaves its outboxes behind, closed
+        slots
+            .iter()
+            .filter(|slot| {
+                slot.outbox
+                    .as_ref()
+                    .is_some_and(|outbox| !outbox.is_closed())
+            })
+            .count()
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        self.channels.borrow().clone()
+    }
+
+    // The channels each connection is currently subscribed to.
+    pub fn assignments(&self) -> Vec<Vec<String>> {
+        let slots = self.slots.borrow();
+        slots.iter().map(|slot| slot.channels.clone()).collect()
+    }
+
+    // Channels no connected connection has room for.
+    pub fn unassigned(&self) -> Vec<String> {
+        let slots = self.slots.borrow();
+        self.channels
+            .borrow()
+            .iter()
+            .filter(|channel| !slots.iter().any(|slot| slot.channels.contains(channel)))
+            .cloned()
+            .collect()
+    }
+
+    // Connects and drops of each connection. Channels of a dropped
+    // connection move to others straight away, missing messages meanwhile;
+    // reset on `Disconnected` (`ResetGroup::reset_when`) to cover them.
+    pub fn connection_events(&self) -> Stream<ConnectionEvent> {
+        self.events.to_stream()
+    }
+
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    // Subscribes to `channels` on connections with room, opening more if
+    // there are none; returns the channels that were not subscribed yet.
+    pub fn add_channels(&self, channels: &[String]) -> Vec<String> {
+        let mut added = Vec::new();
+        {
+            let mut known = self.channels.borrow_mut();
+            for channel in channels {
+                if !known.contains(channel) && !added.contains(channel) {
+                    known.push(channel.clone());
+                    added.push(channel.clone());
+                }
+            }
+        }
+        let orphans = self.assign(added.clone());
+        if !orphans.is_empty() {
+            if let Some(grow) = self.grow.borrow().as_ref() {
+                let mut slots = self.slots.borrow_mut();
+                let needed = self
+                    .channels
+                    .borrow()
+                    .len()
+                    .div_ceil(self.max_per_connection)
+                    + self.spare_connections;
+                while slots.len() < needed {
+                    slots.push(Slot::default());
+                    let _ = grow.send(slots.len() - 1);
+                }
+            }
+        }
+        added
+    }
+
+    // Unsubscribes from `channels` with the `with_unsubscribe` messages;
+    // returns the channels that were subscribed.
+    pub fn remove_channels(&self, channels: &[String]) -> Result<Vec<String>> {
+        let Some(unsubscribe) = &self.unsubscribe else {
+            return Err(Error::config(format!(
+                "{} has no unsubscribe messages (with_unsubscribe)",
+                self.name
+            )));
+        };
+        let mut removed = Vec::new();
+        self.channels.borrow_mut().retain(|channel| {
+            let keep = !channels.contains(channel);
+            if !keep {
+                removed.push(channel.clone());
+            }
+            keep
+        });
+        for slot in self.slots.borrow_mut().iter_mut() {
+            let dropped: Vec<String> = slot
+                .channels
+                .iter()
+                .filter(|channel| channels.contains(channel))
+                .cloned()
+                .collect();
+            if dropped.is_empty() {
+                continue;
+            }
+            slot.channels.retain(|channel| !dropped.contains(channel));
+            if let Some(outbox) = &slot.outbox {
+                let _ = outbox.send(unsubscribe(&dropped));
+            }
+        }
+        Ok(removed)
+    }
+
+    // Runs until the engine stops it, even with no channels, so channels can
+    // be added later.
+    pub async fn start(&self) -> Result<()> {
+        let (grow, mut added) = unbounded_channel();
+        *self.slots.borrow_mut() = Vec::new();
+        let count = self.connections();
+        *self.slots.borrow_mut() = (0..count).map(|_| Slot::default()).collect();
+        *self.grow.borrow_mut() = Some(grow);
+        let mut connections: FuturesUnordered<_> =
+            (0..count).map(|index| self.run_connection(index)).collect();
+        loop {
+            tokio::select! {
+                _ = connections.next(), if !connections.is_empty() => {}
+                Some(index) = added.recv() => connections.push(self.run_connection(index)),
+            }
+        }
+    }
+
+    async fn run_connection(&self, index: usize) {
+        let budget = self.reconnect_budget.as_ref();
+        loop {
+            let permit = reconnect::permit(budget).await;
+            match self.connect(index, permit).await {
+                Ok(()) => log::warn(
+                    "websocket_pool",
+                    format_args!("{} connection {} closed; reconnecting", self.name, index),
+                ),
+                Err(err) => log::warn(
+                    "websocket_pool",
+                    format_args!(
+                        "{} connection {} failed: {}; reconnecting",
+                        self.name, index, err
+                    ),
+                ),
+            }
+            self.release(index);
+            rt::sleep_until(Instant::now() + reconnect::delay(budget, self.reconnect_delay)).await;
+        }
+    }
+
+    async fn connect(&self, index: usize, permit: ReconnectPermit) -> Result<()> {
+        let label = &self.url;
+        let (ws_stream, _) = proxy::connect_async(label, None, None)
+            .await
+            .map_err(|err| Error::connect(label, err))?;
+        permit.connected();
+        let (mut write, mut read) = ws_stream.split();
+        let (outbox, mut moved) = unbounded_channel();
+        let claimed = self.claim(index, outbox);
+        self.events
+            .emit(if self.connected_once.borrow_mut().insert(index) {
+                ConnectionEvent::Connected
+            } else {
+                ConnectionEvent::Reconnected
+            });
+        if !claimed.is_empty() {
+            for message in (self.subscribe)(&claimed) {
+                write
+                    .send(Message::Text(message.into()))
+                    .await
+                    .map_err(|err| Error::connect(label, err))?;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                message = read.next() => {
+                    let Some(message) = message else { break };
+                    match message.map_err(|err| Error::connect(label, err))? {
+                        Message::Text(text) => {
+                            self.add_bytes(text.len());
+                            self.source.emit(text.to_string());
+                        }
+                        Message::Binary(data) => {
+                            self.add_bytes(data.len());
+                            match String::from_utf8(data.to_vec()) {
+                                Ok(text) => self.source.emit(text),
+                                Err(_) => self.decode_errors.set(self.decode_errors.get() + 1),
+                            }
+                        }
+                        Message::Close(_) => break,
+                        _ => {}
+                    }
+                }
+                Some(messages) = moved.recv() => {
+                    for message in messages {
+                        write
+                            .send(Message::Text(message.into()))
+                            .await
+                            .map_err(|err| Error::connect(label, err))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Hands a (re)connected connection the channels nobody holds, up to the
+    // per-connection limit.
+    fn claim(&self, index: usize, outbox: UnboundedSender<Vec<String>>) -> Vec<String> {
+        let unassigned = self.unassigned();
+        let mut slots = self.slots.borrow_mut();
+        let slot = &mut slots[index];
+        let room = self.max_per_connection - slot.channels.len();
+        let claimed: Vec<String> = unassigned.into_iter().take(room).collect();
+        slot.channels.extend(claimed.iter().cloned());
+        slot.outbox = Some(outbox);
+        claimed
+    }
+
+    // Moves the channels of a dropped connection to connected ones with room.
+    fn release(&self, index: usize) {
+        let (was_connected, orphans) = {
+            let mut slots = self.slots.borrow_mut();
+            let was_connected = slots[index].outbox.take().is_some();
+            (was_connected, std::mem::take(&mut slots[index].channels))
+        };
+        self.assign(orphans);
+        if was_connected {
+            self.events.emit(ConnectionEvent::Disconnected);
+        }
+    }
+
+    // Subscribes connected connections with room to `orphans`, returning
+    // those left over.
+    fn assign(&self, mut orphans: Vec<String>) -> Vec<String> {
+        let mut slots = self.slots.borrow_mut();
+        for slot in slots.iter_mut() {
+            let Some(outbox) = &slot.outbox else { continue };
+            let room = self.max_per_connection - slot.channels.len();
+            if room == 0 || orphans.is_empty() {
+                continue;
+            }
+            let moved: Vec<String> = orphans.drain(..room.min(orphans.len())).collect();
+            if outbox.send((self.subscribe)(&moved)).is_ok() {
+                slot.channels.extend(moved);
+            } else {
+                orphans.extend(moved);
+            }
+        }
+        orphans
+    }
+
+    fn add_bytes(&self, len: usize) {
+        self.bytes.set(self.bytes.get() + len as u64);
+    }
+}
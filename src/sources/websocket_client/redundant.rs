@@ -0,0 +1,364 @@
+use super::{WebSocketClient, WebSocketClientConfig};
+use crate::log;
+use crate::metrics::{Counter, Gauge, MetricsRegistry};
+use crate::reconnect::{self, ReconnectBudget, ReconnectPermit};
+use crate::rt::{self, Instant};
+use crate::{ConnectionEvent, Result, Source, Stream};
+use futures_util::future::join;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::rc::Rc;
+use std::time::Duration;
+
+const DEFAULT_DEDUP_WINDOW: usize = 10_000;
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Health of one connection of a `RedundantWebSocketClient`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LegHealth {
+    // set by the first message after (re)connecting
+    pub connected: bool,
+    pub messages: u64,
+    // messages this leg delivered before the other one
+    pub first: u64,
+    pub disconnects: u64,
+}
+
+// Keeps two connections to a feed (optionally to different endpoints) and
+// emits each message once, from whichever leg delivers it first. Messages are
+// matched by the id `key_fn` extracts; messages without one are only taken
+// from the first connected leg. Each leg reconnects on its own.
+pub struct RedundantWebSocketClient<K> {
+    name: String,
+    legs: [WebSocketClient; 2],
+    arbiter: Rc<Arbiter<K>>,
+    reconnect_delay: Duration,
+    reconnect_budget: Option<ReconnectBudget>,
+    metrics: Option<MetricsRegistry>,
+}
+
+type KeyFn<K> = Box<dyn Fn(&str) -> Option<K>>;
+
+struct Arbiter<K> {
+    key_fn: KeyFn<K>,
+    seen: RefCell<RecentKeys<K>>,
+    health: [Cell<LegHealth>; 2],
+    duplicates: Cell<u64>,
+    metrics: RefCell<Option<ArbiterMetrics>>,
+    source: Source<String>,
+    // legs connected, and whether any has been
+    up: Cell<usize>,
+    ever_up: Cell<bool>,
+    events: Source<ConnectionEvent>,
+    // each leg's connection attempt, until it connects
+    permits: [RefCell<Option<ReconnectPermit>>; 2],
+}
+
+struct ArbiterMetrics {
+    legs: [LegMetrics; 2],
+    duplicates: Counter,
+}
+
+struct LegMetrics {
+    messages: Counter,
+    first: Counter,
+    disconnects: Counter,
+    connected: Gauge,
+}
+
+// The ids of the last `capacity` messages.
+struct RecentKeys<K> {
+    capacity: usize,
+    order: VecDeque<K>,
+    keys: HashSet<K>,
+}
+
+impl<K> RedundantWebSocketClient<K>
+where
+    K: Hash + Eq + Clone + 'static,
+{
+    pub async fn new<F>(
+        primary: WebSocketClientConfig,
+        secondary: WebSocketClientConfig,
+        key_fn: F,
+    ) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<K> + 'static,
+    {
+        let legs = [
+            WebSocketClient::new(primary).await?,
+            WebSocketClient::new(secondary).await?,
+        ];
+        let arbiter = Rc::new(Arbiter {
+            key_fn: Box::new(key_fn),
+            seen: RefCell::new(RecentKeys::new(DEFAULT_DEDUP_WINDOW)),
+            health: Default::default(),
+            duplicates: Cell::new(0),
+            metrics: RefCell::new(None),
+            source: Source::new(),
+            up: Cell::new(0),
+            ever_up: Cell::new(false),
+            events: Source::new(),
+            permits: Default::default(),
+        });
+        for (leg, client) in legs.iter().enumerate() {
+            let events = arbiter.clone();
+            client
+                .connection_events()
+                .sink(move |event: &ConnectionEvent| events.leg_event(leg, *event));
+            let arbiter = arbiter.clone();
+            client
+                .source()
+                .to_stream()
+                .sink(move |message: &String| arbiter.arbitrate(leg, message));
+        }
+        Ok(Self {
+            name: "redundant_websocket".to_string(),
+            legs,
+            arbiter,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            reconnect_budget: None,
+            metrics: None,
+        })
+    }
+
+    // Label used in log lines and metrics.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    // How many recent ids are remembered to spot duplicates; it has to cover
+    // the largest lag expected between the two legs.
+    pub fn with_dedup_window(self, capacity: usize) -> Self {
+        *self.arbiter.seen.borrow_mut() = RecentKeys::new(capacity);
+        self
+    }
+
+    pub fn with_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    // Connects and reconnects both legs within `budget`, shared with other
+    // sources.
+    pub fn with_reconnect_budget(mut self, budget: &ReconnectBudget) -> Self {
+        self.reconnect_budget = Some(budget.clone());
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: MetricsRegistry) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn source(&self) -> &Source<String> {
+        &self.arbiter.source
+    }
+
+    // Connects and drops of the feed as a whole: it is only disconnected, and
+    // missing messages, while both legs are down.
+    pub fn connection_events(&self) -> Stream<ConnectionEvent> {
+        self.arbiter.events.to_stream()
+    }
+
+    pub fn leg_health(&self) -> [LegHealth; 2] {
+        [self.arbiter.health[0].get(), self.arbiter.health[1].get()]
+    }
+
+    // Messages dropped because the other leg already delivered them.
+    pub fn duplicates(&self) -> u64 {
+        self.arbiter.duplicates.get()
+    }
+
+    pub fn decode_errors(&self) -> u64 {
+        self.legs.iter().map(WebSocketClient::decode_errors).sum()
+    }
+
+    // Received over both legs, duplicates included.
+    pub fn bytes_received(&self) -> u64 {
+        self.legs.iter().map(WebSocketClient::bytes_received).sum()
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        if let Some(metrics) = &self.metrics {
+            *self.arbiter.metrics.borrow_mut() = Some(ArbiterMetrics::new(metrics, &self.name));
+        }
+        // legs stopped with the previous run never reported their drop
+        self.arbiter.up.set(0);
+        join(self.run_leg(0), self.run_leg(1)).await;
+        Ok(())
+    }
+
+    async fn run_leg(&self, leg: usize) {
+        let budget = self.reconnect_budget.as_ref();
+        loop {
+            *self.arbiter.permits[leg].borrow_mut() = Some(reconnect::permit(budget).await);
+            let result = self.legs[leg].start().await;
+            // a leg that never connected failed its attempt
+            self.arbiter.permits[leg].borrow_mut().take();
+            self.arbiter.update(leg, |health| {
+                health.connected = false;
+                health.disconnects += 1;
+            });
+            if let Some(metrics) = &*self.arbiter.metrics.borrow() {
+                metrics.legs[leg].disconnects.inc();
+            }
+            match result {
+                Ok(()) => log::warn(
+                    "redundant_websocket",
+                    format_args!("{} leg {} closed; reconnecting", self.name, leg),
+                ),
+                Err(err) => log::warn(
+                    "redundant_websocket",
+                    format_args!("{} leg {} failed: {}; reconnecting", self.name, leg, err),
+                ),
+            }
+            rt::sleep_until(Instant::now() + reconnect::delay(budget, self.reconnect_delay)).await;
+        }
+    }
+}
+
+impl<K> Arbiter<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn leg_event(&self, leg: usize, event: ConnectionEvent) {
+        if event != ConnectionEvent::Disconnected {
+            let permit = self.permits[leg].borrow_mut().take();
+            if let Some(permit) = permit {
+                permit.connected();
+            }
+        }
+        match event {
+            ConnectionEvent::Disconnected => {
+                self.up.set(self.up.get().saturating_sub(1));
+                if self.up.get() == 0 {
+                    self.events.emit(ConnectionEvent::Disconnected);
+                }
+            }
+            _ => {
+                self.up.set(self.up.get() + 1);
+                if self.up.get() == 1 {
+                    self.events.emit(if self.ever_up.replace(true) {
+                        ConnectionEvent::Reconnected
+                    } else {
+                        ConnectionEvent::Connected
+                    });
+                }
+            }
+        }
+    }
+
+    fn arbitrate(&self, leg: usize, message: &str) {
+        self.update(leg, |health| {
+            health.connected = true;
+            health.messages += 1;
+        });
+        let first = match (self.key_fn)(message) {
+            Some(key) => self.seen.borrow_mut().insert(key),
+            None => self.preferred_leg() == leg,
+        };
+        if first {
+            self.update(leg, |health| health.first += 1);
+        } else {
+            self.duplicates.set(self.duplicates.get() + 1);
+        }
+        if let Some(metrics) = &*self.metrics.borrow() {
+            metrics.legs[leg].messages.inc();
+            if first {
+                metrics.legs[leg].first.inc();
+            } else {
+                metrics.duplicates.inc();
+            }
+        }
+        if first {
+            self.source.emit(message.to_string());
+        }
+    }
+
+    fn preferred_leg(&self) -> usize {
+        if self.health[0].get().connected {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn update(&self, leg: usize, f: impl FnOnce(&mut LegHealth)) {
+        let mut health = self.health[leg].get();
+        f(&mut health);
+        self.health[leg].set(health);
+        if let Some(metrics) = &*self.metrics.borrow() {
+            metrics.legs[leg]
+                .connected
+                .set(if health.connected { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+impl ArbiterMetrics {
+    fn new(metrics: &MetricsRegistry, name: &str) -> Self {
+        let leg = |leg: &str| {
+            let labels = [("source", name), ("leg", leg)];
+            LegMetrics {
+                messages: metrics.counter(
+                    "streamz_ws_leg_messages_total",
+                    "Messages received per redundant websocket leg.",
+                    &labels,
+                ),
+                first: metrics.counter(
+                    "streamz_ws_leg_first_total",
+                    "Messages a redundant websocket leg delivered first.",
+                    &labels,
+                ),
+                disconnects: metrics.counter(
+                    "streamz_ws_leg_disconnects_total",
+                    "Disconnects per redundant websocket leg.",
+                    &labels,
+                ),
+                connected: metrics.gauge(
+                    "streamz_ws_leg_connected",
+                    "Whether a redundant websocket leg is receiving messages.",
+                    &labels,
+                ),
+            }
+        };
+        Self {
+            legs: [leg("0"), leg("1")],
+            duplicates: metrics.counter(
+                "streamz_ws_duplicates_total",
+                "Messages dropped because the other leg delivered them first.",
+                &[("source", name)],
+            ),
+        }
+    }
+}
+
+impl<K> RecentKeys<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            order: VecDeque::new(),
+            keys: HashSet::new(),
+        }
+    }
+
+    // Returns false for a key that is still remembered.
+    fn insert(&mut self, key: K) -> bool {
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,260 @@
+use super::WebSocketPool;
+use crate::log;
+use crate::sources::http_client::{PollingHttpClient, PollingHttpClientConfig};
+use crate::{Error, Result, Source, Stream};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, sleep_until, Instant};
+
+type ParseFn = Box<dyn Fn(&str) -> Result<Vec<String>>>;
+type ChannelsFn = Box<dyn Fn(&str) -> Vec<String>>;
+
+// Instruments listed and delisted between two polls.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UniverseChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+// Keeps a `WebSocketPool` subscribed to the instruments an endpoint lists,
+// e.g. Deribit's `public/get_instruments`. Every poll is parsed into names,
+// diffed against the last listing and emitted as a `UniverseChange`; the
+// pool is then subscribed to `channels(name)` of new instruments and
+// unsubscribed (it needs `with_unsubscribe`) from delisted ones, at most
+// `with_rate_limit` instruments at a time. A failed poll, or an empty
+// listing while instruments are known, leaves the universe as it is and
+// backs the next poll off, doubling up to `with_max_backoff`. Register it
+// and the pool with the engine.
+pub struct InstrumentUniverse {
+    client: PollingHttpClient,
+    pool: Arc<WebSocketPool>,
+    parse: ParseFn,
+    channels: ChannelsFn,
+    period: Duration,
+    max_backoff: Duration,
+    batch: usize,
+    batch_interval: Duration,
+    listed: RefCell<BTreeSet<String>>,
+    subscribed: RefCell<BTreeSet<String>>,
+    changes: Source<UniverseChange>,
+    failures: Cell<u64>,
+    failing: Cell<u32>,
+}
+
+impl InstrumentUniverse {
+    // Polls every `config.period`; `parse` turns a response body into
+    // instrument names.
+    pub async fn new<P, C>(
+        pool: Arc<WebSocketPool>,
+        config: PollingHttpClientConfig,
+        parse: P,
+        channels: C,
+    ) -> Result<Self>
+    where
+        P: Fn(&str) -> Result<Vec<String>> + 'static,
+        C: Fn(&str) -> Vec<String> + 'static,
+    {
+        let period = config.period;
+        let client = PollingHttpClient::new(config).await?;
+        Ok(Self {
+            client,
+            pool,
+            parse: Box::new(parse),
+            channels: Box::new(channels),
+            period,
+            max_backoff: period * 8,
+            batch: 20,
+            batch_interval: Duration::from_secs(1),
+            listed: RefCell::new(BTreeSet::new()),
+            subscribed: RefCell::new(BTreeSet::new()),
+            changes: Source::new(),
+            failures: Cell::new(0),
+            failing: Cell::new(0),
+        })
+    }
+
+    // Subscribes or unsubscribes at most `instruments` every `interval`,
+    // delistings first; 20 a second by default.
+    pub fn with_rate_limit(mut self, instruments: usize, interval: Duration) -> Result<Self> {
+        if instruments == 0 || interval.is_zero() {
+            return Err(Error::config(
+                "universe rate limit must allow at least one instrument per non-zero interval",
+            ));
+        }
+        self.batch = instruments;
+        self.batch_interval = interval;
+        Ok(self)
+    }
+
+    // The longest delay between polls while they fail; 8 periods by default.
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff.max(self.period);
+        self
+    }
+
+    // Names of a Deribit `public/get_instruments` response.
+    pub fn deribit_instruments(body: &str) -> Result<Vec<String>> {
+        let value: serde_json::Value =
+            serde_json::from_str(body).map_err(|err| Error::decode("get_instruments", err))?;
+        let Some(instruments) = value.get("result").and_then(|result| result.as_array()) else {
+            return Err(Error::protocol(
+                "get_instruments",
+                "response has no result array",
+            ));
+        };
+        Ok(instruments
+            .iter()
+            .filter(|instrument| instrument["is_active"].as_bool() != Some(false))
+            .filter_map(|instrument| instrument["instrument_name"].as_str())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn changes(&self) -> Stream<UniverseChange> {
+        self.changes.to_stream()
+    }
+
+    pub fn source(&self) -> &Source<UniverseChange> {
+        &self.changes
+    }
+
+    // The instruments of the last successful poll.
+    pub fn instruments(&self) -> Vec<String> {
+        self.listed.borrow().iter().cloned().collect()
+    }
+
+    // The instruments the pool is subscribed to, behind `instruments` while
+    // changes are rate limited.
+    pub fn subscribed(&self) -> Vec<String> {
+        self.subscribed.borrow().iter().cloned().collect()
+    }
+
+    // Instruments still to be subscribed or unsubscribed.
+    pub fn pending(&self) -> usize {
+        let listed = self.listed.borrow();
+        let subscribed = self.subscribed.borrow();
+        listed.symmetric_difference(&subscribed).count()
+    }
+
+    // Polls that failed so far.
+    pub fn failures(&self) -> u64 {
+        self.failures.get()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.client.bytes_received()
+    }
+
+    // True unless the last poll failed.
+    pub fn healthy(&self) -> bool {
+        self.failing.get() == 0
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        loop {
+            self.poll().await;
+            let next_poll = Instant::now() + self.delay();
+            while self.pending() > 0 && Instant::now() < next_poll {
+                self.apply_batch()?;
+                sleep(self.batch_interval).await;
+            }
+            sleep_until(next_poll).await;
+        }
+    }
+
+    async fn poll(&self) {
+        let names = match self.client.fetch_once().await {
+            Ok(Some(body)) => (self.parse)(&body),
+            // unchanged since the last poll
+            Ok(None) => Ok(self.instruments()),
+            Err(err) => Err(err),
+        };
+        match names {
+            Ok(names) if names.is_empty() && !self.listed.borrow().is_empty() => self.failed(
+                Error::protocol("universe", "instruments endpoint listed none"),
+            ),
+            Ok(names) => self.update(names.into_iter().collect()),
+            Err(err) => self.failed(err),
+        }
+    }
+
+    fn update(&self, names: BTreeSet<String>) {
+        if self.failing.replace(0) > 0 {
+            log::info("universe", format_args!("instruments endpoint recovered"));
+        }
+        let change = {
+            let listed = self.listed.borrow();
+            UniverseChange {
+                added: names.difference(&listed).cloned().collect(),
+                removed: listed.difference(&names).cloned().collect(),
+            }
+        };
+        *self.listed.borrow_mut() = names;
+        if !change.added.is_empty() || !change.removed.is_empty() {
+            self.changes.emit(change);
+        }
+    }
+
+    // Logged once per run of failures.
+    fn failed(&self, err: Error) {
+        self.failures.set(self.failures.get() + 1);
+        let failing = self.failing.get() + 1;
+        self.failing.set(failing);
+        if failing == 1 {
+            log::warn(
+                "universe",
+                format_args!("polling instruments failed, backing off: {}", err),
+            );
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        let failing = self.failing.get();
+        if failing == 0 {
+            return self.period;
+        }
+        self.period
+            .saturating_mul(1 << failing.min(16))
+            .min(self.max_backoff)
+    }
+
+    fn apply_batch(&self) -> Result<()> {
+        let (removed, added): (Vec<String>, Vec<String>) = {
+            let listed = self.listed.borrow();
+            let subscribed = self.subscribed.borrow();
+            let removed: Vec<String> = subscribed
+                .difference(&listed)
+                .take(self.batch)
+                .cloned()
+                .collect();
+            let added = listed
+                .difference(&subscribed)
+                .take(self.batch - removed.len())
+                .cloned()
+                .collect();
+            (removed, added)
+        };
+        if !removed.is_empty() {
+            let channels: Vec<String> = removed
+                .iter()
+                .flat_map(|name| (self.channels)(name))
+                .collect();
+            self.pool.remove_channels(&channels)?;
+        }
+        if !added.is_empty() {
+            let channels: Vec<String> = added
+                .iter()
+                .flat_map(|name| (self.channels)(name))
+                .collect();
+            self.pool.add_channels(&channels);
+        }
+        let mut subscribed = self.subscribed.borrow_mut();
+        for name in removed {
+            subscribed.remove(&name);
+        }
+        subscribed.extend(added);
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use crate::rt::Instant;
+use crate::source::track_timed_emitter;
+use crate::{Source, Stream, TimedEmitter};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+// Timers check four times per window, so held items go out at most a
+// quarter of the window late.
+const CHECKS_PER_WINDOW: u32 = 4;
+const MIN_CHECK: Duration = Duration::from_millis(1);
+
+fn check_period(window: Duration) -> Duration {
+    (window / CHECKS_PER_WINDOW).max(MIN_CHECK)
+}
+
+pub struct Debouncer<T> {
+    inner: Rc<DebounceInner<T>>,
+}
+
+struct DebounceInner<T> {
+    quiet: Duration,
+    // latest item and when it arrived
+    pending: RefCell<Option<(T, Instant)>>,
+    output: Source<T>,
+    // set by `as_timed_emitter`, checked by `EngineBuilder::build`
+    registered: Rc<Cell<bool>>,
+}
+
+pub struct Throttler<T> {
+    inner: Rc<ThrottleInner<T>>,
+}
+
+struct ThrottleInner<T> {
+    interval: Duration,
+    last_emit: Cell<Option<Instant>>,
+    // latest item held back since the last emit
+    pending: RefCell<Option<T>>,
+    dropped: Cell<u64>,
+    output: Source<T>,
+    registered: Rc<Cell<bool>>,
+}
+
+impl<T> Stream<T>
+where
+    T: Clone + 'static,
+{
+    // The latest item once `quiet` has passed without another, e.g. to
+    // recompute only after a burst of book updates settles. Held items are
+    // released by the engine's timers; register the debouncer's timed
+    // emitter, or `EngineBuilder::build` fails. The held item is emitted
+    // when `self` completes.
+    pub fn debounce(&self, quiet: Duration) -> Debouncer<T> {
+        let inner = Rc::new(DebounceInner {
+            quiet,
+            pending: RefCell::new(None),
+            output: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("debounce", quiet, &inner.registered);
+        let inner_item = inner.clone();
+        let inner_complete = inner.clone();
+
+        self.sink(move |item: &T| {
+            *inner_item.pending.borrow_mut() = Some((item.clone(), Instant::now()));
+        });
+        self.on_complete(move || {
+            let pending = inner_complete.pending.borrow_mut().take();
+            if let Some((item, _)) = pending {
+                inner_complete.output.emit(item);
+            }
+            inner_complete.output.complete();
+        });
+
+        Debouncer { inner }
+    }
+
+    // At most one item per `interval`: an item arriving after a quiet
+    // interval passes at once, later ones are held and only the latest is
+    // emitted when the interval is up, so the last state is never lost.
+    // Held items are released by the engine's timers; register the
+    // throttler's timed emitter, or `EngineBuilder::build` fails.
+    pub fn throttle(&self, interval: Duration) -> Throttler<T> {
+        let inner = Rc::new(ThrottleInner {
+            interval,
+            last_emit: Cell::new(None),
+            pending: RefCell::new(None),
+            dropped: Cell::new(0),
+            output: Source::new(),
+            registered: Rc::new(Cell::new(false)),
+        });
+        track_timed_emitter("throttle", interval, &inner.registered);
+        let inner_item = inner.clone();
+        let inner_complete = inner.clone();
+
+        self.sink(move |item: &T| {
+            let now = Instant::now();
+            if inner_item.due(now) {
+                // anything held is older than `item`
+                if inner_item.pending.take().is_some() {
+                    inner_item.dropped.set(inner_item.dropped.get() + 1);
+                }
+                inner_item.last_emit.set(Some(now));
+                inner_item.output.emit(item.clone());
+            } else if inner_item.pending.replace(Some(item.clone())).is_some() {
+                inner_item.dropped.set(inner_item.dropped.get() + 1);
+            }
+        });
+        self.on_complete(move || {
+            let pending = inner_complete.pending.borrow_mut().take();
+            if let Some(item) = pending {
+                inner_complete.output.emit(item);
+            }
+            inner_complete.output.complete();
+        });
+
+        Throttler { inner }
+    }
+}
+
+impl<T> Debouncer<T>
+where
+    T: Clone + 'static,
+{
+    pub fn stream(&self) -> Stream<T> {
+        self.inner.output.to_stream()
+    }
+
+    pub fn quiet(&self) -> Duration {
+        self.inner.quiet
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl<T> Clone for Debouncer<T> {
+    fn clone(&self) -> Self {
+        Debouncer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> TimedEmitter for DebounceInner<T>
+where
+    T: Clone + 'static,
+{
+    fn period(&self) -> Duration {
+        check_period(self.quiet)
+    }
+
+    fn name(&self) -> String {
+        "debounce".to_string()
+    }
+
+    fn flush(&self) {
+        let settled = self
+            .pending
+            .borrow()
+            .as_ref()
+            .is_some_and(|(_, arrived)| arrived.elapsed() >= self.quiet);
+        if !settled {
+            return;
+        }
+        let pending = self.pending.borrow_mut().take();
+        if let Some((item, _)) = pending {
+            self.output.emit(item);
+        }
+    }
+}
+
+impl<T> ThrottleInner<T> {
+    fn due(&self, now: Instant) -> bool {
+        self.last_emit
+            .get()
+            .is_none_or(|last| now.duration_since(last) >= self.interval)
+    }
+}
+
+impl<T> Throttler<T>
+where
+    T: Clone + 'static,
+{
+    pub fn stream(&self) -> Stream<T> {
+        self.inner.output.to_stream()
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.inner.interval
+    }
+
+    // Items replaced by a later one before they could be emitted.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.get()
+    }
+
+    pub fn as_timed_emitter(&self) -> Rc<dyn TimedEmitter> {
+        self.inner.registered.set(true);
+        self.inner.clone() as Rc<dyn TimedEmitter>
+    }
+}
+
+impl<T> Clone for Throttler<T> {
+    fn clone(&self) -> Self {
+        Throttler {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> TimedEmitter for ThrottleInner<T>
+where
+    T: Clone + 'static,
+{
+    fn period(&self) -> Duration {
+        check_period(self.interval)
+    }
+
+    fn name(&self) -> String {
+        "throttle".to_string()
+    }
+
+    fn flush(&self) {
+        let now = Instant::now();
+        if self.pending.borrow().is_none() || !self.due(now) {
+            return;
+        }
+        let pending = self.pending.borrow_mut().take();
+        if let Some(item) = pending {
+            self.last_emit.set(Some(now));
+            self.output.emit(item);
+        }
+    }
+}